@@ -0,0 +1,177 @@
+//! A log of CPU bus accesses, for chasing mapper and PPU register timing
+//! problems: each read and write the CPU makes, with the cycle it happened
+//! on and the instruction that made it. Accesses are kept in a ring of the
+//! latest ones or written to a file as they happen, optionally only those
+//! to some address ranges. DMA fetches aren't CPU accesses and aren't
+//! logged.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::trace::TraceRange;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    /// CPU cycles since power-on.
+    pub cycle: u64,
+    /// Address of the instruction that made the access.
+    pub pc: u16,
+    pub write: bool,
+    pub addr: u16,
+    /// The byte read or written, after cheats.
+    pub value: u8,
+}
+
+impl fmt::Display for Access {
+    /// E.g. `CYC:1234 PC:C000 W $2006 = 23`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "CYC:{} PC:{:04X} {} ${:04X} = {:02X}",
+            self.cycle,
+            self.pc,
+            if self.write { 'W' } else { 'R' },
+            self.addr,
+            self.value
+        )
+    }
+}
+
+enum Sink {
+    Ring {
+        entries: VecDeque<Access>,
+        capacity: usize,
+    },
+    File {
+        out: BufWriter<File>,
+        /// The first write that failed; nothing more is written after it.
+        error: Option<String>,
+    },
+}
+
+pub struct AccessLog {
+    sink: Sink,
+    ranges: Vec<TraceRange>,
+}
+
+impl AccessLog {
+    /// Keeps the latest `capacity` accesses in memory.
+    pub fn ring(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        AccessLog {
+            sink: Sink::Ring {
+                entries: VecDeque::with_capacity(capacity),
+                capacity,
+            },
+            ranges: Vec::new(),
+        }
+    }
+
+    /// Writes each access to `path`, one per line.
+    pub fn to_file(path: &Path) -> Result<Self, String> {
+        let file = File::create(path)
+            .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+        Ok(AccessLog {
+            sink: Sink::File {
+                out: BufWriter::new(file),
+                error: None,
+            },
+            ranges: Vec::new(),
+        })
+    }
+
+    /// Only logs accesses in `range`; may be given more than once. With no
+    /// ranges every access is logged.
+    pub fn range(mut self, range: TraceRange) -> Self {
+        self.ranges.push(range);
+        self
+    }
+
+    /// Accesses kept in the ring, oldest first; empty when logging to a
+    /// file.
+    pub fn entries(&self) -> Vec<Access> {
+        match &self.sink {
+            Sink::Ring { entries, .. } => entries.iter().copied().collect(),
+            Sink::File { .. } => Vec::new(),
+        }
+    }
+
+    /// Writes out anything buffered, reporting the first write that failed.
+    pub fn flush(&mut self) -> Result<(), String> {
+        match &mut self.sink {
+            Sink::Ring { .. } => Ok(()),
+            Sink::File { out, error } => match error.take() {
+                Some(error) => Err(error),
+                None => out
+                    .flush()
+                    .map_err(|e| format!("Failed to write access log: {}", e)),
+            },
+        }
+    }
+
+    /// Notes an access, made with PRG bank `bank` mapped at `access.addr`.
+    pub(crate) fn record(&mut self, access: Access, bank: Option<usize>) {
+        if !self.ranges.is_empty() && !self.ranges.iter().any(|r| r.contains(access.addr, bank)) {
+            return;
+        }
+        match &mut self.sink {
+            Sink::Ring { entries, capacity } => {
+                if entries.len() == *capacity {
+                    entries.pop_front();
+                }
+                entries.push_back(access);
+            }
+            Sink::File { out, error } => {
+                if error.is_none()
+                    && let Err(e) = writeln!(out, "{}", access)
+                {
+                    *error = Some(format!("Failed to write access log: {}", e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::cart::test::test_nes;
+
+    #[test]
+    fn test_logs_accesses_in_range() {
+        let program = assemble(
+            "
+                    .org $8000
+            reset:  lda #$23
+                    sta $2006
+            load:   lda $2002
+            done:   jmp done
+                    .org $FFFC
+                    .word reset, reset
+            ",
+        )
+        .unwrap();
+        let mut nes = test_nes(&program.slice(0x8000, 0x8000));
+        let range = TraceRange::parse("2000-3FFF").unwrap();
+        nes.set_access_log(Some(AccessLog::ring(16).range(range)));
+        while nes.bus.cpu.registers.pc != program.labels["done"] {
+            nes.clock();
+        }
+
+        let entries = nes.access_log_mut().unwrap().entries();
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].write);
+        assert_eq!((entries[0].addr, entries[0].value), (0x2006, 0x23));
+        assert_eq!(entries[1].pc, program.labels["load"]);
+        assert_eq!(entries[1].addr, 0x2002);
+        assert_eq!(entries[1].cycle - entries[0].cycle, 4);
+        assert_eq!(
+            entries[0].to_string(),
+            format!("CYC:{} PC:8002 W $2006 = 23", entries[0].cycle)
+        );
+    }
+}
@@ -1,3 +1,4 @@
+#[derive(Clone)]
 pub struct RingBuffer {
     data: Vec<i16>,
     index: usize,
@@ -23,4 +24,14 @@ impl RingBuffer {
             self.filled = true;
         }
     }
+
+    /// The last `count` samples pushed, oldest first. Slots not yet written
+    /// read as zero.
+    pub fn latest(&self, count: usize) -> Vec<i16> {
+        let len = self.data.len();
+        let count = count.min(len);
+        (0..count)
+            .map(|i| self.data[(self.index + len - count + i) % len])
+            .collect()
+    }
 }
@@ -6,6 +6,7 @@ pub const DMC_RATE_TABLE: [u16; 16] = [
 ];
 
 // TODO: This thing sounds kinda off compared to real hardware, needs more investigation.
+#[derive(Clone)]
 pub struct DmcChannel {
     pub debug_disable: bool,
     pub output_buffer: RingBuffer,
@@ -9,16 +9,25 @@ mod dmc;
 mod envelope;
 mod noise;
 mod pulse;
+mod spectrum;
+mod stems;
+mod timing;
 mod triangle;
 
+pub use spectrum::{CHANNEL_NAMES, SPECTRUM_WINDOW, Spectrum};
+pub use timing::ApuRevision;
+
+use buffer::RingBuffer;
 use channel::Channel;
 use dmc::DmcChannel;
 use noise::NoiseChannel;
 use pulse::PulseChannel;
+use stems::StemRecorder;
 use triangle::TriangleChannel;
 
-use crate::apu::dmc::DMC_RATE_TABLE;
-use crate::apu::noise::NOISE_PERIOD_TABLE;
+use timing::ApuTables;
+
+use crate::cpu::CpuModel;
 
 const CPU_CLOCK_NTSC: u64 = 1_789_773;
 
@@ -57,9 +66,25 @@ impl LengthCounter {
     }
 }
 
+/// Whether an APU channel is sounding, for activity indicators.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChannelActivity {
+    /// Producing a tone: counters running, volume above zero, period in
+    /// range and not muted. A DMC holding a level without a sample to play
+    /// is silent.
+    pub audible: bool,
+    /// Length counter, or for the DMC the sample bytes left to fetch.
+    pub length: u16,
+}
+
+#[derive(Clone)]
 pub struct APU {
     current_cycle: u64,
 
+    revision: ApuRevision,
+    cpu_model: CpuModel,
+    tables: ApuTables,
+
     frame_sequencer_mode: u8,
     frame_sequencer: u16,
     frame_reset_delay: u8,
@@ -89,6 +114,14 @@ pub struct APU {
     // DC offset removal filter for click/pop prevention
     dc_filter_x1: f32,
     dc_filter_y1: f32,
+
+    // Mixed output kept for the spectrum analyzer, which is off by default.
+    spectrum_enabled: bool,
+    mix_history: RingBuffer,
+    spectrum: Option<Spectrum>,
+
+    // Per-channel recordings, off by default.
+    stems: Option<StemRecorder>,
 }
 
 impl APU {
@@ -98,6 +131,9 @@ impl APU {
 
         APU {
             current_cycle: 0,
+            revision: ApuRevision::default(),
+            cpu_model: CpuModel::default(),
+            tables: ApuRevision::default().tables(CpuModel::default()),
             frame_sequencer_mode: 0,
             frame_sequencer: 0,
             frame_reset_delay: 0,
@@ -111,7 +147,7 @@ impl APU {
             noise: NoiseChannel::new(),
             dmc: DmcChannel::new(),
             sample_rate,
-            cpu_clock_rate: CPU_CLOCK_NTSC,
+            cpu_clock_rate: CpuModel::default().clock_rate(),
             generated_samples: 0,
             next_sample_at: 0,
             pulse_table: generate_pulse_table(),
@@ -120,6 +156,10 @@ impl APU {
             max_buffer_samples: max_samples,
             dc_filter_x1: 0.0,
             dc_filter_y1: 0.0,
+            spectrum_enabled: false,
+            mix_history: RingBuffer::new(SPECTRUM_WINDOW),
+            spectrum: None,
+            stems: None,
         }
     }
 
@@ -128,6 +168,58 @@ impl APU {
         self.max_buffer_samples = (self.sample_rate as usize).saturating_mul(4);
     }
 
+    /// Selects the console revision whose frame-counter, DMC and noise
+    /// tables to use. Takes effect from the next register write.
+    pub fn set_revision(&mut self, revision: ApuRevision) {
+        self.revision = revision;
+        self.tables = revision.tables(self.cpu_model);
+    }
+
+    /// Matches the sample timing and tables to the CPU clocking the APU.
+    /// [`crate::nes::Nes::with_model`] calls this.
+    pub fn set_cpu_model(&mut self, model: CpuModel) {
+        self.cpu_model = model;
+        self.cpu_clock_rate = model.clock_rate();
+        self.tables = self.revision.tables(model);
+    }
+
+    pub fn revision(&self) -> ApuRevision {
+        self.revision
+    }
+
+    /// Turns the spectrum analyzer on or off. While on, [`APU::spectrum`] is
+    /// refreshed about 15 times per second of emulated audio.
+    pub fn set_spectrum_enabled(&mut self, enabled: bool) {
+        self.spectrum_enabled = enabled;
+        if !enabled {
+            self.spectrum = None;
+        }
+    }
+
+    /// The latest spectrum of the mix and of each channel, if the analyzer is
+    /// enabled and has run at least once.
+    pub fn spectrum(&self) -> Option<&Spectrum> {
+        self.spectrum.as_ref()
+    }
+
+    /// Starts or stops recording each channel on its own alongside the mix.
+    /// Stopping drops whatever hasn't been taken.
+    pub fn set_stems_enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.stems = None;
+        } else if self.stems.is_none() {
+            self.stems = Some(StemRecorder::new());
+        }
+    }
+
+    /// Samples recorded per channel since the last call, in
+    /// [`CHANNEL_NAMES`] order and at the mix's sample rate, if recording.
+    /// Each channel goes through the DAC alone, so the stems only
+    /// approximate the mix.
+    pub fn take_stems(&mut self) -> Option<[Vec<f32>; 5]> {
+        self.stems.as_mut().map(StemRecorder::take)
+    }
+
     pub fn write_register(&mut self, addr: u16, value: u8) {
         let duty_table = [0b1000_0000, 0b1100_0000, 0b1111_0000, 0b0011_1111];
         match addr {
@@ -228,8 +320,12 @@ impl APU {
             }
             0x400E => {
                 let period_index = value & 0b0000_1111;
-                self.noise.mode = (value & 0b1000_0000) >> 7;
-                self.noise.period_initial = NOISE_PERIOD_TABLE[period_index as usize];
+                self.noise.mode = if self.tables.noise_short_mode {
+                    (value & 0b1000_0000) >> 7
+                } else {
+                    0
+                };
+                self.noise.period_initial = self.tables.noise_periods[period_index as usize];
                 self.noise.period_current = self.noise.period_initial;
             }
             0x400F => {
@@ -244,7 +340,7 @@ impl APU {
                     self.dmc.interrupt_flag = false;
                 }
                 let period_index = value & 0b0000_1111;
-                self.dmc.period_initial = DMC_RATE_TABLE[period_index as usize];
+                self.dmc.period_initial = self.tables.dmc_rates[period_index as usize];
                 self.dmc.period_current = self.dmc.period_initial;
             }
             0x4011 => {
@@ -291,7 +387,9 @@ impl APU {
         self.dmc.interrupt_flag = false;
     }
 
-    pub fn read_status(&mut self) -> u8 {
+    /// $4015 as a read would return it, without acknowledging the frame or
+    /// DMC interrupt.
+    pub fn peek_status(&self) -> u8 {
         let mut status = 0u8;
         if self.pulse1.length_counter.length > 0 {
             status |= 0x01;
@@ -314,6 +412,11 @@ impl APU {
         if self.dmc.interrupt_flag {
             status |= 0x80;
         }
+        status
+    }
+
+    pub fn read_status(&mut self) -> u8 {
+        let status = self.peek_status();
         self.frame_interrupt = false;
         self.dmc.interrupt_flag = false;
         status
@@ -332,16 +435,76 @@ impl APU {
         }
     }
 
+    /// Activity of each channel, in [`CHANNEL_NAMES`] order.
+    pub fn channel_activity(&self) -> [ChannelActivity; 5] {
+        let activity = |channel: &dyn Channel, length: u16| ChannelActivity {
+            audible: channel.playing() && !channel.muted(),
+            length,
+        };
+        [
+            activity(&self.pulse1, self.pulse1.length_counter.length as u16),
+            activity(&self.pulse2, self.pulse2.length_counter.length as u16),
+            activity(&self.triangle, self.triangle.length_counter.length as u16),
+            activity(&self.noise, self.noise.length_counter.length as u16),
+            ChannelActivity {
+                audible: self.dmc.bytes_remaining > 0 && !self.dmc.muted(),
+                length: self.dmc.bytes_remaining,
+            },
+        ]
+    }
+
+    /// Timer period of a channel, indexed as in [`CHANNEL_NAMES`].
+    pub fn channel_period(&self, channel: usize) -> Option<u16> {
+        match channel {
+            0 => Some(self.pulse1.period_initial),
+            1 => Some(self.pulse2.period_initial),
+            2 => Some(self.triangle.period_initial),
+            3 => Some(self.noise.period_initial),
+            4 => Some(self.dmc.period_initial),
+            _ => None,
+        }
+    }
+
+    /// Debug write of a channel's timer period, bypassing the registers and
+    /// their rate tables. The new period starts at once.
+    pub fn set_channel_period(&mut self, channel: usize, period: u16) -> Result<(), String> {
+        let (initial, current) = match channel {
+            0 => (
+                &mut self.pulse1.period_initial,
+                &mut self.pulse1.period_current,
+            ),
+            1 => (
+                &mut self.pulse2.period_initial,
+                &mut self.pulse2.period_current,
+            ),
+            2 => (
+                &mut self.triangle.period_initial,
+                &mut self.triangle.period_current,
+            ),
+            3 => (
+                &mut self.noise.period_initial,
+                &mut self.noise.period_current,
+            ),
+            4 => (&mut self.dmc.period_initial, &mut self.dmc.period_current),
+            _ => return Err(format!("No APU channel {}", channel)),
+        };
+        *initial = period;
+        *current = period;
+        Ok(())
+    }
+
     pub fn provide_dmc_sample(&mut self, value: u8) {
         self.dmc.provide_sample(value);
     }
 
+    /// Level of the APU's /IRQ output: high for as long as either the frame
+    /// or the DMC interrupt flag is set.
+    pub fn irq_asserted(&self) -> bool {
+        self.frame_interrupt || self.dmc.interrupt_flag
+    }
+
     pub fn poll_irq(&mut self) -> Option<u8> {
-        if self.frame_interrupt || self.dmc.interrupt_flag {
-            Some(0)
-        } else {
-            None
-        }
+        if self.irq_asserted() { Some(0) } else { None }
     }
 
     pub fn clock(&mut self) -> Option<u16> {
@@ -370,6 +533,22 @@ impl APU {
             self.noise.record_current_output();
             self.dmc.record_current_output();
 
+            if self.stems.is_some() {
+                let levels = self.stem_levels();
+                if let Some(stems) = &mut self.stems {
+                    stems.push(levels);
+                }
+            }
+
+            if self.spectrum_enabled {
+                self.mix_history
+                    .push((composite_sample * i16::MAX as f32) as i16);
+                let interval = (self.sample_rate / spectrum::SPECTRUM_RATE).max(1);
+                if self.generated_samples.is_multiple_of(interval) {
+                    self.update_spectrum();
+                }
+            }
+
             self.generated_samples += 1;
             self.next_sample_at =
                 ((self.generated_samples + 1) * self.cpu_clock_rate) / self.sample_rate;
@@ -379,6 +558,34 @@ impl APU {
         dma_request
     }
 
+    fn update_spectrum(&mut self) {
+        fn analyze(buffer: &RingBuffer, full_scale: i16) -> Vec<f32> {
+            let samples: Vec<f32> = buffer
+                .latest(SPECTRUM_WINDOW)
+                .into_iter()
+                .map(|s| s as f32 / full_scale as f32)
+                .collect();
+            spectrum::magnitudes(&samples)
+        }
+
+        fn channel_spectrum(channel: &dyn Channel) -> Vec<f32> {
+            let full_scale = channel.min_sample().abs().max(channel.max_sample().abs());
+            analyze(channel.sample_buffer(), full_scale.max(1))
+        }
+
+        self.spectrum = Some(Spectrum {
+            sample_rate: self.sample_rate as u32,
+            mix: analyze(&self.mix_history, i16::MAX),
+            channels: [
+                channel_spectrum(&self.pulse1),
+                channel_spectrum(&self.pulse2),
+                channel_spectrum(&self.triangle),
+                channel_spectrum(&self.noise),
+                channel_spectrum(&self.dmc),
+            ],
+        });
+    }
+
     fn push_sample(&mut self, sample: f32) {
         if let Ok(mut buffer) = self.audio_buffer.lock() {
             if buffer.len() >= self.max_buffer_samples {
@@ -388,6 +595,24 @@ impl APU {
         }
     }
 
+    // Each channel's DAC output with the others silent.
+    fn stem_levels(&self) -> [f32; 5] {
+        let level = |channel: &dyn Channel, output: i16| {
+            if channel.muted() {
+                0
+            } else {
+                output.max(0) as usize
+            }
+        };
+        [
+            self.pulse_table[level(&self.pulse1, self.pulse1.output()).min(15)],
+            self.pulse_table[level(&self.pulse2, self.pulse2.output()).min(15)],
+            self.tnd_table[level(&self.triangle, self.triangle.output()).min(15) * 3],
+            self.tnd_table[level(&self.noise, self.noise.output()).min(15) * 2],
+            self.tnd_table[level(&self.dmc, self.dmc.output()).min(127)],
+        ]
+    }
+
     fn mix_sample(&mut self) -> f32 {
         let mut combined_pulse = 0;
 
@@ -446,56 +671,47 @@ impl APU {
             }
         }
 
-        if self.frame_sequencer_mode == 0 {
-            match self.frame_sequencer {
-                7457 => self.clock_quarter_frame(),
-                14913 => {
-                    self.clock_quarter_frame();
-                    self.clock_half_frame();
-                }
-                22371 => self.clock_quarter_frame(),
-                29828 => {
-                    if !self.disable_interrupt {
-                        self.frame_interrupt = true;
-                    }
-                }
-                29829 => {
-                    if !self.disable_interrupt {
-                        self.frame_interrupt = true;
-                    }
-                    self.clock_quarter_frame();
-                    self.clock_half_frame();
-                }
-                29830 => {
-                    if !self.disable_interrupt {
-                        self.frame_interrupt = true;
-                    }
-                    self.frame_sequencer = 0;
-                }
-                _ => {}
+        let [first, second, third] = self.tables.steps;
+        let step = self.frame_sequencer;
+        if step == first || step == third {
+            self.clock_quarter_frame();
+        } else if step == second {
+            self.clock_quarter_frame();
+            self.clock_half_frame();
+        } else if self.frame_sequencer_mode == 0 {
+            // The frame IRQ flag is raised on each of the last three cycles of
+            // the 4-step sequence, so a $4015 read inside this window does not
+            // keep it cleared.
+            let end = self.tables.four_step_end;
+            if step == end - 1 {
+                self.assert_frame_irq();
+            } else if step == end {
+                self.assert_frame_irq();
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            } else if step == end + 1 {
+                self.assert_frame_irq();
+                self.frame_sequencer = 0;
             }
         } else {
-            match self.frame_sequencer {
-                7457 => self.clock_quarter_frame(),
-                14913 => {
-                    self.clock_quarter_frame();
-                    self.clock_half_frame();
-                }
-                22371 => self.clock_quarter_frame(),
-                37281 => {
-                    self.clock_quarter_frame();
-                    self.clock_half_frame();
-                }
-                37282 => {
-                    self.frame_sequencer = 0;
-                }
-                _ => {}
+            let end = self.tables.five_step_end;
+            if step == end {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            } else if step == end + 1 {
+                self.frame_sequencer = 0;
             }
         }
 
         self.frame_sequencer += 1;
     }
 
+    fn assert_frame_irq(&mut self) {
+        if !self.disable_interrupt {
+            self.frame_interrupt = true;
+        }
+    }
+
     fn clock_quarter_frame(&mut self) {
         self.pulse1.envelope.clock();
         self.pulse2.envelope.clock();
@@ -531,3 +747,257 @@ fn generate_tnd_table() -> Vec<f32> {
     }
     tnd_table
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apu() -> APU {
+        APU::new(48_000, Arc::new(Mutex::new(VecDeque::new())))
+    }
+
+    const FRAME_IRQ_WINDOW_START: u16 = 29828;
+    const FRAME_IRQ_WINDOW_END: u16 = 29830;
+
+    fn run_until_window(apu: &mut APU) {
+        while apu.frame_sequencer != FRAME_IRQ_WINDOW_START + 1 {
+            apu.clock();
+        }
+    }
+
+    #[test]
+    fn test_frame_irq_reasserts_during_window() {
+        let mut apu = apu();
+        run_until_window(&mut apu);
+        assert!(apu.irq_asserted());
+
+        assert_eq!(apu.read_status() & 0x40, 0x40);
+        assert!(!apu.irq_asserted());
+
+        apu.clock();
+        assert!(apu.irq_asserted());
+        apu.read_status();
+        apu.clock();
+        assert!(apu.irq_asserted());
+
+        // Outside the window the flag stays clear once acknowledged.
+        apu.read_status();
+        apu.clock();
+        assert!(!apu.irq_asserted());
+    }
+
+    #[test]
+    fn test_letterless_revision_ignores_noise_mode() {
+        let mut apu = apu();
+        apu.write_register(0x400E, 0x83);
+        assert_eq!(apu.noise.mode, 1);
+
+        apu.set_revision(ApuRevision::Rp2A03);
+        apu.write_register(0x400E, 0x83);
+        assert_eq!(apu.noise.mode, 0);
+        assert_eq!(apu.noise.period_initial, 32);
+    }
+
+    #[test]
+    fn test_pal_model_uses_pal_tables() {
+        let mut apu = apu();
+        apu.set_cpu_model(CpuModel::Rp2A07);
+        apu.write_register(0x400E, 0x03);
+        assert_eq!(apu.noise.period_initial, 30);
+        apu.write_register(0x4010, 0x0F);
+        assert_eq!(apu.dmc.period_initial, 50);
+        assert_eq!(apu.cpu_clock_rate, 1_662_607);
+    }
+
+    #[test]
+    fn test_spectrum_finds_pulse_tone() {
+        let mut apu = apu();
+        assert!(apu.spectrum().is_none());
+        apu.set_spectrum_enabled(true);
+
+        // Pulse 1 at ~440 Hz, constant volume 15.
+        apu.write_status(0x01);
+        apu.write_register(0x4000, 0xBF);
+        apu.write_register(0x4002, 0xFD);
+        apu.write_register(0x4003, 0x08);
+        for _ in 0..150_000 {
+            apu.clock();
+        }
+
+        let spectrum = apu.spectrum().unwrap();
+        let peak = |bins: &[f32]| {
+            (1..bins.len())
+                .max_by(|&a, &b| bins[a].total_cmp(&bins[b]))
+                .unwrap()
+        };
+        let pulse1 = spectrum.bin_frequency(peak(&spectrum.channels[0]));
+        let mix = spectrum.bin_frequency(peak(&spectrum.mix));
+        assert!((pulse1 - 440.0).abs() < 50.0, "pulse peak at {} Hz", pulse1);
+        assert!((mix - 440.0).abs() < 50.0, "mix peak at {} Hz", mix);
+        assert!(spectrum.channels[1].iter().all(|&m| m == 0.0));
+    }
+
+    #[test]
+    fn test_channel_activity_follows_length_counters() {
+        let mut apu = apu();
+        assert_eq!(apu.channel_activity(), [ChannelActivity::default(); 5]);
+
+        // Pulse 2 at constant volume 15 for length index 1 (254), and the
+        // noise channel with volume 0: counting, but silent.
+        apu.write_status(0x0A);
+        apu.write_register(0x4004, 0x3F);
+        apu.write_register(0x4006, 0xFD);
+        apu.write_register(0x4007, 0x08);
+        apu.write_register(0x400C, 0x10);
+        apu.write_register(0x400F, 0x08);
+        let activity = apu.channel_activity();
+        assert_eq!(
+            activity[1],
+            ChannelActivity {
+                audible: true,
+                length: 254
+            }
+        );
+        assert_eq!(
+            activity[3],
+            ChannelActivity {
+                audible: false,
+                length: 254
+            }
+        );
+        assert!(!activity[0].audible);
+
+        apu.write_status(0x00);
+        assert_eq!(apu.channel_activity(), [ChannelActivity::default(); 5]);
+    }
+
+    #[test]
+    fn test_stems_record_each_channel_alone() {
+        let mut apu = apu();
+        assert_eq!(apu.take_stems(), None);
+        apu.set_stems_enabled(true);
+
+        // Pulse 2 only, at constant volume 15.
+        apu.write_status(0x02);
+        apu.write_register(0x4004, 0x3F);
+        apu.write_register(0x4006, 0xFD);
+        apu.write_register(0x4007, 0x08);
+        for _ in 0..CPU_CLOCK_NTSC / 10 {
+            apu.clock();
+        }
+
+        let stems = apu.take_stems().unwrap();
+        let mix_len = apu.audio_buffer.lock().unwrap().len();
+        assert!(stems.iter().all(|stem| stem.len() == mix_len));
+        let peak = |stem: &[f32]| stem.iter().fold(0f32, |peak, s| peak.max(s.abs()));
+        assert!(peak(&stems[1]) > 0.1);
+        for (i, stem) in stems.iter().enumerate().filter(|&(i, _)| i != 1) {
+            assert_eq!(peak(stem), 0.0, "{}", CHANNEL_NAMES[i]);
+        }
+        assert_eq!(apu.take_stems().unwrap()[1].len(), 0);
+
+        apu.set_stems_enabled(false);
+        assert_eq!(apu.take_stems(), None);
+    }
+
+    #[test]
+    fn test_frame_irq_inhibit_clears_immediately() {
+        let mut apu = apu();
+        run_until_window(&mut apu);
+        assert!(apu.irq_asserted());
+
+        apu.write_frame_counter(0x40);
+        assert!(!apu.irq_asserted());
+
+        for _ in 0..(FRAME_IRQ_WINDOW_END as u32 * 2) {
+            apu.clock();
+            assert!(!apu.irq_asserted());
+        }
+    }
+
+    // Plays the sample set up in `registers` ($4010-$4013), answering each
+    // DMC fetch from `memory`, until `fetches` bytes have been read. Returns
+    // the fetch addresses and, per fetch, whether the IRQ was already up.
+    fn fetch_dmc(apu: &mut APU, registers: [u8; 4], fetches: usize) -> (Vec<u16>, Vec<bool>) {
+        let memory = |addr: u16| addr as u8;
+        apu.write_frame_counter(0x40);
+        for (i, &value) in registers.iter().enumerate() {
+            apu.write_register(0x4010 + i as u16, value);
+        }
+        apu.write_status(0x10);
+
+        let (mut addresses, mut irq) = (Vec::new(), Vec::new());
+        while addresses.len() < fetches {
+            if let Some(addr) = apu.clock() {
+                irq.push(apu.irq_asserted());
+                apu.provide_dmc_sample(memory(addr));
+                addresses.push(addr);
+            }
+        }
+        (addresses, irq)
+    }
+
+    #[test]
+    fn test_dmc_address_wraps_to_8000() {
+        // $FFC0, 65 bytes, IRQ enabled, fastest rate.
+        let mut apu = apu();
+        let (addresses, irq) = fetch_dmc(&mut apu, [0x8F, 0x00, 0xFF, 0x04], 65);
+
+        let expected: Vec<u16> = (0xFFC0..=0xFFFF).chain([0x8000]).collect();
+        assert_eq!(addresses, expected);
+        // The IRQ is raised as the last byte is fetched, not when it plays,
+        // and $4015 reports the sample as finished from then on.
+        assert!(irq.iter().all(|&asserted| !asserted));
+        assert!(apu.irq_asserted());
+        assert_eq!(apu.dmc.sample_buffer, Some(0x00));
+        assert_eq!(apu.read_status() & 0x90, 0x80);
+        assert!(!apu.irq_asserted());
+
+        // No further fetches once the sample has ended.
+        for _ in 0..54 * 8 * 4 {
+            assert_eq!(apu.clock(), None);
+        }
+    }
+
+    #[test]
+    fn test_dmc_loop_restarts_after_wrap() {
+        // Looping with IRQ enabled: the restart happens instead of the IRQ.
+        let mut apu = apu();
+        let (addresses, _) = fetch_dmc(&mut apu, [0xCF, 0x00, 0xFF, 0x04], 65 * 2 + 1);
+
+        let pass: Vec<u16> = (0xFFC0..=0xFFFF).chain([0x8000]).collect();
+        assert_eq!(addresses[..65], pass[..]);
+        assert_eq!(addresses[65..130], pass[..]);
+        assert_eq!(addresses[130], 0xFFC0);
+        assert!(!apu.irq_asserted());
+        // The restart reloads the counter on the fetch that emptied it.
+        assert_eq!(apu.dmc.bytes_remaining, 64);
+        assert_eq!(apu.read_status() & 0x10, 0x10);
+    }
+
+    #[test]
+    fn test_dmc_loop_cleared_mid_sample_ends_with_irq() {
+        let mut apu = apu();
+        fetch_dmc(&mut apu, [0xCF, 0x00, 0xFF, 0x04], 10);
+
+        // Clearing the loop flag lets the current pass finish, wrap
+        // included, and then raise the IRQ.
+        apu.write_register(0x4010, 0x8F);
+        let mut addresses = Vec::new();
+        while addresses.len() < 55 {
+            if let Some(addr) = apu.clock() {
+                apu.provide_dmc_sample(0);
+                addresses.push(addr);
+            }
+        }
+        assert_eq!(addresses.first(), Some(&0xFFCA));
+        assert_eq!(addresses.last(), Some(&0x8000));
+        assert!(apu.irq_asserted());
+
+        // Writing $4015 acknowledges it; restarting reloads from $FFC0.
+        apu.write_status(0x10);
+        assert!(!apu.irq_asserted());
+        assert_eq!(apu.dmc.current_address, 0xFFC0);
+        assert_eq!(apu.dmc.bytes_remaining, 65);
+    }
+}
@@ -7,6 +7,7 @@ pub const NOISE_PERIOD_TABLE: [u16; 16] = [
     4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
 ];
 
+#[derive(Clone)]
 pub struct NoiseChannel {
     pub debug_disable: bool,
     pub output_buffer: RingBuffer,
@@ -7,6 +7,7 @@ use crate::apu::channel::Timbre;
 use crate::apu::channel::Volume;
 use crate::apu::envelope::Envelope;
 
+#[derive(Clone)]
 pub struct PulseChannel {
     pub debug_disable: bool,
     pub output_buffer: RingBuffer,
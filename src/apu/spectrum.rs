@@ -0,0 +1,107 @@
+use std::f32::consts::PI;
+
+/// Samples per analysis window; must be a power of two.
+pub const SPECTRUM_WINDOW: usize = 1024;
+/// Spectrum refreshes per second of emulated audio.
+pub const SPECTRUM_RATE: u64 = 15;
+
+pub const CHANNEL_NAMES: [&str; 5] = ["pulse1", "pulse2", "triangle", "noise", "dmc"];
+
+/// Magnitude spectrum of the most recent [`SPECTRUM_WINDOW`] output samples.
+/// Bin `i` is centred on `i * sample_rate / SPECTRUM_WINDOW` Hz; magnitudes
+/// are linear, with a full-scale sine reading about 1.0.
+#[derive(Clone, Debug)]
+pub struct Spectrum {
+    pub sample_rate: u32,
+    pub mix: Vec<f32>,
+    /// Per channel, in [`CHANNEL_NAMES`] order.
+    pub channels: [Vec<f32>; 5],
+}
+
+impl Spectrum {
+    pub fn bin_frequency(&self, bin: usize) -> f32 {
+        bin as f32 * self.sample_rate as f32 / SPECTRUM_WINDOW as f32
+    }
+}
+
+/// Hann-windowed FFT of `samples`, returning `samples.len() / 2` bins.
+pub fn magnitudes(samples: &[f32]) -> Vec<f32> {
+    let n = samples.len();
+    assert!(n.is_power_of_two(), "FFT size must be a power of two");
+
+    let window = |i: usize| 0.5 - 0.5 * (2.0 * PI * i as f32 / n as f32).cos();
+    let mut re: Vec<f32> = samples
+        .iter()
+        .enumerate()
+        .map(|(i, s)| s * window(i))
+        .collect();
+    let mut im = vec![0.0f32; n];
+    fft(&mut re, &mut im);
+
+    // The Hann window halves the coherent gain, and a real signal splits its
+    // energy between positive and negative frequencies.
+    let scale = 4.0 / n as f32;
+    (0..n / 2)
+        .map(|i| (re[i] * re[i] + im[i] * im[i]).sqrt() * scale)
+        .collect()
+}
+
+// In-place iterative radix-2 Cooley-Tukey.
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let a = start + k;
+                let b = a + len / 2;
+                let tr = re[b] * cos - im[b] * sin;
+                let ti = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - tr;
+                im[b] = im[a] - ti;
+                re[a] += tr;
+                im[a] += ti;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sine_peaks_at_its_bin() {
+        let bin = 40;
+        let samples: Vec<f32> = (0..SPECTRUM_WINDOW)
+            .map(|i| (2.0 * PI * bin as f32 * i as f32 / SPECTRUM_WINDOW as f32).sin())
+            .collect();
+        let spectrum = magnitudes(&samples);
+
+        assert_eq!(spectrum.len(), SPECTRUM_WINDOW / 2);
+        let peak = (0..spectrum.len())
+            .max_by(|&a, &b| spectrum[a].total_cmp(&spectrum[b]))
+            .unwrap();
+        assert_eq!(peak, bin);
+        assert!((spectrum[bin] - 1.0).abs() < 0.01);
+        assert!(spectrum[bin + 5] < 0.01);
+    }
+}
@@ -0,0 +1,56 @@
+//! Records each channel on its own ("stems"), for remixing a soundtrack.
+//!
+//! The console mixes the channels through one nonlinear DAC, so a loud
+//! channel compresses the others. A stem is instead each channel run through
+//! that DAC alone, as if the rest were silent. That makes the stems an
+//! approximation: they sum to slightly louder than the real mix, most so for
+//! the triangle, noise and DMC, which share the more compressed curve.
+
+/// The mix filter's DC blocker, one per stem.
+const DC_ALPHA: f32 = 0.9999;
+
+#[derive(Clone)]
+pub(super) struct StemRecorder {
+    samples: [Vec<f32>; 5],
+    /// Previous input and output of each stem's DC blocker.
+    dc_filters: [(f32, f32); 5],
+    /// Whether the filters have seen a sample yet.
+    primed: bool,
+}
+
+impl StemRecorder {
+    pub(super) fn new() -> Self {
+        StemRecorder {
+            samples: Default::default(),
+            dc_filters: [(0.0, 0.0); 5],
+            primed: false,
+        }
+    }
+
+    /// Adds one sample per channel: its DAC output, from 0 up.
+    pub(super) fn push(&mut self, levels: [f32; 5]) {
+        // Start from the levels at hand, so a channel that idles above zero
+        // (the triangle) doesn't open its stem with a click.
+        if !self.primed {
+            self.primed = true;
+            for ((x1, _), level) in self.dc_filters.iter_mut().zip(levels) {
+                *x1 = level;
+            }
+        }
+        for ((samples, (x1, y1)), level) in self
+            .samples
+            .iter_mut()
+            .zip(&mut self.dc_filters)
+            .zip(levels)
+        {
+            let filtered = DC_ALPHA * (*y1 + level - *x1);
+            *x1 = level;
+            *y1 = filtered;
+            samples.push(filtered.clamp(-1.0, 1.0));
+        }
+    }
+
+    pub(super) fn take(&mut self) -> [Vec<f32>; 5] {
+        std::mem::take(&mut self.samples)
+    }
+}
@@ -0,0 +1,71 @@
+use crate::apu::dmc::DMC_RATE_TABLE;
+use crate::apu::noise::NOISE_PERIOD_TABLE;
+use crate::cpu::CpuModel;
+
+/// Console APU revision, for users matching a specific unit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ApuRevision {
+    /// The original letterless RP2A03, which ignores the noise mode bit.
+    Rp2A03,
+    /// RP2A03E and other early lettered revisions.
+    Rp2A03E,
+    /// RP2A03G and later, as found in most front-loaders.
+    #[default]
+    Rp2A03G,
+}
+
+/// Per-revision timing the APU reads instead of hardcoded constants, so
+/// other console variants only need a new table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct ApuTables {
+    /// CPU cycles of the quarter-frame steps shared by both sequencer modes.
+    pub(crate) steps: [u16; 3],
+    /// Last step of the 4-step sequence; the frame IRQ is raised on this
+    /// cycle and the ones either side of it.
+    pub(crate) four_step_end: u16,
+    /// Last step of the 5-step sequence.
+    pub(crate) five_step_end: u16,
+    pub(crate) dmc_rates: [u16; 16],
+    pub(crate) noise_periods: [u16; 16],
+    /// Whether the noise channel's short (93-step) mode exists.
+    pub(crate) noise_short_mode: bool,
+}
+
+const NTSC: ApuTables = ApuTables {
+    steps: [7457, 14913, 22371],
+    four_step_end: 29829,
+    five_step_end: 37281,
+    dmc_rates: DMC_RATE_TABLE,
+    noise_periods: NOISE_PERIOD_TABLE,
+    noise_short_mode: true,
+};
+
+// The 2A07's APU runs the same sequences against its slower clock.
+const PAL: ApuTables = ApuTables {
+    steps: [8313, 16627, 24939],
+    four_step_end: 33253,
+    five_step_end: 41565,
+    dmc_rates: [
+        398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+    ],
+    noise_periods: [
+        4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+    ],
+    noise_short_mode: true,
+};
+
+impl ApuRevision {
+    pub(crate) fn tables(self, model: CpuModel) -> ApuTables {
+        let base = match model {
+            CpuModel::Rp2A07 => PAL,
+            CpuModel::Rp2A03 | CpuModel::Mos6502 | CpuModel::Wdc65C02 => NTSC,
+        };
+        match self {
+            ApuRevision::Rp2A03 => ApuTables {
+                noise_short_mode: false,
+                ..base
+            },
+            ApuRevision::Rp2A03E | ApuRevision::Rp2A03G => base,
+        }
+    }
+}
@@ -2,6 +2,7 @@ use crate::apu::buffer::RingBuffer;
 use crate::apu::channel::{Channel, PlaybackRate, Timbre, Volume};
 use crate::apu::{CPU_CLOCK_NTSC, LengthCounter};
 
+#[derive(Clone)]
 pub struct TriangleChannel {
     pub debug_disable: bool,
     pub output_buffer: RingBuffer,
@@ -0,0 +1,442 @@
+//! A small 6502 assembler, so tests and tools can write programs as source
+//! instead of hand-encoded bytes:
+//!
+//! ```text
+//!         .org $8000
+//! PPUCTRL = $2000
+//! reset:  ldx #$00
+//! loop:   lda message,x     ; labels may be used before they are defined
+//!         beq done
+//!         inx
+//!         bne loop
+//! done:   jmp done
+//! message: .byte "HI", 0
+//!         .org $FFFC
+//!         .word reset, 0
+//! ```
+//!
+//! Supports all official mnemonics and addressing modes, `name:` labels,
+//! `name = value` constants, `.org`, `.byte`/`.db` (numbers and strings) and
+//! `.word`/`.dw`. Operands are `$hex`, `%binary`, decimal or a name, with
+//! `<`/`>` for the low/high byte and `+`/`-` between terms. Zero page
+//! addressing is used when the address is known to fit in it on first use.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::opcodes::{AddressingMode, CPU_OPCODES};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assembly {
+    /// Address of the first byte, from the first `.org` ($0000 without one).
+    pub origin: u16,
+    /// Everything from `origin` to the last byte emitted; gaps left by
+    /// `.org` are zero.
+    pub bytes: Vec<u8>,
+    pub labels: HashMap<String, u16>,
+}
+
+impl Assembly {
+    /// The bytes assembled for `addr..addr + len`, zero where nothing was.
+    pub fn slice(&self, addr: u16, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| {
+                let offset = (addr as usize + i).wrapping_sub(self.origin as usize);
+                self.bytes.get(offset).copied().unwrap_or(0)
+            })
+            .collect()
+    }
+}
+
+/// Assembles `source`; errors name the offending line.
+pub fn assemble(source: &str) -> Result<Assembly, String> {
+    let mut assembler = Assembler::default();
+    assembler.pass(source, false)?;
+    assembler.pass(source, true)?;
+    Ok(Assembly {
+        origin: assembler.origin.unwrap_or(0),
+        bytes: assembler.output,
+        labels: assembler.labels,
+    })
+}
+
+#[derive(Default)]
+struct Assembler {
+    labels: HashMap<String, u16>,
+    // Lines whose operand named something not yet defined in the first pass;
+    // they keep the absolute form in the second so sizes don't change.
+    forward: HashSet<usize>,
+    origin: Option<u16>,
+    pc: u16,
+    output: Vec<u8>,
+    emitting: bool,
+}
+
+impl Assembler {
+    fn pass(&mut self, source: &str, emitting: bool) -> Result<(), String> {
+        self.emitting = emitting;
+        self.origin = None;
+        self.pc = 0;
+        self.output.clear();
+        for (number, line) in source.lines().enumerate() {
+            self.line(number, line)
+                .map_err(|e| format!("Line {}: {}", number + 1, e))?;
+        }
+        Ok(())
+    }
+
+    fn line(&mut self, number: usize, line: &str) -> Result<(), String> {
+        let mut rest = strip_comment(line).trim();
+        if let Some((name, value)) = rest.split_once('=')
+            && is_name(name.trim())
+        {
+            let value = self.expr(number, value.trim())?;
+            return self.define(name.trim(), value.unwrap_or(0));
+        }
+        if let Some((label, after)) = rest.split_once(':')
+            && is_name(label.trim())
+        {
+            self.define(label.trim(), self.pc)?;
+            rest = after.trim();
+        }
+        if rest.is_empty() {
+            return Ok(());
+        }
+
+        let (word, operand) = match rest.split_once(char::is_whitespace) {
+            Some((word, operand)) => (word, operand.trim()),
+            None => (rest, ""),
+        };
+        match word.to_ascii_lowercase().as_str() {
+            ".org" => {
+                let addr = self
+                    .expr(number, operand)?
+                    .ok_or(".org needs a defined address")?;
+                match self.origin {
+                    None => self.origin = Some(addr),
+                    Some(_) if addr < self.pc => {
+                        return Err(format!(".org ${:04X} is behind ${:04X}", addr, self.pc));
+                    }
+                    Some(_) => self.emit(&vec![0; (addr - self.pc) as usize]),
+                }
+                self.pc = addr;
+                Ok(())
+            }
+            ".byte" | ".db" => {
+                for item in split_list(operand) {
+                    if let Some(text) = item.strip_prefix('"') {
+                        let text = text.strip_suffix('"').ok_or("Unterminated string")?;
+                        self.emit(text.as_bytes());
+                    } else {
+                        let value = self.expr(number, item)?.unwrap_or(0);
+                        if value > 0xFF {
+                            return Err(format!("${:X} doesn't fit in a byte", value));
+                        }
+                        self.emit(&[value as u8]);
+                    }
+                }
+                Ok(())
+            }
+            ".word" | ".dw" => {
+                for item in split_list(operand) {
+                    let value = self.expr(number, item)?.unwrap_or(0);
+                    self.emit(&value.to_le_bytes());
+                }
+                Ok(())
+            }
+            _ if word.starts_with('.') => Err(format!("Unknown directive: {}", word)),
+            _ => self.instruction(number, word, operand),
+        }
+    }
+
+    fn define(&mut self, name: &str, value: u16) -> Result<(), String> {
+        if !is_name(name) {
+            return Err(format!("Invalid name: {}", name));
+        }
+        // Constants defined from later labels only get their value in the
+        // second pass.
+        if self.labels.insert(name.to_string(), value).is_some() && !self.emitting {
+            return Err(format!("{} is defined twice", name));
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        if self.origin.is_none() {
+            self.origin = Some(self.pc);
+        }
+        if self.emitting {
+            self.output.extend_from_slice(bytes);
+        }
+        self.pc = self.pc.wrapping_add(bytes.len() as u16);
+    }
+
+    fn instruction(&mut self, number: usize, mnemonic: &str, operand: &str) -> Result<(), String> {
+        let upper = operand.to_ascii_uppercase();
+        let (modes, expr): (&[AddressingMode], &str) = if operand.is_empty() {
+            (&[AddressingMode::Accumulator, AddressingMode::None], "")
+        } else if upper == "A" {
+            (&[AddressingMode::Accumulator], "")
+        } else if let Some(value) = operand.strip_prefix('#') {
+            (&[AddressingMode::Immediate], value)
+        } else if let Some(inner) = operand.strip_prefix('(') {
+            if let Some(inner) = strip_suffix_ignore_case(inner, ",X)") {
+                (&[AddressingMode::IndirectX], inner)
+            } else if let Some(inner) = strip_suffix_ignore_case(inner, "),Y") {
+                (&[AddressingMode::IndirectY], inner)
+            } else {
+                let inner = inner.strip_suffix(')').ok_or("Missing )")?;
+                (&[AddressingMode::Indirect], inner)
+            }
+        } else if let Some(value) = strip_suffix_ignore_case(operand, ",X") {
+            (
+                &[AddressingMode::ZeroPageX, AddressingMode::AbsoluteX],
+                value,
+            )
+        } else if let Some(value) = strip_suffix_ignore_case(operand, ",Y") {
+            (
+                &[AddressingMode::ZeroPageY, AddressingMode::AbsoluteY],
+                value,
+            )
+        } else {
+            (
+                &[
+                    AddressingMode::Relative,
+                    AddressingMode::ZeroPage,
+                    AddressingMode::Absolute,
+                ],
+                operand,
+            )
+        };
+
+        let value = if expr.is_empty() {
+            None
+        } else {
+            Some(self.expr(number, expr.trim())?)
+        };
+        let wide = match value {
+            Some(None) => true,
+            Some(Some(value)) => value > 0xFF || self.forward.contains(&number),
+            None => false,
+        };
+        let value = value.flatten().unwrap_or(0);
+
+        let opcode = modes
+            .iter()
+            .filter(|mode| {
+                !(wide
+                    && matches!(
+                        mode,
+                        AddressingMode::ZeroPage
+                            | AddressingMode::ZeroPageX
+                            | AddressingMode::ZeroPageY
+                    ))
+            })
+            .find_map(|mode| {
+                CPU_OPCODES.get_opcodes().iter().find(|opcode| {
+                    opcode.mode == *mode
+                        && opcode.mnemonic.to_string().eq_ignore_ascii_case(mnemonic)
+                })
+            })
+            .ok_or_else(|| format!("Invalid instruction: {} {}", mnemonic, operand))?;
+
+        let operand_bytes = match opcode.mode {
+            AddressingMode::Relative => {
+                let next = self.pc.wrapping_add(2);
+                let offset = value.wrapping_sub(next) as i16;
+                if self.emitting && !(-128..=127).contains(&offset) {
+                    return Err(format!("Branch to ${:04X} is out of range", value));
+                }
+                vec![offset as u8]
+            }
+            _ if opcode.bytes == 2 => {
+                if self.emitting && value > 0xFF {
+                    return Err(format!("${:X} doesn't fit in a byte", value));
+                }
+                vec![value as u8]
+            }
+            _ if opcode.bytes == 3 => value.to_le_bytes().to_vec(),
+            _ => Vec::new(),
+        };
+        self.emit(&[opcode.code]);
+        self.emit(&operand_bytes);
+        Ok(())
+    }
+
+    // The value of `text`, or `None` in the first pass when it names
+    // something not defined yet.
+    fn expr(&mut self, number: usize, text: &str) -> Result<Option<u16>, String> {
+        let mut total: u16 = 0;
+        let mut defined = true;
+        let mut rest = text.trim();
+        let mut negate = false;
+        loop {
+            let end = rest[1.min(rest.len())..]
+                .find(['+', '-'])
+                .map_or(rest.len(), |i| i + 1);
+            let (term, tail) = rest.split_at(end);
+            match self.term(term.trim())? {
+                Some(value) if negate => total = total.wrapping_sub(value),
+                Some(value) => total = total.wrapping_add(value),
+                None => defined = false,
+            }
+            let Some(op) = tail.chars().next() else {
+                break;
+            };
+            negate = op == '-';
+            rest = tail[1..].trim();
+        }
+
+        if !defined {
+            if self.emitting {
+                return Err(format!("Undefined name in {}", text));
+            }
+            self.forward.insert(number);
+            return Ok(None);
+        }
+        Ok(Some(total))
+    }
+
+    fn term(&self, term: &str) -> Result<Option<u16>, String> {
+        if let Some(inner) = term.strip_prefix('<') {
+            return Ok(self.term(inner)?.map(|value| value & 0xFF));
+        }
+        if let Some(inner) = term.strip_prefix('>') {
+            return Ok(self.term(inner)?.map(|value| value >> 8));
+        }
+        let number = if let Some(hex) = term.strip_prefix('$') {
+            u16::from_str_radix(hex, 16)
+        } else if let Some(binary) = term.strip_prefix('%') {
+            u16::from_str_radix(binary, 2)
+        } else if term.starts_with(|c: char| c.is_ascii_digit()) {
+            term.parse()
+        } else if is_name(term) {
+            return Ok(self.labels.get(term).copied());
+        } else {
+            return Err(format!("Invalid operand: {}", term));
+        };
+        number
+            .map(Some)
+            .map_err(|_| format!("Invalid number: {}", term))
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+// Splits a directive's operands on commas outside strings.
+fn split_list(text: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut start = 0;
+    let mut in_string = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            ',' if !in_string => {
+                items.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(text[start..].trim());
+    items.retain(|item| !item.is_empty());
+    items
+}
+
+fn strip_suffix_ignore_case<'a>(text: &'a str, suffix: &str) -> Option<&'a str> {
+    let split = text.len().checked_sub(suffix.len())?;
+    (text.is_char_boundary(split) && text[split..].eq_ignore_ascii_case(suffix))
+        .then(|| text[..split].trim_end())
+}
+
+fn is_name(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_assembles_addressing_modes() {
+        let program = assemble(
+            "
+            .org $8000
+            lda #$05        ; immediate
+            sta $10
+            sta $0200,x
+            lda ($20),y
+            jmp ($FFFC)
+            asl
+            ror a
+            ldx %11,y
+            ",
+        )
+        .unwrap();
+        assert_eq!(program.origin, 0x8000);
+        assert_eq!(
+            program.bytes,
+            [
+                0xA9, 0x05, 0x85, 0x10, 0x9D, 0x00, 0x02, 0xB1, 0x20, 0x6C, 0xFC, 0xFF, 0x0A, 0x6A,
+                0xB6, 0x03
+            ]
+        );
+    }
+
+    #[test]
+    fn test_labels_constants_and_data() {
+        let program = assemble(
+            "
+            PTR = $30
+            .org $C000
+    reset:  ldx #<message
+    loop:   lda message,x
+            beq done
+            sta PTR+1
+            inx
+            bne loop
+    done:   jmp done
+    message: .byte \"A;\", 0
+            .org $C020
+            .word reset, >message
+            ",
+        )
+        .unwrap();
+        assert_eq!(program.labels["loop"], 0xC002);
+        // `message` is a forward reference, so it stays absolute.
+        assert_eq!(program.slice(0xC000, 5), [0xA2, 0x0F, 0xBD, 0x0F, 0xC0]);
+        // BEQ +5, STA $31, INX, BNE -10
+        assert_eq!(
+            program.slice(0xC005, 7),
+            [0xF0, 0x05, 0x85, 0x31, 0xE8, 0xD0, 0xF6]
+        );
+        assert_eq!(
+            program.slice(0xC00C, 6),
+            [0x4C, 0x0C, 0xC0, b'A', b';', 0x00]
+        );
+        assert_eq!(program.slice(0xC020, 4), [0x00, 0xC0, 0xC0, 0x00]);
+        assert_eq!(program.bytes.len(), 0x24);
+    }
+
+    #[test]
+    fn test_errors_name_the_line() {
+        assert_eq!(
+            assemble("nop\nsta #$10").unwrap_err(),
+            "Line 2: Invalid instruction: sta #$10"
+        );
+        assert!(assemble("jmp nowhere").unwrap_err().contains("Undefined"));
+        assert!(assemble("a: nop\na: nop").is_err());
+        assert!(assemble(".org $8000\nbne far\n.org $8100\nfar: nop").is_err());
+        assert!(assemble("lda #$100").is_err());
+    }
+}
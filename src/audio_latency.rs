@@ -0,0 +1,123 @@
+//! Sizes the audio queue from how regularly the host runs the audio
+//! callback. For the first seconds the gaps between callbacks are measured;
+//! the queue then has to hold the longest gap seen plus the frame of
+//! samples the emulator adds at a time, with some margin. Each underrun
+//! after that grows it by a step.
+
+use std::time::{Duration, Instant};
+
+/// How long callback timing is measured before the first decision.
+pub const MEASURE_FOR: Duration = Duration::from_secs(3);
+/// The emulator queues a frame of samples at a time.
+const FRAME: Duration = Duration::from_micros(16_639);
+const MIN_LATENCY: Duration = Duration::from_millis(20);
+const MAX_LATENCY: Duration = Duration::from_millis(250);
+/// Added for each callback that finds new underruns after the decision.
+const UNDERRUN_STEP: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone)]
+pub struct AdaptiveLatency {
+    latency: Duration,
+    started: Option<Instant>,
+    last_callback: Option<Instant>,
+    gaps: Vec<Duration>,
+    /// Longest gap minus the typical one, once measured.
+    jitter: Option<Duration>,
+    underruns: u64,
+    increases: u32,
+}
+
+impl AdaptiveLatency {
+    /// `initial` is used while measuring.
+    pub fn new(initial: Duration) -> Self {
+        AdaptiveLatency {
+            latency: initial,
+            started: None,
+            last_callback: None,
+            gaps: Vec::new(),
+            jitter: None,
+            underruns: 0,
+            increases: 0,
+        }
+    }
+
+    /// The queue size to use now.
+    pub fn latency(&self) -> Duration {
+        self.latency
+    }
+
+    /// Callback jitter found by the measurement, or `None` while measuring.
+    pub fn jitter(&self) -> Option<Duration> {
+        self.jitter
+    }
+
+    /// Times the latency was raised after underruns.
+    pub fn increases(&self) -> u32 {
+        self.increases
+    }
+
+    /// Records an audio callback at `now`, with `underruns` the running
+    /// total of callbacks that ran out of samples, and returns the latency
+    /// to use from here on.
+    pub fn record_callback(&mut self, now: Instant, underruns: u64) -> Duration {
+        let started = *self.started.get_or_insert(now);
+        let last = self.last_callback.replace(now);
+
+        if self.jitter.is_none() {
+            if let Some(last) = last {
+                self.gaps.push(now - last);
+            }
+            if now - started >= MEASURE_FOR && !self.gaps.is_empty() {
+                self.decide();
+                self.underruns = underruns;
+            }
+        } else if underruns > self.underruns {
+            self.underruns = underruns;
+            if self.latency < MAX_LATENCY {
+                self.latency = (self.latency + UNDERRUN_STEP).min(MAX_LATENCY);
+                self.increases += 1;
+            }
+        }
+        self.latency
+    }
+
+    fn decide(&mut self) {
+        let mut gaps = std::mem::take(&mut self.gaps);
+        gaps.sort_unstable();
+        let typical = gaps[gaps.len() / 2];
+        let worst = gaps[gaps.len() - 1];
+        self.jitter = Some(worst - typical);
+        self.latency = ((worst + FRAME) * 5 / 4).clamp(MIN_LATENCY, MAX_LATENCY);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sizes_from_jitter_and_grows_on_underruns() {
+        let mut adaptive = AdaptiveLatency::new(Duration::from_millis(60));
+        let start = Instant::now();
+        // Callbacks every 10ms, one of them 30ms late.
+        let mut now = start;
+        while now - start < MEASURE_FOR {
+            assert_eq!(adaptive.record_callback(now, 0), Duration::from_millis(60));
+            now += Duration::from_millis(10);
+        }
+        now += Duration::from_millis(30);
+        assert_eq!(adaptive.jitter(), None);
+        let latency = adaptive.record_callback(now, 2);
+
+        assert_eq!(adaptive.jitter(), Some(Duration::from_millis(30)));
+        // (40ms + one frame) * 1.25
+        assert_eq!(latency, (Duration::from_millis(40) + FRAME) * 5 / 4);
+        // Underruns counted while measuring don't count against it.
+        assert_eq!(adaptive.record_callback(now, 2), latency);
+        assert_eq!(
+            adaptive.record_callback(now, 3),
+            latency + Duration::from_millis(10)
+        );
+        assert_eq!(adaptive.increases(), 1);
+    }
+}
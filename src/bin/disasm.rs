@@ -0,0 +1,116 @@
+//! Prints 6502 disassembly of a ROM image or raw binary.
+//!
+//! ```text
+//! cargo run --bin disasm -- game.nes --start C000 --count 20
+//! cargo run --bin disasm -- code.bin --origin 0600
+//! cargo run --bin disasm -- game.nes --symbols game.dbg
+//! ```
+//!
+//! For iNES/NES 2.0 files the PRG ROM is disassembled, mapped as it would be
+//! at power-on for NROM (the last 32KB ending at $FFFF).
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::Parser;
+use pico::cart::RomHeader;
+use pico::disasm::Disassembler;
+use pico::symbols::SymbolTable;
+
+#[derive(Parser)]
+struct Args {
+    file: PathBuf,
+
+    /// Load address of the data (hex); defaults to the PRG mapping for ROMs
+    #[arg(long, value_parser = parse_hex)]
+    origin: Option<u16>,
+
+    /// Address to start disassembling at (hex)
+    #[arg(long, value_parser = parse_hex)]
+    start: Option<u16>,
+
+    /// Maximum number of instructions to print
+    #[arg(long)]
+    count: Option<usize>,
+
+    /// Treat the file as a raw binary even if it has an iNES header
+    #[arg(long)]
+    raw: bool,
+
+    /// FCEUX `.nl` or ca65 `.dbg` files with labels to show for addresses
+    #[arg(long)]
+    symbols: Vec<PathBuf>,
+}
+
+fn parse_hex(value: &str) -> Result<u16, String> {
+    let digits = value
+        .trim_start_matches('$')
+        .trim_start_matches("0x")
+        .trim_start_matches("0X");
+    u16::from_str_radix(digits, 16).map_err(|e| format!("invalid address {}: {}", value, e))
+}
+
+fn load(args: &Args) -> Result<(Vec<u8>, u16), String> {
+    let bytes = std::fs::read(&args.file)
+        .map_err(|e| format!("failed to read {}: {}", args.file.display(), e))?;
+
+    if args.raw || !bytes.starts_with(b"NES\x1a") {
+        return Ok((bytes, args.origin.unwrap_or(0)));
+    }
+
+    let header = RomHeader::parse(&bytes)?;
+    let start = header.prg_rom_start();
+    let prg = bytes
+        .get(start..start + header.prg_rom_size)
+        .ok_or("PRG ROM is truncated")?;
+    // Only the last 32KB are visible at $8000-$FFFF; a 16KB ROM is mirrored.
+    let visible = &prg[prg.len().saturating_sub(0x8000)..];
+    let origin = (0x10000 - visible.len()) as u16;
+    Ok((visible.to_vec(), args.origin.unwrap_or(origin)))
+}
+
+fn load_symbols(paths: &[PathBuf]) -> Result<SymbolTable, String> {
+    let mut symbols = SymbolTable::new();
+    for path in paths {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        symbols
+            .load(&path.to_string_lossy(), &text)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    Ok(symbols)
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let (data, origin) = match load(&args) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let symbols = match load_symbols(&args.symbols) {
+        Ok(symbols) => symbols,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut disasm = Disassembler::new(&data, origin);
+    if let Some(start) = args.start {
+        disasm.seek(start);
+    }
+
+    for instruction in disasm.take(args.count.unwrap_or(usize::MAX)) {
+        if let Some(label) = symbols.label(instruction.address, None) {
+            println!("{}:", label);
+        }
+        println!("{}", instruction.labeled(&symbols));
+    }
+
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,54 @@
+//! Fetches the public NES test ROM suites used by `tests/test_roms.rs`.
+//!
+//! ```text
+//! cargo run --bin fetch_test_roms
+//! cargo test --test test_roms
+//! ```
+//!
+//! ROMs are cloned into `target/test-roms`, or the directory named by
+//! `PICO_TEST_ROMS`. Running it again updates an existing checkout.
+
+use std::path::PathBuf;
+use std::process::{Command, ExitCode};
+
+const TEST_ROMS_REPO: &str = "https://github.com/christopherpow/nes-test-roms.git";
+
+fn cache_dir() -> PathBuf {
+    std::env::var_os("PICO_TEST_ROMS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/test-roms"))
+}
+
+fn git(args: &[&str]) -> Result<(), String> {
+    let status = Command::new("git")
+        .args(args)
+        .status()
+        .map_err(|e| format!("failed to run git: {}", e))?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("git {} exited with {}", args.join(" "), status))
+    }
+}
+
+fn main() -> ExitCode {
+    let dir = cache_dir();
+    let dir_str = dir.to_string_lossy().into_owned();
+
+    let result = if dir.join(".git").is_dir() {
+        println!("Updating test ROMs in {}", dir_str);
+        git(&["-C", &dir_str, "pull", "--ff-only"])
+    } else {
+        println!("Cloning test ROMs into {}", dir_str);
+        git(&["clone", "--depth", "1", TEST_ROMS_REPO, &dir_str])
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
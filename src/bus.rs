@@ -1,12 +1,35 @@
+//! The NES CPU's view of the console. [`Bus`] owns the 2KB of internal RAM
+//! (mirrored up to $1FFF) and routes $2000-$3FFF to the PPU registers,
+//! $4000-$4017 to the APU, OAM DMA and controllers, and $4020-$FFFF to the
+//! cartridge's [`Mapper`]. The CPU itself is generic over [`Memory`], so
+//! tests and non-NES programs can run it on [`crate::memory::FlatMemory`].
+
+#[cfg(feature = "expansion-port")]
+use crate::expansion::{EXPANSION_RANGE, ExpansionPort};
+#[cfg(test)]
+use crate::mock_device::BusDevice;
 use crate::{
+    access_log::{Access, AccessLog},
     apu::APU,
+    callstack::CallStack,
     cart::Cart,
-    cpu::CPU,
+    cheats::CheatList,
+    cpu::{CPU, CpuModel, Registers, ResetKind, StepResult},
+    hooks::{HookKind, Hooks},
     joypad::Joypad,
+    link::{LINK_DATA, LINK_STATUS, LinkPort},
     mapper::Mapper,
     memory::Memory,
+    movie::FM2Movie,
     ppu::{PPU, framebuffer::Framebuffer, render},
+    profiler::{CodeAddr, Profiler},
+    reverse::History,
+    stack_check::StackCheck,
+    trace::TraceHistory,
+    watchdog::Watchdog,
 };
+#[cfg(test)]
+use std::ops::RangeInclusive;
 
 // Address ranges per https://www.nesdev.org/wiki/CPU_memory_map
 const CPU_RAM_MIRROR_MASK: u16 = 0x07FF;
@@ -15,22 +38,186 @@ const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
 const DISABLED_APU_IO_END: u16 = 0x401F;
 const CARTRIDGE_SPACE_START: u16 = 0x4020;
 
+/// What the CPU reads from an address nothing drives, such as $4018-$401F
+/// or disabled PRG RAM, and from the unused bits of $4015-$4017.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OpenBus {
+    /// Always 0, the default.
+    #[default]
+    Zero,
+    /// The last byte on the data bus, as on hardware: usually the high
+    /// byte of the address, the last operand byte fetched.
+    Last,
+    /// The last byte, until nothing has driven the bus for about a second
+    /// and it has leaked away to 0. Real consoles vary.
+    Decay,
+}
+
+type ReadHandler = fn(&mut Bus, u16) -> u8;
+type WriteHandler = fn(&mut Bus, u16, u8);
+
+/// Read and write handlers for each 256-byte page of the CPU address space,
+/// so an access is one indexed call instead of a walk down the memory map.
+/// Pages wholly inside RAM, the PPU registers or the cartridge go straight
+/// to their handler; page $40, and with the `expansion-port` feature pages
+/// with an expansion device on them, go the long way, through the whole
+/// memory map. The mapper still does its own banking, so only mapping or
+/// unmapping a device rebuilds the table.
+struct PageTable {
+    read: [ReadHandler; 256],
+    write: [WriteHandler; 256],
+}
+
+impl PageTable {
+    fn new() -> PageTable {
+        let mut table = PageTable {
+            read: [Bus::read_mixed; 256],
+            write: [Bus::write_mixed; 256],
+        };
+        for page in 0..=0xFF_u8 {
+            let (read, write): (ReadHandler, WriteHandler) = match (page as u16) << 8 {
+                0x0000..=CPU_RAM_MIRRORS_END => (Bus::read_ram, Bus::write_ram),
+                0x2000..=PPU_REGISTERS_MIRRORS_END => (Bus::read_ppu, Bus::write_ppu),
+                0x4000 => continue,
+                _ => (Bus::read_cart, Bus::write_cart),
+            };
+            table.read[page as usize] = read;
+            table.write[page as usize] = write;
+        }
+        table
+    }
+}
+
+/// The DMA unit, which halts the CPU to copy memory for the PPU and APU.
+/// Writing $4014 requests an OAM DMA, a copy of one CPU page to OAM; it
+/// starts once the writing instruction finishes and holds the CPU for 513
+/// cycles, or 514 when it starts on an odd cycle. The DMC requests a sample
+/// fetch whenever its buffer empties, which halts the CPU on its next read
+/// for 3 or 4 cycles. The two share one bus: a fetch due during an OAM DMA
+/// takes one of its read cycles and, the CPU being halted already, costs 2
+/// cycles.
+#[derive(Clone, Default)]
+pub(crate) struct Dma {
+    oam_page: Option<u8>,
+    // Sample address of a DMC fetch waiting for the CPU's next read cycle.
+    dmc_addr: Option<u16>,
+}
+
+/// What [`Bus::step_cpu`] notes about an instruction before it runs, for the
+/// debugger tools to record once it's done.
+#[derive(Default)]
+pub(crate) struct StepStart {
+    code: Option<(CodeAddr, u8, u8)>,
+    traced: Option<(Registers, [u8; 3], u64)>,
+}
+
+/// What the PPU did while the bus was ticked, collected until
+/// [`Bus::take_events`].
+#[derive(Clone, Copy, Default)]
+pub(crate) struct TickEvents {
+    pub(crate) frame_complete: bool,
+    pub(crate) vblank: bool,
+}
+
 pub struct Bus {
     pub cpu: CPU,
     pub cart: Cart,
     pub ppu: PPU,
     pub apu: APU,
-    joypads: [Joypad; 2],
+    pub(crate) joypads: [Joypad; 2],
+    /// Strobe-timed movie applied at each controller latch.
+    pub(crate) subframe_movie: Option<FM2Movie>,
+    pub(crate) dma: Dma,
+    /// CPU cycles since power-on, including DMA stalls.
+    pub(crate) cpu_cycles: u64,
+    /// PPU dots since power-on.
+    pub system_clock: u64,
+    /// `system_clock` at the start of the last vblank.
+    pub(crate) vblank_dot: u64,
+    pub(crate) link: Option<LinkPort>,
+    pub(crate) profiler: Option<Profiler>,
+    pub(crate) call_stack: Option<CallStack>,
+    pub(crate) stack_check: Option<StackCheck>,
+    pub(crate) trace_history: Option<TraceHistory>,
+    pub(crate) cheats: CheatList,
+    pub(crate) hooks: Hooks,
+    #[cfg(feature = "expansion-port")]
+    pub(crate) expansion: ExpansionPort,
+    pages: Box<PageTable>,
+    #[cfg(test)]
+    devices: Vec<(RangeInclusive<u16>, Box<dyn BusDevice>)>,
+    pub(crate) history: Option<History>,
+    pub(crate) watchdog: Option<Watchdog>,
+    pub(crate) access_log: Option<AccessLog>,
+    events: TickEvents,
+    // Set while `step_cpu_cycle` is part way through an instruction.
+    pub(crate) step_start: Option<StepStart>,
+    // Interrupt lines seen while ticking, handed to the CPU once the access
+    // in progress is over.
+    pub(crate) nmi_latched: bool,
+    irq_line: bool,
+    dpcm_conflict: bool,
+    open_bus: OpenBus,
+    // The last byte on the CPU data bus and the CPU cycle it was driven on.
+    pub(crate) data_bus: u8,
+    pub(crate) data_bus_cycle: u64,
+    // Extra scanlines of CPU time at the start of each vblank, and the CPU
+    // cycles of them still to run.
+    overclock_scanlines: u16,
+    pub(crate) overclock_cycles: u32,
+    // The two lines at the end of each of the last eight cycles, newest in
+    // bit 0, for finding what the CPU saw at its interrupt poll.
+    pub(crate) nmi_history: u8,
+    pub(crate) irq_history: u8,
 }
 
 impl Bus {
     pub fn new(cart: Cart, apu: APU) -> Bus {
+        Self::with_model(cart, apu, CpuModel::default())
+    }
+
+    pub fn with_model(cart: Cart, mut apu: APU, model: CpuModel) -> Bus {
+        apu.set_cpu_model(model);
+        let mut ppu = PPU::new();
+        ppu.set_cpu_model(model);
         Bus {
-            cpu: CPU::new(),
+            cpu: CPU::with_model(model),
             cart,
-            ppu: PPU::new(),
+            ppu,
             apu,
             joypads: [Joypad::new(), Joypad::new()],
+            subframe_movie: None,
+            dma: Dma::default(),
+            cpu_cycles: 0,
+            system_clock: 0,
+            vblank_dot: 0,
+            link: None,
+            profiler: None,
+            call_stack: None,
+            stack_check: None,
+            trace_history: None,
+            cheats: CheatList::new(),
+            hooks: Hooks::new(),
+            #[cfg(feature = "expansion-port")]
+            expansion: ExpansionPort::new(),
+            pages: Box::new(PageTable::new()),
+            #[cfg(test)]
+            devices: Vec::new(),
+            history: None,
+            watchdog: None,
+            access_log: None,
+            events: TickEvents::default(),
+            step_start: None,
+            nmi_latched: false,
+            irq_line: false,
+            dpcm_conflict: true,
+            open_bus: OpenBus::default(),
+            data_bus: 0,
+            data_bus_cycle: 0,
+            overclock_scanlines: 0,
+            overclock_cycles: 0,
+            nmi_history: 0,
+            irq_history: 0,
         }
     }
 
@@ -42,6 +229,35 @@ impl Bus {
         addr & 0b00100000_00000111
     }
 
+    /// Rebuilds the page table after expansion devices were mapped or
+    /// unmapped.
+    #[cfg(any(test, feature = "expansion-port"))]
+    pub(crate) fn update_pages(&mut self) {
+        *self.pages = PageTable::new();
+        #[cfg(feature = "expansion-port")]
+        for page in 0..=0xFF_u8 {
+            if self.expansion.covers_page(page) {
+                self.pages.read[page as usize] = Bus::read_mixed;
+                self.pages.write[page as usize] = Bus::write_mixed;
+            }
+        }
+        #[cfg(test)]
+        for (range, _) in &self.devices {
+            for page in range.start() >> 8..=range.end() >> 8 {
+                self.pages.read[page as usize] = Bus::read_device;
+                self.pages.write[page as usize] = Bus::write_device;
+            }
+        }
+    }
+
+    /// Hands CPU accesses to `range` to a test device; see
+    /// [`crate::mock_device`]. Earlier devices win where ranges overlap.
+    #[cfg(test)]
+    pub(crate) fn attach_device(&mut self, range: RangeInclusive<u16>, device: Box<dyn BusDevice>) {
+        self.devices.push((range, device));
+        self.update_pages();
+    }
+
     pub fn mapper_mut(&mut self) -> &mut dyn Mapper {
         self.cart.mapper.as_mut()
     }
@@ -59,139 +275,960 @@ impl Bus {
         (&mut left[0], &mut right[0])
     }
 
-    pub fn ppu_clock(&mut self) -> bool {
-        let mapper = self.cart.mapper.as_mut();
-        self.ppu.clock(mapper)
-    }
+    /// Advances everything but the CPU by one CPU cycle: the PPU up to and
+    /// including the dot the cycle falls on (3 on NTSC, 3.2 on average on
+    /// PAL), then the APU once. Interrupts raised meanwhile are latched for
+    /// the CPU, and a DMC sample fetch waits for its next read.
+    pub fn tick(&mut self) {
+        if self.overclock_cycles > 0 {
+            // An overclocked cycle: the rest of the console waits for it.
+            self.overclock_cycles -= 1;
+            self.cpu_cycles += 1;
+            self.nmi_history = self.nmi_history << 1 | self.nmi_latched as u8;
+            self.irq_history = self.irq_history << 1 | self.irq_line as u8;
+            return;
+        }
 
-    pub fn apu_clock(&mut self) {
+        let model = self.cpu.model();
+        let mut vblank = false;
+        loop {
+            let mapper = self.cart.mapper.as_mut();
+            self.events.frame_complete |= self.ppu.clock(mapper);
+            if self.ppu.vblank_started() {
+                vblank = true;
+                self.vblank_dot = self.system_clock;
+            }
+            self.nmi_latched |= self.ppu.poll_nmi_interrupt().is_some();
+
+            let cpu_dot = model.clocks_on_dot(self.system_clock);
+            self.system_clock = self.system_clock.wrapping_add(1);
+            if cpu_dot {
+                break;
+            }
+        }
+        self.events.vblank |= vblank;
+        if vblank && self.overclock_scanlines > 0 {
+            let (dots, cycles) = model.ppu_dots_per_cycle();
+            self.overclock_cycles = (self.overclock_scanlines as u64 * 341 * cycles / dots) as u32;
+        }
+
+        self.cpu_cycles += 1;
         if let Some(addr) = self.apu.clock() {
-            let value = self.read(addr);
-            self.apu.provide_dmc_sample(value);
+            self.dma.dmc_addr = Some(addr);
         }
+        self.irq_line = self.apu.poll_irq().is_some() || self.cart.mapper.poll_irq().is_some();
+        self.nmi_history = self.nmi_history << 1 | self.nmi_latched as u8;
+        self.irq_history = self.irq_history << 1 | self.irq_line as u8;
+    }
+
+    pub(crate) fn take_events(&mut self) -> TickEvents {
+        std::mem::take(&mut self.events)
     }
 
-    pub fn poll_nmi(&mut self) -> bool {
-        self.ppu.poll_nmi_interrupt().is_some()
+    /// Writes the enabled freeze cheats. Only internal and cartridge RAM
+    /// are touched, never registers.
+    pub(crate) fn apply_freeze_cheats(&mut self) {
+        let freezes: Vec<(u16, u8)> = self.cheats.freezes().collect();
+        for (addr, value) in freezes {
+            if Self::is_ram(addr) {
+                self.write(addr, value);
+            }
+        }
     }
 
-    pub fn poll_irq(&mut self) -> bool {
-        self.apu.poll_irq().is_some() || self.cart.mapper.poll_irq().is_some()
+    /// Internal or cartridge RAM, the only places cheats may write.
+    fn is_ram(addr: u16) -> bool {
+        matches!(addr, 0x0000..=CPU_RAM_MIRRORS_END | 0x6000..=0x7FFF)
     }
 
+    /// The byte a CPU read of `addr` would return, without its side
+    /// effects: $2002 keeps its vblank flag, $2007 its address and read
+    /// buffer, $4015 its interrupt flags and the controllers their place in
+    /// the report. Safe for memory viewers and the trace logger. Expansion
+    /// devices aren't asked and read as open bus.
     pub fn peek(&self, addr: u16) -> u8 {
         match addr {
             0x0000..=CPU_RAM_MIRRORS_END => self.cpu.vram[Self::mirror_cpu_vram_addr(addr)],
-            CARTRIDGE_SPACE_START..=0xFFFF => self.cart.mapper.peek_prg(addr),
-            _ => 0,
+            0x2000..=PPU_REGISTERS_MIRRORS_END => match Self::normalize_ppu_register_addr(addr) {
+                0x2002 => self.ppu.peek_status(),
+                0x2004 => self.ppu.read_oam_data(),
+                0x2007 => self.ppu.peek_data(),
+                _ => 0,
+            },
+            0x4000..=0x4014 => self.open_bus_value(),
+            0x4015 => self.apu.peek_status() | (self.open_bus_value() & 0x20),
+            0x4016 => self.joypads[0].peek() | (self.open_bus_value() & 0xE0),
+            0x4017 => self.joypads[1].peek() | (self.open_bus_value() & 0xE0),
+            LINK_DATA | LINK_STATUS => match &self.link {
+                Some(link) => link.peek(addr),
+                None => self.open_bus_value(),
+            },
+            0x4018..=DISABLED_APU_IO_END => self.open_bus_value(),
+            CARTRIDGE_SPACE_START..=0xFFFF => {
+                if self.cart.mapper.prg_mapped(addr) {
+                    self.cart.mapper.peek_prg(addr)
+                } else {
+                    self.open_bus_value()
+                }
+            }
         }
     }
 
+    /// [`Bus::peek`] over `len` bytes from `start`, wrapping at $FFFF.
+    pub fn peek_range(&self, start: u16, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| self.peek(start.wrapping_add(i as u16)))
+            .collect()
+    }
+
     pub fn render_frame(&mut self, framebuffer: &mut Framebuffer) {
-        let mapper = self.cart.mapper.as_mut();
-        render::render(&self.ppu, mapper, framebuffer);
+        render::render(&self.ppu, framebuffer);
         self.ppu.reset_scroll_segments_for_new_frame();
     }
 
-    pub fn cpu_clock(&mut self) -> bool {
-        let cpu_ptr = std::ptr::addr_of_mut!(self.cpu);
-        unsafe { (*cpu_ptr).clock(self) }
+    /// Renders the frame like [`Bus::render_frame`] but leaves the scroll
+    /// segments for the frontend's own render of it.
+    pub(crate) fn render_frame_copy(&mut self, framebuffer: &mut Framebuffer) {
+        render::render(&self.ppu, framebuffer);
     }
 
-    pub fn cpu_reset(&mut self) {
+    /// Runs a pending OAM DMA, then one instruction or interrupt entry.
+    /// Every memory access the CPU makes ticks the bus first, and cycles
+    /// without an access are ticked once the instruction is done, so the
+    /// PPU and APU see each read and write on the cycle it happens. A CPU
+    /// that is stopped or halted still lets one cycle pass. An instruction
+    /// [`Bus::step_cpu_cycle`] is part way through is finished instead.
+    pub fn step_cpu(&mut self) -> StepResult {
+        let done = self.cpu.instruction_cycle();
+        let start = if done > 0 {
+            self.step_start.take().unwrap_or_default()
+        } else {
+            if let Some(page) = self.dma.oam_page.take() {
+                self.run_oam_dma(page);
+            }
+            self.begin_step()
+        };
+
         let cpu_ptr = std::ptr::addr_of_mut!(self.cpu);
-        unsafe { (*cpu_ptr).reset(self) }
+        let pc = self.cpu.registers.pc;
+        let mut memory = CpuView {
+            bus: self,
+            accesses: 0,
+            pc,
+        };
+        let result = unsafe { (*cpu_ptr).step(&mut memory) };
+        let accesses = memory.accesses;
+        for _ in done + accesses..result.cycles.max(1) {
+            self.tick();
+        }
+        if result.cycles == 0 {
+            // A stopped CPU makes no reads to halt on.
+            self.run_dmc_dma(None);
+        }
+        self.end_step(start, &result);
+        result
     }
 
-    pub fn cpu_nmi(&mut self) {
+    /// Runs one CPU cycle: a pending OAM DMA whole, or one cycle of an
+    /// instruction or interrupt entry, ticking the bus once. Returns the
+    /// instruction's result on the cycle that finishes it, as
+    /// [`Bus::step_cpu`] would have. A snapshot taken between two cycles
+    /// resumes the instruction in progress; see [`CPU::step_cycle`].
+    pub fn step_cpu_cycle(&mut self) -> Option<StepResult> {
+        if self.cpu.instruction_cycle() == 0 {
+            if let Some(page) = self.dma.oam_page.take() {
+                self.run_oam_dma(page);
+                return None;
+            }
+            self.step_start = Some(self.begin_step());
+        }
+
         let cpu_ptr = std::ptr::addr_of_mut!(self.cpu);
-        unsafe { (*cpu_ptr).nmi(self) }
+        let pc = self.cpu.registers.pc;
+        let mut memory = CpuView {
+            bus: self,
+            accesses: 0,
+            pc,
+        };
+        let result = unsafe { (*cpu_ptr).step_cycle(&mut memory) };
+        if memory.accesses == 0 {
+            self.tick();
+        }
+        let result = result?;
+        if result.cycles == 0 {
+            self.run_dmc_dma(None);
+        }
+        let start = self.step_start.take().unwrap_or_default();
+        self.end_step(start, &result);
+        Some(result)
     }
 
-    pub fn cpu_irq(&mut self) {
+    // Notes what the debugger tools record about the instruction about to
+    // run.
+    fn begin_step(&mut self) -> StepStart {
+        let tracking =
+            self.profiler.is_some() || self.call_stack.is_some() || self.stack_check.is_some();
+        let code = tracking.then(|| {
+            let pc = self.cpu.registers.pc;
+            (self.code_addr(pc), self.peek(pc), self.cpu.registers.sp)
+        });
+
+        if let Some(history) = &mut self.history {
+            history.begin(&self.cpu.registers);
+        }
+        let traced = self.trace_history.is_some().then(|| {
+            let pc = self.cpu.registers.pc;
+            let bytes = [0, 1, 2].map(|i| self.peek(pc.wrapping_add(i)));
+            (self.cpu.registers.clone(), bytes, self.cpu_cycles)
+        });
+        StepStart { code, traced }
+    }
+
+    fn end_step(&mut self, start: StepStart, result: &StepResult) {
+        if let Some(history) = &mut self.history {
+            history.commit(result.cycles > 0);
+        }
+        if let Some((registers, bytes, cycle)) = start.traced
+            && let Some(trace_history) = &mut self.trace_history
+            && result.cycles > 0
+        {
+            trace_history.record(registers, bytes, cycle, result.interrupt);
+        }
+
+        if let Some((at, opcode, sp)) = start.code
+            && result.cycles > 0
+        {
+            let next = self.code_addr(self.cpu.registers.pc);
+            if let Some(profiler) = &mut self.profiler {
+                profiler.record(at, opcode, result.cycles, result.interrupt, next);
+            }
+            if let Some(call_stack) = &mut self.call_stack {
+                let next_sp = self.cpu.registers.sp;
+                call_stack.record(at.addr, opcode, result.interrupt, sp, next.addr, next_sp);
+            }
+            if let Some(stack_check) = &mut self.stack_check {
+                let next_sp = self.cpu.registers.sp;
+                stack_check.record(at.addr, opcode, result.interrupt, sp, next_sp);
+            }
+        }
+
+        self.hand_over_interrupts(result);
+    }
+
+    // Passes the interrupt lines to the CPU as it saw them at its poll,
+    // which is on the second to last cycle of an instruction, or the first
+    // cycle of a taken branch that stayed on its page. An NMI raised after
+    // the poll stays latched for the next instruction. Interrupt entries
+    // don't poll, so the handler's first instruction always runs.
+    fn hand_over_interrupts(&mut self, result: &StepResult) {
+        let poll_bit = if result.cycles == 0 {
+            // Nothing ran; the CPU will poll before its next opcode.
+            0
+        } else if result.interrupt.is_some() {
+            self.cpu.set_irq_line(self.irq_line);
+            return;
+        } else if result.branch_delay {
+            2
+        } else {
+            1
+        };
+
+        if self.nmi_history & (1 << poll_bit) != 0 {
+            self.nmi_latched = false;
+            self.nmi_history = 0;
+            self.cpu.nmi();
+        }
+        self.cpu
+            .set_irq_line(self.irq_history & (1 << poll_bit) != 0);
+    }
+
+    fn code_addr(&self, addr: u16) -> CodeAddr {
+        CodeAddr {
+            bank: self.prg_bank(addr),
+            addr,
+        }
+    }
+
+    /// Whether a DMC fetch that halts the CPU on a read corrupts it, as on
+    /// hardware: a controller read clocks the controller an extra time,
+    /// dropping a button, and a $2007 read moves the VRAM address on once
+    /// for each cycle the CPU is held. On by default.
+    pub fn set_dpcm_conflict(&mut self, enabled: bool) {
+        self.dpcm_conflict = enabled;
+    }
+
+    pub fn set_open_bus(&mut self, open_bus: OpenBus) {
+        self.open_bus = open_bus;
+    }
+
+    /// The value an undriven read returns now.
+    fn open_bus_value(&self) -> u8 {
+        match self.open_bus {
+            OpenBus::Zero => 0,
+            OpenBus::Last => self.data_bus,
+            OpenBus::Decay => {
+                let decay = self.cpu.model().clock_rate();
+                if self.cpu_cycles - self.data_bus_cycle > decay {
+                    0
+                } else {
+                    self.data_bus
+                }
+            }
+        }
+    }
+
+    /// Overclocks the CPU like Mesen does: at the start of each vblank, just
+    /// after the NMI is raised, it runs for `scanlines` more scanlines while
+    /// the PPU and APU stand still, so a game that lags gets more time per
+    /// frame without its music speeding up. Games that count cycles between
+    /// vblank and rendering may break. 0, the default, turns it off.
+    pub fn set_overclock(&mut self, scanlines: u16) {
+        self.overclock_scanlines = scanlines;
+        if scanlines == 0 {
+            self.overclock_cycles = 0;
+        }
+    }
+
+    pub fn overclock(&self) -> u16 {
+        self.overclock_scanlines
+    }
+
+    // Runs a pending DMC fetch, the cycle just ticked being the one the CPU
+    // halted on. Then a dummy cycle, one to align to a get cycle if needed,
+    // and the fetch itself: 3 or 4 cycles stolen from the CPU. The CPU
+    // repeats `cpu_read` on each cycle it is held before the fetch; the
+    // controllers only see the first of back-to-back reads, so they are
+    // clocked once more, but the PPU counts every read of $2007.
+    fn run_dmc_dma(&mut self, cpu_read: Option<u16>) {
+        let Some(addr) = self.dma.dmc_addr.take() else {
+            return;
+        };
+        let repeated = cpu_read.filter(|_| self.dpcm_conflict);
+        if let Some(port @ (0x4016 | 0x4017)) = repeated {
+            self.read(port);
+        }
+        let ppu_data = repeated.filter(|&addr| {
+            (0x2000..=PPU_REGISTERS_MIRRORS_END).contains(&addr)
+                && Self::normalize_ppu_register_addr(addr) == 0x2007
+        });
+        let held = |bus: &mut Bus| {
+            if let Some(addr) = ppu_data {
+                bus.read(addr);
+            }
+        };
+
+        held(self);
+        self.tick();
+        held(self);
+        if !self.get_cycle_next() {
+            self.tick();
+            held(self);
+        }
+        self.tick();
+        self.fetch_dmc_sample(addr);
+    }
+
+    // DMA reads only on get cycles, every other CPU cycle; writes and
+    // waits go on the put cycles between them.
+    fn get_cycle_next(&self) -> bool {
+        self.cpu_cycles & 1 == 1
+    }
+
+    fn fetch_dmc_sample(&mut self, addr: u16) {
+        let value = self.read(addr);
+        self.apu.provide_dmc_sample(value);
+    }
+
+    // One halt cycle, one more to align to a get (read) cycle if needed,
+    // then 256 get/put pairs. A DMC fetch due by a get cycle takes it, and
+    // the put after it idles while the OAM DMA realigns.
+    fn run_oam_dma(&mut self, page: u8) {
+        self.tick();
+
+        let mut buffer: [u8; 256] = [0; 256];
+        let hi: u16 = (page as u16) << 8;
+        let mut copied = 0;
+        let mut fetched = None;
+        while copied < 256 {
+            let dmc_due = self.dma.dmc_addr.is_some();
+            let get = self.get_cycle_next();
+            self.tick();
+            match (get, fetched.take()) {
+                (false, Some(value)) => {
+                    buffer[copied] = value;
+                    copied += 1;
+                }
+                (false, None) => {}
+                (true, _) if dmc_due => {
+                    if let Some(addr) = self.dma.dmc_addr.take() {
+                        self.fetch_dmc_sample(addr);
+                    }
+                }
+                (true, _) => fetched = Some(self.read(hi + copied as u16)),
+            }
+        }
+        self.ppu.write_oam_dma(&buffer);
+    }
+
+    /// Undoes the newest instruction in the reverse-step history; see
+    /// [`crate::reverse`].
+    pub(crate) fn step_back(&mut self) -> bool {
+        let Some((registers, writes)) = self.history.as_mut().and_then(History::pop) else {
+            return false;
+        };
+        for (addr, old) in writes.into_iter().rev() {
+            self.write(addr, old);
+        }
+        self.cpu.registers = registers;
+        true
+    }
+
+    pub fn cpu_reset(&mut self, kind: ResetKind) {
+        self.nmi_latched = false;
+        self.step_start = None;
+        self.dma.dmc_addr = None;
         let cpu_ptr = std::ptr::addr_of_mut!(self.cpu);
-        unsafe { (*cpu_ptr).irq(self) }
+        unsafe { (*cpu_ptr).reset(self, kind) }
     }
 }
 
-impl Memory for Bus {
+// The bus as the CPU sees it while running: each access first ticks the rest
+// of the machine by one cycle.
+struct CpuView<'a> {
+    bus: &'a mut Bus,
+    accesses: u8,
+    /// Address of the instruction being run.
+    pc: u16,
+}
+
+impl CpuView<'_> {
+    fn read_as(&mut self, addr: u16, kind: HookKind) -> u8 {
+        self.bus.tick();
+        if self.bus.dma.dmc_addr.is_some() {
+            self.bus.run_dmc_dma(Some(addr));
+            self.bus.tick();
+        }
+        self.accesses = self.accesses.saturating_add(1);
+        self.note_stack_access(addr, false);
+        let value = self.bus.read(addr);
+        let value = self.bus.cheats.patch_read(addr, value);
+        self.bus.data_bus = value;
+        self.bus.data_bus_cycle = self.bus.cpu_cycles;
+        self.log_access(addr, value, false);
+        if !self.bus.hooks.is_empty() {
+            self.bus.hooks.fire(kind, addr, value);
+        }
+        value
+    }
+
+    fn log_access(&mut self, addr: u16, value: u8, write: bool) {
+        if self.bus.access_log.is_none() {
+            return;
+        }
+        let bank = self.bus.prg_bank(addr);
+        if let Some(access_log) = &mut self.bus.access_log {
+            let access = Access {
+                cycle: self.bus.cpu_cycles,
+                pc: self.pc,
+                write,
+                addr,
+                value,
+            };
+            access_log.record(access, bank);
+        }
+    }
+
+    fn note_stack_access(&mut self, addr: u16, write: bool) {
+        if let Some(stack_check) = &mut self.bus.stack_check
+            && addr <= CPU_RAM_MIRRORS_END
+            && Bus::mirror_cpu_vram_addr(addr) >> 8 == 0x01
+        {
+            stack_check.access(addr as u8, write);
+        }
+    }
+}
+
+impl Memory for CpuView<'_> {
     fn read(&mut self, addr: u16) -> u8 {
+        self.read_as(addr, HookKind::Read)
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.bus.tick();
+        let data = if Bus::is_ram(addr) {
+            self.bus.cheats.patch_write(addr, data)
+        } else {
+            data
+        };
+        self.accesses = self.accesses.saturating_add(1);
+        self.note_stack_access(addr, true);
+        if self.bus.history.is_some() {
+            let old = self.bus.peek(addr);
+            if let Some(history) = &mut self.bus.history {
+                history.record_write(addr, old);
+            }
+        }
+        self.bus.write(addr, data);
+        self.bus.data_bus = data;
+        self.bus.data_bus_cycle = self.bus.cpu_cycles;
+        self.log_access(addr, data, true);
+        if let Some(watchdog) = &mut self.bus.watchdog {
+            watchdog.write(addr);
+        }
+        if !self.bus.hooks.is_empty() {
+            self.bus.hooks.fire(HookKind::Write, addr, data);
+        }
+    }
+
+    fn fetch_opcode(&mut self, addr: u16) -> u8 {
+        self.read_as(addr, HookKind::Execute)
+    }
+
+    fn prg_bank(&self, addr: u16) -> Option<usize> {
+        self.bus.prg_bank(addr)
+    }
+
+    fn take_nmi(&mut self) -> bool {
+        self.bus.nmi_history = 0;
+        std::mem::take(&mut self.bus.nmi_latched)
+    }
+}
+
+// Page handlers; see [`PageTable`].
+impl Bus {
+    fn read_ram(&mut self, addr: u16) -> u8 {
+        self.cpu.vram[Self::mirror_cpu_vram_addr(addr)]
+    }
+
+    fn write_ram(&mut self, addr: u16, data: u8) {
+        self.cpu.vram[Self::mirror_cpu_vram_addr(addr)] = data;
+    }
+
+    fn read_ppu(&mut self, addr: u16) -> u8 {
+        match Self::normalize_ppu_register_addr(addr) {
+            0x2002 => self.ppu.read_status(),
+            0x2004 => self.ppu.read_oam_data(),
+            0x2007 => {
+                let mapper = self.cart.mapper.as_mut();
+                self.ppu.read_data(mapper)
+            }
+            _ => 0,
+        }
+    }
+
+    fn write_ppu(&mut self, addr: u16, data: u8) {
+        match Self::normalize_ppu_register_addr(addr) {
+            0x2000 => self.ppu.write_to_ctrl(data),
+            0x2001 => self.ppu.write_to_mask(data),
+            0x2003 => self.ppu.write_to_oam_addr(data),
+            0x2004 => self.ppu.write_to_oam_data(data),
+            0x2005 => self.ppu.write_to_scroll(data),
+            0x2006 => self.ppu.write_to_ppu_addr(data),
+            0x2007 => {
+                let mapper = self.cart.mapper.as_mut();
+                self.ppu.write_to_data(mapper, data);
+            }
+            _ => {}
+        }
+    }
+
+    fn read_cart(&mut self, addr: u16) -> u8 {
+        if self.cart.mapper.prg_mapped(addr) {
+            self.cart.mapper.read_prg(addr)
+        } else {
+            self.open_bus_value()
+        }
+    }
+
+    fn write_cart(&mut self, addr: u16, data: u8) {
+        self.cart.mapper.write_prg(addr, data);
+    }
+
+    #[cfg(test)]
+    fn read_device(&mut self, addr: u16) -> u8 {
+        match self
+            .devices
+            .iter_mut()
+            .find(|(range, _)| range.contains(&addr))
+        {
+            Some((_, device)) => device.read(addr),
+            None => self.read_mixed(addr),
+        }
+    }
+
+    #[cfg(test)]
+    fn write_device(&mut self, addr: u16, data: u8) {
+        match self
+            .devices
+            .iter_mut()
+            .find(|(range, _)| range.contains(&addr))
+        {
+            Some((_, device)) => device.write(addr, data),
+            None => self.write_mixed(addr, data),
+        }
+    }
+
+    /// Any address, by the full memory map.
+    fn read_mixed(&mut self, addr: u16) -> u8 {
+        #[cfg(feature = "expansion-port")]
+        if !self.expansion.is_empty()
+            && EXPANSION_RANGE.contains(&addr)
+            && let Some(value) = self.expansion.read(addr)
+        {
+            return value;
+        }
         match addr {
-            0x0000..=CPU_RAM_MIRRORS_END => self.cpu.vram[Self::mirror_cpu_vram_addr(addr)],
-            0x2000..=PPU_REGISTERS_MIRRORS_END => match Self::normalize_ppu_register_addr(addr) {
-                0x2002 => self.ppu.read_status(),
-                0x2004 => self.ppu.read_oam_data(),
-                0x2007 => {
-                    let mapper = self.cart.mapper.as_mut();
-                    self.ppu.read_data(mapper)
-                }
-                _ => 0,
+            0x0000..=CPU_RAM_MIRRORS_END => self.read_ram(addr),
+            0x2000..=PPU_REGISTERS_MIRRORS_END => self.read_ppu(addr),
+            0x4000..=0x4014 => self.open_bus_value(),
+            0x4015 => self.apu.read_status() | (self.open_bus_value() & 0x20),
+            // Controllers drive D0-D4 only.
+            0x4016 => self.joypads[0].read() | (self.open_bus_value() & 0xE0),
+            0x4017 => self.joypads[1].read() | (self.open_bus_value() & 0xE0),
+            LINK_DATA | LINK_STATUS => match &mut self.link {
+                Some(link) => link.read(addr),
+                None => self.open_bus_value(),
             },
-            0x4000..=0x4013 => 0,
-            0x4014 => 0,
-            0x4015 => self.apu.read_status(),
-            0x4016 => self.joypads[0].read(),
-            0x4017 => self.joypads[1].read(),
-            0x4018..=DISABLED_APU_IO_END => 0,
-            CARTRIDGE_SPACE_START..=0xFFFF => self.cart.mapper.read_prg(addr),
+            0x4018..=DISABLED_APU_IO_END => self.open_bus_value(),
+            CARTRIDGE_SPACE_START..=0xFFFF => self.read_cart(addr),
         }
     }
 
-    fn write(&mut self, addr: u16, data: u8) {
+    fn write_mixed(&mut self, addr: u16, data: u8) {
+        #[cfg(feature = "expansion-port")]
+        if !self.expansion.is_empty()
+            && EXPANSION_RANGE.contains(&addr)
+            && self.expansion.write(addr, data)
+        {
+            return;
+        }
         match addr {
-            0x0000..=CPU_RAM_MIRRORS_END => {
-                self.cpu.vram[Self::mirror_cpu_vram_addr(addr)] = data;
-            }
-            0x2000..=PPU_REGISTERS_MIRRORS_END => {
-                let reg = Self::normalize_ppu_register_addr(addr);
-
-                // if reg == 0x2000 || reg == 0x2005 || reg == 0x2006 {
-                //     eprintln!(
-                //         "[PPU WRITE] addr={:04X} norm={:04X} data={:02X}",
-                //         addr, reg, data
-                //     );
-                // }
-
-                match reg {
-                    0x2000 => self.ppu.write_to_ctrl(data),
-                    0x2001 => self.ppu.write_to_mask(data),
-                    0x2003 => self.ppu.write_to_oam_addr(data),
-                    0x2004 => self.ppu.write_to_oam_data(data),
-                    0x2005 => self.ppu.write_to_scroll(data),
-                    0x2006 => self.ppu.write_to_ppu_addr(data),
-                    0x2007 => {
-                        let mapper = self.cart.mapper.as_mut();
-                        self.ppu.write_to_data(mapper, data);
-                    }
-                    _ => {}
-                }
-            }
+            0x0000..=CPU_RAM_MIRRORS_END => self.write_ram(addr, data),
+            0x2000..=PPU_REGISTERS_MIRRORS_END => self.write_ppu(addr, data),
             0x4000..=0x4013 => {
                 self.apu.write_register(addr, data);
             }
             0x4014 => {
-                let mut buffer: [u8; 256] = [0; 256];
-                let hi: u16 = (data as u16) << 8;
-                for i in 0..256u16 {
-                    buffer[i as usize] = self.read(hi + i);
-                }
-
-                self.ppu.write_oam_dma(&buffer);
+                self.dma.oam_page = Some(data);
             }
             0x4015 => {
                 self.apu.write_status(data);
             }
             0x4016 => {
+                let latches = self.joypads[0].latch_count();
                 self.joypads[0].write(data);
                 self.joypads[1].write(data);
+
+                let latch = self.joypads[0].latch_count();
+                if latch != latches
+                    && let Some(input) = self
+                        .subframe_movie
+                        .as_ref()
+                        .and_then(|movie| movie.get_latch_input(latch))
+                {
+                    let [joypad1, joypad2] = &mut self.joypads;
+                    input.apply(joypad1, joypad2);
+                }
             }
             0x4017 => {
                 self.apu.write_frame_counter(data);
             }
+            LINK_DATA | LINK_STATUS => {
+                if let Some(link) = &mut self.link {
+                    link.write(addr, data);
+                }
+            }
             0x4018..=DISABLED_APU_IO_END => {
                 // disabled APU and IO functionality
             }
-            CARTRIDGE_SPACE_START..=0xFFFF => self.cart.mapper.write_prg(addr, data),
+            CARTRIDGE_SPACE_START..=0xFFFF => self.write_cart(addr, data),
         }
     }
 }
+
+impl Memory for Bus {
+    fn read(&mut self, addr: u16) -> u8 {
+        let read = self.pages.read[(addr >> 8) as usize];
+        read(self, addr)
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        let write = self.pages.write[(addr >> 8) as usize];
+        write(self, addr, data)
+    }
+
+    fn prg_bank(&self, addr: u16) -> Option<usize> {
+        match addr {
+            CARTRIDGE_SPACE_START..=0xFFFF => self.cart.mapper.prg_bank(addr),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cart::test::test_rom;
+    use crate::joypad::JoypadButton;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    fn clocks_per_instruction(program: &[u8], ram: &[(u16, u8)], count: usize) -> (Bus, Vec<u32>) {
+        let mut prg = program.to_vec();
+        prg.resize(0x8000, 0xEA);
+        prg[0x7FFC] = 0x00;
+        prg[0x7FFD] = 0x80;
+        let apu = APU::new(48_000, Arc::new(Mutex::new(VecDeque::new())));
+        let mut bus = Bus::new(test_rom(prg), apu);
+        bus.cpu_reset(ResetKind::PowerOn);
+        for &(addr, value) in ram {
+            bus.write(addr, value);
+        }
+
+        let mut clocks = Vec::new();
+        for _ in 0..count {
+            let start = bus.cpu_cycles;
+            bus.step_cpu();
+            clocks.push((bus.cpu_cycles - start) as u32);
+        }
+        (bus, clocks)
+    }
+
+    #[test]
+    fn test_internal_ram_is_mirrored_to_1fff() {
+        // LDA #$5A; STA $0875; LDX $1875; STX $1FFF
+        let program = [
+            0xA9, 0x5A, 0x8D, 0x75, 0x08, 0xAE, 0x75, 0x18, 0x8E, 0xFF, 0x1F,
+        ];
+        let (mut bus, _) = clocks_per_instruction(&program, &[], 4);
+        assert_eq!(bus.cpu.registers.x, 0x5A);
+        for addr in [0x0075, 0x0875, 0x1075, 0x1875] {
+            assert_eq!(bus.read(addr), 0x5A);
+        }
+        assert_eq!(bus.cpu.vram[0x07FF], 0x5A);
+        assert_eq!(bus.peek(0x07FF), 0x5A);
+    }
+
+    #[test]
+    fn test_ppu_registers_repeat_every_eight_bytes() {
+        let (mut bus, _) = clocks_per_instruction(&[], &[], 0);
+        // PPUADDR ($2006) through $3FFE, PPUDATA ($2007) through $200F.
+        bus.write(0x3FFE, 0x23);
+        bus.write(0x3FFE, 0x05);
+        bus.write(0x200F, 0xAB);
+
+        bus.write(0x2806, 0x23);
+        bus.write(0x2806, 0x05);
+        bus.read(0x3FFF);
+        assert_eq!(bus.read(0x2007), 0xAB);
+
+        // PPUSTATUS ($2002) through $3FFA clears vblank like the original.
+        bus.ppu.status.set_vblank_status(true);
+        assert_eq!(bus.read(0x3FFA) & 0x80, 0x80);
+        assert_eq!(bus.read(0x2002) & 0x80, 0);
+    }
+
+    #[test]
+    fn test_page_table_matches_memory_map() {
+        let (mut paged, _) = clocks_per_instruction(&[0xA9, 0x42], &[(0x0123, 0x45)], 0);
+        let (mut mixed, _) = clocks_per_instruction(&[0xA9, 0x42], &[(0x0123, 0x45)], 0);
+        for addr in 0..=0xFFFF {
+            assert_eq!(paged.read(addr), mixed.read_mixed(addr), "${:04X}", addr);
+        }
+    }
+
+    #[test]
+    fn test_peek_leaves_registers_alone() {
+        let (mut bus, _) = clocks_per_instruction(&[], &[(0x0300, 0x77)], 0);
+        // VRAM $2305 holds $AB; point PPUADDR at it and prime the buffer.
+        bus.write(0x2006, 0x23);
+        bus.write(0x2006, 0x05);
+        bus.write(0x2007, 0xAB);
+        bus.write(0x2006, 0x23);
+        bus.write(0x2006, 0x05);
+        bus.read(0x2007);
+        bus.ppu.status.set_vblank_status(true);
+        bus.joypads[0].set_button_pressed_status(JoypadButton::BUTTON_A, true);
+        bus.write(0x4016, 1);
+        bus.write(0x4016, 0);
+
+        for _ in 0..2 {
+            assert_eq!(bus.peek(0x2002) & 0x80, 0x80);
+            assert_eq!(bus.peek(0x2007), 0xAB);
+            assert_eq!(bus.peek(0x4016), 1);
+        }
+        assert_eq!(bus.peek_range(0x0300, 2), [0x77, 0x00]);
+        // The IRQ vector's high byte, then RAM.
+        assert_eq!(bus.peek_range(0xFFFF, 2), [0xEA, 0x00]);
+
+        assert_eq!(bus.read(0x2002) & 0x80, 0x80);
+        assert_eq!(bus.read(0x2007), 0xAB);
+        assert_eq!(bus.read(0x4016), 1);
+        assert_eq!(bus.read(0x4016), 0);
+    }
+
+    #[test]
+    fn test_unmapped_reads_see_open_bus() {
+        // LDA $4018; LDX $4016; LDY $5000 (nothing on the cartridge there)
+        let program = [0xAD, 0x18, 0x40, 0xAE, 0x16, 0x40, 0xAC, 0x00, 0x50];
+        let run = |open_bus: OpenBus| {
+            let (mut bus, _) = clocks_per_instruction(&program, &[], 0);
+            bus.set_open_bus(open_bus);
+            for _ in 0..3 {
+                bus.step_cpu();
+            }
+            let registers = &bus.cpu.registers;
+            let read = (registers.a, registers.x, registers.y);
+
+            bus.cpu_cycles += bus.cpu.model().clock_rate() + 1;
+            (read, bus.read(0x4018))
+        };
+
+        assert_eq!(run(OpenBus::Zero), ((0x00, 0x00, 0x00), 0x00));
+        // The high byte of each address, the last byte fetched before it.
+        assert_eq!(run(OpenBus::Last), ((0x40, 0x40, 0x50), 0x50));
+        assert_eq!(run(OpenBus::Decay), ((0x40, 0x40, 0x50), 0x00));
+    }
+
+    #[test]
+    fn test_oam_dma_copies_page_and_stalls_cpu() {
+        // LDA #$02; STA $4014; NOP
+        let program = [0xA9, 0x02, 0x8D, 0x14, 0x40, 0xEA];
+        let (bus, clocks) = clocks_per_instruction(&program, &[(0x0200, 0x11), (0x02FF, 0x22)], 3);
+
+        assert_eq!(clocks[..2], [2, 4]);
+        let parity = (2 + 4) % 2;
+        assert_eq!(clocks[2], 513 + parity + 2);
+        assert_eq!(bus.ppu.oam_data[0], 0x11);
+        assert_eq!(bus.ppu.oam_data[255], 0x22);
+    }
+
+    #[test]
+    fn test_oam_dma_takes_extra_cycle_when_odd() {
+        // NOP; LDA #$02; STA $4014; NOP, and the same starting one cycle later.
+        let even = [0xEA, 0xA9, 0x02, 0x8D, 0x14, 0x40, 0xEA];
+        let odd = [0xA5, 0x00, 0xA9, 0x02, 0x8D, 0x14, 0x40, 0xEA];
+        let (_, even_clocks) = clocks_per_instruction(&even, &[], 4);
+        let (_, odd_clocks) = clocks_per_instruction(&odd, &[], 4);
+
+        assert_eq!(even_clocks[0] + 1, odd_clocks[0]);
+        assert_ne!(even_clocks[3], odd_clocks[3]);
+        let mut stalls = [even_clocks[3] - 2, odd_clocks[3] - 2];
+        stalls.sort();
+        assert_eq!(stalls, [513, 514]);
+    }
+
+    #[test]
+    fn test_read_sees_ppu_on_its_own_cycle() {
+        // LDA $2002 reads on its 4th cycle, after 10 dots (1 + 3 + 3 + 3)
+        // from power-on.
+        let vblank_seen = |dots_until_vblank: i16| {
+            let (mut bus, _) = clocks_per_instruction(&[0xAD, 0x02, 0x20], &[], 0);
+            bus.ppu.scanline = 240;
+            bus.ppu.cycle = 341 - dots_until_vblank;
+            bus.step_cpu();
+            assert_eq!(bus.system_clock, 10);
+            bus.cpu.registers.a & 0x80 != 0
+        };
+
+        assert!(vblank_seen(10));
+        assert!(!vblank_seen(11));
+    }
+
+    #[test]
+    fn test_dmc_fetch_on_controller_read_drops_a_button() {
+        // A held, B not: a dropped bit reads B instead of A.
+        let read_a = |conflict: bool| {
+            let (mut bus, _) = clocks_per_instruction(&[], &[(0x4016, 1), (0x4016, 0)], 0);
+            bus.set_dpcm_conflict(conflict);
+            bus.joypads[0].set_button_pressed_status(JoypadButton::BUTTON_A, true);
+            bus.dma.dmc_addr = Some(0xC000);
+            let start = bus.cpu_cycles;
+            let mut memory = CpuView {
+                bus: &mut bus,
+                accesses: 0,
+                pc: 0,
+            };
+            let value = memory.read(0x4016);
+            let stolen = bus.cpu_cycles - start - 1;
+            assert!(matches!(stolen, 3 | 4));
+            assert_eq!(bus.dma.dmc_addr, None);
+            value & 1
+        };
+
+        assert_eq!(read_a(false), 1);
+        assert_eq!(read_a(true), 0);
+    }
+
+    #[test]
+    fn test_dmc_fetch_during_oam_dma_costs_two_cycles() {
+        // LDA #$02; STA $4014; NOP
+        let program = [0xA9, 0x02, 0x8D, 0x14, 0x40, 0xEA];
+        let oam_dma_clocks = |dmc: bool| {
+            let (mut bus, _) = clocks_per_instruction(&program, &[(0x0200, 0x11)], 2);
+            if dmc {
+                bus.dma.dmc_addr = Some(0xC000);
+            }
+            let start = bus.cpu_cycles;
+            bus.step_cpu();
+            assert_eq!(bus.dma.dmc_addr, None);
+            assert_eq!(bus.ppu.oam_data[0], 0x11);
+            bus.cpu_cycles - start
+        };
+
+        assert_eq!(oam_dma_clocks(true), oam_dma_clocks(false) + 2);
+    }
+
+    #[test]
+    fn test_dmc_fetch_on_ppudata_read_repeats_it() {
+        // VRAM $2000-$2007 holds 0-7; the first read of $2007 fills the
+        // buffer, and each repeat of the second moves the address on.
+        let mut ram: Vec<(u16, u8)> = vec![(0x2006, 0x20), (0x2006, 0x00)];
+        ram.extend((0..8).map(|i| (0x2007, i)));
+        ram.extend([(0x2006, 0x20), (0x2006, 0x00)]);
+        let read_data = |conflict: bool| {
+            let (mut bus, _) = clocks_per_instruction(&[], &ram, 0);
+            bus.set_dpcm_conflict(conflict);
+            bus.read(0x2007);
+            bus.dma.dmc_addr = Some(0xC000);
+            let start = bus.cpu_cycles;
+            let mut memory = CpuView {
+                bus: &mut bus,
+                accesses: 0,
+                pc: 0,
+            };
+            let value = memory.read(0x2007);
+            let stolen = (bus.cpu_cycles - start - 1) as u8;
+            (value, stolen)
+        };
+
+        assert_eq!(read_data(false).0, 0);
+        let (value, stolen) = read_data(true);
+        assert!(matches!(stolen, 3 | 4));
+        assert_eq!(value, stolen - 1);
+    }
+
+    #[test]
+    fn test_taken_branch_delays_interrupt_poll() {
+        // Where the NMI is taken when vblank starts `dots` dots in.
+        let nmi_taken_at = |program: &[u8], dots: i16| {
+            let (mut bus, _) = clocks_per_instruction(program, &[(0x2000, 0x80)], 0);
+            bus.ppu.scanline = 240;
+            bus.ppu.cycle = 341 - dots;
+            loop {
+                let pc = bus.cpu.registers.pc;
+                if bus.step_cpu().interrupt.is_some() {
+                    return pc;
+                }
+            }
+        };
+        // CLC; BCC +0, taking 3 cycles ending 7, 10 and 13 dots in.
+        let branch = [0x18, 0x90, 0x00];
+        assert_eq!(nmi_taken_at(&branch, 7), 0x8003);
+        // Raised after the branch's poll, the NMI waits for the NOP.
+        assert_eq!(nmi_taken_at(&branch, 10), 0x8004);
+        // CLC; LDA $00 is as long but polls on its second to last cycle.
+        assert_eq!(nmi_taken_at(&[0x18, 0xA5, 0x00], 10), 0x8003);
+        assert_eq!(nmi_taken_at(&[0x18, 0xA5, 0x00], 11), 0x8004);
+    }
+}
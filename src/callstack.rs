@@ -0,0 +1,202 @@
+//! Shadow call stack for the debugger. JSR, BRK and interrupt entries push
+//! a frame; RTS and RTI pop it after checking that they return where the
+//! call said they would, with the stack pointer back where it was.
+//!
+//! A return that doesn't match is recorded as a [`StackMismatch`]. If a
+//! deeper frame matches instead (a routine dropped its return address and
+//! returned to its caller's caller), the frames above it are discarded. If
+//! none does, the stack is left alone: that is how the RTS trick (pushing
+//! an address and returning to it) looks, and a real call is still pending.
+
+use crate::cpu::InterruptType;
+
+/// Frames kept before the oldest are forgotten, for code that never
+/// returns from what it calls.
+const MAX_DEPTH: usize = 256;
+/// Mismatches kept until [`CallStack::take_mismatches`].
+const MAX_MISMATCHES: usize = 64;
+
+const BRK: u8 = 0x00;
+const JSR: u8 = 0x20;
+const RTI: u8 = 0x40;
+const RTS: u8 = 0x60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    Jsr,
+    Brk,
+    Interrupt(InterruptType),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallFrame {
+    pub kind: FrameKind,
+    /// Address of the JSR or BRK, or of the instruction the interrupt
+    /// preempted.
+    pub caller: u16,
+    /// The routine or handler entered.
+    pub target: u16,
+    /// Where the matching RTS or RTI should continue.
+    pub return_addr: u16,
+    /// Stack pointer before the call, and after a matching return.
+    pub sp: u8,
+}
+
+/// A return that didn't match the innermost frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackMismatch {
+    /// Address of the RTS or RTI.
+    pub at: u16,
+    /// The frame it should have returned from, if any was open.
+    pub expected: Option<CallFrame>,
+    pub returned_to: u16,
+    /// Stack pointer after the return.
+    pub sp: u8,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CallStack {
+    frames: Vec<CallFrame>,
+    mismatches: Vec<StackMismatch>,
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open frames, outermost first.
+    pub fn frames(&self) -> &[CallFrame] {
+        &self.frames
+    }
+
+    /// Mismatches seen since the last call, oldest first.
+    pub fn take_mismatches(&mut self) -> Vec<StackMismatch> {
+        std::mem::take(&mut self.mismatches)
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Records one CPU step: the opcode at `at` ran with the stack pointer
+    /// at `sp`, and the CPU continues at `next` with `next_sp`.
+    pub(crate) fn record(
+        &mut self,
+        at: u16,
+        opcode: u8,
+        interrupt: Option<InterruptType>,
+        sp: u8,
+        next: u16,
+        next_sp: u8,
+    ) {
+        let call = |kind, return_addr| CallFrame {
+            kind,
+            caller: at,
+            target: next,
+            return_addr,
+            sp,
+        };
+        if let Some(interrupt) = interrupt {
+            self.push(call(FrameKind::Interrupt(interrupt), at));
+            return;
+        }
+        match opcode {
+            JSR => self.push(call(FrameKind::Jsr, at.wrapping_add(3))),
+            BRK => self.push(call(FrameKind::Brk, at.wrapping_add(2))),
+            RTS | RTI => self.ret(at, opcode == RTI, next, next_sp),
+            _ => {}
+        }
+    }
+
+    fn push(&mut self, frame: CallFrame) {
+        if self.frames.len() == MAX_DEPTH {
+            self.frames.remove(0);
+        }
+        self.frames.push(frame);
+    }
+
+    fn ret(&mut self, at: u16, rti: bool, next: u16, next_sp: u8) {
+        let matches = |frame: &CallFrame| {
+            (frame.kind != FrameKind::Jsr) == rti
+                && frame.return_addr == next
+                && frame.sp == next_sp
+        };
+        if self.frames.last().is_some_and(matches) {
+            self.frames.pop();
+            return;
+        }
+
+        if self.mismatches.len() < MAX_MISMATCHES {
+            self.mismatches.push(StackMismatch {
+                at,
+                expected: self.frames.last().copied(),
+                returned_to: next,
+                sp: next_sp,
+            });
+        }
+        if let Some(depth) = self.frames.iter().rposition(matches) {
+            self.frames.truncate(depth);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::asm::assemble;
+    use crate::cart::test::test_nes;
+
+    #[test]
+    fn test_tracks_calls_and_flags_bad_returns() {
+        let program = assemble(
+            "
+                    .org $8000
+            reset:  jsr outer
+                    jsr skip
+            done:   jmp done
+            outer:  jsr inner
+                    rts
+            inner:  nop
+            hold:   jmp hold
+            skip:   jsr drop
+                    brk
+            drop:   pla
+                    pla
+                    rts
+                    .org $FFFC
+                    .word reset, reset
+            ",
+        )
+        .unwrap();
+        let mut nes = test_nes(&program.slice(0x8000, 0x8000));
+        nes.enable_call_stack();
+        let addr = |name: &str| program.labels[name];
+
+        while nes.bus.cpu.registers.pc != addr("hold") {
+            nes.clock();
+        }
+        let stack = nes.call_stack().unwrap();
+        let targets: Vec<u16> = stack.frames().iter().map(|frame| frame.target).collect();
+        assert_eq!(targets, [addr("outer"), addr("inner")]);
+        assert_eq!(stack.frames()[1].return_addr, addr("outer") + 3);
+
+        // Leave `inner` without returning, as if it had, and let `outer`
+        // return; its RTS matches its own frame after `inner` is dropped.
+        nes.bus.cpu.registers.pc = addr("outer") + 3;
+        nes.bus.cpu.registers.sp = nes.bus.cpu.registers.sp.wrapping_add(2);
+        while nes.bus.cpu.registers.pc != addr("done") {
+            nes.clock();
+        }
+        let stack = nes.call_stack_mut().unwrap();
+        assert!(stack.frames().is_empty());
+        let mismatches = stack.take_mismatches();
+        assert_eq!(mismatches.len(), 2);
+        // `outer`'s RTS found `inner` still open.
+        assert_eq!(mismatches[0].expected.unwrap().target, addr("inner"));
+        assert_eq!(mismatches[0].returned_to, addr("reset") + 3);
+        // `drop` pulled its own return address and went back to `reset`.
+        assert_eq!(mismatches[1].at, addr("drop") + 2);
+        assert_eq!(mismatches[1].expected.unwrap().target, addr("drop"));
+        assert_eq!(mismatches[1].returned_to, addr("done"));
+    }
+}
@@ -1,3 +1,5 @@
+use std::fmt;
+
 use crate::mapper::{
     Mapper, cnrom::CnromMapper, mmc1::Mmc1Mapper, mmc3::Mmc3Mapper, nrom::NromMapper,
     nsf::NsfMapper, uxrom::UxromMapper,
@@ -6,6 +8,98 @@ use crate::mapper::{
 const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
 const PRG_ROM_PAGE_SIZE: usize = 16384;
 const CHR_ROM_PAGE_SIZE: usize = 8192;
+// fwNES header of an .fds image, and the block that starts every raw disk
+// side.
+const FDS_TAG: [u8; 4] = [0x46, 0x44, 0x53, 0x1A];
+const FDS_DISK_INFO: &[u8] = b"\x01*NINTENDO-HVC*";
+
+/// iNES mapper numbers [`Cart::new`] can load, in ascending order.
+pub const SUPPORTED_MAPPERS: [u8; 8] = [0, 1, 2, 3, 4, 31, 119, 185];
+
+/// Something a ROM needs that pico doesn't emulate yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Feature {
+    Mapper(u8),
+    /// A Famicom Disk System image rather than a cartridge.
+    DiskSystem,
+    VsSystem,
+    PlayChoice10,
+    /// An NES 2.0 extended console type, such as a Famiclone with decimal
+    /// mode.
+    ExtendedConsole(u8),
+    /// NES 2.0 timing for the Dendy and other UMC-based Famiclones.
+    DendyTiming,
+    /// NES 2.0 miscellaneous ROMs after CHR ROM.
+    MiscRoms(u8),
+    /// An NES 2.0 default expansion device other than the standard
+    /// controllers.
+    ExpansionDevice(u8),
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Feature::Mapper(mapper) => write!(f, "mapper {}", mapper),
+            Feature::DiskSystem => write!(
+                f,
+                "the Famicom Disk System (booting it needs the FDS BIOS, \
+                disksys.rom, 8KB, dumped from a Famicom Disk System RAM adapter; \
+                there is no setting for its path yet)"
+            ),
+            Feature::VsSystem => write!(f, "Vs. System hardware"),
+            Feature::PlayChoice10 => write!(f, "PlayChoice-10 hardware"),
+            Feature::ExtendedConsole(kind) => write!(f, "extended console type {}", kind),
+            Feature::DendyTiming => write!(f, "Dendy timing"),
+            Feature::MiscRoms(count) => write!(f, "{} miscellaneous ROM(s)", count),
+            Feature::ExpansionDevice(device) => write!(f, "expansion device ${:02X}", device),
+        }
+    }
+}
+
+/// Every feature a ROM needs that pico lacks, not only the first found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnsupportedFeature {
+    pub missing: Vec<Feature>,
+}
+
+impl fmt::Display for UnsupportedFeature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let missing: Vec<String> = self.missing.iter().map(Feature::to_string).collect();
+        write!(
+            f,
+            "ROM needs what pico doesn't support yet: {}",
+            missing.join(", ")
+        )
+    }
+}
+
+/// Why [`Cart::new`] didn't load a ROM.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CartError {
+    /// The file is damaged or not a ROM at all.
+    Invalid(String),
+    Unsupported(UnsupportedFeature),
+}
+
+impl fmt::Display for CartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CartError::Invalid(message) => write!(f, "{}", message),
+            CartError::Unsupported(unsupported) => write!(f, "{}", unsupported),
+        }
+    }
+}
+
+impl From<CartError> for String {
+    fn from(error: CartError) -> Self {
+        error.to_string()
+    }
+}
+
+/// Whether `raw` is a Famicom Disk System image rather than a cartridge.
+pub fn is_fds_image(raw: &[u8]) -> bool {
+    raw.starts_with(&FDS_TAG) || raw.starts_with(FDS_DISK_INFO)
+}
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Mirroring {
@@ -67,17 +161,33 @@ fn calculate_ram_size(shift_count: u8) -> usize {
     }
 }
 
-pub struct Cart {
-    pub mapper: Box<dyn Mapper>,
-    pub screen_mirroring: Mirroring,
+/// The fields of an iNES / NES 2.0 header that decide how the file is laid
+/// out and which mapper runs it.
+#[derive(Debug, Clone)]
+pub struct RomHeader {
     pub format: RomFormat,
-    pub nes2_data: Option<Nes2Data>,
+    pub mapper: u8,
+    pub mirroring: Mirroring,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub has_trainer: bool,
+    /// PRG RAM is battery-backed and should be persisted between sessions.
+    pub has_battery: bool,
 }
 
-impl Cart {
-    pub fn new(raw: &Vec<u8>) -> Result<Cart, String> {
-        if raw[0..4] != NES_TAG {
-            return Err("File is not in iNES file format".to_string());
+impl RomHeader {
+    pub const SIZE: usize = 16;
+
+    pub fn parse(raw: &[u8]) -> Result<RomHeader, CartError> {
+        if is_fds_image(raw) {
+            return Err(CartError::Unsupported(UnsupportedFeature {
+                missing: vec![Feature::DiskSystem],
+            }));
+        }
+        if raw.len() < Self::SIZE || raw[0..4] != NES_TAG {
+            return Err(CartError::Invalid(
+                "File is not in iNES file format".to_string(),
+            ));
         }
 
         // Check for NES 2.0 format: header[7] bits 2 and 3 set to 1 and 0 respectively
@@ -93,13 +203,15 @@ impl Cart {
         if let RomFormat::INes = format {
             let ines_ver = (raw[7] >> 2) & 0b11;
             if ines_ver != 0 {
-                return Err("Invalid iNES format version".to_string());
+                return Err(CartError::Invalid(
+                    "Invalid iNES format version".to_string(),
+                ));
             }
         }
 
         let four_screen = raw[6] & 0b1000 != 0;
         let vertical_mirroring = raw[6] & 0b1 != 0;
-        let screen_mirroring = match (four_screen, vertical_mirroring) {
+        let mirroring = match (four_screen, vertical_mirroring) {
             (true, _) => Mirroring::FourScreen,
             (false, true) => Mirroring::Vertical,
             (false, false) => Mirroring::Horizontal,
@@ -116,13 +228,60 @@ impl Cart {
             ),
         };
 
-        let skip_trainer = raw[6] & 0b100 != 0;
+        Ok(RomHeader {
+            format,
+            mapper,
+            mirroring,
+            prg_rom_size,
+            chr_rom_size,
+            has_trainer: raw[6] & 0b100 != 0,
+            has_battery: raw[6] & 0b10 != 0,
+        })
+    }
+
+    pub fn prg_rom_start(&self) -> usize {
+        Self::SIZE + if self.has_trainer { 512 } else { 0 }
+    }
+
+    pub fn chr_rom_start(&self) -> usize {
+        self.prg_rom_start() + self.prg_rom_size
+    }
+
+    /// Total file size implied by the header.
+    pub fn expected_len(&self) -> usize {
+        self.chr_rom_start() + self.chr_rom_size
+    }
+}
+
+pub struct Cart {
+    pub mapper: Box<dyn Mapper>,
+    pub screen_mirroring: Mirroring,
+    pub format: RomFormat,
+    pub nes2_data: Option<Nes2Data>,
+    pub has_battery: bool,
+}
+
+impl Cart {
+    pub fn new(raw: &Vec<u8>) -> Result<Cart, CartError> {
+        let header = RomHeader::parse(raw)?;
+
+        if raw.len() < header.expected_len() {
+            return Err(CartError::Invalid(format!(
+                "ROM is truncated: header expects {} bytes, file has {}",
+                header.expected_len(),
+                raw.len()
+            )));
+        }
 
-        let prg_rom_start = 16 + if skip_trainer { 512 } else { 0 };
-        let chr_rom_start = prg_rom_start + prg_rom_size;
+        let prg_rom_start = header.prg_rom_start();
+        let chr_rom_start = header.chr_rom_start();
 
-        let prg_rom = raw[prg_rom_start..(prg_rom_start + prg_rom_size)].to_vec();
-        let chr_rom = raw[chr_rom_start..(chr_rom_start + chr_rom_size)].to_vec();
+        let prg_rom = raw[prg_rom_start..(prg_rom_start + header.prg_rom_size)].to_vec();
+        let chr_rom = raw[chr_rom_start..(chr_rom_start + header.chr_rom_size)].to_vec();
+        let format = header.format;
+        let screen_mirroring = header.mirroring;
+        let mapper = header.mapper;
+        let has_battery = header.has_battery;
 
         let nes2_data = if let RomFormat::Nes2 = format {
             Some(Nes2Data {
@@ -138,6 +297,11 @@ impl Cart {
             None
         };
 
+        let missing = unsupported_features(mapper, raw[7] & 0x03, nes2_data.as_ref(), raw);
+        if !missing.is_empty() {
+            return Err(CartError::Unsupported(UnsupportedFeature { missing }));
+        }
+
         println!("Mapper: {mapper}");
 
         let mapper: Box<dyn Mapper> = match mapper {
@@ -145,9 +309,16 @@ impl Cart {
             1 => Box::new(Mmc1Mapper::new(prg_rom, chr_rom, screen_mirroring.clone())),
             2 => Box::new(UxromMapper::new(prg_rom, chr_rom, screen_mirroring.clone())),
             3 => Box::new(CnromMapper::new(prg_rom, chr_rom, screen_mirroring.clone())),
+            185 => Box::new(CnromMapper::protected(
+                prg_rom,
+                chr_rom,
+                screen_mirroring.clone(),
+                nes2_data.as_ref().map_or(0, |data| data.submapper),
+            )),
             4 => Box::new(Mmc3Mapper::new(prg_rom, chr_rom, screen_mirroring.clone())),
+            119 => Box::new(Mmc3Mapper::tqrom(prg_rom, chr_rom, screen_mirroring.clone())),
             31 => Box::new(NsfMapper::new(prg_rom, chr_rom, screen_mirroring.clone())),
-            _ => return Err(format!("Mapper {} not supported", mapper)),
+            _ => unreachable!("mapper {} passed the support check", mapper),
         };
 
         Ok(Cart {
@@ -155,6 +326,7 @@ impl Cart {
             screen_mirroring,
             format,
             nes2_data,
+            has_battery,
         })
     }
 
@@ -164,8 +336,40 @@ impl Cart {
             screen_mirroring: Mirroring::Vertical,
             format: RomFormat::INes,
             nes2_data: None,
+            has_battery: false,
+        }
+    }
+}
+
+fn unsupported_features(
+    mapper: u8,
+    console_type: u8,
+    nes2_data: Option<&Nes2Data>,
+    raw: &[u8],
+) -> Vec<Feature> {
+    let mut missing = Vec::new();
+    if !SUPPORTED_MAPPERS.contains(&mapper) {
+        missing.push(Feature::Mapper(mapper));
+    }
+    match console_type {
+        1 => missing.push(Feature::VsSystem),
+        2 => missing.push(Feature::PlayChoice10),
+        3 if nes2_data.is_some() => missing.push(Feature::ExtendedConsole(raw[13] & 0x0F)),
+        _ => {}
+    }
+    if let Some(data) = nes2_data {
+        if data.timing & 0x03 == 3 {
+            missing.push(Feature::DendyTiming);
+        }
+        if data.misc_rom_count > 0 {
+            missing.push(Feature::MiscRoms(data.misc_rom_count));
+        }
+        let device = data.default_expansion_device & 0x3F;
+        if device > 1 {
+            missing.push(Feature::ExpansionDevice(device));
         }
     }
+    missing
 }
 
 pub mod test {
@@ -213,6 +417,29 @@ pub mod test {
         Cart::new(&test_rom).unwrap()
     }
 
+    /// A console powered on with `program` at $8000. A program shorter than
+    /// the 32KB of PRG ROM is padded with NOPs and resets to $8000; a whole
+    /// image keeps its own vectors.
+    #[cfg(test)]
+    pub(crate) fn test_nes(program: &[u8]) -> crate::nes::Nes {
+        use crate::apu::APU;
+        use crate::cpu::ResetKind;
+        use std::collections::VecDeque;
+        use std::sync::{Arc, Mutex};
+
+        let mut prg = vec![0xEA; 2 * PRG_ROM_PAGE_SIZE];
+        prg[..program.len()].copy_from_slice(program);
+        if program.len() < prg.len() {
+            prg[0x7FFC] = 0x00;
+            prg[0x7FFD] = 0x80;
+        }
+
+        let apu = APU::new(48_000, Arc::new(Mutex::new(VecDeque::new())));
+        let mut nes = crate::nes::Nes::new(test_rom(prg), apu);
+        nes.reset(ResetKind::PowerOn);
+        nes
+    }
+
     #[test]
     fn test() {
         let test_rom = create_rom(TestRom {
@@ -285,4 +512,56 @@ pub mod test {
             Result::Err(_) => assert!(false, "should load NES 2.0 rom"),
         }
     }
+
+    #[test]
+    fn test_lists_every_unsupported_feature() {
+        let test_rom = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x50, 0x09, 00, 00, 00, 00, 0x03, 00, 0x01,
+                0x08,
+            ],
+            trainer: None,
+            pgp_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+        let Err(CartError::Unsupported(unsupported)) = Cart::new(&test_rom) else {
+            panic!("should not load mapper 5 on a Vs. System");
+        };
+        assert_eq!(
+            unsupported.missing,
+            [
+                Feature::Mapper(5),
+                Feature::VsSystem,
+                Feature::DendyTiming,
+                Feature::MiscRoms(1),
+                Feature::ExpansionDevice(8),
+            ]
+        );
+        assert_eq!(
+            unsupported.to_string(),
+            "ROM needs what pico doesn't support yet: mapper 5, Vs. System hardware, \
+            Dendy timing, 1 miscellaneous ROM(s), expansion device $08"
+        );
+    }
+
+    #[test]
+    fn test_fds_images_are_named_in_the_error() {
+        let mut fwnes = vec![0x46, 0x44, 0x53, 0x1A, 0x01];
+        fwnes.resize(16, 0);
+        fwnes.extend_from_slice(b"\x01*NINTENDO-HVC*");
+        let raw_disk = fwnes[16..].to_vec();
+        for image in [fwnes, raw_disk] {
+            assert_eq!(
+                RomHeader::parse(&image).err(),
+                Some(CartError::Unsupported(UnsupportedFeature {
+                    missing: vec![Feature::DiskSystem],
+                }))
+            );
+            let Err(error) = Cart::new(&image) else {
+                panic!("should not load an FDS image");
+            };
+            let error = error.to_string();
+            assert!(error.contains("disksys.rom"), "{}", error);
+        }
+    }
 }
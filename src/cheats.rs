@@ -0,0 +1,448 @@
+//! Cheat engine and cheat list import/export.
+//!
+//! A cheat either freezes a RAM byte, writing its value at the end of every
+//! frame; locks one, so the game can't change it at all; or substitutes the
+//! value the CPU reads from an address, optionally only while the original
+//! byte equals a compare value (how Game Genie codes work). Lists can be read
+//! from and written to:
+//!
+//! - FCEUX `.cht`: one `[S][C][:]AAAA:VV[:CC]:Name` per line. `S` marks a
+//!   substitute cheat, `C` a compare value, and a leading `:` a disabled one.
+//!   Locks are written as freezes, the closest FCEUX has.
+//! - Mesen's exported cheat XML (`<CheatInfo>` entries). Game Genie codes are
+//!   decoded; Pro Action Rocky codes and addresses relative to PRG ROM are
+//!   not supported and are reported as errors.
+
+use std::fmt::Write;
+
+use crate::game_genie;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheatKind {
+    /// Write the value to RAM at the end of every frame.
+    Freeze,
+    /// Return the value when the CPU reads the address.
+    Substitute,
+    /// Hold a RAM byte at the value, Pro Action Replay style: CPU reads
+    /// return it, CPU writes store it instead of what was written, and it is
+    /// rewritten at the end of every frame.
+    Lock,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cheat {
+    pub name: String,
+    pub addr: u16,
+    pub value: u8,
+    /// Only substitute while the real byte equals this.
+    pub compare: Option<u8>,
+    pub kind: CheatKind,
+    pub enabled: bool,
+}
+
+impl Cheat {
+    pub fn substitute(addr: u16, value: u8, compare: Option<u8>) -> Self {
+        Cheat {
+            name: String::new(),
+            addr,
+            value,
+            compare,
+            kind: CheatKind::Substitute,
+            enabled: true,
+        }
+    }
+
+    pub fn freeze(addr: u16, value: u8) -> Self {
+        Cheat {
+            kind: CheatKind::Freeze,
+            ..Self::substitute(addr, value, None)
+        }
+    }
+
+    pub fn lock(addr: u16, value: u8) -> Self {
+        Cheat {
+            kind: CheatKind::Lock,
+            ..Self::substitute(addr, value, None)
+        }
+    }
+
+    /// Decodes a 6- or 8-letter Game Genie code; see [`crate::game_genie`].
+    pub fn from_game_genie(code: &str) -> Result<Self, String> {
+        let decoded = game_genie::decode(code)?;
+        Ok(Cheat {
+            name: code.trim().to_string(),
+            ..Self::substitute(decoded.addr, decoded.value, decoded.compare)
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CheatList {
+    pub cheats: Vec<Cheat>,
+}
+
+impl CheatList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+
+    pub fn add(&mut self, cheat: Cheat) {
+        self.cheats.push(cheat);
+    }
+
+    /// Removes every cheat on `addr` and returns them.
+    pub fn remove(&mut self, addr: u16) -> Vec<Cheat> {
+        let (removed, kept) = std::mem::take(&mut self.cheats)
+            .into_iter()
+            .partition(|cheat| cheat.addr == addr);
+        self.cheats = kept;
+        removed
+    }
+
+    /// The value the CPU sees when it reads `real` from `addr`.
+    pub(crate) fn patch_read(&self, addr: u16, real: u8) -> u8 {
+        self.cheats
+            .iter()
+            .find(|cheat| {
+                cheat.enabled
+                    && same_addr(cheat.addr, addr)
+                    && match cheat.kind {
+                        CheatKind::Substitute => {
+                            cheat.compare.is_none_or(|compare| compare == real)
+                        }
+                        CheatKind::Lock => true,
+                        CheatKind::Freeze => false,
+                    }
+            })
+            .map_or(real, |cheat| cheat.value)
+    }
+
+    /// The value stored when the CPU writes `data` to RAM at `addr`.
+    pub(crate) fn patch_write(&self, addr: u16, data: u8) -> u8 {
+        self.cheats
+            .iter()
+            .find(|cheat| {
+                cheat.enabled && cheat.kind == CheatKind::Lock && same_addr(cheat.addr, addr)
+            })
+            .map_or(data, |cheat| cheat.value)
+    }
+
+    /// Enabled freeze and lock cheats as `(addr, value)`.
+    pub fn freezes(&self) -> impl Iterator<Item = (u16, u8)> + '_ {
+        self.cheats
+            .iter()
+            .filter(|cheat| cheat.enabled && cheat.kind != CheatKind::Substitute)
+            .map(|cheat| (cheat.addr, cheat.value))
+    }
+
+    /// Reads a list by file extension: `.cht` for FCEUX, `.xml` for Mesen.
+    pub fn parse_file(name: &str, text: &str) -> Result<Self, String> {
+        let lower = name.to_ascii_lowercase();
+        if lower.ends_with(".cht") {
+            Self::parse_fceux(text)
+        } else if lower.ends_with(".xml") {
+            Self::parse_mesen(text)
+        } else {
+            Err(format!("Unknown cheat file type: {}", name))
+        }
+    }
+
+    /// Writes the list in the format matching `name`'s extension.
+    pub fn to_file_format(&self, name: &str) -> Result<String, String> {
+        let lower = name.to_ascii_lowercase();
+        if lower.ends_with(".cht") {
+            Ok(self.to_fceux())
+        } else if lower.ends_with(".xml") {
+            Ok(self.to_mesen())
+        } else {
+            Err(format!("Unknown cheat file type: {}", name))
+        }
+    }
+
+    pub fn parse_fceux(text: &str) -> Result<Self, String> {
+        let mut list = CheatList::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() {
+                continue;
+            }
+            let cheat =
+                parse_fceux_line(line).map_err(|e| format!("Line {}: {}", number + 1, e))?;
+            list.cheats.push(cheat);
+        }
+        Ok(list)
+    }
+
+    pub fn to_fceux(&self) -> String {
+        let mut out = String::new();
+        for cheat in &self.cheats {
+            if cheat.kind == CheatKind::Substitute {
+                out.push('S');
+            }
+            if cheat.compare.is_some() {
+                out.push('C');
+            }
+            if !cheat.enabled {
+                out.push(':');
+            }
+            let _ = write!(out, "{:04x}:{:02x}:", cheat.addr, cheat.value);
+            if let Some(compare) = cheat.compare {
+                let _ = write!(out, "{:02x}:", compare);
+            }
+            let _ = writeln!(out, "{}", cheat.name);
+        }
+        out
+    }
+
+    pub fn parse_mesen(text: &str) -> Result<Self, String> {
+        let mut list = CheatList::new();
+        for entry in xml_elements(text, "CheatInfo") {
+            let field = |tag: &str| xml_elements(entry, tag).next().map(xml_unescape);
+            let flag = |tag: &str| field(tag).is_some_and(|value| value == "true");
+            let number = |tag: &str| -> Result<u32, String> {
+                let value = field(tag).unwrap_or_default();
+                value
+                    .parse()
+                    .map_err(|_| format!("Invalid {}: {:?}", tag, value))
+            };
+
+            let name = field("CheatName").unwrap_or_default();
+            let mut cheat = match field("CheatType").as_deref() {
+                Some("GameGenie") => {
+                    Cheat::from_game_genie(&field("GameGenieCode").unwrap_or_default())?
+                }
+                Some("Custom") => {
+                    if flag("IsRelativeAddress") {
+                        return Err(format!(
+                            "{}: PRG-relative addresses are not supported",
+                            name
+                        ));
+                    }
+                    let compare = flag("UseCompareValue")
+                        .then(|| number("CompareValue"))
+                        .transpose()?;
+                    Cheat::substitute(
+                        number("Address")? as u16,
+                        number("Value")? as u8,
+                        compare.map(|c| c as u8),
+                    )
+                }
+                other => {
+                    return Err(format!(
+                        "{}: unsupported cheat type {}",
+                        name,
+                        other.unwrap_or("(none)")
+                    ));
+                }
+            };
+            cheat.name = name;
+            cheat.enabled = field("Enabled").is_none_or(|value| value == "true");
+            list.cheats.push(cheat);
+        }
+        Ok(list)
+    }
+
+    /// Mesen's cheat XML. Every cheat is written as a custom code; Mesen
+    /// only substitutes reads, which also holds a frozen or locked RAM byte
+    /// for the game.
+    pub fn to_mesen(&self) -> String {
+        let mut out =
+            String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<ArrayOfCheatInfo>\n");
+        for cheat in &self.cheats {
+            let _ = writeln!(out, "  <CheatInfo>");
+            let _ = writeln!(out, "    <Enabled>{}</Enabled>", cheat.enabled);
+            let _ = writeln!(
+                out,
+                "    <CheatName>{}</CheatName>",
+                xml_escape(&cheat.name)
+            );
+            let _ = writeln!(out, "    <CheatType>Custom</CheatType>");
+            let _ = writeln!(out, "    <Address>{}</Address>", cheat.addr);
+            let _ = writeln!(out, "    <Value>{}</Value>", cheat.value);
+            let _ = writeln!(
+                out,
+                "    <CompareValue>{}</CompareValue>",
+                cheat.compare.unwrap_or(0)
+            );
+            let _ = writeln!(
+                out,
+                "    <UseCompareValue>{}</UseCompareValue>",
+                cheat.compare.is_some()
+            );
+            let _ = writeln!(out, "    <IsRelativeAddress>false</IsRelativeAddress>");
+            let _ = writeln!(out, "  </CheatInfo>");
+        }
+        out += "</ArrayOfCheatInfo>\n";
+        out
+    }
+}
+
+/// Whether `a` and `b` are the same byte, counting the mirrors of internal
+/// RAM up to $1FFF.
+fn same_addr(a: u16, b: u16) -> bool {
+    if a <= 0x1FFF && b <= 0x1FFF {
+        a & 0x07FF == b & 0x07FF
+    } else {
+        a == b
+    }
+}
+
+fn parse_fceux_line(line: &str) -> Result<Cheat, String> {
+    let mut rest = line;
+    let substitute = rest.starts_with('S');
+    rest = rest.strip_prefix('S').unwrap_or(rest);
+    let has_compare = rest.starts_with('C');
+    rest = rest.strip_prefix('C').unwrap_or(rest);
+    let enabled = !rest.starts_with(':');
+    rest = rest.strip_prefix(':').unwrap_or(rest);
+
+    let fields = if has_compare { 4 } else { 3 };
+    let parts: Vec<&str> = rest.splitn(fields, ':').collect();
+    if parts.len() != fields {
+        return Err(format!("Invalid cheat: {}", line));
+    }
+    let hex =
+        |text: &str| u16::from_str_radix(text, 16).map_err(|_| format!("Invalid number: {}", text));
+
+    let addr = hex(parts[0])?;
+    let value = hex(parts[1])? as u8;
+    let compare = if has_compare {
+        Some(hex(parts[2])? as u8)
+    } else {
+        None
+    };
+    let mut cheat = if substitute {
+        Cheat::substitute(addr, value, compare)
+    } else {
+        Cheat {
+            compare,
+            ..Cheat::freeze(addr, value)
+        }
+    };
+    cheat.name = parts[fields - 1].to_string();
+    cheat.enabled = enabled;
+    Ok(cheat)
+}
+
+// The contents of each `<tag>...</tag>` in `text`, not nested.
+fn xml_elements<'a>(text: &'a str, tag: &str) -> impl Iterator<Item = &'a str> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let mut rest = text;
+    std::iter::from_fn(move || {
+        let start = rest.find(&open)? + open.len();
+        let len = rest[start..].find(&close)?;
+        let content = &rest[start..start + len];
+        rest = &rest[start + len + close.len()..];
+        Some(content)
+    })
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn xml_unescape(text: &str) -> String {
+    text.trim()
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fceux_round_trip() {
+        let text =
+            "0075:09:Infinite lives\n:0076:01:Disabled\nSCc123:ea:20:Skip check\nS:8000:60:Off\n";
+        let list = CheatList::parse_fceux(text).unwrap();
+        assert_eq!(list.cheats.len(), 4);
+        assert_eq!(
+            list.cheats[0],
+            Cheat {
+                name: "Infinite lives".to_string(),
+                ..Cheat::freeze(0x0075, 0x09)
+            }
+        );
+        assert!(!list.cheats[1].enabled);
+        assert_eq!(list.cheats[2].kind, CheatKind::Substitute);
+        assert_eq!(list.cheats[2].compare, Some(0x20));
+        assert!(!list.cheats[3].enabled);
+
+        assert_eq!(list.to_fceux(), text);
+        assert!(CheatList::parse_fceux("0075-09").is_err());
+    }
+
+    #[test]
+    fn test_mesen_import_and_export() {
+        let xml = r#"<?xml version="1.0"?>
+<ArrayOfCheatInfo>
+  <CheatInfo>
+    <Enabled>true</Enabled>
+    <CheatName>Lives &amp; more</CheatName>
+    <CheatType>GameGenie</CheatType>
+    <GameGenieCode>SXIOPO</GameGenieCode>
+  </CheatInfo>
+  <CheatInfo>
+    <Enabled>false</Enabled>
+    <CheatName>Custom</CheatName>
+    <CheatType>Custom</CheatType>
+    <Address>49443</Address>
+    <Value>234</Value>
+    <CompareValue>32</CompareValue>
+    <UseCompareValue>true</UseCompareValue>
+    <IsRelativeAddress>false</IsRelativeAddress>
+  </CheatInfo>
+</ArrayOfCheatInfo>"#;
+        let list = CheatList::parse_mesen(xml).unwrap();
+        assert_eq!(list.cheats[0].name, "Lives & more");
+        assert_eq!((list.cheats[0].addr, list.cheats[0].value), (0x91D9, 0xAD));
+        assert_eq!(
+            list.cheats[1],
+            Cheat {
+                name: "Custom".to_string(),
+                enabled: false,
+                ..Cheat::substitute(0xC123, 0xEA, Some(0x20))
+            }
+        );
+
+        assert_eq!(CheatList::parse_mesen(&list.to_mesen()).unwrap(), list);
+    }
+
+    #[test]
+    fn test_substitution_respects_compare() {
+        let mut list = CheatList::new();
+        list.cheats
+            .push(Cheat::substitute(0x8000, 0xEA, Some(0x20)));
+        list.cheats.push(Cheat::freeze(0x0075, 0x09));
+        assert_eq!(list.patch_read(0x8000, 0x20), 0xEA);
+        assert_eq!(list.patch_read(0x8000, 0x4C), 0x4C);
+        assert_eq!(list.patch_read(0x0075, 0x01), 0x01);
+        assert_eq!(list.freezes().collect::<Vec<_>>(), [(0x0075, 0x09)]);
+
+        list.add(Cheat::lock(0x0076, 0x03));
+        assert_eq!(list.patch_read(0x0076, 0x00), 0x03);
+        assert_eq!(list.patch_write(0x0076, 0x00), 0x03);
+        assert_eq!(list.patch_write(0x0075, 0x00), 0x00);
+        assert_eq!(list.patch_write(0x1876, 0x00), 0x03);
+        assert_eq!(list.freezes().count(), 2);
+        assert_eq!(list.remove(0x0076), [Cheat::lock(0x0076, 0x03)]);
+        assert_eq!(list.cheats.len(), 2);
+
+        let code = Cheat::from_game_genie("YEUZUGAA").unwrap();
+        assert_eq!(
+            (code.addr, code.value, code.compare),
+            (0xACB3, 0x07, Some(0x00))
+        );
+    }
+}
@@ -0,0 +1,418 @@
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+use crate::input_macro::InputMacro;
+use crate::joypad::JoypadButton;
+use crate::pacing::{ThreadPriority, WaitStrategy};
+use crate::ppu::palette::NtscSettings;
+use crate::trigger::{Condition, Trigger};
+
+pub const DEFAULT_PROFILE: &str = "default";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoFilter {
+    Nearest,
+    Linear,
+}
+
+impl VideoFilter {
+    pub fn name(&self) -> &'static str {
+        match self {
+            VideoFilter::Nearest => "nearest",
+            VideoFilter::Linear => "linear",
+        }
+    }
+
+    fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "nearest" => Ok(VideoFilter::Nearest),
+            "linear" => Ok(VideoFilter::Linear),
+            _ => Err(format!("Unknown video filter: {}", value)),
+        }
+    }
+}
+
+/// Where a profile's colours come from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PaletteSource {
+    /// The bundled palette.
+    Builtin,
+    /// A `.pal` file.
+    File(PathBuf),
+    /// Generated from the profile's [`Profile::ntsc`] knobs; see
+    /// [`crate::ppu::palette::generate_ntsc`].
+    Ntsc,
+}
+
+pub(crate) const BUTTON_NAMES: [(&str, JoypadButton); 8] = [
+    ("up", JoypadButton::UP),
+    ("down", JoypadButton::DOWN),
+    ("left", JoypadButton::LEFT),
+    ("right", JoypadButton::RIGHT),
+    ("select", JoypadButton::SELECT),
+    ("start", JoypadButton::START),
+    ("a", JoypadButton::BUTTON_A),
+    ("b", JoypadButton::BUTTON_B),
+];
+
+/// A named bundle of audio/visual settings and input bindings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    pub video_filter: VideoFilter,
+    pub scale: u32,
+    pub palette: PaletteSource,
+    pub ntsc: NtscSettings,
+    pub audio_latency_ms: u32,
+    /// `audio_latency_ms = auto`: size the audio queue from measured
+    /// callback jitter, starting from `audio_latency_ms`; see
+    /// [`crate::audio_latency`].
+    pub adaptive_audio_latency: bool,
+    /// How the frame limiter waits; see [`crate::pacing`].
+    pub wait_strategy: WaitStrategy,
+    /// Read once at startup; switching profiles doesn't change it.
+    pub thread_priority: ThreadPriority,
+    /// Frontend key name (e.g. "Return") for each controller button.
+    pub bindings: Vec<(JoypadButton, String)>,
+    /// Input sequences played on controller 1 when their key is pressed.
+    pub macros: Vec<(String, InputMacro)>,
+    /// Screenshot and state captures; see [`crate::trigger`].
+    pub triggers: Vec<Trigger>,
+}
+
+impl Profile {
+    pub fn new(name: &str) -> Self {
+        Profile {
+            name: name.to_string(),
+            video_filter: VideoFilter::Nearest,
+            scale: 3,
+            palette: PaletteSource::Builtin,
+            ntsc: NtscSettings::default(),
+            audio_latency_ms: 60,
+            adaptive_audio_latency: false,
+            wait_strategy: WaitStrategy::default(),
+            thread_priority: ThreadPriority::default(),
+            bindings: vec![
+                (JoypadButton::UP, "Up".to_string()),
+                (JoypadButton::DOWN, "Down".to_string()),
+                (JoypadButton::LEFT, "Left".to_string()),
+                (JoypadButton::RIGHT, "Right".to_string()),
+                (JoypadButton::SELECT, "Space".to_string()),
+                (JoypadButton::START, "Return".to_string()),
+                (JoypadButton::BUTTON_A, "X".to_string()),
+                (JoypadButton::BUTTON_B, "Z".to_string()),
+            ],
+            macros: Vec::new(),
+            triggers: Vec::new(),
+        }
+    }
+
+    pub fn bind(&mut self, button: JoypadButton, key: &str) {
+        match self.bindings.iter_mut().find(|(b, _)| *b == button) {
+            Some((_, existing)) => *existing = key.to_string(),
+            None => self.bindings.push((button, key.to_string())),
+        }
+    }
+
+    /// Binds `key` to play `sequence` (see [`InputMacro::parse`]).
+    pub fn set_macro(&mut self, key: &str, sequence: &str) -> Result<(), String> {
+        let input_macro = InputMacro::parse(sequence)?;
+        match self.macros.iter_mut().find(|(k, _)| k == key) {
+            Some((_, existing)) => *existing = input_macro,
+            None => self.macros.push((key.to_string(), input_macro)),
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "video_filter" => self.video_filter = VideoFilter::parse(value)?,
+            "scale" => {
+                self.scale = value
+                    .parse()
+                    .map_err(|_| format!("Invalid scale: {}", value))?
+            }
+            "palette" => {
+                self.palette = match value {
+                    "" => PaletteSource::Builtin,
+                    "ntsc" => PaletteSource::Ntsc,
+                    path => PaletteSource::File(PathBuf::from(path)),
+                }
+            }
+            "ntsc_hue" => {
+                self.ntsc.hue = value
+                    .parse()
+                    .map_err(|_| format!("Invalid hue: {}", value))?
+            }
+            "ntsc_saturation" => {
+                self.ntsc.saturation = value
+                    .parse()
+                    .map_err(|_| format!("Invalid saturation: {}", value))?
+            }
+            "audio_latency_ms" if value == "auto" => self.adaptive_audio_latency = true,
+            "audio_latency_ms" => {
+                self.audio_latency_ms = value
+                    .parse()
+                    .map_err(|_| format!("Invalid audio latency: {}", value))?;
+                self.adaptive_audio_latency = false;
+            }
+            "wait_strategy" => self.wait_strategy = WaitStrategy::parse(value)?,
+            "thread_priority" => self.thread_priority = ThreadPriority::parse(value)?,
+            _ if key.starts_with("macro.") => self.set_macro(&key["macro.".len()..], value)?,
+            _ if key.starts_with("trigger.") => {
+                let name = &key["trigger.".len()..];
+                let condition = Condition::parse(value)?;
+                match self.triggers.iter_mut().find(|t| t.name == name) {
+                    Some(existing) => existing.condition = condition,
+                    None => self.triggers.push(Trigger {
+                        name: name.to_string(),
+                        condition,
+                    }),
+                }
+            }
+            _ => {
+                let button = key
+                    .strip_prefix("bind.")
+                    .and_then(|name| BUTTON_NAMES.iter().find(|(n, _)| *n == name))
+                    .map(|(_, button)| *button)
+                    .ok_or_else(|| format!("Unknown profile setting: {}", key))?;
+                self.bind(button, value);
+            }
+        }
+        Ok(())
+    }
+
+    fn write_to(&self, out: &mut String) {
+        let _ = writeln!(out, "[profile {}]", self.name);
+        let _ = writeln!(out, "video_filter = {}", self.video_filter.name());
+        let _ = writeln!(out, "scale = {}", self.scale);
+        let palette = match &self.palette {
+            PaletteSource::Builtin => String::new(),
+            PaletteSource::File(path) => path.to_string_lossy().into_owned(),
+            PaletteSource::Ntsc => "ntsc".to_string(),
+        };
+        let _ = writeln!(out, "palette = {}", palette);
+        let _ = writeln!(out, "ntsc_hue = {}", self.ntsc.hue);
+        let _ = writeln!(out, "ntsc_saturation = {}", self.ntsc.saturation);
+        if self.adaptive_audio_latency {
+            let _ = writeln!(out, "audio_latency_ms = auto");
+        } else {
+            let _ = writeln!(out, "audio_latency_ms = {}", self.audio_latency_ms);
+        }
+        let _ = writeln!(out, "wait_strategy = {}", self.wait_strategy.name());
+        let _ = writeln!(out, "thread_priority = {}", self.thread_priority.name());
+        for (button, key) in &self.bindings {
+            if let Some((name, _)) = BUTTON_NAMES.iter().find(|(_, b)| b == button) {
+                let _ = writeln!(out, "bind.{} = {}", name, key);
+            }
+        }
+        for (key, input_macro) in &self.macros {
+            let _ = writeln!(out, "macro.{} = {}", key, input_macro);
+        }
+        for trigger in &self.triggers {
+            let _ = writeln!(out, "trigger.{} = {}", trigger.name, trigger.condition);
+        }
+    }
+}
+
+/// User settings file. A plain `key = value` format with one `[profile NAME]`
+/// section per profile:
+///
+/// ```text
+/// active = laptop
+///
+/// [profile laptop]
+/// video_filter = linear
+/// scale = 2
+/// bind.a = X
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub active: String,
+    pub profiles: Vec<Profile>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            active: DEFAULT_PROFILE.to_string(),
+            profiles: vec![Profile::new(DEFAULT_PROFILE)],
+        }
+    }
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/pico/config.ini`, falling back to `~/.config`.
+    pub fn default_path() -> PathBuf {
+        let base = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .unwrap_or_default();
+        base.join("pico").join("config.ini")
+    }
+
+    pub fn load_or_default<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        match std::fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(format!("Failed to read config: {}", e)),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        if let Some(dir) = path.as_ref().parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create config directory: {}", e))?;
+        }
+        std::fs::write(path, self.to_text()).map_err(|e| format!("Failed to write config: {}", e))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut config = Config {
+            active: DEFAULT_PROFILE.to_string(),
+            profiles: Vec::new(),
+        };
+
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let name = section
+                    .strip_prefix("profile ")
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .ok_or_else(|| {
+                        format!("Line {}: invalid section [{}]", line_no + 1, section)
+                    })?;
+                config.profiles.push(Profile::new(name));
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .map(|(k, v)| (k.trim(), v.trim()))
+                .ok_or_else(|| format!("Line {}: expected key = value", line_no + 1))?;
+
+            match config.profiles.last_mut() {
+                Some(profile) => profile
+                    .set(key, value)
+                    .map_err(|e| format!("Line {}: {}", line_no + 1, e))?,
+                None if key == "active" => config.active = value.to_string(),
+                None => return Err(format!("Line {}: unknown setting {}", line_no + 1, key)),
+            }
+        }
+
+        if config.profiles.is_empty() {
+            config.profiles.push(Profile::new(DEFAULT_PROFILE));
+        }
+
+        Ok(config)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "active = {}", self.active);
+        for profile in &self.profiles {
+            out.push('\n');
+            profile.write_to(&mut out);
+        }
+        out
+    }
+
+    pub fn profile(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|p| p.name == name)
+    }
+
+    /// The active profile, or the first one if `active` names no profile.
+    pub fn active_profile(&self) -> &Profile {
+        self.profile(&self.active).unwrap_or(&self.profiles[0])
+    }
+
+    /// Makes the next profile (in file order) active and returns it.
+    pub fn cycle_profile(&mut self) -> &Profile {
+        let current = self
+            .profiles
+            .iter()
+            .position(|p| p.name == self.active)
+            .unwrap_or(0);
+        let next = (current + 1) % self.profiles.len();
+        self.active = self.profiles[next].name.clone();
+        &self.profiles[next]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SAMPLE: &str = "
+active = kids
+
+[profile TV setup]
+video_filter = linear
+scale = 4
+palette = palettes/Sony CXA.pal
+audio_latency_ms = 100
+wait_strategy = hybrid
+thread_priority = high
+
+[profile kids]
+palette = ntsc
+ntsc_hue = -5
+audio_latency_ms = auto
+bind.a = Space
+macro.F1 = start*2 .*30 start
+trigger.lives = $0075 changes to 8
+";
+
+    #[test]
+    fn test_parse_profiles() {
+        let config = Config::parse(SAMPLE).unwrap();
+        assert_eq!(config.profiles.len(), 2);
+
+        let tv = config.profile("TV setup").unwrap();
+        assert_eq!(tv.video_filter, VideoFilter::Linear);
+        assert_eq!(tv.scale, 4);
+        assert_eq!(
+            tv.palette,
+            PaletteSource::File(PathBuf::from("palettes/Sony CXA.pal"))
+        );
+        assert_eq!(tv.audio_latency_ms, 100);
+        assert_eq!(tv.wait_strategy, WaitStrategy::Hybrid);
+        assert_eq!(tv.thread_priority, ThreadPriority::High);
+
+        let kids = config.active_profile();
+        assert_eq!(kids.name, "kids");
+        assert!(
+            kids.bindings
+                .contains(&(JoypadButton::BUTTON_A, "Space".to_string()))
+        );
+        assert_eq!(kids.macros.len(), 1);
+        assert_eq!(kids.macros[0].0, "F1");
+        assert_eq!(kids.macros[0].1.frames.len(), 33);
+        assert_eq!(kids.triggers[0].condition, Condition::ChangesTo(0x0075, 8));
+        assert_eq!(kids.wait_strategy, WaitStrategy::Sleep);
+        assert!(kids.adaptive_audio_latency);
+        assert!(!tv.adaptive_audio_latency);
+        assert_eq!(kids.palette, PaletteSource::Ntsc);
+        assert_eq!(kids.ntsc.hue, -5.0);
+        assert_eq!(kids.ntsc.saturation, 1.0);
+    }
+
+    #[test]
+    fn test_round_trip_and_cycle() {
+        let mut config = Config::parse(SAMPLE).unwrap();
+        assert_eq!(Config::parse(&config.to_text()).unwrap(), config);
+
+        assert_eq!(config.cycle_profile().name, "TV setup");
+        assert_eq!(config.cycle_profile().name, "kids");
+    }
+
+    #[test]
+    fn test_rejects_unknown_setting() {
+        assert!(Config::parse("[profile x]\nshader = crt\n").is_err());
+    }
+}
@@ -0,0 +1,80 @@
+//! What this build of the core supports, so a frontend, a netplay peer or
+//! a movie's author can check compatibility before a session starts rather
+//! than failing partway through one.
+
+use std::fmt;
+
+use crate::cart::SUPPORTED_MAPPERS;
+use crate::nes::DUMP_VERSION;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoreInfo {
+    /// The crate version. In-memory snapshots ([`crate::nes::Snapshot`])
+    /// and exact replays are only guaranteed within one version.
+    pub version: &'static str,
+    /// Cargo features compiled in. pico has none yet.
+    pub features: &'static [&'static str],
+    /// iNES mapper numbers that load.
+    pub mappers: &'static [u8],
+    /// Version byte of [`crate::nes::Nes::dump_state`]. pico has no
+    /// loadable save-state format yet.
+    pub state_dump_version: u8,
+}
+
+impl CoreInfo {
+    pub fn supports_mapper(&self, mapper: u8) -> bool {
+        self.mappers.contains(&mapper)
+    }
+
+    /// Whether runs on `self` and `other` replay identically: the same
+    /// version with the same features.
+    pub fn is_compatible_with(&self, other: &CoreInfo) -> bool {
+        self.version == other.version && self.features == other.features
+    }
+}
+
+impl fmt::Display for CoreInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mappers: Vec<String> = self.mappers.iter().map(u8::to_string).collect();
+        writeln!(f, "pico {}", self.version)?;
+        writeln!(f, "features: {}", self.features.join(", "))?;
+        writeln!(f, "mappers: {}", mappers.join(", "))?;
+        write!(f, "state dump version: {}", self.state_dump_version)
+    }
+}
+
+pub fn core_info() -> CoreInfo {
+    CoreInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        features: &[],
+        mappers: &SUPPORTED_MAPPERS,
+        state_dump_version: DUMP_VERSION,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cart::Cart;
+
+    #[test]
+    fn test_lists_the_mappers_that_load() {
+        let info = core_info();
+        assert!(info.is_compatible_with(&core_info()));
+        assert!(info.supports_mapper(4) && !info.supports_mapper(5));
+
+        let rom = |mapper: u8| {
+            let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 2, 1, mapper << 4, mapper & 0xF0];
+            rom.resize(16 + 2 * 16384 + 8192, 0);
+            rom
+        };
+        for mapper in 0..=255 {
+            assert_eq!(
+                Cart::new(&rom(mapper)).is_ok(),
+                info.supports_mapper(mapper),
+                "mapper {}",
+                mapper
+            );
+        }
+    }
+}
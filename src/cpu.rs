@@ -1,14 +1,16 @@
 use std::fmt::Debug;
+use std::time::Duration;
 
 use bitflags::bitflags;
 
 use crate::memory::Memory;
-use crate::opcodes::{AddressingMode, CPU_OPCODES, Mnemonic};
+use crate::opcodes::{AddressingMode, CMOS_OPCODES, CPU_OPCODES, Mnemonic, OpcodeMap};
 
 pub const STACK_START: u16 = 0x0100;
 pub const PRG_START: u16 = 0x8000;
 
 bitflags! {
+    #[derive(Clone, Copy)]
     pub struct StatusFlags: u8 {
         const CARRY = 0b0000_0001;
         const ZERO = 0b0000_0010;
@@ -21,6 +23,7 @@ bitflags! {
     }
 }
 
+#[derive(Clone)]
 pub struct Registers {
     pub a: u8,
     pub x: u8,
@@ -41,13 +44,16 @@ impl Debug for Registers {
 }
 
 mod interrupt {
-    #[derive(PartialEq, Eq)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub enum InterruptType {
         NMI,
         IRQ,
         BRK,
     }
 
+    /// Describes one kind of interrupt entry. All three share the same 7-cycle
+    /// sequence: push PC (high, low), push P with `b_flag_mask` applied to bits
+    /// 4-5, set I, then load PC from `vector_addr`.
     #[derive(PartialEq, Eq)]
     pub(super) struct Interrupt {
         pub(super) itype: InterruptType,
@@ -60,34 +66,432 @@ mod interrupt {
         itype: InterruptType::NMI,
         vector_addr: 0xFFFA,
         b_flag_mask: 0b00100000,
-        cpu_cycles: 2,
+        cpu_cycles: 7,
     };
 
     pub(super) const IRQ: Interrupt = Interrupt {
         itype: InterruptType::IRQ,
         vector_addr: 0xFFFE,
         b_flag_mask: 0b00100000,
-        cpu_cycles: 2,
+        cpu_cycles: 7,
     };
 
+    // BRK's 7 cycles are already counted by its opcode table entry.
     pub(super) const BRK: Interrupt = Interrupt {
         itype: InterruptType::BRK,
         vector_addr: 0xFFFE,
         b_flag_mask: 0b00110000,
-        cpu_cycles: 1,
+        cpu_cycles: 0,
     };
 }
 
+pub use interrupt::InterruptType;
+
+/// Outcome of [`CPU::step`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepResult {
+    /// CPU cycles consumed, including page-cross and branch penalties.
+    pub cycles: u8,
+    /// Set when an NMI or IRQ was entered instead of executing an opcode.
+    pub interrupt: Option<InterruptType>,
+    /// Set when the CPU stopped, either before executing anything or, for a
+    /// watchpoint, after the instruction that touched the watched address.
+    pub stop: Option<StopReason>,
+    /// Set for a taken branch that stayed on its page. Such a branch polls
+    /// for interrupts before its operand fetch and not again, so an
+    /// interrupt raised during its last two cycles waits one more
+    /// instruction.
+    pub branch_delay: bool,
+}
+
+/// Which chip the core emulates, chosen when it is constructed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CpuModel {
+    /// NTSC NES/Famicom CPU.
+    #[default]
+    Rp2A03,
+    /// PAL NES CPU: slower clock, 3.2 PPU dots per cycle and PAL APU tables.
+    Rp2A07,
+    /// A stock NMOS 6502 with working decimal mode, for use outside a NES.
+    Mos6502,
+    /// A CMOS 65C02: the NMOS core plus the extensions in
+    /// [`OpcodeMap::cmos`], a `JMP ($xxFF)` that doesn't wrap within the
+    /// page and a D flag cleared on BRK, IRQ and NMI entry. Decimal mode
+    /// works but keeps NMOS behaviour: N and Z aren't valid after a BCD
+    /// ADC/SBC, and it doesn't take the 65C02's extra cycle.
+    Wdc65C02,
+}
+
+impl CpuModel {
+    /// CPU clock in Hz. The generic 6502 and 65C02 run at a nominal 1 MHz.
+    pub fn clock_rate(self) -> u64 {
+        match self {
+            CpuModel::Rp2A03 => 1_789_773,
+            CpuModel::Rp2A07 => 1_662_607,
+            CpuModel::Mos6502 | CpuModel::Wdc65C02 => 1_000_000,
+        }
+    }
+
+    /// Whether ADC and SBC honour the D flag. Both NES CPUs have the BCD
+    /// logic disconnected.
+    pub fn has_decimal_mode(self) -> bool {
+        matches!(self, CpuModel::Mos6502 | CpuModel::Wdc65C02)
+    }
+
+    /// The opcode table this model decodes with.
+    pub fn opcodes(self) -> &'static OpcodeMap {
+        match self {
+            CpuModel::Wdc65C02 => &CMOS_OPCODES,
+            CpuModel::Rp2A03 | CpuModel::Rp2A07 | CpuModel::Mos6502 => &CPU_OPCODES,
+        }
+    }
+
+    /// PPU dots per CPU cycle as a fraction `(dots, cycles)`.
+    pub fn ppu_dots_per_cycle(self) -> (u64, u64) {
+        match self {
+            CpuModel::Rp2A07 => (16, 5),
+            CpuModel::Rp2A03 | CpuModel::Mos6502 | CpuModel::Wdc65C02 => (3, 1),
+        }
+    }
+
+    /// Whether the CPU clocks on PPU dot `dot`, spreading the cycles as
+    /// evenly as the master clock dividers do.
+    pub fn clocks_on_dot(self, dot: u64) -> bool {
+        let (dots, cycles) = self.ppu_dots_per_cycle();
+        (dot * cycles) % dots < cycles
+    }
+
+    /// Scanlines per frame of the PPU this CPU is paired with. The PAL PPU
+    /// has 50 more lines of vertical blank.
+    pub fn scanlines(self) -> u16 {
+        match self {
+            CpuModel::Rp2A07 => 312,
+            CpuModel::Rp2A03 | CpuModel::Mos6502 | CpuModel::Wdc65C02 => 262,
+        }
+    }
+
+    /// Whether the paired PPU drops the last dot of the pre-render line on
+    /// every other frame while rendering is on. The PAL PPU doesn't.
+    pub fn skips_odd_frame_dot(self) -> bool {
+        self != CpuModel::Rp2A07
+    }
+
+    /// Real time taken by one frame of 341 dots by
+    /// [`CpuModel::scanlines`], averaged over the odd-frame dot skip of a
+    /// PPU that is rendering; see [`CpuModel::skips_odd_frame_dot`].
+    pub fn frame_time(self) -> Duration {
+        let (dots, cycles) = self.ppu_dots_per_cycle();
+        let skipped = if self.skips_odd_frame_dot() { 0.5 } else { 0.0 };
+        let frame_dots = 341.0 * self.scanlines() as f64 - skipped;
+        let frame_cycles = frame_dots * cycles as f64 / dots as f64;
+        Duration::from_secs_f64(frame_cycles / self.clock_rate() as f64)
+    }
+
+    /// Real time taken by `dots` PPU dots, to the nanosecond.
+    pub fn dots_to_time(self, dots: u64) -> Duration {
+        let (per, cycles) = self.ppu_dots_per_cycle();
+        let nanos = dots as u128 * cycles as u128 * 1_000_000_000
+            / (per as u128 * self.clock_rate() as u128);
+        Duration::from_nanos(nanos as u64)
+    }
+}
+
+/// How the CPU is being (re)started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetKind {
+    /// First power-up: A, X and Y are cleared, SP is $FD and P is $34.
+    PowerOn,
+    /// The reset button: registers keep their values, SP drops by 3 as if
+    /// an interrupt had pushed (with writes suppressed), and I is set.
+    Reset,
+}
+
+/// A program for [`CPU::load`]: code and data at any addresses, an image
+/// of RAM from $0000 and the vectors to install.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RawProgram {
+    /// `(address, bytes)`, written in order after `ram`, so a later segment
+    /// wins where two overlap.
+    pub segments: Vec<(u16, Vec<u8>)>,
+    pub ram: Vec<u8>,
+    pub nmi_vector: Option<u16>,
+    pub reset_vector: Option<u16>,
+    pub irq_vector: Option<u16>,
+}
+
+/// Why the CPU stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The opcode fetch at this address matched a breakpoint. The next
+    /// step or clock executes the instruction instead of stopping again.
+    Breakpoint(u16),
+    /// The instruction just executed accessed a watched address.
+    Watchpoint(WatchHit),
+    /// A BRK at this address was executed with [`CPU::set_stop_on_brk`]
+    /// on. The CPU is at the start of the IRQ handler.
+    Brk(u16),
+    /// A STP/KIL opcode locked up the CPU until reset.
+    Halted,
+    /// The byte fetched at `pc` decodes to no instruction of the CPU model.
+    /// The CPU halts, and later steps report [`StopReason::Halted`].
+    InvalidOpcode { pc: u16, byte: u8 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    /// Either a read or a write.
+    Access,
+}
+
+/// Memory watchpoint over an inclusive address range. Reads include opcode
+/// and operand fetches, and writes include stack pushes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    pub fn new(addr: u16, kind: WatchKind) -> Self {
+        Self::range(addr, addr, kind)
+    }
+
+    pub fn range(start: u16, end: u16, kind: WatchKind) -> Self {
+        Watchpoint { start, end, kind }
+    }
+
+    fn matches(&self, addr: u16, write: bool) -> bool {
+        let kind = match self.kind {
+            WatchKind::Read => !write,
+            WatchKind::Write => write,
+            WatchKind::Access => true,
+        };
+        kind && (self.start..=self.end).contains(&addr)
+    }
+}
+
+/// The access that triggered a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    /// Address of the instruction (or interrupted instruction) responsible.
+    pub pc: u16,
+    pub addr: u16,
+    pub value: u8,
+    /// `WatchKind::Read` or `WatchKind::Write`.
+    pub kind: WatchKind,
+}
+
+// Passes accesses through to the real memory and records the first one that
+// matches a watchpoint.
+struct WatchedMemory<'a, M: Memory> {
+    inner: &'a mut M,
+    watchpoints: &'a [Watchpoint],
+    pc: u16,
+    hit: Option<WatchHit>,
+}
+
+impl<M: Memory> WatchedMemory<'_, M> {
+    fn check(&mut self, addr: u16, value: u8, write: bool) {
+        if self.hit.is_none() && self.watchpoints.iter().any(|wp| wp.matches(addr, write)) {
+            self.hit = Some(WatchHit {
+                pc: self.pc,
+                addr,
+                value,
+                kind: if write {
+                    WatchKind::Write
+                } else {
+                    WatchKind::Read
+                },
+            });
+        }
+    }
+}
+
+impl<M: Memory> Memory for WatchedMemory<'_, M> {
+    fn read(&mut self, addr: u16) -> u8 {
+        let value = self.inner.read(addr);
+        self.check(addr, value, false);
+        value
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.inner.write(addr, data);
+        self.check(addr, data, true);
+    }
+
+    fn fetch_opcode(&mut self, addr: u16) -> u8 {
+        let value = self.inner.fetch_opcode(addr);
+        self.check(addr, value, false);
+        value
+    }
+
+    fn prg_bank(&self, addr: u16) -> Option<usize> {
+        self.inner.prg_bank(addr)
+    }
+
+    fn take_nmi(&mut self) -> bool {
+        self.inner.take_nmi()
+    }
+}
+
+/// An instruction part way through [`CPU::step_cycle`]: the CPU as it was
+/// before the instruction, the inputs it has had from memory so far (read
+/// values and NMI hijack checks, in order) and the cycles it has run.
+#[derive(Clone)]
+struct InstructionProgress {
+    start: Box<CPU>,
+    inputs: Vec<u8>,
+    cycles: u8,
+}
+
+// Reruns an instruction from its start. Accesses before `first_live` were
+// made on earlier cycles, so their reads are fed back from `inputs` and
+// their writes skipped. Those up to `last_live` go to the memory and their
+// inputs are logged. Later ones are left out, writes dropped and reads
+// returning 0, and `cut_short` records that the run can't be trusted past
+// `last_live`.
+struct ReplayMemory<'a, M: Memory> {
+    inner: &'a mut M,
+    inputs: &'a mut Vec<u8>,
+    cursor: usize,
+    accesses: u8,
+    first_live: u8,
+    last_live: u8,
+    cut_short: bool,
+}
+
+impl<M: Memory> ReplayMemory<'_, M> {
+    fn input(&mut self, live: impl FnOnce(&mut M) -> u8) -> u8 {
+        let access = self.accesses;
+        self.accesses = self.accesses.saturating_add(1);
+        if access < self.first_live {
+            self.replayed()
+        } else if access <= self.last_live {
+            self.logged(live)
+        } else {
+            self.cut_short = true;
+            0
+        }
+    }
+
+    fn replayed(&mut self) -> u8 {
+        let value = self.inputs[self.cursor];
+        self.cursor += 1;
+        value
+    }
+
+    fn logged(&mut self, live: impl FnOnce(&mut M) -> u8) -> u8 {
+        let value = live(self.inner);
+        self.inputs.push(value);
+        self.cursor += 1;
+        value
+    }
+}
+
+impl<M: Memory> Memory for ReplayMemory<'_, M> {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.input(|inner| inner.read(addr))
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        let access = self.accesses;
+        self.accesses = self.accesses.saturating_add(1);
+        if access > self.last_live {
+            self.cut_short = true;
+        } else if access >= self.first_live {
+            self.inner.write(addr, data);
+        }
+    }
+
+    fn fetch_opcode(&mut self, addr: u16) -> u8 {
+        self.input(|inner| inner.fetch_opcode(addr))
+    }
+
+    fn prg_bank(&self, addr: u16) -> Option<usize> {
+        self.inner.prg_bank(addr)
+    }
+
+    // Checked after the access it follows, so it is live on that access's
+    // cycle and replayed on later ones.
+    fn take_nmi(&mut self) -> bool {
+        if self.cursor < self.inputs.len() {
+            self.replayed() != 0
+        } else if self.cut_short {
+            false
+        } else {
+            self.logged(|inner| inner.take_nmi() as u8) != 0
+        }
+    }
+}
+
+/// Execution breakpoint. With `bank` set it only matches while that PRG
+/// bank (see [`crate::mapper::Mapper::prg_bank`]) is mapped at `addr`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub addr: u16,
+    pub bank: Option<usize>,
+}
+
+impl Breakpoint {
+    pub fn at(addr: u16) -> Self {
+        Breakpoint { addr, bank: None }
+    }
+
+    pub fn in_bank(addr: u16, bank: usize) -> Self {
+        Breakpoint {
+            addr,
+            bank: Some(bank),
+        }
+    }
+
+    fn matches<M: Memory>(&self, pc: u16, memory: &M) -> bool {
+        self.addr == pc
+            && self
+                .bank
+                .is_none_or(|bank| memory.prg_bank(pc) == Some(bank))
+    }
+}
+
+#[derive(Clone)]
 pub struct CPU {
     pub registers: Registers,
     pub vram: [u8; 2048],
     extra_cycles: u8,
     cycles_wait: u8,
-    halted: bool,
+    model: CpuModel,
+    /// Honour the D flag in ADC/SBC like an NMOS 6502. The 2A03 has the BCD
+    /// logic disconnected, so this is off for the NES.
+    decimal_enabled: bool,
+    /// Address of the JAM opcode that halted the CPU, cleared by reset.
+    jammed_at: Option<u16>,
+    nmi_pending: bool,
+    irq_line: bool,
+    /// The I flag as it was before a CLI, SEI or PLP. Those change the flag
+    /// after the interrupt poll, so the next poll still sees the old value.
+    i_flag_delay: Option<bool>,
+    branch_delay: bool,
+    /// Clocks left in which a newly latched NMI takes over the vector fetch
+    /// of the BRK or IRQ sequence in progress. Only [`CPU::clock`] runs a
+    /// sequence over several calls; [`CPU::step`] closes the window.
+    hijack_window: u8,
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    resume_from_break: bool,
+    stop_on_brk: bool,
+    stop: Option<StopReason>,
+    /// The instruction [`CPU::step_cycle`] is part way through, if any.
+    progress: Option<InstructionProgress>,
 }
 
 impl CPU {
     pub fn new() -> Self {
+        Self::with_model(CpuModel::default())
+    }
+
+    pub fn with_model(model: CpuModel) -> Self {
         CPU {
             registers: Registers {
                 a: 0,
@@ -100,34 +504,45 @@ impl CPU {
             vram: [0; 2048],
             extra_cycles: 0,
             cycles_wait: 0,
-            halted: false,
+            model,
+            decimal_enabled: model.has_decimal_mode(),
+            jammed_at: None,
+            nmi_pending: false,
+            irq_line: false,
+            i_flag_delay: None,
+            branch_delay: false,
+            hijack_window: 0,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            resume_from_break: false,
+            stop_on_brk: false,
+            stop: None,
+            progress: None,
         }
     }
 
     pub fn clock<M: Memory>(&mut self, memory: &mut M) -> bool {
-        if self.halted {
+        if self.jammed_at.is_some() {
+            self.stop = Some(StopReason::Halted);
             return false;
         }
 
-        if self.cycles_wait == 0 {
-            let opcode = memory.read(self.registers.pc);
-            self.registers.pc = self.registers.pc.wrapping_add(1);
-
-            if let Some(opcode_info) = CPU_OPCODES.find_by_code(opcode) {
-                self.extra_cycles = 0;
-                self.execute_instruction(
-                    memory,
-                    opcode_info.bytes,
-                    &opcode_info.mnemonic,
-                    &opcode_info.mode,
-                );
-                self.cycles_wait = opcode_info.cycles + self.extra_cycles;
-                self.extra_cycles = 0;
-            } else {
-                panic!("Unknown opcode: {opcode:#04X}");
+        if self.hijack_window > 0 {
+            self.hijack_window -= 1;
+            if self.nmi_pending {
+                self.hijack_window = 0;
+                self.nmi_pending = false;
+                self.registers.pc = memory.read_u16(interrupt::NMI.vector_addr);
             }
         }
 
+        if self.cycles_wait == 0
+            && let Err(reason) = self.begin_next(memory)
+        {
+            self.stop = Some(reason);
+            return false;
+        }
+
         if self.cycles_wait > 0 {
             self.cycles_wait -= 1;
         }
@@ -135,29 +550,316 @@ impl CPU {
         self.cycles_wait == 0
     }
 
-    pub fn nmi<M: Memory>(&mut self, memory: &mut M) {
-        self.interrupt(memory, interrupt::NMI);
+    /// Returns and clears the reason the last [`CPU::clock`] call stopped.
+    pub fn take_stop(&mut self) -> Option<StopReason> {
+        self.stop.take()
     }
 
-    pub fn irq<M: Memory>(&mut self, memory: &mut M) {
-        if !self
-            .registers
-            .status
-            .contains(StatusFlags::INTERRUPT_DISABLE)
-        {
+    pub fn model(&self) -> CpuModel {
+        self.model
+    }
+
+    /// Enables BCD arithmetic for using the core as a generic 6502. The
+    /// default comes from the [`CpuModel`].
+    pub fn set_decimal_enabled(&mut self, enabled: bool) {
+        self.decimal_enabled = enabled;
+    }
+
+    pub fn decimal_enabled(&self) -> bool {
+        self.decimal_enabled
+    }
+
+    /// Where the CPU executed a JAM (KIL/STP) opcode, if it is halted. The
+    /// registers are left as they were so the crash can be inspected; only a
+    /// reset starts the CPU again.
+    pub fn jammed_at(&self) -> Option<u16> {
+        self.jammed_at
+    }
+
+    /// Reports each executed BRK as [`StopReason::Brk`], for programs that
+    /// use it to signal a crash or the end of a test.
+    pub fn set_stop_on_brk(&mut self, enabled: bool) {
+        self.stop_on_brk = enabled;
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, breakpoint: Breakpoint) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|bp| *bp != breakpoint);
+        self.breakpoints.len() != len
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|wp| *wp != watchpoint);
+        self.watchpoints.len() != len
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Copies the machine state from `snapshot` while keeping this CPU's
+    /// breakpoints and watchpoints, which belong to the debugger session.
+    pub(crate) fn restore_from(&mut self, snapshot: &CPU) {
+        let breakpoints = std::mem::take(&mut self.breakpoints);
+        let watchpoints = std::mem::take(&mut self.watchpoints);
+        self.clone_from(snapshot);
+        self.breakpoints = breakpoints;
+        self.watchpoints = watchpoints;
+    }
+
+    /// Latches an NMI edge. It is serviced before the next opcode fetch.
+    pub fn nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+
+    /// Drives the shared, level-triggered /IRQ line. While it is held and the
+    /// I flag is clear, the CPU enters the IRQ handler at every instruction
+    /// boundary.
+    pub fn set_irq_line(&mut self, asserted: bool) {
+        self.irq_line = asserted;
+    }
+
+    /// Runs one whole instruction, or one interrupt entry if an NMI or IRQ is
+    /// pending, and reports how many cycles it took. If the CPU is part way
+    /// through an instruction started by [`CPU::clock`], the remaining cycles
+    /// of that instruction are returned instead. One started by
+    /// [`CPU::step_cycle`] is finished with only its remaining accesses
+    /// made, and reported whole.
+    pub fn step<M: Memory>(&mut self, memory: &mut M) -> StepResult {
+        match self.progress.take() {
+            Some(mut progress) => self.replay(memory, &mut progress, u8::MAX).0,
+            None => self.run_step(memory),
+        }
+    }
+
+    /// Runs one cycle of [`CPU::step`], making at most one memory access,
+    /// and returns its result on the cycle that finishes the instruction or
+    /// interrupt entry. The rest of the machine can be advanced, and the CPU
+    /// cloned, between any two cycles. Until the last cycle the registers
+    /// read as they were before the instruction.
+    ///
+    /// Every cycle reruns the instruction from its start, with the reads it
+    /// made on earlier cycles fed back from a log kept in the CPU, so a
+    /// clone taken part way through carries on exactly where it was.
+    pub fn step_cycle<M: Memory>(&mut self, memory: &mut M) -> Option<StepResult> {
+        let mut progress = self.progress.take().unwrap_or_else(|| InstructionProgress {
+            start: Box::new(self.clone()),
+            inputs: Vec::new(),
+            cycles: 0,
+        });
+        let cycle = progress.cycles;
+        let (result, cut_short) = self.replay(memory, &mut progress, cycle);
+        if !cut_short && cycle + 1 >= result.cycles.max(1) {
+            return Some(result);
+        }
+
+        let vram = self.vram;
+        self.restore_from(&progress.start);
+        self.vram = vram;
+        progress.cycles += 1;
+        self.progress = Some(progress);
+        None
+    }
+
+    /// Cycles [`CPU::step_cycle`] has run of the current instruction, 0
+    /// between instructions.
+    pub fn instruction_cycle(&self) -> u8 {
+        self.progress.as_ref().map_or(0, |progress| progress.cycles)
+    }
+
+    // Runs the instruction in `progress` from its start, which the CPU is
+    // at, making its accesses from `progress.cycles` to `last_live` for
+    // real. Also reports whether it had accesses past `last_live`.
+    fn replay<M: Memory>(
+        &mut self,
+        memory: &mut M,
+        progress: &mut InstructionProgress,
+        last_live: u8,
+    ) -> (StepResult, bool) {
+        let mut replay = ReplayMemory {
+            inner: memory,
+            inputs: &mut progress.inputs,
+            cursor: 0,
+            accesses: 0,
+            first_live: progress.cycles,
+            last_live,
+            cut_short: false,
+        };
+        let result = self.run_step(&mut replay);
+        (result, replay.cut_short)
+    }
+
+    fn run_step<M: Memory>(&mut self, memory: &mut M) -> StepResult {
+        let stopped = |reason| StepResult {
+            cycles: 0,
+            interrupt: None,
+            stop: Some(reason),
+            branch_delay: false,
+        };
+
+        if self.jammed_at.is_some() {
+            return stopped(StopReason::Halted);
+        }
+
+        let interrupt = if self.cycles_wait == 0 {
+            match self.begin_next(memory) {
+                Ok(interrupt) => interrupt,
+                Err(reason) => return stopped(reason),
+            }
+        } else {
+            None
+        };
+
+        // The whole sequence has run, so only `Memory::take_nmi` could have
+        // hijacked a BRK or IRQ; a later NMI is taken on its own.
+        self.hijack_window = 0;
+
+        let halted = self.jammed_at.map(|_| StopReason::Halted);
+        StepResult {
+            cycles: std::mem::take(&mut self.cycles_wait),
+            interrupt,
+            stop: self.stop.take().or(halted),
+            branch_delay: std::mem::take(&mut self.branch_delay),
+        }
+    }
+
+    // Runs `start_next`, routing its memory accesses past the watchpoints if
+    // there are any. A hit is left in `stop` for the caller to report.
+    fn begin_next<M: Memory>(
+        &mut self,
+        memory: &mut M,
+    ) -> Result<Option<InterruptType>, StopReason> {
+        if self.watchpoints.is_empty() {
+            return self.start_next(memory);
+        }
+
+        let watchpoints = std::mem::take(&mut self.watchpoints);
+        let mut watched = WatchedMemory {
+            inner: memory,
+            watchpoints: &watchpoints,
+            pc: self.registers.pc,
+            hit: None,
+        };
+        let result = self.start_next(&mut watched);
+        if let Some(hit) = watched.hit {
+            self.stop = Some(StopReason::Watchpoint(hit));
+        }
+        self.watchpoints = watchpoints;
+        result
+    }
+
+    // Starts the next instruction at a boundary and loads `cycles_wait` with
+    // its length. A pending interrupt sequence replaces the opcode fetch; a
+    // breakpoint on the fetch address stops before anything is executed.
+    fn start_next<M: Memory>(
+        &mut self,
+        memory: &mut M,
+    ) -> Result<Option<InterruptType>, StopReason> {
+        let resuming = std::mem::take(&mut self.resume_from_break);
+
+        if let Some(interrupt) = self.poll_interrupts(memory) {
+            return Ok(Some(interrupt));
+        }
+
+        let pc = self.registers.pc;
+        if !resuming && self.breakpoints.iter().any(|bp| bp.matches(pc, memory)) {
+            self.resume_from_break = true;
+            return Err(StopReason::Breakpoint(pc));
+        }
+
+        let opcode = memory.fetch_opcode(self.registers.pc);
+        self.registers.pc = self.registers.pc.wrapping_add(1);
+
+        if let Some(opcode_info) = self.model.opcodes().find_by_code(opcode) {
+            let mnemonic = &opcode_info.mnemonic;
+            let i_flag = self.interrupts_disabled();
+            self.extra_cycles = 0;
+            self.execute_instruction(memory, mnemonic, &opcode_info.mode);
+            self.cycles_wait = opcode_info.cycles + self.extra_cycles;
+            // Branches add one cycle when taken and one more on a page cross.
+            self.branch_delay = self.extra_cycles == 1
+                && matches!(
+                    mnemonic,
+                    Mnemonic::BCC
+                        | Mnemonic::BCS
+                        | Mnemonic::BEQ
+                        | Mnemonic::BMI
+                        | Mnemonic::BNE
+                        | Mnemonic::BPL
+                        | Mnemonic::BVC
+                        | Mnemonic::BVS
+                        | Mnemonic::BRA
+                );
+            if matches!(mnemonic, Mnemonic::CLI | Mnemonic::SEI | Mnemonic::PLP) {
+                self.i_flag_delay = Some(i_flag);
+            }
+            if self.stop_on_brk && *mnemonic == Mnemonic::BRK {
+                self.stop = Some(StopReason::Brk(pc));
+            }
+            self.extra_cycles = 0;
+        } else {
+            log::error!("Unknown opcode {opcode:#04X} at {pc:#06X}, halting");
+            self.jammed_at = Some(pc);
+            return Err(StopReason::InvalidOpcode { pc, byte: opcode });
+        }
+
+        Ok(None)
+    }
+
+    fn poll_interrupts<M: Memory>(&mut self, memory: &mut M) -> Option<InterruptType> {
+        let masked = self
+            .i_flag_delay
+            .take()
+            .unwrap_or_else(|| self.interrupts_disabled());
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            self.interrupt(memory, interrupt::NMI);
+            Some(InterruptType::NMI)
+        } else if self.irq_line && !masked {
             self.interrupt(memory, interrupt::IRQ);
+            Some(InterruptType::IRQ)
+        } else {
+            None
         }
     }
 
+    fn interrupts_disabled(&self) -> bool {
+        self.registers
+            .status
+            .contains(StatusFlags::INTERRUPT_DISABLE)
+    }
+
     fn execute_instruction<M: Memory>(
         &mut self,
         memory: &mut M,
-        bytes: u8,
         mnemonic: &Mnemonic,
         mode: &AddressingMode,
     ) {
-        let pc_state = self.registers.pc;
-
         match mnemonic {
             Mnemonic::ADC => self.adc(memory, mode),
             Mnemonic::AND => self.and(memory, mode),
@@ -169,6 +871,7 @@ impl CPU {
             Mnemonic::BMI => self.bmi(memory, mode),
             Mnemonic::BNE => self.bne(memory, mode),
             Mnemonic::BPL => self.bpl(memory, mode),
+            Mnemonic::BRA => self.bra(memory, mode),
             Mnemonic::BRK => self.brk(memory, mode),
             Mnemonic::BVC => self.bvc(memory, mode),
             Mnemonic::BVS => self.bvs(memory, mode),
@@ -196,8 +899,12 @@ impl CPU {
             Mnemonic::ORA => self.ora(memory, mode),
             Mnemonic::PHA => self.pha(memory),
             Mnemonic::PHP => self.php(memory),
+            Mnemonic::PHX => self.push_stack(memory, self.registers.x),
+            Mnemonic::PHY => self.push_stack(memory, self.registers.y),
             Mnemonic::PLA => self.pla(memory),
             Mnemonic::PLP => self.plp(memory),
+            Mnemonic::PLX => self.plx(memory),
+            Mnemonic::PLY => self.ply(memory),
             Mnemonic::ROL => self.rol(memory, mode),
             Mnemonic::ROR => self.ror(memory, mode),
             Mnemonic::RTI => self.rti(memory),
@@ -209,8 +916,11 @@ impl CPU {
             Mnemonic::STA => self.sta(memory, mode),
             Mnemonic::STX => self.stx(memory, mode),
             Mnemonic::STY => self.sty(memory, mode),
+            Mnemonic::STZ => self.stz(memory, mode),
             Mnemonic::TAX => self.tax(),
             Mnemonic::TAY => self.tay(),
+            Mnemonic::TRB => self.trb(memory, mode),
+            Mnemonic::TSB => self.tsb(memory, mode),
             Mnemonic::TSX => self.tsx(),
             Mnemonic::TXA => self.txa(),
             Mnemonic::TXS => self.txs(),
@@ -236,27 +946,69 @@ impl CPU {
             Mnemonic::TAS => self.tas(memory, mode),
             Mnemonic::XAA => self.xaa(memory, mode),
         }
-
-        if pc_state == self.registers.pc {
-            self.registers.pc = self
-                .registers
-                .pc
-                .wrapping_add(bytes.saturating_sub(1) as u16);
-        }
     }
 
-    pub fn reset<M: Memory>(&mut self, memory: &mut M) {
-        self.registers.a = 0;
-        self.registers.x = 0;
-        self.registers.y = 0;
-
-        self.registers.status = StatusFlags::empty();
-        self.registers.status.insert(StatusFlags::INTERRUPT_DISABLE);
+    /// Writes `program` into `memory` and powers on at its reset vector.
+    /// Vectors left unset keep whatever the segments or the memory already
+    /// hold there. Nothing is written if a segment runs past $FFFF.
+    pub fn load<M: Memory>(&mut self, memory: &mut M, program: &RawProgram) -> Result<(), String> {
+        let blocks = std::iter::once((0, &program.ram)).chain(
+            program
+                .segments
+                .iter()
+                .map(|(start, bytes)| (*start, bytes)),
+        );
+        for (start, bytes) in blocks.clone() {
+            if start as usize + bytes.len() > 0x10000 {
+                return Err(format!(
+                    "{} bytes at ${:04X} run past $FFFF",
+                    bytes.len(),
+                    start
+                ));
+            }
+        }
 
-        self.registers.sp = 0xFD;
+        for (start, bytes) in blocks {
+            memory.write_slice(start, bytes);
+        }
+        for (addr, vector) in [
+            (0xFFFA, program.nmi_vector),
+            (0xFFFC, program.reset_vector),
+            (0xFFFE, program.irq_vector),
+        ] {
+            if let Some(vector) = vector {
+                memory.write_u16(addr, vector);
+            }
+        }
+        self.reset(memory, ResetKind::PowerOn);
+        Ok(())
+    }
+
+    pub fn reset<M: Memory>(&mut self, memory: &mut M, kind: ResetKind) {
+        match kind {
+            ResetKind::PowerOn => {
+                self.registers.a = 0;
+                self.registers.x = 0;
+                self.registers.y = 0;
+                self.registers.status = StatusFlags::from_bits_truncate(0x34);
+                self.registers.sp = 0xFD;
+            }
+            ResetKind::Reset => {
+                self.registers.status.insert(StatusFlags::INTERRUPT_DISABLE);
+                self.registers.sp = self.registers.sp.wrapping_sub(3);
+            }
+        }
 
         self.registers.pc = memory.read_u16(0xFFFC);
-        self.halted = false;
+        self.jammed_at = None;
+        self.cycles_wait = 0;
+        self.nmi_pending = false;
+        self.i_flag_delay = None;
+        self.branch_delay = false;
+        self.hijack_window = 0;
+        self.resume_from_break = false;
+        self.stop = None;
+        self.progress = None;
     }
 }
 
@@ -273,6 +1025,10 @@ impl CPU {
     }
 
     fn adc_value(&mut self, value: u8) {
+        if self.decimal_active() {
+            return self.adc_decimal(value);
+        }
+
         let sum = self.registers.a as u16
             + value as u16
             + if self.registers.status.contains(StatusFlags::CARRY) {
@@ -294,6 +1050,38 @@ impl CPU {
         self.update_zero_and_negative_flags(self.registers.a);
     }
 
+    fn decimal_active(&self) -> bool {
+        self.decimal_enabled && self.registers.status.contains(StatusFlags::DECIMAL_MODE)
+    }
+
+    // NMOS 6502 BCD addition: Z reflects the binary sum, N and V the sum
+    // after the low digit is adjusted but before the high digit is.
+    fn adc_decimal(&mut self, value: u8) {
+        let a = self.registers.a;
+        let carry_in = self.registers.status.contains(StatusFlags::CARRY) as u16;
+
+        let binary = a.wrapping_add(value).wrapping_add(carry_in as u8);
+        self.registers.status.set(StatusFlags::ZERO, binary == 0);
+
+        let mut lo = (a & 0x0F) as u16 + (value & 0x0F) as u16 + carry_in;
+        if lo >= 0x0A {
+            lo = ((lo + 0x06) & 0x0F) + 0x10;
+        }
+        let mut sum = (a & 0xF0) as u16 + (value & 0xF0) as u16 + lo;
+        self.registers
+            .status
+            .set(StatusFlags::NEGATIVE, sum & 0x80 != 0);
+        self.registers.status.set(
+            StatusFlags::OVERFLOW,
+            ((a ^ sum as u8) & (value ^ sum as u8) & 0x80) != 0,
+        );
+        if sum >= 0xA0 {
+            sum += 0x60;
+        }
+        self.registers.status.set(StatusFlags::CARRY, sum > 0xFF);
+        self.registers.a = sum as u8;
+    }
+
     fn and<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
         let (addr, page_cross) = self.get_operand_address(memory, mode);
         if page_cross {
@@ -319,50 +1107,57 @@ impl CPU {
             return;
         }
 
-        let (addr, _) = self.get_operand_address(memory, mode);
-        let mut value = memory.read(addr);
-
-        self.registers
-            .status
-            .set(StatusFlags::CARRY, value & 0b1000_0000 != 0);
-
-        value <<= 1;
-        memory.write(addr, value);
+        let value = self.read_modify_write(memory, mode, |cpu, value| {
+            cpu.registers
+                .status
+                .set(StatusFlags::CARRY, value & 0b1000_0000 != 0);
+            value << 1
+        });
         self.update_zero_and_negative_flags(value);
     }
 
     fn bcc<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
-        let base_pc = self.registers.pc + 1;
         let (addr, _) = self.get_operand_address(memory, mode);
+        let base_pc = self.registers.pc;
         if !self.registers.status.contains(StatusFlags::CARRY) {
             self.registers.pc = addr;
             self.extra_cycles += 1;
             if (base_pc & 0xFF00) != (addr & 0xFF00) {
-                self.extra_cycles += 2;
+                self.extra_cycles += 1;
             }
         }
     }
 
+    fn bra<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
+        let (addr, _) = self.get_operand_address(memory, mode);
+        let base_pc = self.registers.pc;
+        self.registers.pc = addr;
+        self.extra_cycles += 1;
+        if (base_pc & 0xFF00) != (addr & 0xFF00) {
+            self.extra_cycles += 1;
+        }
+    }
+
     fn bcs<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
-        let base_pc = self.registers.pc + 1;
         let (addr, _) = self.get_operand_address(memory, mode);
+        let base_pc = self.registers.pc;
         if self.registers.status.contains(StatusFlags::CARRY) {
             self.registers.pc = addr;
             self.extra_cycles += 1;
             if (base_pc & 0xFF00) != (addr & 0xFF00) {
-                self.extra_cycles += 2;
+                self.extra_cycles += 1;
             }
         }
     }
 
     fn beq<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
-        let base_pc = self.registers.pc + 1;
         let (addr, _) = self.get_operand_address(memory, mode);
+        let base_pc = self.registers.pc;
         if self.registers.status.contains(StatusFlags::ZERO) {
             self.registers.pc = addr;
             self.extra_cycles += 1;
             if (base_pc & 0xFF00) != (addr & 0xFF00) {
-                self.extra_cycles += 2;
+                self.extra_cycles += 1;
             }
         }
     }
@@ -393,72 +1188,67 @@ impl CPU {
     }
 
     fn bmi<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
-        let base_pc = self.registers.pc + 1;
         let (addr, _) = self.get_operand_address(memory, mode);
+        let base_pc = self.registers.pc;
         if self.registers.status.contains(StatusFlags::NEGATIVE) {
             self.registers.pc = addr;
             self.extra_cycles += 1;
             if (base_pc & 0xFF00) != (addr & 0xFF00) {
-                self.extra_cycles += 2;
+                self.extra_cycles += 1;
             }
         }
     }
 
     fn bne<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
-        let base_pc = self.registers.pc + 1;
         let (addr, _) = self.get_operand_address(memory, mode);
+        let base_pc = self.registers.pc;
         if !self.registers.status.contains(StatusFlags::ZERO) {
             self.registers.pc = addr;
             self.extra_cycles += 1;
             if (base_pc & 0xFF00) != (addr & 0xFF00) {
-                self.extra_cycles += 2;
+                self.extra_cycles += 1;
             }
         }
     }
 
     fn bpl<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
-        let base_pc = self.registers.pc + 1;
         let (addr, _) = self.get_operand_address(memory, mode);
+        let base_pc = self.registers.pc;
         if !self.registers.status.contains(StatusFlags::NEGATIVE) {
             self.registers.pc = addr;
             self.extra_cycles += 1;
             if (base_pc & 0xFF00) != (addr & 0xFF00) {
-                self.extra_cycles += 2;
+                self.extra_cycles += 1;
             }
         }
     }
 
     fn brk<M: Memory>(&mut self, memory: &mut M, _mode: &AddressingMode) {
-        self.registers.pc += 1;
-        if !self
-            .registers
-            .status
-            .contains(StatusFlags::INTERRUPT_DISABLE)
-        {
-            self.interrupt(memory, interrupt::BRK);
-        }
+        // BRK skips its padding byte and cannot be masked by I.
+        self.registers.pc = self.registers.pc.wrapping_add(1);
+        self.interrupt(memory, interrupt::BRK);
     }
 
     fn bvc<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
-        let base_pc = self.registers.pc + 1;
         let (addr, _) = self.get_operand_address(memory, mode);
+        let base_pc = self.registers.pc;
         if !self.registers.status.contains(StatusFlags::OVERFLOW) {
             self.registers.pc = addr;
             self.extra_cycles += 1;
             if (base_pc & 0xFF00) != (addr & 0xFF00) {
-                self.extra_cycles += 2;
+                self.extra_cycles += 1;
             }
         }
     }
 
     fn bvs<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
-        let base_pc = self.registers.pc + 1;
         let (addr, _) = self.get_operand_address(memory, mode);
+        let base_pc = self.registers.pc;
         if self.registers.status.contains(StatusFlags::OVERFLOW) {
             self.registers.pc = addr;
             self.extra_cycles += 1;
             if (base_pc & 0xFF00) != (addr & 0xFF00) {
-                self.extra_cycles += 2;
+                self.extra_cycles += 1;
             }
         }
     }
@@ -528,11 +1318,7 @@ impl CPU {
     }
 
     fn dec<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
-        let (addr, _) = self.get_operand_address(memory, mode);
-        let mut value = memory.read(addr);
-
-        value = value.wrapping_sub(1);
-        memory.write(addr, value);
+        let value = self.read_modify_write(memory, mode, |_, value| value.wrapping_sub(1));
         self.update_zero_and_negative_flags(value);
     }
 
@@ -558,11 +1344,7 @@ impl CPU {
     }
 
     fn inc<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
-        let (addr, _) = self.get_operand_address(memory, mode);
-        let mut value = memory.read(addr);
-
-        value = value.wrapping_add(1);
-        memory.write(addr, value);
+        let value = self.read_modify_write(memory, mode, |_, value| value.wrapping_add(1));
         self.update_zero_and_negative_flags(value);
     }
 
@@ -585,7 +1367,8 @@ impl CPU {
     fn jsr<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
         let (addr, _) = self.get_operand_address(memory, mode);
 
-        let return_addr = self.registers.pc.wrapping_add(2).wrapping_sub(1);
+        // The return address pushed is that of JSR's last byte.
+        let return_addr = self.registers.pc.wrapping_sub(1);
 
         self.push_stack_u16(memory, return_addr);
 
@@ -645,17 +1428,12 @@ impl CPU {
             return;
         }
 
-        let (addr, _) = self.get_operand_address(memory, mode);
-        let mut value = memory.read(addr);
-
-        if value & 0b0000_0001 != 0 {
-            self.registers.status.insert(StatusFlags::CARRY); // Set carry flag
-        } else {
-            self.registers.status.remove(StatusFlags::CARRY); // Clear carry flag
-        }
-
-        value >>= 1;
-        memory.write(addr, value);
+        let value = self.read_modify_write(memory, mode, |cpu, value| {
+            cpu.registers
+                .status
+                .set(StatusFlags::CARRY, value & 0b0000_0001 != 0);
+            value >> 1
+        });
         self.update_zero_and_negative_flags(value);
     }
 
@@ -699,6 +1477,16 @@ impl CPU {
         self.update_zero_and_negative_flags(self.registers.a);
     }
 
+    fn plx<M: Memory>(&mut self, memory: &mut M) {
+        self.registers.x = self.pull_stack(memory);
+        self.update_zero_and_negative_flags(self.registers.x);
+    }
+
+    fn ply<M: Memory>(&mut self, memory: &mut M) {
+        self.registers.y = self.pull_stack(memory);
+        self.update_zero_and_negative_flags(self.registers.y);
+    }
+
     fn plp<M: Memory>(&mut self, memory: &mut M) {
         self.registers.sp = self.registers.sp.wrapping_add(1);
         let sp_addr = self.stack_addr();
@@ -726,20 +1514,17 @@ impl CPU {
             return;
         }
 
-        let (addr, _) = self.get_operand_address(memory, mode);
-        let mut value = memory.read(addr);
         let carry_in = if self.registers.status.contains(StatusFlags::CARRY) {
             1
         } else {
             0
         };
-
-        self.registers
-            .status
-            .set(StatusFlags::CARRY, value & 0b1000_0000 != 0);
-
-        value = (value << 1) | carry_in;
-        memory.write(addr, value);
+        let value = self.read_modify_write(memory, mode, |cpu, value| {
+            cpu.registers
+                .status
+                .set(StatusFlags::CARRY, value & 0b1000_0000 != 0);
+            (value << 1) | carry_in
+        });
         self.update_zero_and_negative_flags(value);
     }
 
@@ -762,30 +1547,22 @@ impl CPU {
             return;
         }
 
-        let (addr, _) = self.get_operand_address(memory, mode);
-        let mut value = memory.read(addr);
         let carry_in = if self.registers.status.contains(StatusFlags::CARRY) {
             0b1000_0000
         } else {
             0
         };
-
-        self.registers
-            .status
-            .set(StatusFlags::CARRY, value & 0b0000_0001 != 0);
-
-        value = (value >> 1) | carry_in;
-        memory.write(addr, value);
+        let value = self.read_modify_write(memory, mode, |cpu, value| {
+            cpu.registers
+                .status
+                .set(StatusFlags::CARRY, value & 0b0000_0001 != 0);
+            (value >> 1) | carry_in
+        });
         self.update_zero_and_negative_flags(value);
     }
 
     fn rti<M: Memory>(&mut self, memory: &mut M) {
-        let status = self.pull_stack(memory);
-        self.registers.status = StatusFlags::from_bits_truncate(status);
-        self.registers.status.remove(StatusFlags::BREAK_COMMAND);
-        self.registers.status.insert(StatusFlags::UNUSED);
-
-        self.registers.pc = self.pull_stack_u16(memory);
+        self.return_from_interrupt(memory);
     }
 
     fn rts<M: Memory>(&mut self, memory: &mut M) {
@@ -804,6 +1581,25 @@ impl CPU {
     }
 
     fn sbc_value(&mut self, value: u8) {
+        let a = self.registers.a;
+        let borrow_in = !self.registers.status.contains(StatusFlags::CARRY) as i16;
+        self.sbc_binary(value);
+
+        // NMOS flags come from the binary subtraction; only A is adjusted.
+        if self.decimal_active() {
+            let mut lo = (a & 0x0F) as i16 - (value & 0x0F) as i16 - borrow_in;
+            if lo < 0 {
+                lo = ((lo - 0x06) & 0x0F) - 0x10;
+            }
+            let mut result = (a & 0xF0) as i16 - (value & 0xF0) as i16 + lo;
+            if result < 0 {
+                result -= 0x60;
+            }
+            self.registers.a = result as u8;
+        }
+    }
+
+    fn sbc_binary(&mut self, value: u8) {
         let carry = if self.registers.status.contains(StatusFlags::CARRY) {
             0
         } else {
@@ -840,20 +1636,47 @@ impl CPU {
     }
 
     fn sta<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
-        let (addr, _) = self.get_operand_address(memory, mode);
+        let addr = self.get_write_address(memory, mode);
         memory.write(addr, self.registers.a);
     }
 
     fn stx<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
-        let (addr, _) = self.get_operand_address(memory, mode);
+        let addr = self.get_write_address(memory, mode);
         memory.write(addr, self.registers.x);
     }
 
     fn sty<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
-        let (addr, _) = self.get_operand_address(memory, mode);
+        let addr = self.get_write_address(memory, mode);
         memory.write(addr, self.registers.y);
     }
 
+    fn stz<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
+        let addr = self.get_write_address(memory, mode);
+        memory.write(addr, 0);
+    }
+
+    // TSB and TRB set Z from A & M, then set or clear A's bits in M. The
+    // 65C02 re-reads rather than writing the old value back.
+    fn tsb<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
+        let addr = self.get_write_address(memory, mode);
+        let value = memory.read(addr);
+        memory.read(addr);
+        self.registers
+            .status
+            .set(StatusFlags::ZERO, value & self.registers.a == 0);
+        memory.write(addr, value | self.registers.a);
+    }
+
+    fn trb<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
+        let addr = self.get_write_address(memory, mode);
+        let value = memory.read(addr);
+        memory.read(addr);
+        self.registers
+            .status
+            .set(StatusFlags::ZERO, value & self.registers.a == 0);
+        memory.write(addr, value & !self.registers.a);
+    }
+
     fn tax(&mut self) {
         self.registers.x = self.registers.a;
         self.update_zero_and_negative_flags(self.registers.x);
@@ -941,66 +1764,60 @@ impl CPU {
     }
 
     fn slo<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
-        let (addr, _) = self.get_operand_address(memory, mode);
-        let mut value = memory.read(addr);
-        self.registers
-            .status
-            .set(StatusFlags::CARRY, (value & 0x80) != 0);
-        value <<= 1;
-        memory.write(addr, value);
+        let value = self.read_modify_write(memory, mode, |cpu, value| {
+            cpu.registers
+                .status
+                .set(StatusFlags::CARRY, (value & 0x80) != 0);
+            value << 1
+        });
         self.registers.a |= value;
         self.update_zero_and_negative_flags(self.registers.a);
     }
 
     fn rla<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
-        let (addr, _) = self.get_operand_address(memory, mode);
-        let mut value = memory.read(addr);
         let carry_in = if self.registers.status.contains(StatusFlags::CARRY) {
             1
         } else {
             0
         };
-        self.registers
-            .status
-            .set(StatusFlags::CARRY, (value & 0x80) != 0);
-        value = (value << 1) | carry_in;
-        memory.write(addr, value);
+        let value = self.read_modify_write(memory, mode, |cpu, value| {
+            cpu.registers
+                .status
+                .set(StatusFlags::CARRY, (value & 0x80) != 0);
+            (value << 1) | carry_in
+        });
         self.registers.a &= value;
         self.update_zero_and_negative_flags(self.registers.a);
     }
 
     fn sre<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
-        let (addr, _) = self.get_operand_address(memory, mode);
-        let mut value = memory.read(addr);
-        self.registers
-            .status
-            .set(StatusFlags::CARRY, (value & 0x01) != 0);
-        value >>= 1;
-        memory.write(addr, value);
+        let value = self.read_modify_write(memory, mode, |cpu, value| {
+            cpu.registers
+                .status
+                .set(StatusFlags::CARRY, (value & 0x01) != 0);
+            value >> 1
+        });
         self.registers.a ^= value;
         self.update_zero_and_negative_flags(self.registers.a);
     }
 
     fn rra<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
-        let (addr, _) = self.get_operand_address(memory, mode);
-        let mut value = memory.read(addr);
         let carry_in = if self.registers.status.contains(StatusFlags::CARRY) {
             0x80
         } else {
             0
         };
-        self.registers
-            .status
-            .set(StatusFlags::CARRY, (value & 0x01) != 0);
-        value = (value >> 1) | carry_in;
-        memory.write(addr, value);
+        let value = self.read_modify_write(memory, mode, |cpu, value| {
+            cpu.registers
+                .status
+                .set(StatusFlags::CARRY, (value & 0x01) != 0);
+            (value >> 1) | carry_in
+        });
         self.adc_value(value);
     }
 
     fn dcp<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
-        let (addr, _) = self.get_operand_address(memory, mode);
-        let value = memory.read(addr).wrapping_sub(1);
-        memory.write(addr, value);
+        let value = self.read_modify_write(memory, mode, |_, value| value.wrapping_sub(1));
         self.registers
             .status
             .set(StatusFlags::CARRY, self.registers.a >= value);
@@ -1009,14 +1826,12 @@ impl CPU {
     }
 
     fn isc<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
-        let (addr, _) = self.get_operand_address(memory, mode);
-        let value = memory.read(addr).wrapping_add(1);
-        memory.write(addr, value);
+        let value = self.read_modify_write(memory, mode, |_, value| value.wrapping_add(1));
         self.sbc_value(value);
     }
 
     fn sax<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
-        let (addr, _) = self.get_operand_address(memory, mode);
+        let addr = self.get_write_address(memory, mode);
         let value = self.registers.a & self.registers.x;
         memory.write(addr, value);
     }
@@ -1054,21 +1869,21 @@ impl CPU {
     }
 
     fn ahx<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
-        let (addr, _) = self.get_operand_address(memory, mode);
+        let addr = self.get_write_address(memory, mode);
         let high = ((addr >> 8) as u8).wrapping_add(1);
         let value = self.registers.a & self.registers.x & high;
         memory.write(addr, value);
     }
 
     fn shy<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
-        let (addr, _) = self.get_operand_address(memory, mode);
+        let addr = self.get_write_address(memory, mode);
         let high = ((addr >> 8) as u8).wrapping_add(1);
         let value = self.registers.y & high;
         memory.write(addr, value);
     }
 
     fn shx<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
-        let (addr, _) = self.get_operand_address(memory, mode);
+        let addr = self.get_write_address(memory, mode);
         let high = ((addr >> 8) as u8).wrapping_add(1);
         let value = self.registers.x & high;
         memory.write(addr, value);
@@ -1077,7 +1892,7 @@ impl CPU {
     fn tas<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) {
         let mut masked = self.registers.a & self.registers.x;
         self.registers.sp = masked;
-        let (addr, _) = self.get_operand_address(memory, mode);
+        let addr = self.get_write_address(memory, mode);
         let high = ((addr >> 8) as u8).wrapping_add(1);
         masked &= high;
         memory.write(addr, masked);
@@ -1093,7 +1908,7 @@ impl CPU {
     }
 
     fn stp(&mut self) {
-        self.halted = true;
+        self.jammed_at = Some(self.registers.pc.wrapping_sub(1));
     }
 
     fn update_zero_and_negative_flags(&mut self, value: u8) {
@@ -1110,52 +1925,90 @@ impl CPU {
         }
     }
 
+    /// Effective address for an instruction that reads its operand, and
+    /// whether indexing crossed a page. A page cross costs the dummy read
+    /// from the unfixed address that the hardware makes. The operand bytes
+    /// are consumed, leaving PC on the next instruction.
     pub fn get_operand_address<M: Memory>(
         &mut self,
         memory: &mut M,
         mode: &AddressingMode,
+    ) -> (u16, bool) {
+        self.operand_address(memory, mode, false)
+    }
+
+    // Stores and read-modify-write instructions always make the dummy read on
+    // indexed modes, page cross or not.
+    fn get_write_address<M: Memory>(&mut self, memory: &mut M, mode: &AddressingMode) -> u16 {
+        self.operand_address(memory, mode, true).0
+    }
+
+    // Read, write back the unmodified value, then write the result, as the
+    // 6502 does. The extra write is visible to mappers and PPU registers.
+    fn read_modify_write<M: Memory>(
+        &mut self,
+        memory: &mut M,
+        mode: &AddressingMode,
+        modify: impl FnOnce(&mut Self, u8) -> u8,
+    ) -> u8 {
+        let addr = self.get_write_address(memory, mode);
+        let value = memory.read(addr);
+        memory.write(addr, value);
+        let result = modify(self, value);
+        memory.write(addr, result);
+        result
+    }
+
+    fn operand_address<M: Memory>(
+        &mut self,
+        memory: &mut M,
+        mode: &AddressingMode,
+        always_dummy_read: bool,
     ) -> (u16, bool) {
         match mode {
-            AddressingMode::Immediate => (self.registers.pc, false),
+            AddressingMode::Immediate => {
+                let addr = self.registers.pc;
+                self.registers.pc = addr.wrapping_add(1);
+                (addr, false)
+            }
 
-            AddressingMode::ZeroPage => (memory.read(self.registers.pc) as u16, false),
+            AddressingMode::ZeroPage => (self.fetch_operand(memory) as u16, false),
 
-            AddressingMode::Absolute => (memory.read_u16(self.registers.pc), false),
+            AddressingMode::Absolute => (self.fetch_operand_u16(memory), false),
 
             AddressingMode::ZeroPageX => {
-                let pos = memory.read(self.registers.pc);
+                let pos = self.fetch_operand(memory);
                 let addr = pos.wrapping_add(self.registers.x) as u16;
                 (addr, false)
             }
             AddressingMode::ZeroPageY => {
-                let pos = memory.read(self.registers.pc);
+                let pos = self.fetch_operand(memory);
                 let addr = pos.wrapping_add(self.registers.y) as u16;
                 (addr, false)
             }
 
             AddressingMode::Relative => {
-                let offset = memory.read(self.registers.pc) as i8;
-                let next = self.registers.pc.wrapping_add(1) as i32;
-                ((next + offset as i32) as u16, false)
+                let offset = self.fetch_operand(memory) as i8;
+                (self.registers.pc.wrapping_add(offset as u16), false)
             }
 
             AddressingMode::AbsoluteX => {
-                let base = memory.read_u16(self.registers.pc);
-                let addr = base.wrapping_add(self.registers.x as u16);
-                let page_cross = (base & 0xFF00) != (addr & 0xFF00);
-                (addr, page_cross)
+                let base = self.fetch_operand_u16(memory);
+                let index = self.registers.x;
+                Self::indexed(memory, base, index, always_dummy_read)
             }
             AddressingMode::AbsoluteY => {
-                let base = memory.read_u16(self.registers.pc);
-                let addr = base.wrapping_add(self.registers.y as u16);
-                let page_cross = (base & 0xFF00) != (addr & 0xFF00);
-                (addr, page_cross)
+                let base = self.fetch_operand_u16(memory);
+                let index = self.registers.y;
+                Self::indexed(memory, base, index, always_dummy_read)
             }
 
             AddressingMode::Indirect => {
-                let addr = memory.read_u16(self.registers.pc);
+                let addr = self.fetch_operand_u16(memory);
 
-                let indirect_ref = if addr & 0x00FF == 0x00FF {
+                // The NMOS part doesn't carry into the high byte of the
+                // pointer; the 65C02 fixed that.
+                let indirect_ref = if addr & 0x00FF == 0x00FF && self.model != CpuModel::Wdc65C02 {
                     let lo = memory.read(addr);
                     let hi = memory.read(addr & 0xFF00);
                     (hi as u16) << 8 | (lo as u16)
@@ -1165,7 +2018,7 @@ impl CPU {
                 (indirect_ref, false)
             }
             AddressingMode::IndirectX => {
-                let base = memory.read(self.registers.pc);
+                let base = self.fetch_operand(memory);
 
                 let ptr: u8 = base.wrapping_add(self.registers.x);
                 let lo = memory.read(ptr as u16);
@@ -1173,22 +2026,56 @@ impl CPU {
                 ((hi as u16) << 8 | (lo as u16), false)
             }
             AddressingMode::IndirectY => {
-                let base = memory.read(self.registers.pc);
+                let base = self.fetch_operand(memory);
 
                 let lo = memory.read(base as u16);
                 let hi = memory.read(base.wrapping_add(1) as u16);
                 let deref_base = (hi as u16) << 8 | (lo as u16);
-                let deref = deref_base.wrapping_add(self.registers.y as u16);
-                let page_cross = (deref_base & 0xFF00) != (deref & 0xFF00);
-                (deref, page_cross)
+                Self::indexed(memory, deref_base, self.registers.y, always_dummy_read)
+            }
+
+            AddressingMode::ZeroPageIndirect => {
+                let base = self.fetch_operand(memory);
+                let lo = memory.read(base as u16);
+                let hi = memory.read(base.wrapping_add(1) as u16);
+                ((hi as u16) << 8 | (lo as u16), false)
             }
 
             AddressingMode::None | AddressingMode::Accumulator => {
-                // dummy read
-                (memory.read(self.registers.pc + 1) as u16, false)
+                // Dummy read of the byte after the opcode, which isn't
+                // consumed.
+                (memory.read(self.registers.pc) as u16, false)
             }
         }
     }
+
+    fn fetch_operand<M: Memory>(&mut self, memory: &mut M) -> u8 {
+        let value = memory.read(self.registers.pc);
+        self.registers.pc = self.registers.pc.wrapping_add(1);
+        value
+    }
+
+    fn fetch_operand_u16<M: Memory>(&mut self, memory: &mut M) -> u16 {
+        let lo = self.fetch_operand(memory) as u16;
+        let hi = self.fetch_operand(memory) as u16;
+        (hi << 8) | lo
+    }
+
+    // Adds the index to the low byte first; the 6502 reads from that
+    // not-yet-carried address before fixing up the high byte.
+    fn indexed<M: Memory>(
+        memory: &mut M,
+        base: u16,
+        index: u8,
+        always_dummy_read: bool,
+    ) -> (u16, bool) {
+        let addr = base.wrapping_add(index as u16);
+        let page_cross = (base & 0xFF00) != (addr & 0xFF00);
+        if page_cross || always_dummy_read {
+            memory.read((base & 0xFF00) | (addr & 0x00FF));
+        }
+        (addr, page_cross)
+    }
 }
 
 /// Helpers
@@ -1223,14 +2110,793 @@ impl CPU {
 
     fn interrupt<M: Memory>(&mut self, memory: &mut M, interrupt: interrupt::Interrupt) {
         self.push_stack_u16(memory, self.registers.pc);
-        let mut flag = StatusFlags::from_bits_truncate(self.registers.status.bits());
-        flag.remove(StatusFlags::BREAK_COMMAND);
-        flag.insert(StatusFlags::UNUSED);
 
-        self.push_stack(memory, flag.bits());
+        let flags = (self.registers.status.bits() & !0b00110000) | interrupt.b_flag_mask;
+        self.push_stack(memory, flags);
         self.registers.status.insert(StatusFlags::INTERRUPT_DISABLE);
+        if self.model == CpuModel::Wdc65C02 {
+            self.registers.status.remove(StatusFlags::DECIMAL_MODE);
+        }
 
         self.cycles_wait = self.cycles_wait.wrapping_add(interrupt.cpu_cycles);
-        self.registers.pc = memory.read_u16(interrupt.vector_addr);
+
+        // The vector is only fetched on cycles 6-7. An NMI latched before
+        // that hijacks it: the handler entered is the NMI's, with the B flag
+        // already pushed as for BRK/IRQ, and the NMI is not taken again.
+        // Memory that ticks the machine reports the NMI directly; otherwise
+        // the sequence has run up front and `clock` checks the window.
+        let hijacked = interrupt.itype != interrupt::InterruptType::NMI && memory.take_nmi();
+        let vector = if hijacked {
+            interrupt::NMI.vector_addr
+        } else {
+            interrupt.vector_addr
+        };
+        self.registers.pc = memory.read_u16(vector);
+
+        if interrupt.itype != interrupt::InterruptType::NMI && !hijacked {
+            self.hijack_window = 4;
+        }
+    }
+
+    fn return_from_interrupt<M: Memory>(&mut self, memory: &mut M) {
+        // B and U don't exist in the status register; the bits pulled from
+        // the stack are discarded.
+        let status = self.pull_stack(memory);
+        self.registers.status = StatusFlags::from_bits_truncate(status);
+        self.registers.status.remove(StatusFlags::BREAK_COMMAND);
+        self.registers.status.insert(StatusFlags::UNUSED);
+
+        self.registers.pc = self.pull_stack_u16(memory);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory::FlatMemory;
+
+    const NMI_HANDLER: u16 = 0x9000;
+    const IRQ_HANDLER: u16 = 0xA000;
+
+    #[derive(Clone)]
+    struct TestMemory {
+        data: Vec<u8>,
+        /// NMI edge to report from `take_nmi`, as memory that ticks the PPU
+        /// would.
+        nmi: bool,
+    }
+
+    impl TestMemory {
+        fn new(program: &[u8]) -> Self {
+            let mut data = vec![0xEA; 0x10000];
+            data[PRG_START as usize..PRG_START as usize + program.len()].copy_from_slice(program);
+            data[0xFFFA] = (NMI_HANDLER & 0xFF) as u8;
+            data[0xFFFB] = (NMI_HANDLER >> 8) as u8;
+            data[0xFFFC] = (PRG_START & 0xFF) as u8;
+            data[0xFFFD] = (PRG_START >> 8) as u8;
+            data[0xFFFE] = (IRQ_HANDLER & 0xFF) as u8;
+            data[0xFFFF] = (IRQ_HANDLER >> 8) as u8;
+            TestMemory { data, nmi: false }
+        }
+    }
+
+    impl Memory for TestMemory {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.data[addr as usize]
+        }
+
+        fn write(&mut self, addr: u16, data: u8) {
+            self.data[addr as usize] = data;
+        }
+
+        fn take_nmi(&mut self) -> bool {
+            std::mem::take(&mut self.nmi)
+        }
+    }
+
+    fn boot(program: &[u8]) -> (CPU, TestMemory) {
+        let mut mem = TestMemory::new(program);
+        let mut cpu = CPU::new();
+        cpu.reset(&mut mem, ResetKind::PowerOn);
+        (cpu, mem)
+    }
+
+    fn run_instruction(cpu: &mut CPU, mem: &mut TestMemory) -> u32 {
+        let mut cycles = 1;
+        while !cpu.clock(mem) {
+            cycles += 1;
+        }
+        cycles
+    }
+
+    #[test]
+    fn test_load_places_segments_ram_and_vectors() {
+        // LDA $6000; ADC $10; STA $11; JMP *
+        let program = RawProgram {
+            segments: vec![
+                (
+                    0x9000,
+                    vec![0xAD, 0x00, 0x60, 0x65, 0x10, 0x85, 0x11, 0x4C, 0x07, 0x90],
+                ),
+                (0x6000, vec![0x30]),
+            ],
+            ram: vec![0; 0x10].into_iter().chain([0x05]).collect(),
+            reset_vector: Some(0x9000),
+            irq_vector: Some(0xA000),
+            ..RawProgram::default()
+        };
+        let mut mem = FlatMemory::new();
+        let mut cpu = CPU::new();
+        cpu.load(&mut mem, &program).unwrap();
+
+        assert_eq!(cpu.registers.pc, 0x9000);
+        for _ in 0..3 {
+            cpu.step(&mut mem);
+        }
+        assert_eq!(mem.data[0x11], 0x35);
+        assert_eq!(mem.read_u16(0xFFFE), 0xA000);
+        // No NMI vector given: the memory keeps what it had.
+        assert_eq!(mem.read_u16(0xFFFA), 0x0000);
+
+        let too_long = RawProgram {
+            segments: vec![(0xFFF0, vec![0; 0x20])],
+            ..RawProgram::default()
+        };
+        let mut mem = FlatMemory::new();
+        assert!(cpu.load(&mut mem, &too_long).is_err());
+        assert!(mem.data.iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn test_operands_move_pc_to_next_instruction() {
+        for model in [CpuModel::Rp2A03, CpuModel::Wdc65C02] {
+            for opcode in (0..=255).filter_map(|code| model.opcodes().find_by_code(code)) {
+                let mut mem = FlatMemory::new();
+                mem.data[0x8000..0x8003].copy_from_slice(&[opcode.code, 0x10, 0x02]);
+                mem.write_u16(0xFFFC, 0x8000);
+                let mut cpu = CPU::with_model(model);
+                cpu.reset(&mut mem, ResetKind::PowerOn);
+                cpu.step(&mut mem);
+
+                let next = 0x8000 + opcode.bytes as u16;
+                let expected: &[u16] = match opcode.mnemonic {
+                    Mnemonic::BCC
+                    | Mnemonic::BCS
+                    | Mnemonic::BEQ
+                    | Mnemonic::BMI
+                    | Mnemonic::BNE
+                    | Mnemonic::BPL
+                    | Mnemonic::BVC
+                    | Mnemonic::BVS
+                    | Mnemonic::BRA => &[next, next + 0x10],
+                    Mnemonic::JMP | Mnemonic::JSR => &[0x0210, 0x0000],
+                    Mnemonic::BRK | Mnemonic::RTI | Mnemonic::RTS => continue,
+                    _ => &[next],
+                };
+                assert!(
+                    expected.contains(&cpu.registers.pc),
+                    "{:?} {:02X}: PC {:04X}",
+                    model,
+                    opcode.code,
+                    cpu.registers.pc
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_branch_cycles() {
+        // BEQ with Z clear falls through.
+        let (mut cpu, mut mem) = boot(&[0xF0, 0x10]);
+        assert_eq!(run_instruction(&mut cpu, &mut mem), 2);
+        assert_eq!(cpu.registers.pc, 0x8002);
+
+        // BNE taken within the page.
+        let (mut cpu, mut mem) = boot(&[0xD0, 0x10]);
+        assert_eq!(run_instruction(&mut cpu, &mut mem), 3);
+        assert_eq!(cpu.registers.pc, 0x8012);
+
+        // BNE taken back across the page boundary.
+        let (mut cpu, mut mem) = boot(&[0xD0, 0x80]);
+        assert_eq!(run_instruction(&mut cpu, &mut mem), 4);
+        assert_eq!(cpu.registers.pc, 0x7F82);
+    }
+
+    #[test]
+    fn test_irq_masked_by_interrupt_disable() {
+        let (mut cpu, mut mem) = boot(&[0xEA, 0x58, 0xEA]);
+        cpu.set_irq_line(true);
+
+        // I is set after reset, so the NOP runs normally.
+        run_instruction(&mut cpu, &mut mem);
+        assert_eq!(cpu.registers.pc, 0x8001);
+
+        // CLI
+        run_instruction(&mut cpu, &mut mem);
+        assert_eq!(cpu.registers.pc, 0x8002);
+
+        // CLI clears I after its interrupt poll, so one more NOP runs.
+        run_instruction(&mut cpu, &mut mem);
+        assert_eq!(cpu.registers.pc, 0x8003);
+
+        let cycles = run_instruction(&mut cpu, &mut mem);
+        assert_eq!(cycles, 7);
+        assert_eq!(cpu.registers.pc, IRQ_HANDLER);
+        assert!(
+            cpu.registers
+                .status
+                .contains(StatusFlags::INTERRUPT_DISABLE)
+        );
+        assert_eq!(mem.data[0x01FD], 0x80);
+        assert_eq!(mem.data[0x01FC], 0x03);
+        assert_eq!(mem.data[0x01FB] & 0x10, 0);
+    }
+
+    #[test]
+    fn test_irq_sneaks_in_between_cli_and_sei() {
+        // CLI; SEI: each changes I after its poll, so the IRQ is taken
+        // after the SEI, with I set in the pushed status.
+        let (mut cpu, mut mem) = boot(&[0x58, 0x78, 0xEA]);
+        cpu.set_irq_line(true);
+        assert_eq!(cpu.step(&mut mem).interrupt, None);
+        assert_eq!(cpu.step(&mut mem).interrupt, None);
+        assert_eq!(cpu.step(&mut mem).interrupt, Some(InterruptType::IRQ));
+        assert_eq!(mem.data[0x01FC], 0x02);
+        assert_eq!(mem.data[0x01FB] & 0x04, 0x04);
+    }
+
+    #[test]
+    fn test_irq_waits_for_instruction_boundary() {
+        // CLI; LDA $1234 (4 cycles)
+        let (mut cpu, mut mem) = boot(&[0x58, 0xAD, 0x34, 0x12]);
+        run_instruction(&mut cpu, &mut mem);
+
+        assert!(!cpu.clock(&mut mem));
+        cpu.set_irq_line(true);
+        while !cpu.clock(&mut mem) {}
+        assert_eq!(cpu.registers.pc, 0x8004);
+
+        run_instruction(&mut cpu, &mut mem);
+        assert_eq!(cpu.registers.pc, IRQ_HANDLER);
+    }
+
+    #[test]
+    fn test_stop_on_brk() {
+        // NOP; BRK
+        let (mut cpu, mut mem) = boot(&[0xEA, 0x00, 0xFF]);
+        assert_eq!(cpu.step(&mut mem).stop, None);
+        cpu.set_stop_on_brk(true);
+        let result = cpu.step(&mut mem);
+        assert_eq!(result.stop, Some(StopReason::Brk(0x8001)));
+        assert_eq!(cpu.registers.pc, IRQ_HANDLER);
+        // The handler runs on from there.
+        assert_eq!(cpu.step(&mut mem).stop, None);
+    }
+
+    #[test]
+    fn test_brk_pushes_b_flag_and_ignores_i_flag() {
+        let (mut cpu, mut mem) = boot(&[0x00, 0xFF, 0xEA]);
+        mem.data[IRQ_HANDLER as usize] = 0x40; // RTI
+
+        let cycles = run_instruction(&mut cpu, &mut mem);
+        assert_eq!(cycles, 7);
+        assert_eq!(cpu.registers.pc, IRQ_HANDLER);
+        assert_eq!(mem.data[0x01FD], 0x80);
+        assert_eq!(mem.data[0x01FC], 0x02);
+        assert_eq!(mem.data[0x01FB] & 0x30, 0x30);
+
+        let cycles = run_instruction(&mut cpu, &mut mem);
+        assert_eq!(cycles, 6);
+        assert_eq!(cpu.registers.pc, 0x8002);
+        assert_eq!(cpu.registers.sp, 0xFD);
+        assert!(!cpu.registers.status.contains(StatusFlags::BREAK_COMMAND));
+    }
+
+    #[test]
+    fn test_rti_restores_interrupted_state() {
+        // CLI; LDA #$00 (sets Z)
+        let (mut cpu, mut mem) = boot(&[0x58, 0xA9, 0x00, 0xEA]);
+        mem.data[IRQ_HANDLER as usize] = 0x40; // RTI
+        run_instruction(&mut cpu, &mut mem);
+        run_instruction(&mut cpu, &mut mem);
+
+        cpu.set_irq_line(true);
+        run_instruction(&mut cpu, &mut mem);
+        cpu.set_irq_line(false);
+        assert_eq!(mem.data[0x01FB] & 0x30, 0x20);
+
+        run_instruction(&mut cpu, &mut mem);
+        assert_eq!(cpu.registers.pc, 0x8003);
+        assert!(cpu.registers.status.contains(StatusFlags::ZERO));
+        assert!(
+            !cpu.registers
+                .status
+                .contains(StatusFlags::INTERRUPT_DISABLE)
+        );
+    }
+
+    #[test]
+    fn test_step_reports_cycles_and_interrupts() {
+        // NOP; LDA $12FF,X (page cross with X=1); CLI; NOP
+        let (mut cpu, mut mem) = boot(&[0xEA, 0xBD, 0xFF, 0x12, 0x58, 0xEA]);
+        cpu.registers.x = 1;
+
+        assert_eq!(cpu.step(&mut mem).cycles, 2);
+        assert_eq!(cpu.step(&mut mem).cycles, 5);
+        assert_eq!(cpu.step(&mut mem).cycles, 2);
+
+        cpu.set_irq_line(true);
+        assert_eq!(cpu.step(&mut mem).interrupt, None);
+        let result = cpu.step(&mut mem);
+        assert_eq!(
+            result,
+            StepResult {
+                cycles: 7,
+                interrupt: Some(InterruptType::IRQ),
+                stop: None,
+                branch_delay: false,
+            }
+        );
+        assert_eq!(cpu.registers.pc, IRQ_HANDLER);
+    }
+
+    #[test]
+    fn test_step_finishes_instruction_started_by_clock() {
+        // LDA $1234
+        let (mut cpu, mut mem) = boot(&[0xAD, 0x34, 0x12, 0xEA]);
+        assert!(!cpu.clock(&mut mem));
+        assert_eq!(cpu.step(&mut mem).cycles, 3);
+        assert_eq!(cpu.registers.pc, 0x8003);
+        assert_eq!(cpu.step(&mut mem).cycles, 2);
+    }
+
+    #[test]
+    fn test_step_cycle_matches_step() {
+        // LDX #$30; INC $01F0,X; LDA $01F0,X (crosses a page); STA $00
+        let program = [0xA2, 0x30, 0xFE, 0xF0, 0x01, 0xBD, 0xF0, 0x01, 0x85, 0x00];
+        let (mut whole, mut whole_mem) = boot(&program);
+        let (mut cpu, mut mem) = boot(&program);
+        whole_mem.data[0x0220] = 0x41;
+        mem.data[0x0220] = 0x41;
+
+        for _ in 0..4 {
+            let expected = whole.step(&mut whole_mem);
+            let mut cycles = 1;
+            let result = loop {
+                if let Some(result) = cpu.step_cycle(&mut mem) {
+                    break result;
+                }
+                cycles += 1;
+            };
+            assert_eq!(result, expected);
+            assert_eq!(cycles, expected.cycles);
+            assert_eq!(cpu.instruction_cycle(), 0);
+        }
+        assert_eq!(mem.data, whole_mem.data);
+        assert_eq!(cpu.registers.a, 0x42);
+        assert_eq!(mem.data[0x00], 0x42);
+    }
+
+    #[test]
+    fn test_clone_resumes_instruction_part_way_through() {
+        // INC $0200
+        let (mut cpu, mut mem) = boot(&[0xEE, 0x00, 0x02]);
+        mem.data[0x0200] = 0x41;
+        for _ in 0..4 {
+            assert_eq!(cpu.step_cycle(&mut mem), None);
+        }
+        assert_eq!(cpu.instruction_cycle(), 4);
+        assert_eq!(cpu.registers.pc, 0x8000);
+
+        // The operand has been read, so a change to it now is overwritten.
+        mem.data[0x0200] = 0x10;
+        let (mut copy, mut copy_mem) = (cpu.clone(), mem.clone());
+        assert_eq!(cpu.step_cycle(&mut mem), None);
+        assert_eq!(
+            cpu.step_cycle(&mut mem).map(|result| result.cycles),
+            Some(6)
+        );
+        assert_eq!(mem.data[0x0200], 0x42);
+        assert_eq!(cpu.registers.pc, 0x8003);
+
+        assert_eq!(copy.step(&mut copy_mem).cycles, 6);
+        assert_eq!(copy_mem.data, mem.data);
+        assert_eq!(copy.registers.pc, 0x8003);
+    }
+
+    #[test]
+    fn test_nmi_is_edge_latched_and_ignores_i_flag() {
+        let (mut cpu, mut mem) = boot(&[0xEA, 0xEA]);
+        cpu.nmi();
+        run_instruction(&mut cpu, &mut mem);
+        assert_eq!(cpu.registers.pc, NMI_HANDLER);
+
+        run_instruction(&mut cpu, &mut mem);
+        assert_eq!(cpu.registers.pc, NMI_HANDLER + 1);
+    }
+
+    #[test]
+    fn test_decimal_mode_is_opt_in() {
+        // SED; CLC; LDA #$09; ADC #$01
+        let program = [0xF8, 0x18, 0xA9, 0x09, 0x69, 0x01];
+        let (mut cpu, mut mem) = boot(&program);
+        for _ in 0..4 {
+            cpu.step(&mut mem);
+        }
+        assert_eq!(cpu.registers.a, 0x0A);
+
+        let (mut cpu, mut mem) = boot(&program);
+        cpu.set_decimal_enabled(true);
+        for _ in 0..4 {
+            cpu.step(&mut mem);
+        }
+        assert_eq!(cpu.registers.a, 0x10);
+
+        assert!(CPU::with_model(CpuModel::Mos6502).decimal_enabled());
+        assert!(!CPU::with_model(CpuModel::Rp2A07).decimal_enabled());
+    }
+
+    #[test]
+    fn test_frame_time() {
+        let ntsc = CpuModel::Rp2A03.frame_time();
+        assert_eq!(ntsc.as_micros(), 16_639);
+        assert_eq!(CpuModel::Rp2A07.frame_time().as_micros(), 19_997);
+
+        let two_frames = CpuModel::Rp2A03.dots_to_time(2 * 341 * 262 - 1);
+        assert!((two_frames / 2).abs_diff(ntsc).as_nanos() <= 1);
+    }
+
+    #[test]
+    fn test_decimal_adc_and_sbc() {
+        let (mut cpu, _) = boot(&[]);
+        cpu.set_decimal_enabled(true);
+        cpu.registers.status.insert(StatusFlags::DECIMAL_MODE);
+
+        let adc = |cpu: &mut CPU, a: u8, value: u8, carry: bool| {
+            cpu.registers.a = a;
+            cpu.registers.status.set(StatusFlags::CARRY, carry);
+            cpu.adc_value(value);
+            (
+                cpu.registers.a,
+                cpu.registers.status.contains(StatusFlags::CARRY),
+            )
+        };
+        assert_eq!(adc(&mut cpu, 0x12, 0x34, false), (0x46, false));
+        assert_eq!(adc(&mut cpu, 0x58, 0x46, true), (0x05, true));
+        assert_eq!(adc(&mut cpu, 0x99, 0x01, false), (0x00, true));
+
+        let sbc = |cpu: &mut CPU, a: u8, value: u8, carry: bool| {
+            cpu.registers.a = a;
+            cpu.registers.status.set(StatusFlags::CARRY, carry);
+            cpu.sbc_value(value);
+            (
+                cpu.registers.a,
+                cpu.registers.status.contains(StatusFlags::CARRY),
+            )
+        };
+        assert_eq!(sbc(&mut cpu, 0x46, 0x12, true), (0x34, true));
+        assert_eq!(sbc(&mut cpu, 0x40, 0x13, true), (0x27, true));
+        assert_eq!(sbc(&mut cpu, 0x12, 0x21, true), (0x91, false));
+        assert_eq!(sbc(&mut cpu, 0x32, 0x02, false), (0x29, true));
+    }
+
+    #[test]
+    fn test_nmi_hijacks_brk_vector() {
+        let (mut cpu, mut mem) = boot(&[0x00, 0x00]);
+        assert!(!cpu.clock(&mut mem));
+        cpu.clock(&mut mem);
+        cpu.nmi();
+        run_instruction(&mut cpu, &mut mem);
+        assert_eq!(cpu.registers.pc, NMI_HANDLER);
+        // B is set in the pushed status; the NMI is consumed.
+        assert_eq!(mem.data[0x01FD - 2] & 0b0011_0000, 0b0011_0000);
+        run_instruction(&mut cpu, &mut mem);
+        assert_eq!(cpu.registers.pc, NMI_HANDLER + 1);
+    }
+
+    #[test]
+    fn test_step_nmi_hijacks_brk_vector() {
+        let (mut cpu, mut mem) = boot(&[0x00, 0x00, 0x00, 0x00]);
+        mem.nmi = true;
+        cpu.step(&mut mem);
+        assert_eq!(cpu.registers.pc, NMI_HANDLER);
+        assert_eq!(mem.data[0x01FD - 2] & 0b0011_0000, 0b0011_0000);
+
+        // An NMI raised after a BRK that `step` ran whole is entered on its
+        // own, not folded into the finished sequence.
+        cpu.registers.pc = PRG_START + 2;
+        cpu.step(&mut mem);
+        assert_eq!(cpu.registers.pc, IRQ_HANDLER);
+        let sp = cpu.registers.sp;
+        cpu.nmi();
+        assert_eq!(run_instruction(&mut cpu, &mut mem), 7);
+        assert_eq!(cpu.registers.pc, NMI_HANDLER);
+        assert_eq!(cpu.registers.sp, sp.wrapping_sub(3));
+    }
+
+    #[test]
+    fn test_late_nmi_runs_after_brk() {
+        let (mut cpu, mut mem) = boot(&[0x00, 0x00]);
+        for _ in 0..5 {
+            cpu.clock(&mut mem);
+        }
+        cpu.nmi();
+        run_instruction(&mut cpu, &mut mem);
+        assert_eq!(cpu.registers.pc, IRQ_HANDLER);
+        run_instruction(&mut cpu, &mut mem);
+        assert_eq!(cpu.registers.pc, NMI_HANDLER);
+    }
+
+    #[test]
+    fn test_jam_halts_until_reset() {
+        // LDA #$42; JAM
+        let (mut cpu, mut mem) = boot(&[0xA9, 0x42, 0x02, 0xEA]);
+        cpu.step(&mut mem);
+        assert_eq!(cpu.step(&mut mem).stop, Some(StopReason::Halted));
+        assert_eq!(cpu.jammed_at(), Some(0x8002));
+
+        cpu.nmi();
+        assert_eq!(cpu.step(&mut mem).stop, Some(StopReason::Halted));
+        assert!(!cpu.clock(&mut mem));
+        assert_eq!(cpu.take_stop(), Some(StopReason::Halted));
+        assert_eq!(cpu.registers.a, 0x42);
+        assert_eq!(cpu.registers.pc, 0x8003);
+
+        cpu.reset(&mut mem, ResetKind::Reset);
+        assert_eq!(cpu.jammed_at(), None);
+        assert_eq!(cpu.step(&mut mem).stop, None);
+    }
+
+    #[test]
+    fn test_65c02_extensions() {
+        #[rustfmt::skip]
+        let program = [
+            0xA2, 0x12,       // LDX #$12
+            0xDA,             // PHX
+            0x7A,             // PLY
+            0x64, 0x10,       // STZ $10
+            0xA9, 0x0F,       // LDA #$0F
+            0x04, 0x11,       // TSB $11
+            0x14, 0x11,       // TRB $11
+            0x80, 0x02,       // BRA +2
+            0x00, 0x00,
+            0xB2, 0x20,       // LDA ($20)
+            0x6C, 0xFF, 0x30, // JMP ($30FF)
+        ];
+        let mut mem = TestMemory::new(&program);
+        mem.data[0x20] = 0x00;
+        mem.data[0x21] = 0x03;
+        mem.data[0x0300] = 0x5A;
+        mem.data[0x30FF] = 0x00;
+        mem.data[0x3000] = 0x12;
+        mem.data[0x3100] = 0x90;
+
+        let mut cpu = CPU::with_model(CpuModel::Wdc65C02);
+        cpu.reset(&mut mem, ResetKind::PowerOn);
+        for _ in 0..7 {
+            cpu.step(&mut mem);
+        }
+        assert_eq!(cpu.registers.y, 0x12);
+        assert_eq!(mem.data[0x10], 0x00);
+        assert_eq!(mem.data[0x11], 0xE0);
+        assert!(!cpu.registers.status.contains(StatusFlags::ZERO));
+
+        cpu.step(&mut mem);
+        assert_eq!(cpu.registers.pc, 0x8010);
+        cpu.step(&mut mem);
+        assert_eq!(cpu.registers.a, 0x5A);
+        cpu.step(&mut mem);
+        assert_eq!(cpu.registers.pc, 0x9000);
+
+        // The same opcodes jam an NMOS part.
+        let (mut cpu, mut mem) = boot(&[0xB2, 0x20]);
+        assert_eq!(cpu.step(&mut mem).stop, Some(StopReason::Halted));
+    }
+
+    #[test]
+    fn test_65c02_interrupts_clear_decimal_mode() {
+        // SED; BRK
+        let mut mem = TestMemory::new(&[0xF8, 0x00, 0x00]);
+        let mut cpu = CPU::with_model(CpuModel::Wdc65C02);
+        cpu.reset(&mut mem, ResetKind::PowerOn);
+        cpu.step(&mut mem);
+        cpu.step(&mut mem);
+        assert_eq!(cpu.registers.pc, IRQ_HANDLER);
+        assert!(!cpu.registers.status.contains(StatusFlags::DECIMAL_MODE));
+        // D is still set in the pushed status.
+        assert_eq!(mem.data[0x01FD - 2] & 0x08, 0x08);
+
+        // An NMOS part leaves it set.
+        let (mut cpu, mut mem) = boot(&[0xF8, 0x00, 0x00]);
+        cpu.step(&mut mem);
+        cpu.step(&mut mem);
+        assert!(cpu.registers.status.contains(StatusFlags::DECIMAL_MODE));
+    }
+
+    #[test]
+    fn test_65c02_runs_nmos_illegals_as_nops() {
+        // JAM #$00; SLO (one byte on a 65C02); NOP $1234 (eight cycles)
+        let mut mem = TestMemory::new(&[0x02, 0x00, 0x07, 0x5C, 0x34, 0x12]);
+        let mut cpu = CPU::with_model(CpuModel::Wdc65C02);
+        cpu.reset(&mut mem, ResetKind::PowerOn);
+
+        let result = cpu.step(&mut mem);
+        assert_eq!(result.stop, None);
+        assert_eq!(result.cycles, 2);
+        assert_eq!(cpu.registers.pc, 0x8002);
+        assert_eq!(run_instruction(&mut cpu, &mut mem), 1);
+        assert_eq!(cpu.registers.pc, 0x8003);
+        assert_eq!(run_instruction(&mut cpu, &mut mem), 8);
+        assert_eq!(cpu.registers.pc, 0x8006);
+    }
+
+    #[test]
+    fn test_65c02_jmp_indirect_through_ffff() {
+        // JMP ($FFFF): the pointer's high byte comes from $0000.
+        let mut mem = TestMemory::new(&[0x6C, 0xFF, 0xFF]);
+        mem.data[0x0000] = 0x12;
+        let mut cpu = CPU::with_model(CpuModel::Wdc65C02);
+        cpu.reset(&mut mem, ResetKind::PowerOn);
+        cpu.step(&mut mem);
+        assert_eq!(cpu.registers.pc, 0x12A0);
+    }
+
+    #[test]
+    fn test_power_on_and_reset_state() {
+        // LDA #$42; LDX #$07; CLI; SEC
+        let (mut cpu, mut mem) = boot(&[0xA9, 0x42, 0xA2, 0x07, 0x58, 0x38]);
+        assert_eq!(cpu.registers.sp, 0xFD);
+        assert_eq!(cpu.registers.status.bits(), 0x34);
+        for _ in 0..4 {
+            cpu.step(&mut mem);
+        }
+
+        cpu.reset(&mut mem, ResetKind::Reset);
+        assert_eq!(cpu.registers.sp, 0xFA);
+        assert_eq!(cpu.registers.a, 0x42);
+        assert_eq!(cpu.registers.x, 0x07);
+        assert!(
+            cpu.registers
+                .status
+                .contains(StatusFlags::INTERRUPT_DISABLE)
+        );
+        assert!(cpu.registers.status.contains(StatusFlags::CARRY));
+        assert_eq!(cpu.registers.pc, 0x8000);
+
+        cpu.reset(&mut mem, ResetKind::PowerOn);
+        assert_eq!(cpu.registers.sp, 0xFD);
+        assert_eq!(cpu.registers.a, 0);
+        assert_eq!(cpu.registers.status.bits(), 0x34);
+    }
+
+    #[test]
+    fn test_breakpoint_stops_before_fetch_then_resumes() {
+        // NOP; NOP; LDA #$42
+        let (mut cpu, mut mem) = boot(&[0xEA, 0xEA, 0xA9, 0x42]);
+        cpu.add_breakpoint(Breakpoint::at(0x8002));
+        cpu.add_breakpoint(Breakpoint::in_bank(0x8001, 3));
+
+        assert_eq!(cpu.step(&mut mem).stop, None);
+        assert_eq!(cpu.step(&mut mem).stop, None);
+
+        let result = cpu.step(&mut mem);
+        assert_eq!(result.stop, Some(StopReason::Breakpoint(0x8002)));
+        assert_eq!(result.cycles, 0);
+        assert_eq!(cpu.registers.pc, 0x8002);
+
+        assert_eq!(cpu.step(&mut mem).cycles, 2);
+        assert_eq!(cpu.registers.a, 0x42);
+    }
+
+    #[test]
+    fn test_clock_reports_breakpoint() {
+        let (mut cpu, mut mem) = boot(&[0xEA, 0xEA]);
+        cpu.add_breakpoint(Breakpoint::at(0x8000));
+
+        assert!(!cpu.clock(&mut mem));
+        assert_eq!(cpu.take_stop(), Some(StopReason::Breakpoint(0x8000)));
+        assert_eq!(cpu.take_stop(), None);
+
+        run_instruction(&mut cpu, &mut mem);
+        assert_eq!(cpu.registers.pc, 0x8001);
+        assert!(cpu.remove_breakpoint(Breakpoint::at(0x8000)));
+    }
+
+    #[test]
+    fn test_watchpoint_reports_pc_after_instruction() {
+        // LDA #$07; STA $10; LDA $0300; NOP
+        let (mut cpu, mut mem) = boot(&[0xA9, 0x07, 0x85, 0x10, 0xAD, 0x00, 0x03, 0xEA]);
+        cpu.add_watchpoint(Watchpoint::new(0x10, WatchKind::Write));
+        cpu.add_watchpoint(Watchpoint::range(0x0300, 0x03FF, WatchKind::Read));
+
+        assert_eq!(cpu.step(&mut mem).stop, None);
+
+        let result = cpu.step(&mut mem);
+        assert_eq!(result.cycles, 3);
+        assert_eq!(
+            result.stop,
+            Some(StopReason::Watchpoint(WatchHit {
+                pc: 0x8002,
+                addr: 0x10,
+                value: 0x07,
+                kind: WatchKind::Write,
+            }))
+        );
+        assert_eq!(mem.data[0x10], 0x07);
+
+        let result = cpu.step(&mut mem);
+        assert!(matches!(
+            result.stop,
+            Some(StopReason::Watchpoint(WatchHit {
+                pc: 0x8004,
+                addr: 0x0300,
+                ..
+            }))
+        ));
+        assert_eq!(cpu.step(&mut mem).stop, None);
+    }
+
+    // Records every bus access made through it as (address, value, is_write).
+    struct RecordingMemory {
+        inner: TestMemory,
+        accesses: Vec<(u16, u8, bool)>,
+    }
+
+    impl Memory for RecordingMemory {
+        fn read(&mut self, addr: u16) -> u8 {
+            let value = self.inner.read(addr);
+            self.accesses.push((addr, value, false));
+            value
+        }
+
+        fn write(&mut self, addr: u16, data: u8) {
+            self.inner.write(addr, data);
+            self.accesses.push((addr, data, true));
+        }
+    }
+
+    fn record(cpu: &mut CPU, mem: TestMemory) -> Vec<(u16, u8, bool)> {
+        let mut mem = RecordingMemory {
+            inner: mem,
+            accesses: Vec::new(),
+        };
+        cpu.step(&mut mem);
+        mem.accesses
+    }
+
+    #[test]
+    fn test_indexed_read_dummy_reads_only_on_page_cross() {
+        // LDA $80F0,X
+        let (mut cpu, mem) = boot(&[0xBD, 0xF0, 0x80]);
+        cpu.registers.x = 0x20;
+        let accesses = record(&mut cpu, mem);
+        let reads: Vec<u16> = accesses.iter().skip(3).map(|a| a.0).collect();
+        assert_eq!(reads, vec![0x8010, 0x8110]);
+
+        let (mut cpu, mem) = boot(&[0xBD, 0x00, 0x80]);
+        cpu.registers.x = 0x20;
+        assert_eq!(record(&mut cpu, mem).len(), 4);
+    }
+
+    #[test]
+    fn test_indexed_store_always_dummy_reads() {
+        // STA $0200,X
+        let (mut cpu, mem) = boot(&[0x9D, 0x00, 0x02]);
+        cpu.registers.x = 0x01;
+        cpu.registers.a = 0x55;
+        let accesses = record(&mut cpu, mem);
+        assert_eq!(accesses[3..], [(0x0201, 0xEA, false), (0x0201, 0x55, true)]);
+    }
+
+    #[test]
+    fn test_read_modify_write_writes_twice() {
+        // INC $10
+        let (mut cpu, mut mem) = boot(&[0xE6, 0x10]);
+        mem.data[0x10] = 0x05;
+        let accesses = record(&mut cpu, mem);
+        assert_eq!(
+            accesses[2..],
+            [(0x10, 0x05, false), (0x10, 0x05, true), (0x10, 0x06, true)]
+        );
     }
 }
@@ -0,0 +1,173 @@
+//! Crash report bundles: one zip a user can attach to an issue, holding what
+//! is needed to look into a crash without their machine. That is a summary
+//! (message, pico version, ROM name and checksum), the machine state as
+//! [`Nes::dump_state`] writes it, the instructions leading up to the crash
+//! if [`Nes::enable_trace_history`] was on, and the settings in use.
+//!
+//! The state dump is for reading, not loading: pico has no save-state
+//! format on disk yet.
+
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::nes::Nes;
+use crate::romdb::crc32;
+
+pub struct CrashReport {
+    pub message: String,
+    pub rom_name: String,
+    pub rom_crc32: u32,
+    pub frame: u64,
+    pub state: Vec<u8>,
+    pub trace: Vec<String>,
+    /// The settings file's text.
+    pub config: Option<String>,
+}
+
+impl CrashReport {
+    /// Collects a report on `nes`, which was running `rom`.
+    pub fn new(nes: &Nes, message: &str, rom_name: &str, rom: &[u8]) -> Self {
+        CrashReport {
+            message: message.to_string(),
+            rom_name: rom_name.to_string(),
+            rom_crc32: crc32(rom),
+            frame: nes.bus.ppu.frame_count,
+            state: nes.dump_state(),
+            trace: nes
+                .trace_history()
+                .map(|history| history.lines(nes.bus.cpu.model()))
+                .unwrap_or_default(),
+            config: None,
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "{}\n\npico {}\nROM: {} (CRC32 {:08X})\nframe: {}\n",
+            self.message,
+            env!("CARGO_PKG_VERSION"),
+            self.rom_name,
+            self.rom_crc32,
+            self.frame
+        )
+    }
+
+    /// The bundle as an uncompressed zip: `report.txt`, `state.bin`,
+    /// `trace.txt` when there is a trace, and `config.ini` when known.
+    pub fn to_zip(&self) -> Vec<u8> {
+        let summary = self.summary();
+        let trace = self.trace.join("\n");
+        let mut files = vec![
+            ("report.txt", summary.as_bytes()),
+            ("state.bin", &self.state[..]),
+        ];
+        if !self.trace.is_empty() {
+            files.push(("trace.txt", trace.as_bytes()));
+        }
+        if let Some(config) = &self.config {
+            files.push(("config.ini", config.as_bytes()));
+        }
+        zip_stored(&files)
+    }
+
+    /// Writes the bundle to a new `pico-crash-<time>.zip` in `dir`.
+    pub fn write_to(&self, dir: &Path) -> Result<PathBuf, String> {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        let path = dir.join(format!("pico-crash-{}.zip", seconds));
+        std::fs::write(&path, self.to_zip())
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        Ok(path)
+    }
+}
+
+// A zip archive with every file stored uncompressed.
+fn zip_stored(files: &[(&str, &[u8])]) -> Vec<u8> {
+    // 1980-01-01 00:00, the earliest time a zip entry can carry.
+    const DOS_DATE: u16 = 0x0021;
+
+    let mut out = Vec::new();
+    let mut central = Vec::new();
+    for &(name, data) in files {
+        let offset = out.len() as u32;
+        let crc = crc32(data);
+        let size = data.len() as u32;
+
+        let mut common = Vec::new();
+        common.extend_from_slice(&10u16.to_le_bytes()); // version needed
+        common.extend_from_slice(&0u16.to_le_bytes()); // flags
+        common.extend_from_slice(&0u16.to_le_bytes()); // stored
+        common.extend_from_slice(&0u16.to_le_bytes()); // time
+        common.extend_from_slice(&DOS_DATE.to_le_bytes());
+        common.extend_from_slice(&crc.to_le_bytes());
+        common.extend_from_slice(&size.to_le_bytes());
+        common.extend_from_slice(&size.to_le_bytes());
+        common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes()); // extra field
+
+        out.extend_from_slice(&0x0403_4B50u32.to_le_bytes());
+        out.extend_from_slice(&common);
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(data);
+
+        central.extend_from_slice(&0x0201_4B50u32.to_le_bytes());
+        central.extend_from_slice(&20u16.to_le_bytes()); // version made by
+        central.extend_from_slice(&common);
+        central.extend_from_slice(&[0; 6]); // comment, disk, internal attributes
+        central.extend_from_slice(&0u32.to_le_bytes()); // external attributes
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+    }
+
+    let central_offset = out.len() as u32;
+    out.extend_from_slice(&central);
+    out.extend_from_slice(&0x0605_4B50u32.to_le_bytes());
+    out.extend_from_slice(&[0; 4]); // disk numbers
+    out.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(central.len() as u32).to_le_bytes());
+    out.extend_from_slice(&central_offset.to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes()); // comment
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::apu::APU;
+    use crate::cart::Cart;
+    use crate::cpu::ResetKind;
+    use crate::demo;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_bundles_state_and_trace() {
+        let rom = demo::hello_world_rom();
+        let apu = APU::new(48_000, Arc::new(Mutex::new(VecDeque::new())));
+        let mut nes = Nes::new(Cart::new(&rom).unwrap(), apu);
+        nes.reset(ResetKind::PowerOn);
+        nes.enable_trace_history(100);
+        nes.step_frame();
+
+        let mut report = CrashReport::new(&nes, "boom", "demo", &rom);
+        report.config = Some("[profile default]\n".to_string());
+        assert_eq!(report.trace.len(), 100);
+        assert!(report.summary().starts_with("boom\n\npico "));
+
+        let zip = report.to_zip();
+        assert_eq!(&zip[..4], b"PK\x03\x04");
+        assert_eq!(&zip[30..40], b"report.txt");
+        // End of central directory: four entries.
+        let end = &zip[zip.len() - 22..];
+        assert_eq!(&end[..4], b"PK\x05\x06");
+        assert_eq!(&end[8..12], &[4, 0, 4, 0]);
+        let central_offset = u32::from_le_bytes(end[16..20].try_into().unwrap()) as usize;
+        assert_eq!(&zip[central_offset..central_offset + 4], b"PK\x01\x02");
+        let state = nes.dump_state();
+        assert!(zip.windows(state.len()).any(|window| window == state));
+    }
+}
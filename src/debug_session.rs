@@ -0,0 +1,211 @@
+//! Debugger state that outlives a run: breakpoints, watchpoints, address
+//! labels and the frontend's window layout, saved per ROM through a
+//! [`StorageBackend`] so a session can be picked up after a restart.
+//!
+//! Stored as `key = value` lines:
+//!
+//! ```text
+//! break = C012
+//! break = 03:8004
+//! watch = 0200-02FF w
+//! label.C000 = reset
+//! layout.memory_viewer = 640,0,320,480
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::cpu::{Breakpoint, CPU, WatchKind, Watchpoint};
+use crate::romdb::crc32;
+use crate::storage::StorageBackend;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DebugSession {
+    pub breakpoints: Vec<Breakpoint>,
+    pub watchpoints: Vec<Watchpoint>,
+    pub labels: BTreeMap<u16, String>,
+    /// Window placement and similar frontend state. The core doesn't
+    /// interpret it; values must fit on one line.
+    pub layout: BTreeMap<String, String>,
+}
+
+impl DebugSession {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Storage key for the session of the ROM file `rom`, e.g.
+    /// `1a2b3c4d.debug`. Keyed by content so renamed copies share it.
+    pub fn storage_key(rom: &[u8]) -> String {
+        format!("{:08x}.debug", crc32(rom))
+    }
+
+    /// Takes the breakpoints and watchpoints currently set on `cpu`.
+    pub fn capture(&mut self, cpu: &CPU) {
+        self.breakpoints = cpu.breakpoints().to_vec();
+        self.watchpoints = cpu.watchpoints().to_vec();
+    }
+
+    /// Replaces the breakpoints and watchpoints on `cpu` with the session's.
+    pub fn apply(&self, cpu: &mut CPU) {
+        cpu.clear_breakpoints();
+        cpu.clear_watchpoints();
+        for &breakpoint in &self.breakpoints {
+            cpu.add_breakpoint(breakpoint);
+        }
+        for &watchpoint in &self.watchpoints {
+            cpu.add_watchpoint(watchpoint);
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut session = DebugSession::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .map(|(key, value)| (key.trim(), value.trim()))
+                .ok_or_else(|| format!("Line {}: expected key = value", number + 1))?;
+            session
+                .set(key, value)
+                .map_err(|e| format!("Line {}: {}", number + 1, e))?;
+        }
+        Ok(session)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "break" => self.breakpoints.push(parse_breakpoint(value)?),
+            "watch" => self.watchpoints.push(parse_watchpoint(value)?),
+            _ if key.starts_with("label.") => {
+                let addr = parse_addr(&key["label.".len()..])?;
+                self.labels.insert(addr, value.to_string());
+            }
+            _ if key.starts_with("layout.") => {
+                self.layout
+                    .insert(key["layout.".len()..].to_string(), value.to_string());
+            }
+            _ => return Err(format!("Unknown session setting: {}", key)),
+        }
+        Ok(())
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        for breakpoint in &self.breakpoints {
+            let _ = match breakpoint.bank {
+                Some(bank) => writeln!(out, "break = {:02X}:{:04X}", bank, breakpoint.addr),
+                None => writeln!(out, "break = {:04X}", breakpoint.addr),
+            };
+        }
+        for watchpoint in &self.watchpoints {
+            let kind = match watchpoint.kind {
+                WatchKind::Read => "r",
+                WatchKind::Write => "w",
+                WatchKind::Access => "rw",
+            };
+            let _ = writeln!(
+                out,
+                "watch = {:04X}-{:04X} {}",
+                watchpoint.start, watchpoint.end, kind
+            );
+        }
+        for (addr, name) in &self.labels {
+            let _ = writeln!(out, "label.{:04X} = {}", addr, name);
+        }
+        for (key, value) in &self.layout {
+            let _ = writeln!(out, "layout.{} = {}", key, value);
+        }
+        out
+    }
+
+    /// Loads the session stored under `key`, if there is one.
+    pub fn load(storage: &dyn StorageBackend, key: &str) -> Result<Option<Self>, String> {
+        let Some(data) = storage.load(key)? else {
+            return Ok(None);
+        };
+        let text = String::from_utf8(data).map_err(|_| format!("Session {} is not UTF-8", key))?;
+        Self::parse(&text)
+            .map(Some)
+            .map_err(|e| format!("Session {}: {}", key, e))
+    }
+
+    pub fn save(&self, storage: &mut dyn StorageBackend, key: &str) -> Result<(), String> {
+        storage.store(key, self.to_text().as_bytes())
+    }
+}
+
+fn parse_addr(text: &str) -> Result<u16, String> {
+    u16::from_str_radix(text.trim_start_matches('$'), 16)
+        .map_err(|_| format!("Invalid address: {}", text))
+}
+
+fn parse_breakpoint(text: &str) -> Result<Breakpoint, String> {
+    match text.split_once(':') {
+        Some((bank, addr)) => {
+            let bank =
+                usize::from_str_radix(bank, 16).map_err(|_| format!("Invalid bank: {}", bank))?;
+            Ok(Breakpoint::in_bank(parse_addr(addr)?, bank))
+        }
+        None => Ok(Breakpoint::at(parse_addr(text)?)),
+    }
+}
+
+fn parse_watchpoint(text: &str) -> Result<Watchpoint, String> {
+    let (range, kind) = text
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| format!("Invalid watchpoint: {}", text))?;
+    let kind = match kind.trim() {
+        "r" => WatchKind::Read,
+        "w" => WatchKind::Write,
+        "rw" => WatchKind::Access,
+        other => return Err(format!("Invalid watch kind: {}", other)),
+    };
+    let (start, end) = match range.split_once('-') {
+        Some((start, end)) => (parse_addr(start)?, parse_addr(end)?),
+        None => (parse_addr(range)?, parse_addr(range)?),
+    };
+    Ok(Watchpoint::range(start, end, kind))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[test]
+    fn test_session_round_trip() {
+        let mut cpu = CPU::new();
+        cpu.add_breakpoint(Breakpoint::at(0xC012));
+        cpu.add_breakpoint(Breakpoint::in_bank(0x8004, 3));
+        cpu.add_watchpoint(Watchpoint::range(0x0200, 0x02FF, WatchKind::Write));
+
+        let mut session = DebugSession::new();
+        session.capture(&cpu);
+        session.labels.insert(0xC000, "reset".to_string());
+        session
+            .layout
+            .insert("memory_viewer".to_string(), "640,0,320,480".to_string());
+
+        let key = DebugSession::storage_key(b"rom");
+        let mut storage = MemoryStorage::default();
+        assert_eq!(DebugSession::load(&storage, &key), Ok(None));
+        session.save(&mut storage, &key).unwrap();
+        let text = String::from_utf8(storage.entries[&key].clone()).unwrap();
+        assert!(text.contains("break = 03:8004\n"));
+        assert!(text.contains("watch = 0200-02FF w\n"));
+
+        let loaded = DebugSession::load(&storage, &key).unwrap().unwrap();
+        assert_eq!(loaded, session);
+        let mut restarted = CPU::new();
+        loaded.apply(&mut restarted);
+        assert_eq!(restarted.breakpoints(), cpu.breakpoints());
+        assert_eq!(restarted.watchpoints(), cpu.watchpoints());
+
+        assert!(DebugSession::parse("watch = 0200 x").is_err());
+        assert!(DebugSession::parse("colour = red").is_err());
+    }
+}
@@ -0,0 +1,124 @@
+//! A tiny built-in NROM program, so the emulator can be smoke-tested (and
+//! used as a library example) without an external ROM. It prints
+//! "HELLO WORLD!" and plays a short beep on the first pulse channel.
+
+const PRG_SIZE: usize = 0x4000;
+const CHR_SIZE: usize = 0x2000;
+
+// Hand-assembled; the PRG bank is mapped at $8000 and mirrored at $C000.
+#[rustfmt::skip]
+const PROGRAM: [u8; 0x6D] = [
+    0x78,             // 8000  SEI
+    0xD8,             // 8001  CLD
+    0xA2, 0xFF,       // 8002  LDX #$FF
+    0x9A,             // 8004  TXS
+    0xA9, 0x00,       // 8005  LDA #$00
+    0x8D, 0x00, 0x20, // 8007  STA $2000
+    0x8D, 0x01, 0x20, // 800A  STA $2001
+    0x2C, 0x02, 0x20, // 800D  BIT $2002    ; wait for the PPU to warm up
+    0x10, 0xFB,       // 8010  BPL $800D
+    0x2C, 0x02, 0x20, // 8012  BIT $2002
+    0x10, 0xFB,       // 8015  BPL $8012
+    0xA9, 0x3F,       // 8017  LDA #$3F     ; background palette 0
+    0x8D, 0x06, 0x20, // 8019  STA $2006
+    0xA9, 0x00,       // 801C  LDA #$00
+    0x8D, 0x06, 0x20, // 801E  STA $2006
+    0xA9, 0x0F,       // 8021  LDA #$0F     ; black
+    0x8D, 0x07, 0x20, // 8023  STA $2007
+    0xA9, 0x30,       // 8026  LDA #$30     ; white
+    0x8D, 0x07, 0x20, // 8028  STA $2007
+    0xA9, 0x21,       // 802B  LDA #$21     ; nametable row 14, column 10
+    0x8D, 0x06, 0x20, // 802D  STA $2006
+    0xA9, 0xCA,       // 8030  LDA #$CA
+    0x8D, 0x06, 0x20, // 8032  STA $2006
+    0xA2, 0x00,       // 8035  LDX #$00
+    0xBD, 0x6D, 0x80, // 8037  LDA $806D,X  ; tile numbers are ASCII codes
+    0xF0, 0x07,       // 803A  BEQ $8043
+    0x8D, 0x07, 0x20, // 803C  STA $2007
+    0xE8,             // 803F  INX
+    0x4C, 0x37, 0x80, // 8040  JMP $8037
+    0xA9, 0x00,       // 8043  LDA #$00
+    0x8D, 0x05, 0x20, // 8045  STA $2005
+    0x8D, 0x05, 0x20, // 8048  STA $2005
+    0xA9, 0x01,       // 804B  LDA #$01     ; enable pulse 1
+    0x8D, 0x15, 0x40, // 804D  STA $4015
+    0xA9, 0x9F,       // 8050  LDA #$9F     ; 50% duty, constant volume 15
+    0x8D, 0x00, 0x40, // 8052  STA $4000
+    0xA9, 0xFD,       // 8055  LDA #$FD     ; ~440 Hz
+    0x8D, 0x02, 0x40, // 8057  STA $4002
+    0xA9, 0x30,       // 805A  LDA #$30     ; length 80 half-frames
+    0x8D, 0x03, 0x40, // 805C  STA $4003
+    0xA9, 0x0A,       // 805F  LDA #$0A     ; show background
+    0x8D, 0x01, 0x20, // 8061  STA $2001
+    0xA9, 0x80,       // 8064  LDA #$80     ; NMI on
+    0x8D, 0x00, 0x20, // 8066  STA $2000
+    0x4C, 0x69, 0x80, // 8069  JMP $8069
+    0x40,             // 806C  RTI          ; NMI and IRQ handler
+];
+const TEXT: &[u8] = b"HELLO WORLD!\0";
+const HANDLER: u16 = 0x806C;
+const RESET: u16 = 0x8000;
+
+// 5x7 glyphs, one byte per row, most significant bit leftmost.
+const FONT: [(u8, [u8; 7]); 8] = [
+    (b'!', [0x20, 0x20, 0x20, 0x20, 0x20, 0x00, 0x20]),
+    (b'D', [0xF0, 0x88, 0x88, 0x88, 0x88, 0x88, 0xF0]),
+    (b'E', [0xF8, 0x80, 0x80, 0xF0, 0x80, 0x80, 0xF8]),
+    (b'H', [0x88, 0x88, 0x88, 0xF8, 0x88, 0x88, 0x88]),
+    (b'L', [0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0xF8]),
+    (b'O', [0x70, 0x88, 0x88, 0x88, 0x88, 0x88, 0x70]),
+    (b'R', [0xF0, 0x88, 0x88, 0xF0, 0xA0, 0x90, 0x88]),
+    (b'W', [0x88, 0x88, 0x88, 0xA8, 0xA8, 0xD8, 0x88]),
+];
+
+/// Builds the demo as an iNES image that [`crate::cart::Cart::new`] accepts.
+pub fn hello_world_rom() -> Vec<u8> {
+    let mut rom = vec![0; 16 + PRG_SIZE + CHR_SIZE];
+    // NROM-128, one 8KB CHR bank, vertical mirroring.
+    rom[..8].copy_from_slice(&[b'N', b'E', b'S', 0x1A, 1, 1, 0x01, 0x00]);
+
+    let prg = &mut rom[16..16 + PRG_SIZE];
+    prg[..PROGRAM.len()].copy_from_slice(&PROGRAM);
+    prg[PROGRAM.len()..PROGRAM.len() + TEXT.len()].copy_from_slice(TEXT);
+    for (offset, vector) in [(0x3FFA, HANDLER), (0x3FFC, RESET), (0x3FFE, HANDLER)] {
+        prg[offset..offset + 2].copy_from_slice(&vector.to_le_bytes());
+    }
+
+    // Each glyph goes in the low bit plane of the tile numbered by its ASCII
+    // code, so it draws in colour 1.
+    let chr = &mut rom[16 + PRG_SIZE..];
+    for (code, rows) in FONT {
+        let tile = code as usize * 16;
+        chr[tile..tile + 7].copy_from_slice(&rows);
+    }
+
+    rom
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::apu::APU;
+    use crate::cart::Cart;
+    use crate::cpu::ResetKind;
+    use crate::nes::Nes;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_demo_prints_hello_world() {
+        let cart = Cart::new(&hello_world_rom()).unwrap();
+        let apu = APU::new(48_000, Arc::new(Mutex::new(VecDeque::new())));
+        let mut nes = Nes::new(cart, apu);
+        nes.reset(ResetKind::PowerOn);
+        for _ in 0..5 {
+            nes.step_frame();
+        }
+
+        let row = 0x21CA - 0x2000;
+        assert_eq!(&nes.bus.ppu.vram[row..row + 12], b"HELLO WORLD!");
+        assert_eq!(nes.bus.ppu.peek_palette(0x3F01), 0x30);
+        assert!(nes.bus.ppu.mask.show_background());
+        assert_eq!(nes.bus.cpu.registers.pc, 0x8069);
+    }
+}
@@ -0,0 +1,250 @@
+use core::fmt;
+
+use crate::opcodes::{AddressingMode, CPU_OPCODES, Opcode, OpcodeMap};
+use crate::symbols::SymbolTable;
+
+/// One decoded instruction. `opcode` is `None` when the input ended before
+/// all operand bytes were available; such bytes are shown as `.db`.
+#[derive(Debug)]
+pub struct Instruction<'a> {
+    pub address: u16,
+    pub bytes: &'a [u8],
+    pub opcode: Option<&'a Opcode>,
+}
+
+impl Instruction<'_> {
+    /// Address following this instruction.
+    pub fn next_address(&self) -> u16 {
+        self.address.wrapping_add(self.bytes.len() as u16)
+    }
+
+    /// Branch or jump destination, if it can be known without running the code.
+    pub fn target(&self) -> Option<u16> {
+        let opcode = self.opcode?;
+        match opcode.mode {
+            AddressingMode::Relative => {
+                let offset = self.bytes[1] as i8;
+                Some(self.next_address().wrapping_add(offset as u16))
+            }
+            AddressingMode::Absolute if opcode.code == 0x4c || opcode.code == 0x20 => {
+                Some(self.absolute())
+            }
+            _ => None,
+        }
+    }
+
+    /// The operand in assembler syntax, e.g. `($10),Y`.
+    pub fn operand(&self) -> String {
+        self.format_operand(None)
+    }
+
+    /// [`Self::operand`] with addresses that have a label in `symbols`
+    /// replaced by it, e.g. `(buffer),Y`.
+    pub fn labeled_operand(&self, symbols: &SymbolTable) -> String {
+        self.format_operand(Some(symbols))
+    }
+
+    fn format_operand(&self, symbols: Option<&SymbolTable>) -> String {
+        let Some(opcode) = self.opcode else {
+            return String::new();
+        };
+        let name = |addr: u16, zero_page: bool| match symbols.and_then(|s| s.label(addr, None)) {
+            Some(label) => label.to_string(),
+            None if zero_page => format!("${:02X}", addr),
+            None => format!("${:04X}", addr),
+        };
+
+        match opcode.mode {
+            AddressingMode::None => String::new(),
+            AddressingMode::Accumulator => "A".to_string(),
+            AddressingMode::Immediate => format!("#${:02X}", self.bytes[1]),
+            AddressingMode::ZeroPage => name(self.bytes[1] as u16, true),
+            AddressingMode::ZeroPageX => format!("{},X", name(self.bytes[1] as u16, true)),
+            AddressingMode::ZeroPageY => format!("{},Y", name(self.bytes[1] as u16, true)),
+            AddressingMode::Relative => name(self.target().unwrap_or(0), false),
+            AddressingMode::Absolute => name(self.absolute(), false),
+            AddressingMode::AbsoluteX => format!("{},X", name(self.absolute(), false)),
+            AddressingMode::AbsoluteY => format!("{},Y", name(self.absolute(), false)),
+            AddressingMode::Indirect => format!("({})", name(self.absolute(), false)),
+            AddressingMode::IndirectX => format!("({},X)", name(self.bytes[1] as u16, true)),
+            AddressingMode::IndirectY => format!("({}),Y", name(self.bytes[1] as u16, true)),
+            AddressingMode::ZeroPageIndirect => format!("({})", name(self.bytes[1] as u16, true)),
+        }
+    }
+
+    /// The [`Display`](fmt::Display) line with labels from `symbols` in the
+    /// operand.
+    pub fn labeled(&self, symbols: &SymbolTable) -> String {
+        self.format_line(self.labeled_operand(symbols))
+    }
+
+    fn format_line(&self, operand: String) -> String {
+        let hex = self
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let asm = match self.opcode {
+            Some(opcode) => format!("{} {}", opcode.mnemonic, operand),
+            None => {
+                let data = self
+                    .bytes
+                    .iter()
+                    .map(|byte| format!("${:02X}", byte))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                format!(".db {}", data)
+            }
+        };
+
+        format!("{:04X}  {:8}  {}", self.address, hex, asm.trim_end())
+    }
+
+    fn absolute(&self) -> u16 {
+        u16::from_le_bytes([self.bytes[1], self.bytes[2]])
+    }
+}
+
+/// Formats as `C000  4C F5 C5  JMP $C5F5`.
+impl fmt::Display for Instruction<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.format_line(self.operand()))
+    }
+}
+
+/// Linear-sweep disassembler over a block of memory loaded at `origin`.
+pub struct Disassembler<'a> {
+    data: &'a [u8],
+    origin: u16,
+    offset: usize,
+    opcodes: &'a OpcodeMap,
+}
+
+impl<'a> Disassembler<'a> {
+    pub fn new(data: &'a [u8], origin: u16) -> Self {
+        Self::with_opcodes(data, origin, &CPU_OPCODES)
+    }
+
+    pub fn with_opcodes(data: &'a [u8], origin: u16, opcodes: &'a OpcodeMap) -> Self {
+        Disassembler {
+            data,
+            origin,
+            offset: 0,
+            opcodes,
+        }
+    }
+
+    /// Continues decoding from `address` instead of the next instruction.
+    /// Addresses outside the block end the iteration.
+    pub fn seek(&mut self, address: u16) {
+        self.offset = address.wrapping_sub(self.origin) as usize;
+    }
+}
+
+impl<'a> Iterator for Disassembler<'a> {
+    type Item = Instruction<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = self
+            .data
+            .get(self.offset..)
+            .filter(|rest| !rest.is_empty())?;
+        let address = self.origin.wrapping_add(self.offset as u16);
+
+        let opcode = self.opcodes.find_by_code(rest[0]);
+        let len = opcode.map_or(1, |op| op.bytes as usize);
+        let (bytes, opcode) = if len <= rest.len() {
+            (&rest[..len], opcode)
+        } else {
+            (rest, None)
+        };
+
+        self.offset += bytes.len();
+        Some(Instruction {
+            address,
+            bytes,
+            opcode,
+        })
+    }
+}
+
+/// Convenience wrapper around [`Disassembler::new`].
+pub fn disassemble(data: &[u8], origin: u16) -> Disassembler<'_> {
+    Disassembler::new(data, origin)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_formats_addressing_modes() {
+        let program = [
+            0xA9, 0x10, // LDA #$10
+            0x0A, // ASL A
+            0xB1, 0x20, // LDA ($20),Y
+            0x9D, 0x00, 0x02, // STA $0200,X
+            0x6C, 0xFC, 0xFF, // JMP ($FFFC)
+            0xD0, 0xF3, // BNE $8000
+        ];
+        let lines: Vec<String> = disassemble(&program, 0x8000)
+            .map(|ins| ins.to_string())
+            .collect();
+
+        assert_eq!(
+            lines,
+            vec![
+                "8000  A9 10     LDA #$10",
+                "8002  0A        ASL A",
+                "8003  B1 20     LDA ($20),Y",
+                "8005  9D 00 02  STA $0200,X",
+                "8008  6C FC FF  JMP ($FFFC)",
+                "800B  D0 F3     BNE $8000",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_truncated_instruction_and_targets() {
+        let program = [0x20, 0x34, 0x12, 0x4C];
+        let mut disasm = disassemble(&program, 0xC000);
+
+        let jsr = disasm.next().unwrap();
+        assert_eq!(jsr.target(), Some(0x1234));
+        assert_eq!(jsr.next_address(), 0xC003);
+
+        let tail = disasm.next().unwrap();
+        assert!(tail.opcode.is_none());
+        assert_eq!(tail.to_string(), "C003  4C        .db $4C");
+        assert!(disasm.next().is_none());
+
+        disasm.seek(0xC001);
+        assert_eq!(disasm.next().unwrap().address, 0xC001);
+    }
+
+    #[test]
+    fn test_labels_replace_operand_addresses() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0x0020, None, "pointer");
+        symbols.insert(0xC000, None, "main_loop");
+        let program = [
+            0xB1, 0x20, // LDA ($20),Y
+            0x8D, 0x00, 0x02, // STA $0200
+            0x4C, 0x00, 0xC0, // JMP $C000
+        ];
+        let lines: Vec<String> = disassemble(&program, 0xC000)
+            .map(|ins| ins.labeled(&symbols))
+            .collect();
+
+        assert_eq!(
+            lines,
+            vec![
+                "C000  B1 20     LDA (pointer),Y",
+                "C002  8D 00 02  STA $0200",
+                "C005  4C 00 C0  JMP main_loop",
+            ]
+        );
+    }
+}
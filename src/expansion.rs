@@ -0,0 +1,181 @@
+//! A virtual expansion port: CPU addresses in `$4018-$5FFF` handed to
+//! callbacks, e.g. to light real LEDs or read real buttons from a game or
+//! homebrew ROM running on a microcontroller. That range is unused by the
+//! console itself; the test-mode registers, the link cable and the
+//! cartridge's expansion area all lose the addresses mapped here.
+//!
+//! Callbacks run in the middle of an instruction, on the cycle of the
+//! access, like [`crate::hooks`]. Debugger reads ([`crate::bus::Bus::peek`])
+//! don't reach them.
+
+use std::ops::RangeInclusive;
+
+/// The lowest and highest addresses a device may take.
+pub const EXPANSION_RANGE: RangeInclusive<u16> = 0x4018..=0x5FFF;
+
+pub type ExpansionRead = Box<dyn FnMut(u16) -> u8>;
+pub type ExpansionWrite = Box<dyn FnMut(u16, u8)>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DeviceId(usize);
+
+struct Device {
+    id: DeviceId,
+    start: u16,
+    end: u16,
+    read: ExpansionRead,
+    write: ExpansionWrite,
+}
+
+/// Devices on the port, each over an inclusive address range.
+#[derive(Default)]
+pub struct ExpansionPort {
+    devices: Vec<Device>,
+    next_id: usize,
+}
+
+impl ExpansionPort {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maps `start..=end` to a device. Fails outside [`EXPANSION_RANGE`] or
+    /// over another device.
+    pub fn map(
+        &mut self,
+        start: u16,
+        end: u16,
+        read: ExpansionRead,
+        write: ExpansionWrite,
+    ) -> Result<DeviceId, String> {
+        if start > end || !EXPANSION_RANGE.contains(&start) || !EXPANSION_RANGE.contains(&end) {
+            return Err(format!(
+                "Expansion devices must lie within $4018-$5FFF, not ${:04X}-${:04X}",
+                start, end
+            ));
+        }
+        if let Some(other) = self
+            .devices
+            .iter()
+            .find(|device| start <= device.end && device.start <= end)
+        {
+            return Err(format!(
+                "${:04X}-${:04X} overlaps a device at ${:04X}-${:04X}",
+                start, end, other.start, other.end
+            ));
+        }
+        let id = DeviceId(self.next_id);
+        self.next_id += 1;
+        self.devices.push(Device {
+            id,
+            start,
+            end,
+            read,
+            write,
+        });
+        Ok(id)
+    }
+
+    pub fn unmap(&mut self, id: DeviceId) -> bool {
+        let len = self.devices.len();
+        self.devices.retain(|device| device.id != id);
+        self.devices.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.devices.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    /// Whether any device has an address in CPU page `page`.
+    pub(crate) fn covers_page(&self, page: u8) -> bool {
+        let (start, end) = ((page as u16) << 8, (page as u16) << 8 | 0xFF);
+        self.devices
+            .iter()
+            .any(|device| device.start <= end && start <= device.end)
+    }
+
+    fn device(&mut self, addr: u16) -> Option<&mut Device> {
+        self.devices
+            .iter_mut()
+            .find(|device| (device.start..=device.end).contains(&addr))
+    }
+
+    /// The byte a device returns for `addr`, or `None` if none is mapped.
+    pub(crate) fn read(&mut self, addr: u16) -> Option<u8> {
+        self.device(addr).map(|device| (device.read)(addr))
+    }
+
+    /// Hands a write to the device at `addr`; `false` if none is mapped.
+    pub(crate) fn write(&mut self, addr: u16, value: u8) -> bool {
+        match self.device(addr) {
+            Some(device) => {
+                (device.write)(addr, value);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::cart::test::test_nes;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    #[test]
+    fn test_devices_see_reads_and_writes() {
+        // Copies the buttons at $5001 to the LEDs at $5000.
+        let program = assemble(
+            "
+                    .org $8000
+            reset:  lda $5001
+                    sta $5000
+                    jmp reset
+                    .org $FFFC
+                    .word reset, reset
+            ",
+        )
+        .unwrap();
+        let mut nes = test_nes(&program.slice(0x8000, 0x8000));
+
+        let buttons = Rc::new(Cell::new(0b101));
+        let leds = Rc::new(RefCell::new(Vec::new()));
+        let (read_buttons, write_leds) = (Rc::clone(&buttons), Rc::clone(&leds));
+        nes.map_expansion(
+            0x5000,
+            0x5001,
+            Box::new(move |addr| {
+                if addr == 0x5001 {
+                    read_buttons.get()
+                } else {
+                    0
+                }
+            }),
+            Box::new(move |addr, value| write_leds.borrow_mut().push((addr, value))),
+        )
+        .unwrap();
+
+        for _ in 0..3 {
+            nes.clock();
+        }
+        buttons.set(0b010);
+        for _ in 0..3 {
+            nes.clock();
+        }
+        assert_eq!(*leds.borrow(), [(0x5000, 0b101), (0x5000, 0b010)]);
+        assert_eq!(nes.bus.peek(0x5001), 0);
+
+        let noop = || -> (ExpansionRead, ExpansionWrite) { (Box::new(|_| 0), Box::new(|_, _| {})) };
+        let (read, write) = noop();
+        assert!(nes.map_expansion(0x5001, 0x5002, read, write).is_err());
+        let (read, write) = noop();
+        assert!(nes.map_expansion(0x6000, 0x6000, read, write).is_err());
+    }
+}
@@ -0,0 +1,198 @@
+use crate::ppu::framebuffer::Framebuffer;
+
+/// What the core does with a finished frame when a sink reports it is not
+/// ready to take it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackPressure {
+    /// Deliver every frame regardless; the sink is allowed to slow emulation.
+    Block,
+    /// Skip frames that arrive while the sink is busy.
+    DropNewest,
+    /// Hold on to the most recent frame and deliver it once the sink is ready.
+    KeepLatest,
+}
+
+/// A consumer of completed frames (display, video recorder, netplay, ...).
+pub trait FrameSink {
+    fn consume(&mut self, frame: &Framebuffer);
+
+    fn ready(&self) -> bool {
+        true
+    }
+
+    fn back_pressure(&self) -> BackPressure {
+        BackPressure::Block
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FrameSinkId(usize);
+
+struct SinkSlot {
+    id: FrameSinkId,
+    sink: Box<dyn FrameSink>,
+    pending: Option<Framebuffer>,
+}
+
+/// The set of sinks registered on the core. Each completed frame is offered to
+/// every sink according to that sink's back-pressure policy.
+#[derive(Default)]
+pub struct FrameSinks {
+    slots: Vec<SinkSlot>,
+    next_id: usize,
+}
+
+impl FrameSinks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, sink: Box<dyn FrameSink>) -> FrameSinkId {
+        let id = FrameSinkId(self.next_id);
+        self.next_id += 1;
+        self.slots.push(SinkSlot {
+            id,
+            sink,
+            pending: None,
+        });
+        id
+    }
+
+    pub fn remove(&mut self, id: FrameSinkId) -> Option<Box<dyn FrameSink>> {
+        let index = self.slots.iter().position(|slot| slot.id == id)?;
+        Some(self.slots.remove(index).sink)
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    pub fn dispatch(&mut self, frame: &Framebuffer) {
+        for slot in &mut self.slots {
+            if slot.sink.ready() {
+                slot.pending = None;
+                slot.sink.consume(frame);
+                continue;
+            }
+
+            match slot.sink.back_pressure() {
+                BackPressure::Block => slot.sink.consume(frame),
+                BackPressure::DropNewest => {}
+                BackPressure::KeepLatest => match &mut slot.pending {
+                    Some(pending) => {
+                        pending.data.copy_from_slice(&frame.data);
+                        pending.indices.copy_from_slice(&frame.indices);
+                    }
+                    None => {
+                        slot.pending = Some(Framebuffer {
+                            data: frame.data.clone(),
+                            indices: frame.indices.clone(),
+                        })
+                    }
+                },
+            }
+        }
+    }
+
+    /// Delivers frames held back by `KeepLatest` sinks that have become ready
+    /// since the last dispatch.
+    pub fn flush(&mut self) {
+        for slot in &mut self.slots {
+            if slot.pending.is_some() && slot.sink.ready() {
+                let frame = slot.pending.take().unwrap();
+                slot.sink.consume(&frame);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    struct Recorder {
+        policy: BackPressure,
+        ready: Rc<Cell<bool>>,
+        seen: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl FrameSink for Recorder {
+        fn consume(&mut self, frame: &Framebuffer) {
+            self.seen.borrow_mut().push(frame.data[0]);
+        }
+
+        fn ready(&self) -> bool {
+            self.ready.get()
+        }
+
+        fn back_pressure(&self) -> BackPressure {
+            self.policy
+        }
+    }
+
+    type Handles = (Rc<Cell<bool>>, Rc<RefCell<Vec<u8>>>);
+
+    fn recorder(policy: BackPressure) -> (Recorder, Handles) {
+        let ready = Rc::new(Cell::new(true));
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let sink = Recorder {
+            policy,
+            ready: ready.clone(),
+            seen: seen.clone(),
+        };
+        (sink, (ready, seen))
+    }
+
+    fn frame(tag: u8) -> Framebuffer {
+        let mut frame = Framebuffer::new();
+        frame.data[0] = tag;
+        frame
+    }
+
+    #[test]
+    fn test_back_pressure_policies() {
+        let mut sinks = FrameSinks::new();
+        let (block, (block_ready, block_seen)) = recorder(BackPressure::Block);
+        let (drop, (drop_ready, drop_seen)) = recorder(BackPressure::DropNewest);
+        let (latest, (latest_ready, latest_seen)) = recorder(BackPressure::KeepLatest);
+        sinks.add(Box::new(block));
+        sinks.add(Box::new(drop));
+        sinks.add(Box::new(latest));
+
+        sinks.dispatch(&frame(1));
+        block_ready.set(false);
+        drop_ready.set(false);
+        latest_ready.set(false);
+        sinks.dispatch(&frame(2));
+        sinks.dispatch(&frame(3));
+        sinks.flush();
+
+        block_ready.set(true);
+        drop_ready.set(true);
+        latest_ready.set(true);
+        sinks.flush();
+        sinks.dispatch(&frame(4));
+
+        assert_eq!(*block_seen.borrow(), vec![1, 2, 3, 4]);
+        assert_eq!(*drop_seen.borrow(), vec![1, 4]);
+        assert_eq!(*latest_seen.borrow(), vec![1, 3, 4]);
+    }
+
+    #[test]
+    fn test_remove_sink() {
+        let mut sinks = FrameSinks::new();
+        let (sink, (_, seen)) = recorder(BackPressure::Block);
+        let id = sinks.add(Box::new(sink));
+
+        assert!(sinks.remove(id).is_some());
+        assert!(sinks.remove(id).is_none());
+        sinks.dispatch(&frame(1));
+        assert!(seen.borrow().is_empty());
+    }
+}
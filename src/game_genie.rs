@@ -0,0 +1,119 @@
+//! Game Genie codes. The Game Genie sat between the cartridge and the
+//! console and answered CPU reads of PRG ROM ($8000-$FFFF) itself: a
+//! 6-letter code replaces the byte at an address, an 8-letter one only
+//! while the cartridge would have returned the compare value, so a code
+//! meant for one PRG bank leaves the others alone. pico applies codes the
+//! same way, as substitute cheats; see [`crate::cheats`].
+
+const LETTERS: &[u8; 16] = b"APZLGITYEOXUKSVN";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GameGenieCode {
+    /// Always in $8000-$FFFF.
+    pub addr: u16,
+    pub value: u8,
+    pub compare: Option<u8>,
+}
+
+/// Decodes a 6- or 8-letter code, in either case.
+pub fn decode(code: &str) -> Result<GameGenieCode, String> {
+    let n: Vec<u16> = code
+        .trim()
+        .bytes()
+        .map(|c| {
+            LETTERS
+                .iter()
+                .position(|&l| l == c.to_ascii_uppercase())
+                .map(|i| i as u16)
+        })
+        .collect::<Option<_>>()
+        .ok_or_else(|| format!("Invalid Game Genie code: {}", code))?;
+    if n.len() != 6 && n.len() != 8 {
+        return Err(format!("Invalid Game Genie code: {}", code));
+    }
+
+    let addr = 0x8000
+        | ((n[3] & 7) << 12)
+        | ((n[5] & 7) << 8)
+        | ((n[4] & 8) << 8)
+        | ((n[2] & 7) << 4)
+        | ((n[1] & 8) << 4)
+        | (n[4] & 7)
+        | (n[3] & 8);
+    let value = ((n[1] & 7) << 4) | ((n[0] & 8) << 4) | (n[0] & 7);
+    let (value, compare) = if n.len() == 6 {
+        (value | (n[5] & 8), None)
+    } else {
+        let compare = ((n[7] & 7) << 4) | ((n[6] & 8) << 4) | (n[6] & 7) | (n[5] & 8);
+        (value | (n[7] & 8), Some(compare as u8))
+    };
+    Ok(GameGenieCode {
+        addr,
+        value: value as u8,
+        compare,
+    })
+}
+
+/// The code for `code`, 8 letters if it has a compare value. Addresses
+/// below $8000 can't be written and come back as their PRG ROM mirror.
+pub fn encode(code: GameGenieCode) -> String {
+    let (addr, value) = (code.addr, code.value as u16);
+    let mut n = [0u16; 8];
+    n[0] = (value & 7) | ((value >> 4) & 8);
+    n[1] = ((value >> 4) & 7) | ((addr >> 4) & 8);
+    n[2] = (addr >> 4) & 7;
+    n[3] = ((addr >> 12) & 7) | (addr & 8);
+    n[4] = (addr & 7) | ((addr >> 8) & 8);
+    n[5] = (addr >> 8) & 7;
+    let len = match code.compare {
+        None => {
+            n[5] |= value & 8;
+            6
+        }
+        Some(compare) => {
+            let compare = compare as u16;
+            n[5] |= compare & 8;
+            n[6] = (compare & 7) | ((compare >> 4) & 8);
+            n[7] = ((compare >> 4) & 7) | (value & 8);
+            8
+        }
+    };
+    // The third letter's high bit tells the Game Genie an 8-letter code
+    // follows; decoding ignores it.
+    if len == 8 {
+        n[2] |= 8;
+    }
+    n[..len]
+        .iter()
+        .map(|&i| LETTERS[i as usize] as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode_and_encode() {
+        assert_eq!(
+            decode("SXIOPO").unwrap(),
+            GameGenieCode {
+                addr: 0x91D9,
+                value: 0xAD,
+                compare: None,
+            }
+        );
+        assert_eq!(
+            decode("yeuzugaa").unwrap(),
+            GameGenieCode {
+                addr: 0xACB3,
+                value: 0x07,
+                compare: Some(0x00),
+            }
+        );
+        assert_eq!(encode(decode("SXIOPO").unwrap()), "SXIOPO");
+        assert_eq!(encode(decode("YEUZUGAA").unwrap()), "YEUZUGAA");
+        assert!(decode("SXIOP").is_err());
+        assert!(decode("SXIOPB").is_err());
+    }
+}
@@ -0,0 +1,95 @@
+//! Runs a ROM for a fixed number of frames without a window or audio device,
+//! so a bug report (ROM + movie + frame count) replays identically anywhere.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use crate::apu::APU;
+use crate::cart::Cart;
+use crate::cpu::{ResetKind, StopReason};
+use crate::movie::{FM2Movie, InputTiming};
+use crate::nes::Nes;
+use crate::ppu::framebuffer::Framebuffer;
+
+pub struct HeadlessRun {
+    pub nes: Nes,
+    movie: Option<FM2Movie>,
+    frame: usize,
+}
+
+impl HeadlessRun {
+    /// Sample rate of the APU's output.
+    pub const SAMPLE_RATE: u32 = 48_000;
+
+    /// Resets the console and attaches `movie`, if any. Frame-timed movies
+    /// are applied before each frame; strobe-timed ones from inside the core.
+    pub fn new(cart: Cart, movie: Option<FM2Movie>) -> Result<Self, String> {
+        // Nothing drains the samples; the APU drops the oldest once full.
+        let apu = APU::new(Self::SAMPLE_RATE, Arc::new(Mutex::new(VecDeque::new())));
+        let mut nes = Nes::new(cart, apu);
+        nes.reset(ResetKind::PowerOn);
+
+        let movie = match movie {
+            Some(movie) if movie.header.input_timing == InputTiming::Strobe => {
+                nes.attach_subframe_movie(movie)?;
+                None
+            }
+            movie => movie,
+        };
+
+        Ok(HeadlessRun {
+            nes,
+            movie,
+            frame: 0,
+        })
+    }
+
+    /// Frames run so far.
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    /// Runs `count` frames, stopping early at a breakpoint or watchpoint.
+    pub fn run_frames(&mut self, count: usize) -> Option<StopReason> {
+        for _ in 0..count {
+            if let Some(movie) = &self.movie {
+                let (joypad1, joypad2) = self.nes.joypads_mut();
+                let _ = movie.apply_frame_input(self.frame, joypad1, joypad2);
+            }
+            let stop = self.nes.step_frame();
+            self.frame += 1;
+            if stop.is_some() {
+                return stop;
+            }
+        }
+        None
+    }
+
+    /// Renders the last completed frame.
+    pub fn screenshot(&mut self) -> Framebuffer {
+        let mut framebuffer = Framebuffer::new();
+        self.nes.present_frame(&mut framebuffer);
+        framebuffer
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::demo;
+
+    #[test]
+    fn test_runs_are_reproducible() {
+        let run = || {
+            let cart = Cart::new(&demo::hello_world_rom()).unwrap();
+            let mut run = HeadlessRun::new(cart, None).unwrap();
+            assert_eq!(run.run_frames(10), None);
+            (run.nes.dump_state(), run.screenshot().data)
+        };
+
+        let (state, pixels) = run();
+        assert_eq!(run(), (state.clone(), pixels.clone()));
+        assert_eq!(&state[..8], b"PICODUMP");
+        assert!(pixels.iter().any(|&p| p != pixels[0]));
+    }
+}
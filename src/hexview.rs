@@ -0,0 +1,180 @@
+//! Side-effect-free view of the CPU address space for hex editors, with each
+//! byte labelled by what it is mapped to.
+
+use core::fmt;
+use std::ops::RangeInclusive;
+
+use crate::bus::Bus;
+
+/// What a CPU address is mapped to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    /// Internal RAM, $0000-$07FF.
+    Ram,
+    /// $0800-$1FFF, a mirror of internal RAM.
+    RamMirror,
+    /// $2000-$2007 and its mirrors up to $3FFF.
+    PpuRegister,
+    /// APU and controller registers, $4000-$4017.
+    ApuIo,
+    /// Cartridge work RAM at $6000-$7FFF.
+    PrgRam,
+    /// Cartridge PRG ROM, with the bank currently mapped if the mapper banks.
+    PrgRom { bank: Option<usize> },
+    /// Nothing drives the data bus here; reads return stale bus contents.
+    OpenBus,
+}
+
+impl Region {
+    /// Classifies `addr` using the cartridge's current bank configuration.
+    pub fn of(bus: &Bus, addr: u16) -> Region {
+        match addr {
+            0x0000..=0x07FF => Region::Ram,
+            0x0800..=0x1FFF => Region::RamMirror,
+            0x2000..=0x3FFF => Region::PpuRegister,
+            0x4000..=0x4017 => Region::ApuIo,
+            0x4018..=0x5FFF => Region::OpenBus,
+            0x6000..=0x7FFF if bus.cart.mapper.prg_ram().is_some() => Region::PrgRam,
+            0x6000..=0x7FFF => Region::OpenBus,
+            0x8000..=0xFFFF => Region::PrgRom {
+                bank: bus.cart.mapper.prg_bank(addr),
+            },
+        }
+    }
+
+    /// The address a mirrored byte is an alias of.
+    pub fn mirror_of(&self, addr: u16) -> Option<u16> {
+        match self {
+            Region::RamMirror => Some(addr & 0x07FF),
+            Region::PpuRegister if addr > 0x2007 => Some(0x2000 | (addr & 0x0007)),
+            _ => None,
+        }
+    }
+
+    /// Whether [`HexView::bytes`] shows the real contents. Registers can't be
+    /// read without side effects, so they and open bus show as zero.
+    pub fn is_readable(&self) -> bool {
+        matches!(
+            self,
+            Region::Ram | Region::RamMirror | Region::PrgRam | Region::PrgRom { .. }
+        )
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Region::Ram => write!(f, "RAM"),
+            Region::RamMirror => write!(f, "RAM mirror"),
+            Region::PpuRegister => write!(f, "PPU register"),
+            Region::ApuIo => write!(f, "APU/IO register"),
+            Region::PrgRam => write!(f, "PRG-RAM"),
+            Region::PrgRom { bank: Some(bank) } => write!(f, "PRG bank {}", bank),
+            Region::PrgRom { bank: None } => write!(f, "PRG ROM"),
+            Region::OpenBus => write!(f, "open bus"),
+        }
+    }
+}
+
+/// A run of consecutive addresses in the same region.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Annotation {
+    pub start: u16,
+    pub end: u16,
+    pub region: Region,
+}
+
+#[derive(Debug, Clone)]
+pub struct HexView {
+    pub start: u16,
+    pub bytes: Vec<u8>,
+    pub annotations: Vec<Annotation>,
+}
+
+impl HexView {
+    /// Region for a single address within the view.
+    pub fn region_at(&self, addr: u16) -> Option<Region> {
+        self.annotations
+            .iter()
+            .find(|a| (a.start..=a.end).contains(&addr))
+            .map(|a| a.region)
+    }
+}
+
+/// Peeks `range` without disturbing the machine and annotates it.
+pub fn hex_view(bus: &Bus, range: RangeInclusive<u16>) -> HexView {
+    let start = *range.start();
+    let mut bytes = Vec::with_capacity(range.len());
+    let mut annotations: Vec<Annotation> = Vec::new();
+
+    for addr in range {
+        let region = Region::of(bus, addr);
+        bytes.push(if region.is_readable() {
+            bus.peek(addr)
+        } else {
+            0
+        });
+
+        match annotations.last_mut() {
+            Some(last) if last.region == region && last.end.wrapping_add(1) == addr => {
+                last.end = addr;
+            }
+            _ => annotations.push(Annotation {
+                start: addr,
+                end: addr,
+                region,
+            }),
+        }
+    }
+
+    HexView {
+        start,
+        bytes,
+        annotations,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::apu::APU;
+    use crate::cart::test::test_rom;
+    use crate::memory::Memory;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_annotates_memory_map() {
+        let apu = APU::new(48_000, Arc::new(Mutex::new(VecDeque::new())));
+        let mut bus = Bus::new(test_rom(vec![0xEA; 4]), apu);
+        bus.write(0x0001, 0x42);
+        bus.write(0x6000, 0x17);
+
+        let view = hex_view(&bus, 0x0000..=0xFFFF);
+        let regions: Vec<(u16, u16, String)> = view
+            .annotations
+            .iter()
+            .map(|a| (a.start, a.end, a.region.to_string()))
+            .collect();
+        assert_eq!(
+            regions,
+            vec![
+                (0x0000, 0x07FF, "RAM".to_string()),
+                (0x0800, 0x1FFF, "RAM mirror".to_string()),
+                (0x2000, 0x3FFF, "PPU register".to_string()),
+                (0x4000, 0x4017, "APU/IO register".to_string()),
+                (0x4018, 0x5FFF, "open bus".to_string()),
+                (0x6000, 0x7FFF, "PRG-RAM".to_string()),
+                (0x8000, 0xFFFF, "PRG bank 0".to_string()),
+            ]
+        );
+
+        assert_eq!(view.bytes[0x0801], 0x42);
+        assert_eq!(view.bytes[0x6000], 0x17);
+        assert_eq!(view.bytes[0x8000], 0xEA);
+        assert_eq!(
+            view.region_at(0x2009).unwrap().mirror_of(0x2009),
+            Some(0x2001)
+        );
+    }
+}
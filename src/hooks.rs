@@ -0,0 +1,160 @@
+//! Callbacks on CPU memory accesses, for achievements, scripts and memory
+//! editors that need to see accesses as they happen rather than once per
+//! frame.
+//!
+//! Hooks only see accesses made by the emulated CPU, after cheats have
+//! patched the value read. An opcode fetch fires execute hooks, not read
+//! hooks; operand and data reads fire read hooks. A callback runs in the
+//! middle of an instruction and has no access to the console, so anything
+//! it wants to change has to wait until [`crate::nes::Nes::clock`] returns.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookKind {
+    Read,
+    Write,
+    Execute,
+}
+
+/// The access a hook fired on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub kind: HookKind,
+    pub addr: u16,
+    /// The byte read or written; the opcode for [`HookKind::Execute`].
+    pub value: u8,
+}
+
+pub type HookCallback = Box<dyn FnMut(&Access)>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HookId(usize);
+
+struct Hook {
+    id: HookId,
+    kind: HookKind,
+    start: u16,
+    end: u16,
+    callback: HookCallback,
+}
+
+/// Hooks registered on the bus, each over an inclusive address range.
+#[derive(Default)]
+pub struct Hooks {
+    hooks: Vec<Hook>,
+    next_id: usize,
+}
+
+impl Hooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, kind: HookKind, start: u16, end: u16, callback: HookCallback) -> HookId {
+        let id = HookId(self.next_id);
+        self.next_id += 1;
+        self.hooks.push(Hook {
+            id,
+            kind,
+            start,
+            end,
+            callback,
+        });
+        id
+    }
+
+    pub fn remove(&mut self, id: HookId) -> bool {
+        let len = self.hooks.len();
+        self.hooks.retain(|hook| hook.id != id);
+        self.hooks.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.hooks.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.hooks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    pub(crate) fn fire(&mut self, kind: HookKind, addr: u16, value: u8) {
+        let access = Access { kind, addr, value };
+        for hook in &mut self.hooks {
+            if hook.kind == kind && (hook.start..=hook.end).contains(&addr) {
+                (hook.callback)(&access);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::cart::test::test_nes;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_hooks_see_reads_writes_and_fetches() {
+        let program = assemble(
+            "
+                    .org $8000
+            reset:  lda $10
+                    sta $0300
+            loop:   jmp loop
+                    .org $FFFC
+                    .word reset, reset
+            ",
+        )
+        .unwrap();
+        let mut nes = test_nes(&program.slice(0x8000, 0x8000));
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let log = |seen: &Rc<RefCell<Vec<Access>>>| -> HookCallback {
+            let seen = Rc::clone(seen);
+            Box::new(move |access| seen.borrow_mut().push(*access))
+        };
+        nes.add_hook(HookKind::Read, 0x0000, 0x07FF, log(&seen));
+        nes.add_hook(HookKind::Write, 0x0300, 0x0300, log(&seen));
+        let fetches = nes.add_hook(HookKind::Execute, 0x8000, 0x8004, log(&seen));
+
+        for _ in 0..3 {
+            nes.clock();
+        }
+        assert_eq!(
+            *seen.borrow(),
+            [
+                Access {
+                    kind: HookKind::Execute,
+                    addr: 0x8000,
+                    value: 0xA5
+                },
+                Access {
+                    kind: HookKind::Read,
+                    addr: 0x0010,
+                    value: 0
+                },
+                Access {
+                    kind: HookKind::Execute,
+                    addr: 0x8002,
+                    value: 0x8D
+                },
+                Access {
+                    kind: HookKind::Write,
+                    addr: 0x0300,
+                    value: 0
+                },
+            ]
+        );
+
+        assert!(nes.remove_hook(fetches));
+        assert!(!nes.remove_hook(fetches));
+        seen.borrow_mut().clear();
+        nes.clock();
+        assert!(seen.borrow().is_empty());
+    }
+}
@@ -0,0 +1,132 @@
+//! Short, frame-accurate button sequences that can be bound to a key, e.g.
+//! to practise a trick or to get past a title screen in a smoke test.
+//!
+//! A sequence is written as whitespace-separated steps. Each step is a set of
+//! buttons joined by `+` (or `.` for none), optionally followed by `*N` to hold
+//! it for N frames:
+//!
+//! ```text
+//! start*2 .*30 right+b*45 right+a+b
+//! ```
+
+use core::fmt;
+
+use crate::config::BUTTON_NAMES;
+use crate::joypad::JoypadButton;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputMacro {
+    /// Buttons held on each frame, in order.
+    pub frames: Vec<JoypadButton>,
+}
+
+impl InputMacro {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut frames = Vec::new();
+        for step in text.split_whitespace() {
+            let (buttons, count) = match step.split_once('*') {
+                Some((buttons, count)) => {
+                    let count: usize = count
+                        .parse()
+                        .map_err(|_| format!("Invalid frame count in {:?}", step))?;
+                    (buttons, count)
+                }
+                None => (step, 1),
+            };
+
+            let mut held = JoypadButton::empty();
+            if buttons != "." {
+                for name in buttons.split('+') {
+                    let (_, button) = BUTTON_NAMES
+                        .iter()
+                        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                        .ok_or_else(|| format!("Unknown button {:?} in {:?}", name, step))?;
+                    held |= *button;
+                }
+            }
+            frames.extend(std::iter::repeat_n(held, count));
+        }
+
+        if frames.is_empty() {
+            return Err("Macro has no frames".to_string());
+        }
+        Ok(InputMacro { frames })
+    }
+
+    pub fn play(&self) -> MacroPlayback {
+        MacroPlayback {
+            input_macro: self.clone(),
+            frame: 0,
+        }
+    }
+}
+
+/// Writes the sequence back in the same syntax [`InputMacro::parse`] accepts.
+impl fmt::Display for InputMacro {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut steps = Vec::new();
+        for chunk in self.frames.chunk_by(|a, b| a == b) {
+            let held = chunk[0];
+            let buttons = if held.is_empty() {
+                ".".to_string()
+            } else {
+                BUTTON_NAMES
+                    .iter()
+                    .filter(|(_, button)| held.contains(*button))
+                    .map(|(name, _)| *name)
+                    .collect::<Vec<_>>()
+                    .join("+")
+            };
+            match chunk.len() {
+                1 => steps.push(buttons),
+                n => steps.push(format!("{}*{}", buttons, n)),
+            }
+        }
+        write!(f, "{}", steps.join(" "))
+    }
+}
+
+/// Steps through a macro one frame at a time.
+pub struct MacroPlayback {
+    input_macro: InputMacro,
+    frame: usize,
+}
+
+impl MacroPlayback {
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.input_macro.frames.len()
+    }
+}
+
+impl Iterator for MacroPlayback {
+    type Item = JoypadButton;
+
+    fn next(&mut self) -> Option<JoypadButton> {
+        let held = self.input_macro.frames.get(self.frame).copied()?;
+        self.frame += 1;
+        Some(held)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_play() {
+        let input_macro = InputMacro::parse("start*2 . right+A*3").unwrap();
+        assert_eq!(input_macro.to_string(), "start*2 . right+a*3");
+
+        let mut playback = input_macro.play();
+        let frames: Vec<JoypadButton> = playback.by_ref().collect();
+        assert_eq!(frames.len(), 6);
+        assert_eq!(frames[0], JoypadButton::START);
+        assert_eq!(frames[2], JoypadButton::empty());
+        assert_eq!(frames[5], JoypadButton::RIGHT | JoypadButton::BUTTON_A);
+        assert!(playback.is_finished());
+
+        assert!(InputMacro::parse("jump*2").is_err());
+        assert!(InputMacro::parse("a*x").is_err());
+        assert!(InputMacro::parse("").is_err());
+    }
+}
@@ -1,7 +1,7 @@
 use bitflags::bitflags;
 
 bitflags! {
-    #[derive(Copy, Clone, Eq, PartialEq, Hash)]
+    #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
     // https://wiki.nesdev.com/w/index.php/Controller_reading_code
     pub struct JoypadButton: u8 {
         const RIGHT             = 0b10000000;
@@ -15,10 +15,12 @@ bitflags! {
     }
 }
 
+#[derive(Clone)]
 pub struct Joypad {
     pub button_status: JoypadButton,
     pub button_index: u8,
     strobe: bool,
+    latch_count: u64,
 }
 
 impl Default for Joypad {
@@ -33,21 +35,37 @@ impl Joypad {
             strobe: false,
             button_index: 0,
             button_status: JoypadButton::from_bits_truncate(0),
+            latch_count: 0,
         }
     }
 
     pub fn write(&mut self, data: u8) {
+        let was_strobing = self.strobe;
         self.strobe = data & 1 == 1;
         if self.strobe {
             self.button_index = 0
         }
+        if was_strobing && !self.strobe {
+            self.latch_count += 1;
+        }
     }
 
-    pub fn read(&mut self) -> u8 {
+    /// Number of times the buttons have been latched (strobe released) since
+    /// power-on. Subframe movies key their input records on this.
+    pub fn latch_count(&self) -> u64 {
+        self.latch_count
+    }
+
+    /// The bit the next read returns, without shifting to the one after.
+    pub fn peek(&self) -> u8 {
         if self.button_index > 7 {
             return 1;
         }
-        let response = (self.button_status.bits() & (1 << self.button_index)) >> self.button_index;
+        (self.button_status.bits() & (1 << self.button_index)) >> self.button_index
+    }
+
+    pub fn read(&mut self) -> u8 {
+        let response = self.peek();
         if !self.strobe && self.button_index <= 7 {
             self.button_index += 1;
         }
@@ -100,4 +118,18 @@ mod test {
             joypad.write(0);
         }
     }
+
+    #[test]
+    fn test_latch_count_counts_strobe_release() {
+        let mut joypad = Joypad::new();
+        joypad.write(0);
+        assert_eq!(joypad.latch_count(), 0);
+
+        joypad.write(1);
+        joypad.write(1);
+        assert_eq!(joypad.latch_count(), 0);
+        joypad.write(0);
+        joypad.write(0);
+        assert_eq!(joypad.latch_count(), 1);
+    }
 }
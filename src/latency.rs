@@ -0,0 +1,260 @@
+//! Input latency measurement. The bundled test ROM turns the whole screen
+//! white and starts a short beep in the vblank after it reads A held on
+//! controller 1, so the frontend can time a press against the frame that
+//! shows it and compare vsync and input-polling settings objectively.
+
+use std::time::{Duration, Instant};
+
+use crate::joypad::JoypadButton;
+use crate::nes::Nes;
+use crate::ppu::framebuffer::Framebuffer;
+
+const PRG_SIZE: usize = 0x4000;
+const CHR_SIZE: usize = 0x2000;
+
+// Hand-assembled; the PRG bank is mapped at $8000 and mirrored at $C000.
+// Every tile is blank, so the screen is the backdrop colour at $3F00.
+#[rustfmt::skip]
+const PROGRAM: [u8; 0x6F] = [
+    0x78,             // 8000  SEI
+    0xD8,             // 8001  CLD
+    0xA2, 0xFF,       // 8002  LDX #$FF
+    0x9A,             // 8004  TXS
+    0xA9, 0x00,       // 8005  LDA #$00
+    0x8D, 0x00, 0x20, // 8007  STA $2000
+    0x8D, 0x01, 0x20, // 800A  STA $2001
+    0x2C, 0x02, 0x20, // 800D  BIT $2002    ; wait for the PPU to warm up
+    0x10, 0xFB,       // 8010  BPL $800D
+    0x2C, 0x02, 0x20, // 8012  BIT $2002
+    0x10, 0xFB,       // 8015  BPL $8012
+    0x85, 0x00,       // 8017  STA $00      ; A held last vblank
+    0xA9, 0x01,       // 8019  LDA #$01     ; enable pulse 1
+    0x8D, 0x15, 0x40, // 801B  STA $4015
+    0xA9, 0x0A,       // 801E  LDA #$0A     ; show background
+    0x8D, 0x01, 0x20, // 8020  STA $2001
+    0xA9, 0x80,       // 8023  LDA #$80     ; NMI on
+    0x8D, 0x00, 0x20, // 8025  STA $2000
+    0x4C, 0x28, 0x80, // 8028  JMP $8028
+    0x48,             // 802B  PHA          ; NMI handler
+    0xA9, 0x01,       // 802C  LDA #$01     ; strobe the controllers
+    0x8D, 0x16, 0x40, // 802E  STA $4016
+    0xA9, 0x00,       // 8031  LDA #$00
+    0x8D, 0x16, 0x40, // 8033  STA $4016
+    0xAD, 0x16, 0x40, // 8036  LDA $4016    ; A is the first bit out
+    0x29, 0x01,       // 8039  AND #$01
+    0xAA,             // 803B  TAX
+    0xF0, 0x13,       // 803C  BEQ $8051
+    0xC5, 0x00,       // 803E  CMP $00      ; beep on the press, not while held
+    0xF0, 0x0F,       // 8040  BEQ $8051
+    0xA9, 0x9F,       // 8042  LDA #$9F     ; 50% duty, constant volume 15
+    0x8D, 0x00, 0x40, // 8044  STA $4000
+    0xA9, 0xFD,       // 8047  LDA #$FD     ; ~440 Hz
+    0x8D, 0x02, 0x40, // 8049  STA $4002
+    0xA9, 0x00,       // 804C  LDA #$00     ; length 10 half-frames
+    0x8D, 0x03, 0x40, // 804E  STA $4003
+    0x86, 0x00,       // 8051  STX $00
+    0xA9, 0x3F,       // 8053  LDA #$3F     ; backdrop colour
+    0x8D, 0x06, 0x20, // 8055  STA $2006
+    0xA9, 0x00,       // 8058  LDA #$00
+    0x8D, 0x06, 0x20, // 805A  STA $2006
+    0xBD, 0x6D, 0x80, // 805D  LDA $806D,X
+    0x8D, 0x07, 0x20, // 8060  STA $2007
+    0xA9, 0x00,       // 8063  LDA #$00     ; point the PPU back at $0000
+    0x8D, 0x06, 0x20, // 8065  STA $2006
+    0x8D, 0x06, 0x20, // 8068  STA $2006
+    0x68,             // 806B  PLA
+    0x40,             // 806C  RTI          ; also the IRQ handler
+    0x0F, 0x30,       // 806D  black, white
+];
+const NMI: u16 = 0x802B;
+const IRQ: u16 = 0x806C;
+const RESET: u16 = 0x8000;
+
+/// Builds the latency test ROM as an iNES image.
+pub fn latency_test_rom() -> Vec<u8> {
+    let mut rom = vec![0; 16 + PRG_SIZE + CHR_SIZE];
+    rom[..8].copy_from_slice(&[b'N', b'E', b'S', 0x1A, 1, 1, 0x01, 0x00]);
+
+    let prg = &mut rom[16..16 + PRG_SIZE];
+    prg[..PROGRAM.len()].copy_from_slice(&PROGRAM);
+    for (offset, vector) in [(0x3FFA, NMI), (0x3FFC, RESET), (0x3FFE, IRQ)] {
+        prg[offset..offset + 2].copy_from_slice(&vector.to_le_bytes());
+    }
+    rom
+}
+
+/// Whether a frame from the test ROM shows a press.
+pub fn is_lit(framebuffer: &Framebuffer) -> bool {
+    let center = (Framebuffer::HEIGHT / 2 * Framebuffer::WIDTH + Framebuffer::WIDTH / 2) * 3;
+    let pixel = &framebuffer.data[center..center + 3];
+    pixel.iter().map(|&c| c as u32).sum::<u32>() > 3 * 128
+}
+
+/// Emulation-side latency of `nes` running the test ROM: how many frames,
+/// counting the one the press is first applied to, until one shows it.
+/// Returns `None` if nothing shows within `max_frames`. A is released again
+/// afterwards.
+pub fn measure_frames(nes: &mut Nes, max_frames: u32) -> Option<u32> {
+    let mut framebuffer = Framebuffer::new();
+    let set_a = |nes: &mut Nes, pressed| {
+        if let Some(joypad) = nes.joypad_mut(0) {
+            joypad.set_button_pressed_status(JoypadButton::BUTTON_A, pressed);
+        }
+    };
+
+    set_a(nes, false);
+    for _ in 0..2 {
+        nes.step_frame();
+    }
+
+    set_a(nes, true);
+    let mut result = None;
+    for frames in 1..=max_frames {
+        nes.step_frame();
+        nes.present_frame(&mut framebuffer);
+        if is_lit(&framebuffer) {
+            result = Some(frames);
+            break;
+        }
+    }
+    set_a(nes, false);
+    result
+}
+
+/// One press timed by [`LatencyMeter`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatencySample {
+    /// Frames presented from the press up to and including the one showing
+    /// it.
+    pub frames: u32,
+    /// Host time from the press reaching the core to that frame being
+    /// presented.
+    pub video: Duration,
+    /// Audio queued ahead of the beep when it was generated, i.e. roughly how
+    /// long after the flash it will be heard.
+    pub audio: Duration,
+}
+
+/// Times presses in a frontend running [`latency_test_rom`].
+#[derive(Debug, Default)]
+pub struct LatencyMeter {
+    pending: Option<(Instant, u32)>,
+    held: bool,
+    lit: bool,
+    samples: Vec<LatencySample>,
+}
+
+impl LatencyMeter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Call with controller 1's A state each time input is applied.
+    pub fn input(&mut self, a_held: bool, now: Instant) {
+        if a_held && !self.held && !self.lit && self.pending.is_none() {
+            self.pending = Some((now, 0));
+        }
+        self.held = a_held;
+    }
+
+    /// Call after each frame is presented, with the audio still queued for
+    /// playback. Returns the sample once the press shows up.
+    pub fn frame_presented(
+        &mut self,
+        framebuffer: &Framebuffer,
+        now: Instant,
+        audio_queued: Duration,
+    ) -> Option<LatencySample> {
+        self.lit = is_lit(framebuffer);
+        let (pressed_at, frames) = self.pending.as_mut()?;
+        *frames += 1;
+        if !self.lit {
+            return None;
+        }
+
+        let sample = LatencySample {
+            frames: *frames,
+            video: now - *pressed_at,
+            audio: audio_queued,
+        };
+        self.pending = None;
+        self.samples.push(sample);
+        Some(sample)
+    }
+
+    pub fn samples(&self) -> &[LatencySample] {
+        &self.samples
+    }
+
+    /// Mean of all samples so far.
+    pub fn average(&self) -> Option<LatencySample> {
+        let count = self.samples.len() as u32;
+        if count == 0 {
+            return None;
+        }
+        let frames: u32 = self.samples.iter().map(|s| s.frames).sum();
+        let video: Duration = self.samples.iter().map(|s| s.video).sum();
+        let audio: Duration = self.samples.iter().map(|s| s.audio).sum();
+        Some(LatencySample {
+            frames: frames.div_ceil(count),
+            video: video / count,
+            audio: audio / count,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::apu::APU;
+    use crate::cart::Cart;
+    use crate::cpu::ResetKind;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_press_shows_next_frame() {
+        let cart = Cart::new(&latency_test_rom()).unwrap();
+        let apu = APU::new(48_000, Arc::new(Mutex::new(VecDeque::new())));
+        let mut nes = Nes::new(cart, apu);
+        nes.reset(ResetKind::PowerOn);
+        for _ in 0..4 {
+            nes.step_frame();
+        }
+        let mut framebuffer = Framebuffer::new();
+        nes.present_frame(&mut framebuffer);
+        assert!(!is_lit(&framebuffer));
+        assert!(framebuffer.frame().indices().iter().all(|&i| i == 0x0F));
+
+        // The NMI that sees A comes after its frame has been drawn, so the
+        // press shows in the one after.
+        assert_eq!(measure_frames(&mut nes, 10), Some(2));
+        // Released and pressed again, it measures the same.
+        assert_eq!(measure_frames(&mut nes, 10), Some(2));
+        assert_eq!(nes.bus.peek(0x0000), 1);
+    }
+
+    #[test]
+    fn test_meter_times_each_press_once() {
+        let mut dark = Framebuffer::new();
+        let mut lit = Framebuffer::new();
+        lit.data.fill(0xFF);
+        dark.data.fill(0x10);
+
+        let start = Instant::now();
+        let mut meter = LatencyMeter::new();
+        let audio = Duration::from_millis(40);
+        meter.input(true, start);
+        assert_eq!(meter.frame_presented(&dark, start, audio), None);
+        let later = start + Duration::from_millis(33);
+        let sample = meter.frame_presented(&lit, later, audio).unwrap();
+        assert_eq!(sample.frames, 2);
+        assert_eq!(sample.video, Duration::from_millis(33));
+
+        // Holding the button doesn't start another measurement.
+        meter.input(true, later);
+        assert_eq!(meter.frame_presented(&lit, later, audio), None);
+        assert_eq!(meter.samples().len(), 1);
+        assert_eq!(meter.average(), Some(sample));
+    }
+}
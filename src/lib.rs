@@ -1,14 +1,58 @@
+pub mod access_log;
 pub mod apu;
+pub mod asm;
+pub mod audio_latency;
 pub mod bus;
+pub mod callstack;
 pub mod cart;
+pub mod cheats;
+pub mod config;
+pub mod core_info;
 pub mod cpu;
+pub mod crash_report;
+pub mod debug_session;
+pub mod demo;
+pub mod disasm;
+#[cfg(feature = "expansion-port")]
+pub mod expansion;
+pub mod frame_sink;
+pub mod game_genie;
+pub mod headless;
+pub mod hexview;
+pub mod hooks;
+pub mod input_macro;
 pub mod joypad;
+pub mod latency;
+pub mod link;
 pub mod mapper;
 pub mod memory;
+pub mod metrics;
+#[cfg(test)]
+pub mod mock_device;
 pub mod nes;
+#[cfg(feature = "ntsc-filter")]
+pub mod ntsc_filter;
 pub mod movie;
 pub mod opcodes;
+pub mod pacing;
+pub mod poke;
 pub mod ppu;
+pub mod profiler;
+pub mod reverse;
+pub mod rom_loader;
+pub mod rom_watch;
+pub mod romdb;
+pub mod stack_check;
+pub mod storage;
+pub mod symbols;
+pub mod time_stretch;
 pub mod trace;
+pub mod trace_compare;
+pub mod trigger;
+pub mod tui;
+pub mod wav;
+pub mod watchdog;
+
+pub use core_info::core_info;
 
 extern crate bitflags;
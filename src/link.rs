@@ -0,0 +1,117 @@
+//! Experimental virtual link cable for homebrew multiplayer between two
+//! emulated consoles. There is no such accessory for the NES, so the port is
+//! mapped at the otherwise unused CPU test-mode registers, and only while a
+//! cable is connected:
+//!
+//! - `$4018` write: queue a byte for the other console. Read: take the
+//!   oldest byte received, or `$00` if there is none.
+//! - `$4019` read: bit 7 set while a received byte is waiting, bit 6 set
+//!   while the cable is connected, bits 0-5 the number of bytes waiting
+//!   (saturating at 63). Writes are ignored.
+//!
+//! Bytes are exchanged between frames by [`exchange`]: everything written
+//! during frame N arrives at the other console before its frame N+1 starts.
+//! Both sides therefore see the same data on the same frame however the host
+//! interleaves them, which keeps linked runs deterministic. At most
+//! [`LINK_CAPACITY`] bytes are kept waiting per direction; further writes
+//! are dropped.
+
+use std::collections::VecDeque;
+
+use crate::nes::Nes;
+
+pub const LINK_DATA: u16 = 0x4018;
+pub const LINK_STATUS: u16 = 0x4019;
+pub const LINK_CAPACITY: usize = 256;
+
+/// One console's end of the cable.
+#[derive(Debug, Clone, Default)]
+pub struct LinkPort {
+    outgoing: VecDeque<u8>,
+    incoming: VecDeque<u8>,
+}
+
+impl LinkPort {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn read(&mut self, addr: u16) -> u8 {
+        match addr {
+            LINK_DATA => self.incoming.pop_front().unwrap_or(0),
+            _ => self.peek(addr),
+        }
+    }
+
+    pub(crate) fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            LINK_DATA => self.incoming.front().copied().unwrap_or(0),
+            LINK_STATUS => {
+                let waiting = self.incoming.len().min(0x3F) as u8;
+                let ready = if self.incoming.is_empty() { 0 } else { 0x80 };
+                ready | 0x40 | waiting
+            }
+            _ => 0,
+        }
+    }
+
+    pub(crate) fn write(&mut self, addr: u16, data: u8) {
+        if addr == LINK_DATA && self.outgoing.len() < LINK_CAPACITY {
+            self.outgoing.push_back(data);
+        }
+    }
+
+    /// Bytes received and not yet read by the game.
+    pub fn pending(&self) -> usize {
+        self.incoming.len()
+    }
+
+    fn deliver(&mut self, from: &mut LinkPort) {
+        let room = LINK_CAPACITY.saturating_sub(self.incoming.len());
+        let count = from.outgoing.len().min(room);
+        self.incoming.extend(from.outgoing.drain(..count));
+        from.outgoing.clear();
+    }
+}
+
+/// Swaps the bytes each console sent this frame. Call once between frames,
+/// after both have run. Does nothing unless both have a cable connected.
+pub fn exchange(first: &mut Nes, second: &mut Nes) {
+    if let (Some(a), Some(b)) = (first.link_mut(), second.link_mut()) {
+        a.deliver(b);
+        b.deliver(a);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cart::test::test_nes;
+    use crate::memory::Memory;
+
+    #[test]
+    fn test_bytes_arrive_after_exchange() {
+        let mut first = test_nes(&[]);
+        let mut second = test_nes(&[]);
+        assert_eq!(first.bus.read(LINK_STATUS), 0);
+
+        first.connect_link();
+        second.connect_link();
+        first.bus.write(LINK_DATA, 0x12);
+        first.bus.write(LINK_DATA, 0x34);
+        assert_eq!(second.bus.read(LINK_STATUS), 0x40);
+
+        exchange(&mut first, &mut second);
+        assert_eq!(second.bus.read(LINK_STATUS), 0xC2);
+        assert_eq!(second.bus.peek(LINK_DATA), 0x12);
+        assert_eq!(second.bus.read(LINK_DATA), 0x12);
+        assert_eq!(second.bus.read(LINK_DATA), 0x34);
+        assert_eq!(second.bus.read(LINK_DATA), 0x00);
+        assert_eq!(second.bus.read(LINK_STATUS), 0x40);
+        assert_eq!(first.link_mut().unwrap().pending(), 0);
+
+        // Nothing is sent twice.
+        exchange(&mut first, &mut second);
+        assert_eq!(second.bus.read(LINK_STATUS), 0x40);
+    }
+}
@@ -1,71 +1,622 @@
 use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use clap::Parser;
-use pico::apu::APU;
+use clap::{Parser, Subcommand, ValueEnum};
+use pico::access_log::AccessLog;
+use pico::apu::{APU, ApuRevision, CHANNEL_NAMES};
+use pico::audio_latency::AdaptiveLatency;
+use pico::bus::OpenBus;
 use pico::cart::Cart;
+use pico::cheats::{Cheat, CheatList};
+use pico::config::{Config, PaletteSource, Profile, VideoFilter};
+use pico::cpu::{CpuModel, ResetKind};
+use pico::crash_report::CrashReport;
+use pico::demo;
+use pico::headless::HeadlessRun;
+use pico::input_macro::{InputMacro, MacroPlayback};
 use pico::joypad::JoypadButton;
-use pico::movie::FM2Movie;
+use pico::latency::{self, LatencyMeter, LatencySample};
+use pico::link;
+use pico::metrics::{Metrics, MetricsFormat};
+use pico::movie::{FM2Movie, InputTiming};
 use pico::nes::{ClockResult, Nes};
+#[cfg(feature = "ntsc-filter")]
+use pico::ntsc_filter;
+use pico::pacing::ThreadPriority;
 use pico::ppu::framebuffer::Framebuffer;
-use pico::trace::trace;
+use pico::ppu::palette;
+use pico::profiler::ProfileView;
+use pico::rom_watch::RomWatcher;
+use pico::romdb::{self, RomDatabase};
+use pico::storage::FileStorage;
+use pico::symbols::SymbolTable;
+use pico::time_stretch::TimeStretch;
+use pico::trace::{TraceFilter, TraceRange, trace_with_symbols};
+use pico::trace_compare::TraceCompare;
+use pico::trigger::{Condition, Trigger};
+use pico::tui::{self, HeldButtons, TuiColor, TuiKey};
+use pico::watchdog::Watchdog;
+use pico::wav;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
 
 const WIDTH: u32 = 256;
 const HEIGHT: u32 = 240;
-const SCALE: u32 = 3;
+const METRICS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What paces emulation and presentation.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum VsyncSource {
+    /// Run one emulated frame per host display refresh.
+    Host,
+    /// Present on each emulated vblank at the NES frame rate. Best with a
+    /// variable refresh rate (G-Sync/FreeSync) display.
+    Emulated,
+}
+
+/// Console APU revision to emulate.
+#[derive(Clone, Copy, ValueEnum)]
+enum ApuRevisionArg {
+    /// Original letterless RP2A03
+    #[value(name = "2a03")]
+    Rp2A03,
+    #[value(name = "2a03e")]
+    Rp2A03E,
+    #[value(name = "2a03g")]
+    Rp2A03G,
+}
+
+impl From<ApuRevisionArg> for ApuRevision {
+    fn from(arg: ApuRevisionArg) -> Self {
+        match arg {
+            ApuRevisionArg::Rp2A03 => ApuRevision::Rp2A03,
+            ApuRevisionArg::Rp2A03E => ApuRevision::Rp2A03E,
+            ApuRevisionArg::Rp2A03G => ApuRevision::Rp2A03G,
+        }
+    }
+}
+
+/// CPU (and with it, console region) to emulate.
+#[derive(Clone, Copy, ValueEnum)]
+enum CpuModelArg {
+    /// NTSC
+    #[value(name = "2a03")]
+    Rp2A03,
+    /// PAL
+    #[value(name = "2a07")]
+    Rp2A07,
+    /// Generic NMOS 6502 with decimal mode
+    #[value(name = "6502")]
+    Mos6502,
+    /// 65C02 with its extension opcodes
+    #[value(name = "65c02")]
+    Wdc65C02,
+}
+
+impl From<CpuModelArg> for CpuModel {
+    fn from(arg: CpuModelArg) -> Self {
+        match arg {
+            CpuModelArg::Rp2A03 => CpuModel::Rp2A03,
+            CpuModelArg::Rp2A07 => CpuModel::Rp2A07,
+            CpuModelArg::Mos6502 => CpuModel::Mos6502,
+            CpuModelArg::Wdc65C02 => CpuModel::Wdc65C02,
+        }
+    }
+}
+
+/// Console region whose frame rate `--run-at` runs at.
+#[derive(Clone, Copy, ValueEnum)]
+enum RegionArg {
+    Ntsc,
+    Pal,
+}
+
+impl RegionArg {
+    fn name(self) -> &'static str {
+        match self {
+            RegionArg::Ntsc => "NTSC",
+            RegionArg::Pal => "PAL",
+        }
+    }
+
+    fn frame_time(self) -> Duration {
+        match self {
+            RegionArg::Ntsc => CpuModel::Rp2A03.frame_time(),
+            RegionArg::Pal => CpuModel::Rp2A07.frame_time(),
+        }
+    }
+}
+
+/// Format of the `--metrics-file` export.
+#[derive(Clone, Copy, ValueEnum)]
+enum MetricsFormatArg {
+    Json,
+    /// Prometheus text format, for node_exporter's textfile collector
+    Prometheus,
+}
+
+impl From<MetricsFormatArg> for MetricsFormat {
+    fn from(arg: MetricsFormatArg) -> Self {
+        match arg {
+            MetricsFormatArg::Json => MetricsFormat::Json,
+            MetricsFormatArg::Prometheus => MetricsFormat::Prometheus,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OpenBusArg {
+    /// Always 0
+    Zero,
+    /// The last byte on the data bus, as on hardware
+    Last,
+    /// The last byte, fading to 0 after about a second
+    Decay,
+}
+
+impl From<OpenBusArg> for OpenBus {
+    fn from(arg: OpenBusArg) -> Self {
+        match arg {
+            OpenBusArg::Zero => OpenBus::Zero,
+            OpenBusArg::Last => OpenBus::Last,
+            OpenBusArg::Decay => OpenBus::Decay,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ResetArg {
+    /// The reset button
+    Reset,
+    /// Power off and on
+    PowerOn,
+}
+
+impl From<ResetArg> for ResetKind {
+    fn from(arg: ResetArg) -> Self {
+        match arg {
+            ResetArg::Reset => ResetKind::Reset,
+            ResetArg::PowerOn => ResetKind::PowerOn,
+        }
+    }
+}
+
+/// Grouping of the `--profile` report.
+#[derive(Clone, Copy, ValueEnum)]
+enum ProfileViewArg {
+    /// Per instruction address
+    Address,
+    /// Per JSR target or interrupt handler, excluding the routines it calls
+    Routine,
+}
+
+impl From<ProfileViewArg> for ProfileView {
+    fn from(arg: ProfileViewArg) -> Self {
+        match arg {
+            ProfileViewArg::Address => ProfileView::Address,
+            ProfileViewArg::Routine => ProfileView::Routine,
+        }
+    }
+}
+
+/// Colour output of `--tui`.
+#[derive(Clone, Copy, ValueEnum)]
+enum TuiColorArg {
+    Truecolor,
+    /// xterm 256-colour palette
+    #[value(name = "256")]
+    Ansi256,
+}
+
+impl From<TuiColorArg> for TuiColor {
+    fn from(arg: TuiColorArg) -> Self {
+        match arg {
+            TuiColorArg::Truecolor => TuiColor::TrueColor,
+            TuiColorArg::Ansi256 => TuiColor::Ansi256,
+        }
+    }
+}
 
 struct AudioCallbackImpl {
     audio_buffer: Arc<Mutex<VecDeque<f32>>>,
+    max_queued: Arc<AtomicUsize>,
+    underruns: Arc<AtomicU64>,
+    /// Set when the profile has `audio_latency_ms = auto`; it then decides
+    /// `max_queued`.
+    adaptive: Arc<Mutex<Option<AdaptiveLatency>>>,
+    sample_rate: u32,
+    /// Raised on the first callback, from SDL's audio thread.
+    priority: Option<ThreadPriority>,
 }
 
 impl sdl2::audio::AudioCallback for AudioCallbackImpl {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
+        if let Some(priority) = self.priority.take() {
+            set_thread_priority(priority);
+        }
         let mut buffer = self.audio_buffer.lock().unwrap();
+
+        // Drop the oldest samples so queued audio never exceeds the latency
+        // budget of the active profile.
+        let max_queued = self.max_queued.load(Ordering::Relaxed) + out.len();
+        if buffer.len() > max_queued {
+            let excess = buffer.len() - max_queued;
+            buffer.drain(..excess);
+        }
+
+        if buffer.len() < out.len() {
+            self.underruns.fetch_add(1, Ordering::Relaxed);
+        }
         for sample in out.iter_mut() {
             *sample = buffer.pop_front().unwrap_or(0.0);
         }
+        drop(buffer);
+
+        if let Some(adaptive) = self.adaptive.lock().unwrap().as_mut() {
+            let underruns = self.underruns.load(Ordering::Relaxed);
+            let latency = adaptive.record_callback(Instant::now(), underruns);
+            let samples = latency.as_secs_f64() * self.sample_rate as f64;
+            self.max_queued.store(samples as usize, Ordering::Relaxed);
+        }
     }
 }
 
 #[derive(Parser)]
+#[command(subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
 struct CliArgs {
-    rom_file: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// iNES ROM to run, `demo` for the built-in demo program, or `latency`
+    /// to measure input latency with the built-in test ROM
+    #[arg(required = true)]
+    rom_file: Option<String>,
     movie_file: Option<String>,
 
     #[arg(short, long)]
     debug: bool,
+
+    /// FCEUX `.nl` or ca65 `.dbg` files whose labels the `--debug` trace
+    /// shows in place of addresses
+    #[arg(long, requires = "debug")]
+    symbols: Vec<PathBuf>,
+
+    /// Only trace instructions in this range, e.g. `C000-FFFF`, or
+    /// `03:8000-9FFF` for PRG bank 3; repeatable
+    #[arg(long, value_name = "RANGE", requires = "debug", value_parser = TraceRange::parse)]
+    trace_include: Vec<TraceRange>,
+
+    /// Don't trace instructions in this range, e.g. the NMI handler;
+    /// repeatable, and wins over `--trace-include`
+    #[arg(long, value_name = "RANGE", requires = "debug", value_parser = TraceRange::parse)]
+    trace_exclude: Vec<TraceRange>,
+
+    /// Settings profile to use instead of the one last active
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Settings file (defaults to ~/.config/pico/config.ini)
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// ROM database to verify the ROM against on load
+    #[arg(long)]
+    romdb: Option<PathBuf>,
+
+    /// Correct the in-memory header (mapper, mirroring) from the ROM database
+    #[arg(long, requires = "romdb")]
+    fix_header: bool,
+
+    /// Directory for battery saves (defaults to ~/.local/share/pico)
+    #[arg(long)]
+    save_dir: Option<PathBuf>,
+
+    /// CPU model
+    #[arg(long, value_enum, default_value = "2a03")]
+    cpu_model: CpuModelArg,
+
+    /// APU hardware revision
+    #[arg(long, value_enum, default_value = "2a03g")]
+    apu_revision: ApuRevisionArg,
+
+    /// Don't let DMC sample fetches corrupt controller and PPUDATA reads
+    #[arg(long)]
+    no_dpcm_conflict: bool,
+
+    /// What reads of unmapped addresses return
+    #[arg(long, value_enum, default_value = "zero")]
+    open_bus: OpenBusArg,
+
+    /// Give the CPU this many extra scanlines of time after each NMI, with
+    /// the PPU and APU paused, to cut slowdown. Can break games that count
+    /// cycles
+    #[arg(long, value_name = "SCANLINES", default_value_t = 0)]
+    overclock: u16,
+
+    /// Run at another region's frame rate, e.g. a PAL game at NTSC speed.
+    /// This is not how the game played: its logic and music change tempo.
+    /// The sound is time-stretched to keep its pitch.
+    #[arg(long, value_enum, conflicts_with = "tui")]
+    run_at: Option<RegionArg>,
+
+    /// Present frames on the host's vsync or on the emulated vblank
+    #[arg(long, value_enum, default_value = "host")]
+    vsync_source: VsyncSource,
+
+    /// Run a second console beside the first on the same input, e.g. another
+    /// build of a ROM, or the same ROM to check determinism. Only the first
+    /// is heard.
+    #[arg(long, value_name = "ROM")]
+    side_by_side: Option<String>,
+
+    /// Connect the two consoles with the experimental virtual link cable
+    #[arg(long, requires = "side_by_side")]
+    link: bool,
+
+    /// Periodically write runtime metrics (fps, frame times, audio
+    /// underruns) to this file
+    #[arg(long)]
+    metrics_file: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value = "json", requires = "metrics_file")]
+    metrics_format: MetricsFormatArg,
+
+    /// Simulate composite video: colour fringes on fine detail and dot
+    /// crawl, drawn at twice the width
+    #[cfg(feature = "ntsc-filter")]
+    #[arg(long, conflicts_with = "tui")]
+    ntsc_filter: bool,
+
+    /// Draw in the terminal instead of a window, without sound
+    #[arg(long, conflicts_with = "side_by_side")]
+    tui: bool,
+
+    #[arg(long, value_enum, default_value = "truecolor", requires = "tui")]
+    tui_color: TuiColorArg,
+
+    /// Cheat list to apply, FCEUX `.cht` or Mesen `.xml`
+    #[arg(long)]
+    cheats: Option<PathBuf>,
+
+    /// Game Genie code to apply, e.g. `SXIOPO`; may be repeated
+    #[arg(long, value_name = "CODE", value_parser = Cheat::from_game_genie)]
+    game_genie: Vec<Cheat>,
+
+    /// Where trigger captures (the profile's `trigger.*` settings) are
+    /// written
+    #[arg(long, default_value = ".")]
+    capture_dir: PathBuf,
+
+    /// Warn when the stack pointer wraps around or a push overwrites data
+    /// kept in page one
+    #[arg(long)]
+    stack_check: bool,
+
+    /// Write a crash report bundle (.zip) here when the emulator panics or
+    /// the CPU jams, to attach to an issue
+    #[arg(long, value_name = "DIR", conflicts_with = "tui")]
+    crash_reports: Option<PathBuf>,
+
+    /// Reload the ROM whenever the file changes on disk, e.g. after a
+    /// rebuild. The battery save carries over.
+    #[arg(long, conflicts_with = "tui")]
+    watch: bool,
+
+    /// Key of a profile macro to play after each reload, to get back to the
+    /// spot being worked on
+    #[arg(long, value_name = "KEY", requires = "watch")]
+    watch_macro: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run a ROM headless for a fixed number of frames, e.g. to attach the
+    /// resulting state and screenshot to a bug report
+    Run(Box<RunArgs>),
+    /// Convert a cheat list between FCEUX `.cht` and Mesen `.xml`, picking
+    /// the formats from the file extensions
+    ConvertCheats { input: PathBuf, output: PathBuf },
+    /// Run a ROM beside a Nintendulator, FCEUX or Mesen trace log and stop
+    /// at the first instruction where the registers or cycle counts differ
+    CompareTrace {
+        rom_file: String,
+        log: PathBuf,
+        /// Matching lines to show before the divergence
+        #[arg(long, default_value_t = 20)]
+        context: usize,
+    },
+    /// Print the version, supported mappers and state dump version
+    CoreInfo,
+}
+
+#[derive(clap::Args)]
+struct RunArgs {
+    /// iNES ROM to run, or `demo` for the built-in demo program
+    rom_file: String,
+
+    /// Number of frames to run
+    #[arg(long, default_value_t = 600)]
+    frames: usize,
+
+    /// FM2 movie to play back
+    #[arg(long)]
+    input: Option<PathBuf>,
+
+    /// Write the final machine state here
+    #[arg(long)]
+    dump: Option<PathBuf>,
+
+    /// Write the final frame here as a PNG
+    #[arg(long)]
+    screenshot: Option<PathBuf>,
+
+    /// Write a report of CPU cycles spent per routine or address here
+    #[arg(long)]
+    profile: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value = "routine", requires = "profile")]
+    profile_by: ProfileViewArg,
+
+    /// Cheat list to apply, FCEUX `.cht` or Mesen `.xml`
+    #[arg(long)]
+    cheats: Option<PathBuf>,
+
+    /// Game Genie code to apply, e.g. `SXIOPO`; may be repeated
+    #[arg(long, value_name = "CODE", value_parser = Cheat::from_game_genie)]
+    game_genie: Vec<Cheat>,
+
+    /// Capture a screenshot and state when a RAM condition fires, e.g.
+    /// `lives=$0075 changes to 8`
+    #[arg(long, value_name = "NAME=CONDITION", value_parser = parse_trigger)]
+    trigger: Vec<Trigger>,
+
+    /// Where trigger captures are written
+    #[arg(long, default_value = ".")]
+    capture_dir: PathBuf,
+
+    /// Write each APU channel to its own WAV in this directory
+    #[arg(long, value_name = "DIR")]
+    stems: Option<PathBuf>,
+
+    /// Report the game as stalled when it writes nothing to these
+    /// addresses for `--alive-frames` frames, e.g. `0300-03FF`
+    #[arg(long, value_name = "RANGE", value_parser = parse_alive)]
+    alive: Option<(u16, u16)>,
+
+    #[arg(long, default_value_t = 300, requires = "alive")]
+    alive_frames: u32,
+
+    /// Reset the console when the CPU jams or the game stalls
+    #[arg(long, value_enum)]
+    auto_reset: Option<ResetArg>,
+
+    /// Log every CPU read and write here, with its cycle and instruction
+    #[arg(long, value_name = "FILE")]
+    access_log: Option<PathBuf>,
+
+    /// Only log accesses in this range, e.g. `2000-3FFF`; repeatable
+    #[arg(long, value_name = "RANGE", value_parser = TraceRange::parse, requires = "access_log")]
+    access_log_range: Vec<TraceRange>,
+
+    /// Don't print a summary
+    #[arg(long)]
+    quiet: bool,
 }
 
 fn main() {
     env_logger::init();
     let args = CliArgs::parse();
+    if let Some(command) = args.command {
+        let result = match command {
+            Command::Run(run_args) => run_headless(*run_args),
+            Command::ConvertCheats { input, output } => convert_cheats(&input, &output),
+            Command::CompareTrace {
+                rom_file,
+                log,
+                context,
+            } => compare_trace(&rom_file, &log, context),
+            Command::CoreInfo => {
+                println!("{}", pico::core_info());
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let rom_file = args.rom_file.clone().expect("ROM file is required");
+    if args.tui {
+        if let Err(e) = run_tui(&args, &rom_file) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let config_path = args.config.clone().unwrap_or_else(Config::default_path);
+    let mut config = Config::load_or_default(&config_path).expect("failed to load config");
+    if let Some(name) = &args.profile {
+        if config.profile(name).is_none() {
+            let names: Vec<&str> = config.profiles.iter().map(|p| p.name.as_str()).collect();
+            panic!(
+                "unknown profile {:?}, available: {}",
+                name,
+                names.join(", ")
+            );
+        }
+        config.active = name.clone();
+    }
+    let profile = config.active_profile().clone();
+    if args.crash_reports.is_some() {
+        install_panic_hook();
+    }
 
     let sdl_ctx = sdl2::init().unwrap();
     let video_subsystem = sdl_ctx.video().unwrap();
     let audio_subsystem = sdl_ctx.audio().unwrap();
 
-    let bytes = std::fs::read(&args.rom_file).expect("failed to read ROM");
-    let cart = Cart::new(&bytes).expect("failed to parse cartridge");
+    let mut bytes = read_rom(&rom_file).expect("failed to read ROM");
+    if let Some(path) = &args.romdb {
+        let db = RomDatabase::load_from_file(path).expect("failed to load ROM database");
+        bytes = verify_rom(bytes, &db, args.fix_header);
+    }
+    let cart = Cart::new(&bytes).unwrap_or_else(|e| panic!("failed to parse cartridge: {}", e));
+
+    let native_frame_time = CpuModel::from(args.cpu_model).frame_time();
+    let frame_time = args.run_at.map_or(native_frame_time, RegionArg::frame_time);
+    let tempo = native_frame_time.as_secs_f64() / frame_time.as_secs_f64();
+    let title = match args.run_at {
+        Some(region) if frame_time != native_frame_time => {
+            log::warn!(
+                "Running at {} speed, {:.0}% of the console's own: not how the game plays",
+                region.name(),
+                tempo * 100.0
+            );
+            format!("pico - {} speed (inauthentic)", region.name())
+        }
+        _ => "pico".to_string(),
+    };
 
+    let columns = if args.side_by_side.is_some() { 2 } else { 1 };
     let window = video_subsystem
-        .window("pico", WIDTH * SCALE, HEIGHT * SCALE)
+        .window(
+            &title,
+            WIDTH * profile.scale * columns,
+            HEIGHT * profile.scale,
+        )
         .position_centered()
         .build()
         .unwrap();
 
-    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
+    let mut canvas = match args.vsync_source {
+        VsyncSource::Host => window.into_canvas().present_vsync().build().unwrap(),
+        VsyncSource::Emulated => window.into_canvas().build().unwrap(),
+    };
     canvas.set_draw_color(sdl2::pixels::Color::BLACK);
     canvas.clear();
     canvas.present();
 
     let texture_creator = canvas.texture_creator();
+    set_scale_quality(profile.video_filter);
+    let mut video = Video::new(&args, &profile);
+    let mut second_video = Video::new(&args, &profile);
     let mut texture = texture_creator
-        .create_texture_target(PixelFormatEnum::RGB24, WIDTH, HEIGHT)
+        .create_texture_target(PixelFormatEnum::RGB24, video.width(), HEIGHT)
+        .unwrap();
+    let mut second_texture = texture_creator
+        .create_texture_target(PixelFormatEnum::RGB24, video.width(), HEIGHT)
         .unwrap();
 
     // Initialize emulator
@@ -74,7 +625,19 @@ fn main() {
         sample_rate as usize * 2,
     )));
 
-    let apu = APU::new(sample_rate, audio_buffer.clone());
+    // At another region's speed the APU fills its own buffer, which is
+    // time-stretched into the one played.
+    let mut stretch =
+        (frame_time != native_frame_time).then(|| TimeStretch::new(sample_rate, tempo));
+    let apu_buffer = match stretch {
+        Some(_) => Arc::new(Mutex::new(VecDeque::new())),
+        None => audio_buffer.clone(),
+    };
+    let mut apu = APU::new(sample_rate, apu_buffer.clone());
+    apu.set_revision(args.apu_revision.into());
+    let max_queued = Arc::new(AtomicUsize::new(latency_samples(&profile, sample_rate)));
+    let underruns = Arc::new(AtomicU64::new(0));
+    let adaptive = Arc::new(Mutex::new(adaptive_latency(&profile)));
 
     let audio_device = audio_subsystem
         .open_playback(
@@ -89,6 +652,11 @@ fn main() {
                 assert_eq!(spec.channels, 1);
                 AudioCallbackImpl {
                     audio_buffer: audio_buffer.clone(),
+                    max_queued: max_queued.clone(),
+                    underruns: underruns.clone(),
+                    adaptive: adaptive.clone(),
+                    sample_rate,
+                    priority: Some(profile.thread_priority),
                 }
             },
         )
@@ -96,32 +664,111 @@ fn main() {
 
     audio_device.resume();
 
-    let mut nes = Nes::new(cart, apu);
-    nes.reset();
-
-    // Setup input mapping
-    let mut key_map: HashMap<Keycode, JoypadButton> = HashMap::new();
-    key_map.insert(Keycode::Down, JoypadButton::DOWN);
-    key_map.insert(Keycode::Up, JoypadButton::UP);
-    key_map.insert(Keycode::Right, JoypadButton::RIGHT);
-    key_map.insert(Keycode::Left, JoypadButton::LEFT);
-    key_map.insert(Keycode::Space, JoypadButton::SELECT);
-    key_map.insert(Keycode::Return, JoypadButton::START);
-    key_map.insert(Keycode::X, JoypadButton::BUTTON_A);
-    key_map.insert(Keycode::Z, JoypadButton::BUTTON_B);
+    let mut nes = Nes::with_model(cart, apu, args.cpu_model.into());
+    nes.bus.set_dpcm_conflict(!args.no_dpcm_conflict);
+    nes.bus.set_open_bus(args.open_bus.into());
+    nes.bus.set_overclock(args.overclock);
+    nes.reset(ResetKind::PowerOn);
+
+    let mut storage = FileStorage::new(
+        args.save_dir
+            .clone()
+            .unwrap_or_else(FileStorage::default_dir),
+    );
+    let save_key = save_key(&rom_file);
+    match nes.load_battery(&storage, &save_key) {
+        Ok(true) => log::info!("Loaded {}", save_key),
+        Ok(false) => {}
+        Err(e) => log::warn!("{}", e),
+    }
+
+    apply_palette(&mut nes, &profile);
+    apply_triggers(&mut nes, &profile);
+    let debug_trace = match args.debug.then(|| load_symbols(&args.symbols)).transpose() {
+        Ok(symbols) => symbols.map(|symbols| (symbols, trace_filter(&args))),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Some(path) = &args.cheats {
+        match load_cheats(path) {
+            Ok(cheats) => *nes.cheats_mut() = cheats,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    for code in &args.game_genie {
+        nes.cheats_mut().add(code.clone());
+    }
+    if args.link {
+        nes.connect_link();
+    }
+    if args.stack_check {
+        nes.enable_stack_check();
+    }
+    if args.crash_reports.is_some() {
+        nes.enable_trace_history(CRASH_TRACE_LEN);
+    }
+
+    let mut second = args.side_by_side.as_deref().map(|rom_file| {
+        let bytes = read_rom(rom_file).expect("failed to read ROM");
+        let cart = Cart::new(&bytes).unwrap_or_else(|e| panic!("failed to parse cartridge: {}", e));
+        // Nothing plays this buffer; the APU drops the oldest samples once
+        // it is full.
+        let mut apu = APU::new(sample_rate, Arc::new(Mutex::new(VecDeque::new())));
+        apu.set_revision(args.apu_revision.into());
+        let mut second = Nes::with_model(cart, apu, args.cpu_model.into());
+        second.bus.set_dpcm_conflict(!args.no_dpcm_conflict);
+        second.bus.set_open_bus(args.open_bus.into());
+        second.bus.set_overclock(args.overclock);
+        second.reset(ResetKind::PowerOn);
+        apply_palette(&mut second, &profile);
+        if args.link {
+            second.connect_link();
+        }
+        (second, Framebuffer::new())
+    });
+
+    let mut key_map = build_key_map(&profile);
+    let mut macro_map = build_macro_map(&profile);
+    let mut playing_macro: Option<MacroPlayback> = None;
 
     let mut button_states: HashMap<JoypadButton, bool> =
         key_map.values().copied().map(|btn| (btn, false)).collect();
 
     let mut movie = args
         .movie_file
+        .as_ref()
         .and_then(|path| FM2Movie::load_from_file(path).ok());
+    if let Some(subframe) = movie.take_if(|m| m.header.input_timing == InputTiming::Strobe) {
+        nes.attach_subframe_movie(subframe)
+            .expect("failed to attach movie");
+    }
+
+    let mut watcher = args.watch.then(|| RomWatcher::new(&rom_file));
+    if let Some(key) = &args.watch_macro
+        && watch_macro(&profile, key).is_none()
+    {
+        log::warn!("No macro on {:?} in profile {}", key, profile.name);
+    }
 
     let mut frame_count: usize = 0;
     let mut framebuffer = Framebuffer::new();
 
     let mut event_pump = sdl_ctx.event_pump().unwrap();
     let mut running = true;
+    set_thread_priority(profile.thread_priority);
+    let mut wait_strategy = profile.wait_strategy;
+    let mut next_frame = Instant::now();
+    let mut last_presentation = nes.presentation_time();
+    let mut reported_jam = None;
+    let mut metrics = Metrics::new();
+    let mut latency_meter = (rom_file == "latency").then(LatencyMeter::new);
+    let mut last_present = Instant::now();
+    let mut next_metrics_write = last_present + METRICS_INTERVAL;
 
     while running {
         for event in event_pump.poll_iter() {
@@ -139,13 +786,85 @@ fn main() {
                     keycode: Some(Keycode::R),
                     ..
                 } => {
-                    nes.reset();
+                    nes.reset(ResetKind::Reset);
+                    if let Some((second, _)) = &mut second {
+                        second.reset(ResetKind::Reset);
+                    }
                     frame_count = 0;
                 }
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    ..
+                } => {
+                    let profile = config.cycle_profile().clone();
+                    log::info!("Switched to profile {}", profile.name);
+
+                    apply_palette(&mut nes, &profile);
+                    apply_triggers(&mut nes, &profile);
+                    if let Some((second, _)) = &mut second {
+                        apply_palette(second, &profile);
+                    }
+                    key_map = build_key_map(&profile);
+                    macro_map = build_macro_map(&profile);
+                    playing_macro = None;
+                    button_states = key_map.values().copied().map(|btn| (btn, false)).collect();
+                    max_queued.store(latency_samples(&profile, sample_rate), Ordering::Relaxed);
+                    *adaptive.lock().unwrap() = adaptive_latency(&profile);
+                    wait_strategy = profile.wait_strategy;
+
+                    set_scale_quality(profile.video_filter);
+                    video = Video::new(&args, &profile);
+                    second_video = Video::new(&args, &profile);
+                    texture = texture_creator
+                        .create_texture_target(PixelFormatEnum::RGB24, video.width(), HEIGHT)
+                        .unwrap();
+                    second_texture = texture_creator
+                        .create_texture_target(PixelFormatEnum::RGB24, video.width(), HEIGHT)
+                        .unwrap();
+                    let _ = canvas
+                        .window_mut()
+                        .set_size(WIDTH * profile.scale * columns, HEIGHT * profile.scale);
+
+                    if let Err(e) = config.save(&config_path) {
+                        log::warn!("{}", e);
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    repeat: false,
+                    ..
+                } if macro_map.contains_key(&keycode) => {
+                    playing_macro = Some(macro_map[&keycode].play());
+                }
                 _ => {}
             }
         }
 
+        if let Some(watcher) = &mut watcher
+            && watcher.poll(Instant::now())
+        {
+            let profile = config.active_profile();
+            match reload_rom(&rom_file, &args, profile, sample_rate, apu_buffer.clone()) {
+                Ok(mut reloaded) => {
+                    if let Err(e) = nes.save_battery(&mut storage, &save_key) {
+                        log::warn!("{}", e);
+                    }
+                    if let Err(e) = reloaded.load_battery(&storage, &save_key) {
+                        log::warn!("{}", e);
+                    }
+                    nes = reloaded;
+                    frame_count = 0;
+                    playing_macro = args
+                        .watch_macro
+                        .as_deref()
+                        .and_then(|key| watch_macro(profile, key))
+                        .map(InputMacro::play);
+                    log::info!("Reloaded {}", rom_file);
+                }
+                Err(e) => log::warn!("Keeping the running ROM: {}", e),
+            }
+        }
+
         let keys: Vec<Keycode> = event_pump
             .keyboard_state()
             .pressed_scancodes()
@@ -156,24 +875,664 @@ fn main() {
             button_states.insert(*btn, keys.contains(key));
         }
 
-        apply_inputs(&mut nes, &mut movie, frame_count, &button_states);
-        run_frame(&mut nes, args.debug);
+        apply_inputs(
+            &mut nes,
+            &mut movie,
+            &mut playing_macro,
+            frame_count,
+            &button_states,
+        );
+        if let Some(meter) = &mut latency_meter {
+            let a_held = nes
+                .joypad_mut(0)
+                .is_some_and(|joypad| joypad.button_status.contains(JoypadButton::BUTTON_A));
+            meter.input(a_held, Instant::now());
+        }
+        let frame = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            run_frame(&mut nes, debug_trace.as_ref(), args.vsync_source)
+        }));
+        if let Err(panic) = frame {
+            if let Some(dir) = &args.crash_reports {
+                let message = PANIC_MESSAGE.lock().ok().and_then(|mut m| m.take());
+                let message = message.unwrap_or_else(|| "panicked".to_string());
+                write_crash_report(&nes, &message, &rom_file, &config, dir);
+            }
+            std::panic::resume_unwind(panic);
+        }
+        if let Err(e) = write_captures(&mut nes, &args.capture_dir) {
+            log::warn!("{}", e);
+        }
+        if let Some(stack_check) = nes.stack_check_mut() {
+            for warning in stack_check.take_warnings() {
+                log::warn!("{}", warning);
+            }
+        }
+        if let Some((second, _)) = &mut second {
+            let (joypad1, joypad2) = nes.joypads_mut();
+            let held = (joypad1.button_status, joypad2.button_status);
+            let (joypad1, joypad2) = second.joypads_mut();
+            (joypad1.button_status, joypad2.button_status) = held;
+            run_frame(second, None, args.vsync_source);
+            link::exchange(&mut nes, second);
+        }
+        if let Some(stretch) = &mut stretch {
+            let samples: Vec<f32> = apu_buffer.lock().unwrap().drain(..).collect();
+            stretch.process(samples, &mut audio_buffer.lock().unwrap());
+        }
         frame_count = frame_count.wrapping_add(1);
 
-        framebuffer.data.fill(0);
-        nes.bus.render_frame(&mut framebuffer);
+        let jammed_at = nes.bus.cpu.jammed_at();
+        if jammed_at != reported_jam {
+            let title = match jammed_at {
+                Some(pc) => {
+                    log::error!("CPU jammed at ${:04X}", pc);
+                    if let Some(dir) = &args.crash_reports {
+                        let message = format!("CPU jammed at ${:04X}", pc);
+                        write_crash_report(&nes, &message, &rom_file, &config, dir);
+                    }
+                    format!("{} - CPU jammed at ${:04X}, press R to reset", title, pc)
+                }
+                None => title.clone(),
+            };
+            let _ = canvas.window_mut().set_title(&title);
+            reported_jam = jammed_at;
+        }
+
+        nes.present_frame(&mut framebuffer);
 
+        let pitch = video.width() as usize * 3;
         texture
-            .update(None, &framebuffer.data, (WIDTH * 3) as usize)
+            .update(None, video.pixels(&nes, &framebuffer), pitch)
             .unwrap();
-        canvas.copy(&texture, None, None).unwrap();
+        match &mut second {
+            Some((second, second_framebuffer)) => {
+                second.present_frame(second_framebuffer);
+                let pitch = second_video.width() as usize * 3;
+                second_texture
+                    .update(None, second_video.pixels(second, second_framebuffer), pitch)
+                    .unwrap();
+                let (width, height) = canvas.output_size().unwrap();
+                let left = Rect::new(0, 0, width / 2, height);
+                let right = Rect::new((width / 2) as i32, 0, width / 2, height);
+                canvas.copy(&texture, None, left).unwrap();
+                canvas.copy(&second_texture, None, right).unwrap();
+            }
+            None => canvas.copy(&texture, None, None).unwrap(),
+        }
+
+        if args.vsync_source == VsyncSource::Emulated {
+            // The display follows the emulated vblank, so pace to it here,
+            // frame by frame: odd frames can be a dot short. After a reset,
+            // reload or rewind the gap means nothing, so use the average.
+            let presentation = nes.presentation_time();
+            let interval = presentation
+                .checked_sub(last_presentation)
+                .filter(|gap| !gap.is_zero() && *gap < native_frame_time * 2)
+                .map_or(frame_time, |gap| gap.div_f64(tempo));
+            last_presentation = presentation;
+            next_frame += interval;
+            let now = Instant::now();
+            if next_frame > now {
+                wait_strategy.wait_until(next_frame);
+            } else {
+                next_frame = now;
+            }
+        }
         canvas.present();
+
+        let now = Instant::now();
+        if let Some(meter) = &mut latency_meter {
+            let queued = audio_buffer.lock().unwrap().len() as f64 / sample_rate as f64;
+            if let Some(sample) =
+                meter.frame_presented(&framebuffer, now, Duration::from_secs_f64(queued))
+            {
+                println!("{}", format_latency(&sample));
+            }
+        }
+        metrics.record_frame(now - last_present);
+        last_present = now;
+        if let Some(path) = &args.metrics_file
+            && now >= next_metrics_write
+        {
+            metrics.set_audio_underruns(underruns.load(Ordering::Relaxed));
+            match adaptive.lock().unwrap().as_ref() {
+                Some(adaptive) => metrics.set_audio_latency(adaptive.latency(), adaptive.jitter()),
+                None => metrics.set_audio_latency(
+                    Duration::from_millis(config.active_profile().audio_latency_ms as u64),
+                    None,
+                ),
+            }
+            if let Err(e) = metrics.write_to(path, args.metrics_format.into()) {
+                log::warn!("{}", e);
+            }
+            next_metrics_write = now + METRICS_INTERVAL;
+        }
+    }
+
+    if let Err(e) = nes.save_battery(&mut storage, &save_key) {
+        log::warn!("{}", e);
+    }
+    if let Some(average) = latency_meter.as_ref().and_then(LatencyMeter::average) {
+        println!("average: {}", format_latency(&average));
+    }
+}
+
+fn format_latency(sample: &LatencySample) -> String {
+    format!(
+        "press to photon {} frame(s), {:.1} ms; audio {:.1} ms behind",
+        sample.frames,
+        sample.video.as_secs_f64() * 1000.0,
+        sample.audio.as_secs_f64() * 1000.0
+    )
+}
+
+/// A fresh console for `rom_file` set up like the one started by `main`,
+/// playing into the same audio buffer.
+/// Instructions kept for crash reports.
+const CRASH_TRACE_LEN: usize = 2000;
+
+/// The last panic's message and location, set by the hook [`main`]
+/// installs for `--crash-reports`.
+static PANIC_MESSAGE: Mutex<Option<String>> = Mutex::new(None);
+
+fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if let Ok(mut message) = PANIC_MESSAGE.lock() {
+            *message = Some(info.to_string());
+        }
+        default_hook(info);
+    }));
+}
+
+fn write_crash_report(nes: &Nes, message: &str, rom_file: &str, config: &Config, dir: &Path) {
+    let rom = read_rom(rom_file).unwrap_or_default();
+    let mut report = CrashReport::new(nes, message, rom_file, &rom);
+    report.config = Some(config.to_text());
+    match report.write_to(dir) {
+        Ok(path) => eprintln!("Crash report written to {}", path.display()),
+        Err(e) => log::warn!("{}", e),
+    }
+}
+
+fn reload_rom(
+    rom_file: &str,
+    args: &CliArgs,
+    profile: &Profile,
+    sample_rate: u32,
+    audio_buffer: Arc<Mutex<VecDeque<f32>>>,
+) -> Result<Nes, String> {
+    let mut bytes = read_rom(rom_file)?;
+    if let Some(path) = &args.romdb {
+        let db = RomDatabase::load_from_file(path)?;
+        bytes = verify_rom(bytes, &db, args.fix_header);
+    }
+    let cart = Cart::new(&bytes)?;
+    let mut apu = APU::new(sample_rate, audio_buffer);
+    apu.set_revision(args.apu_revision.into());
+
+    let mut nes = Nes::with_model(cart, apu, args.cpu_model.into());
+    nes.bus.set_dpcm_conflict(!args.no_dpcm_conflict);
+    nes.bus.set_open_bus(args.open_bus.into());
+    nes.bus.set_overclock(args.overclock);
+    nes.reset(ResetKind::PowerOn);
+    apply_palette(&mut nes, profile);
+    apply_triggers(&mut nes, profile);
+    if let Some(path) = &args.cheats {
+        *nes.cheats_mut() = load_cheats(path)?;
+    }
+    for code in &args.game_genie {
+        nes.cheats_mut().add(code.clone());
+    }
+    if args.link {
+        nes.connect_link();
+    }
+    if args.stack_check {
+        nes.enable_stack_check();
+    }
+    if args.crash_reports.is_some() {
+        nes.enable_trace_history(CRASH_TRACE_LEN);
+    }
+    Ok(nes)
+}
+
+fn watch_macro<'a>(profile: &'a Profile, key: &str) -> Option<&'a InputMacro> {
+    profile
+        .macros
+        .iter()
+        .find(|(macro_key, _)| macro_key.eq_ignore_ascii_case(key))
+        .map(|(_, input_macro)| input_macro)
+}
+
+fn read_rom(rom_file: &str) -> Result<Vec<u8>, String> {
+    if rom_file == "demo" && !Path::new("demo").exists() {
+        return Ok(demo::hello_world_rom());
+    }
+    if rom_file == "latency" && !Path::new("latency").exists() {
+        return Ok(latency::latency_test_rom());
+    }
+    std::fs::read(rom_file).map_err(|e| format!("Failed to read {}: {}", rom_file, e))
+}
+
+fn load_symbols(paths: &[PathBuf]) -> Result<SymbolTable, String> {
+    let mut symbols = SymbolTable::new();
+    for path in paths {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        symbols
+            .load(&path.to_string_lossy(), &text)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    Ok(symbols)
+}
+
+fn load_cheats(path: &Path) -> Result<CheatList, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    CheatList::parse_file(&path.to_string_lossy(), &text)
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn convert_cheats(input: &Path, output: &Path) -> Result<(), String> {
+    let text = load_cheats(input)?.to_file_format(&output.to_string_lossy())?;
+    std::fs::write(output, text).map_err(|e| format!("Failed to write {}: {}", output.display(), e))
+}
+
+fn compare_trace(rom_file: &str, log: &Path, context: usize) -> Result<(), String> {
+    let cart = Cart::new(&read_rom(rom_file)?)?;
+    let apu = APU::new(48_000, Arc::new(Mutex::new(VecDeque::new())));
+    let mut nes = Nes::new(cart, apu);
+    nes.reset(ResetKind::PowerOn);
+
+    let file = File::open(log).map_err(|e| format!("Failed to open {}: {}", log.display(), e))?;
+    let mut compare = TraceCompare::new(context);
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|e| format!("Failed to read {}: {}", log.display(), e))?;
+        if let Err(divergence) = compare.check(&mut nes, &line) {
+            return Err(divergence.report());
+        }
     }
+    println!("{} instructions match", compare.matched());
+    Ok(())
+}
+
+fn run_headless(args: RunArgs) -> Result<(), String> {
+    let cart = Cart::new(&read_rom(&args.rom_file)?)?;
+    let movie = args
+        .input
+        .as_ref()
+        .map(FM2Movie::load_from_file)
+        .transpose()?;
+
+    let mut run = HeadlessRun::new(cart, movie)?;
+    if let Some(path) = &args.cheats {
+        *run.nes.cheats_mut() = load_cheats(path)?;
+    }
+    for code in &args.game_genie {
+        run.nes.cheats_mut().add(code.clone());
+    }
+    if args.profile.is_some() {
+        run.nes.enable_profiler();
+    }
+    for trigger in &args.trigger {
+        run.nes.add_trigger(trigger.clone());
+    }
+    run.nes.set_stems_enabled(args.stems.is_some());
+    let mut watchdog = Watchdog::new().auto_reset(args.auto_reset.map(ResetKind::from));
+    if let Some((start, end)) = args.alive {
+        watchdog = watchdog.alive_region(start, end, args.alive_frames);
+    }
+    run.nes.set_watchdog(Some(watchdog));
+    if let Some(path) = &args.access_log {
+        let access_log = args
+            .access_log_range
+            .iter()
+            .fold(AccessLog::to_file(path)?, |log, &range| log.range(range));
+        run.nes.set_access_log(Some(access_log));
+    }
+    let stop = run.run_frames(args.frames);
+    if let Some(access_log) = run.nes.access_log_mut() {
+        access_log.flush()?;
+    }
+    write_captures(&mut run.nes, &args.capture_dir)?;
+
+    if let Some(dir) = &args.stems
+        && let Some(stems) = run.nes.take_stems()
+    {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        for (name, samples) in CHANNEL_NAMES.iter().zip(&stems) {
+            let path = dir.join(format!("{}.wav", name));
+            std::fs::write(&path, wav::encode(samples, HeadlessRun::SAMPLE_RATE))
+                .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        }
+    }
+
+    if let Some(path) = &args.dump {
+        std::fs::write(path, run.nes.dump_state())
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    }
+    if let Some(path) = &args.screenshot {
+        std::fs::write(path, run.screenshot().to_png())
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    }
+    if let Some(path) = &args.profile
+        && let Some(profiler) = run.nes.profiler()
+    {
+        let report = profiler.report(args.profile_by.into(), usize::MAX);
+        std::fs::write(path, report)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    }
+
+    if !args.quiet {
+        if let Some(watchdog) = run.nes.watchdog_mut() {
+            for event in watchdog.take_events() {
+                println!("Watchdog: {}", event);
+            }
+        }
+        println!("{} frames, {:?}", run.frame(), run.nes.bus.cpu.registers);
+        if let Some(stop) = stop {
+            println!("Stopped early: {:?}", stop);
+        }
+    }
+    Ok(())
+}
+
+fn run_tui(args: &CliArgs, rom_file: &str) -> Result<(), String> {
+    let cart = Cart::new(&read_rom(rom_file)?)?;
+    // Nothing plays the samples; the APU drops the oldest once full.
+    let mut apu = APU::new(48_000, Arc::new(Mutex::new(VecDeque::new())));
+    apu.set_revision(args.apu_revision.into());
+    let mut nes = Nes::with_model(cart, apu, args.cpu_model.into());
+    nes.bus.set_dpcm_conflict(!args.no_dpcm_conflict);
+    nes.bus.set_open_bus(args.open_bus.into());
+    nes.bus.set_overclock(args.overclock);
+    nes.reset(ResetKind::PowerOn);
+    if let Some(path) = &args.cheats {
+        *nes.cheats_mut() = load_cheats(path)?;
+    }
+    for code in &args.game_genie {
+        nes.cheats_mut().add(code.clone());
+    }
+
+    let saved_mode = stty(&["-g"])?;
+    stty(&["raw", "-echo"])?;
+    let result = tui_loop(&mut nes, args.tui_color.into());
+    print!("\x1b[0m\x1b[?25h\r\n");
+    stty(&[saved_mode.trim()])?;
+    result
+}
+
+fn tui_loop(nes: &mut Nes, color: TuiColor) -> Result<(), String> {
+    // Leave the last row free so the cursor doesn't scroll the frame.
+    let (columns, rows) = terminal_size();
+    let rows = rows.saturating_sub(1);
+
+    let (sender, keys) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let mut stdin = std::io::stdin();
+        let mut buffer = [0u8; 64];
+        while let Ok(len @ 1..) = stdin.read(&mut buffer) {
+            if sender.send(buffer[..len].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut stdout = std::io::stdout();
+    let write_failed = |e: std::io::Error| format!("Failed to write to terminal: {}", e);
+    write!(stdout, "\x1b[2J\x1b[?25l").map_err(write_failed)?;
+
+    let mut held = HeldButtons::new();
+    let mut framebuffer = Framebuffer::new();
+    let frame_time = nes.bus.cpu.model().frame_time();
+    let mut next_frame = Instant::now();
+    loop {
+        while let Ok(bytes) = keys.try_recv() {
+            for key in tui::parse_keys(&bytes) {
+                match key {
+                    TuiKey::Button(button) => held.press(button),
+                    TuiKey::Reset => nes.reset(ResetKind::Reset),
+                    TuiKey::Quit => return Ok(()),
+                }
+            }
+        }
+        if let Some(joypad) = nes.joypad_mut(0) {
+            joypad.button_status = held.next_frame();
+        }
+
+        nes.step_frame();
+        nes.present_frame(&mut framebuffer);
+        stdout
+            .write_all(tui::render(&framebuffer, columns, rows, color).as_bytes())
+            .and_then(|_| stdout.flush())
+            .map_err(write_failed)?;
+
+        next_frame += frame_time;
+        let now = Instant::now();
+        match next_frame.checked_duration_since(now) {
+            Some(wait) => std::thread::sleep(wait),
+            None => next_frame = now,
+        }
+    }
+}
+
+// Runs stty on the controlling terminal and returns what it printed.
+fn stty(args: &[&str]) -> Result<String, String> {
+    let tty = std::fs::File::open("/dev/tty").map_err(|e| format!("No terminal: {}", e))?;
+    let output = std::process::Command::new("stty")
+        .args(args)
+        .stdin(tty)
+        .output()
+        .map_err(|e| format!("Failed to run stty: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("stty {} failed", args.join(" ")));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Terminal size in (columns, rows), assuming 80x24 if it can't be read.
+fn terminal_size() -> (usize, usize) {
+    let size = stty(&["size"]).ok().and_then(|size| {
+        let mut numbers = size.split_whitespace().map(|n| n.parse().ok());
+        let rows = numbers.next()??;
+        let columns = numbers.next()??;
+        Some((columns, rows))
+    });
+    size.unwrap_or((80, 24))
+}
+
+/// Battery saves are keyed by the ROM's file name, e.g. `zelda.sav`.
+fn save_key(rom_file: &str) -> String {
+    let stem = Path::new(rom_file)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    format!("{}.sav", stem)
+}
+
+fn verify_rom(bytes: Vec<u8>, db: &RomDatabase, fix_header: bool) -> Vec<u8> {
+    let report = match romdb::check(&bytes, db) {
+        Ok(report) => report,
+        Err(e) => {
+            println!("ROM check skipped: {}", e);
+            return bytes;
+        }
+    };
+
+    match &report.matched {
+        Some(entry) => println!("ROM: {}", entry.name),
+        None => println!(
+            "ROM: unknown (PRG {:08x}, CHR {:08x})",
+            report.prg_crc32, report.chr_crc32
+        ),
+    }
+    for issue in &report.issues {
+        println!("  {}", issue);
+    }
+
+    if !fix_header || report.is_clean() {
+        return bytes;
+    }
+
+    let (fixed, changes) = romdb::fix_header(&bytes, &report);
+    for change in &changes {
+        println!("  header override: {}", change);
+    }
+    fixed
+}
+
+fn build_key_map(profile: &Profile) -> HashMap<Keycode, JoypadButton> {
+    let mut key_map = HashMap::new();
+    for (button, key_name) in &profile.bindings {
+        match Keycode::from_name(key_name) {
+            Some(keycode) => {
+                key_map.insert(keycode, *button);
+            }
+            None => log::warn!("Unknown key {:?} in profile {}", key_name, profile.name),
+        }
+    }
+    key_map
+}
+
+fn build_macro_map(profile: &Profile) -> HashMap<Keycode, InputMacro> {
+    let mut macro_map = HashMap::new();
+    for (key_name, input_macro) in &profile.macros {
+        match Keycode::from_name(key_name) {
+            Some(keycode) => {
+                macro_map.insert(keycode, input_macro.clone());
+            }
+            None => log::warn!("Unknown key {:?} in profile {}", key_name, profile.name),
+        }
+    }
+    macro_map
+}
+
+fn apply_triggers(nes: &mut Nes, profile: &Profile) {
+    nes.clear_triggers();
+    for trigger in &profile.triggers {
+        nes.add_trigger(trigger.clone());
+    }
+}
+
+fn parse_trigger(value: &str) -> Result<Trigger, String> {
+    let (name, condition) = value.split_once('=').ok_or("expected NAME=CONDITION")?;
+    Ok(Trigger {
+        name: name.trim().to_string(),
+        condition: Condition::parse(condition)?,
+    })
+}
+
+fn parse_alive(value: &str) -> Result<(u16, u16), String> {
+    let range = TraceRange::parse(value)?;
+    if range.bank.is_some() {
+        return Err("the alive region can't name a bank".to_string());
+    }
+    Ok((range.start, range.end))
+}
+
+/// Writes each new capture as `<trigger>-<frame>.png` and `.state`.
+fn write_captures(nes: &mut Nes, dir: &Path) -> Result<(), String> {
+    for capture in nes.take_captures() {
+        let base = dir.join(format!("{}-{}", capture.trigger, capture.frame));
+        let png = base.with_extension("png");
+        std::fs::write(&png, capture.screenshot.to_png())
+            .map_err(|e| format!("Failed to write {}: {}", png.display(), e))?;
+        let state = base.with_extension("state");
+        std::fs::write(&state, &capture.state)
+            .map_err(|e| format!("Failed to write {}: {}", state.display(), e))?;
+        log::info!("Trigger {} fired, saved {}", capture.trigger, png.display());
+    }
+    Ok(())
+}
+
+fn apply_palette(nes: &mut Nes, profile: &Profile) {
+    let loaded = match &profile.palette {
+        PaletteSource::File(path) => std::fs::read(path)
+            .map_err(|e| format!("Failed to read palette {}: {}", path.display(), e))
+            .and_then(|bytes| palette::parse_pal(&bytes)),
+        PaletteSource::Ntsc => Ok(palette::generate_ntsc(profile.ntsc)),
+        PaletteSource::Builtin => Ok(*palette::SYSTEM_PALLETE),
+    };
+
+    match loaded {
+        Ok(colors) => nes.bus.ppu.system_palette = colors,
+        Err(e) => log::warn!("{}", e),
+    }
+}
+
+// Asks SDL for a higher priority for the calling thread; it picks the
+// platform's call (SetThreadPriority, setpriority or RealtimeKit).
+fn set_thread_priority(priority: ThreadPriority) {
+    if priority == ThreadPriority::Normal {
+        return;
+    }
+    let result = unsafe {
+        sdl2::sys::SDL_SetThreadPriority(sdl2::sys::SDL_ThreadPriority::SDL_THREAD_PRIORITY_HIGH)
+    };
+    if result != 0 {
+        log::warn!("Failed to raise thread priority: {}", sdl2::get_error());
+    }
+}
+
+/// Turns presented frames into the window texture's pixels, through the
+/// NTSC filter if it is on.
+struct Video {
+    #[cfg(feature = "ntsc-filter")]
+    ntsc: Option<ntsc_filter::NtscFilter>,
+}
+
+impl Video {
+    #[cfg_attr(not(feature = "ntsc-filter"), allow(unused_variables))]
+    fn new(args: &CliArgs, profile: &Profile) -> Self {
+        Video {
+            #[cfg(feature = "ntsc-filter")]
+            ntsc: args
+                .ntsc_filter
+                .then(|| ntsc_filter::NtscFilter::new(profile.ntsc)),
+        }
+    }
+
+    fn width(&self) -> u32 {
+        #[cfg(feature = "ntsc-filter")]
+        if self.ntsc.is_some() {
+            return ntsc_filter::WIDTH as u32;
+        }
+        WIDTH
+    }
+
+    #[cfg_attr(not(feature = "ntsc-filter"), allow(unused_variables))]
+    fn pixels<'a>(&'a mut self, nes: &Nes, framebuffer: &'a Framebuffer) -> &'a [u8] {
+        #[cfg(feature = "ntsc-filter")]
+        if let Some(ntsc) = &mut self.ntsc {
+            return ntsc.apply(framebuffer, nes.bus.ppu.frame_count);
+        }
+        &framebuffer.data
+    }
+}
+
+fn set_scale_quality(filter: VideoFilter) {
+    let quality = match filter {
+        VideoFilter::Nearest => "0",
+        VideoFilter::Linear => "1",
+    };
+    sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", quality);
+}
+
+fn latency_samples(profile: &Profile, sample_rate: u32) -> usize {
+    (profile.audio_latency_ms as usize * sample_rate as usize) / 1000
+}
+
+fn adaptive_latency(profile: &Profile) -> Option<AdaptiveLatency> {
+    profile
+        .adaptive_audio_latency
+        .then(|| AdaptiveLatency::new(Duration::from_millis(profile.audio_latency_ms as u64)))
 }
 
 fn apply_inputs(
     nes: &mut Nes,
     movie: &mut Option<FM2Movie>,
+    playing_macro: &mut Option<MacroPlayback>,
     frame_count: usize,
     buttons: &HashMap<JoypadButton, bool>,
 ) {
@@ -185,6 +1544,15 @@ fn apply_inputs(
         }
     }
 
+    // A running macro replaces the keyboard on controller 1 until it ends.
+    if let Some(held) = playing_macro.as_mut().and_then(|playback| playback.next()) {
+        if let Some(joypad) = nes.joypad_mut(0) {
+            joypad.button_status = held;
+        }
+        return;
+    }
+    *playing_macro = None;
+
     if let Some(joypad) = nes.joypad_mut(0) {
         for (btn, state) in buttons {
             joypad.set_button_pressed_status(*btn, *state);
@@ -192,18 +1560,44 @@ fn apply_inputs(
     }
 }
 
-fn run_frame(nes: &mut Nes, debug_trace: bool) {
+fn trace_filter(args: &CliArgs) -> TraceFilter {
+    let mut filter = TraceFilter::new();
+    for &range in &args.trace_include {
+        filter.include(range);
+    }
+    for &range in &args.trace_exclude {
+        filter.exclude(range);
+    }
+    filter
+}
+
+/// Runs until the end of the frame, printing a trace of each instruction
+/// the filter allows when `debug_trace` is given.
+fn run_frame(
+    nes: &mut Nes,
+    debug_trace: Option<&(SymbolTable, TraceFilter)>,
+    vsync_source: VsyncSource,
+) {
     loop {
         let ClockResult {
             frame_complete,
             instruction_complete,
+            vblank,
+            ..
         } = nes.clock();
 
-        if debug_trace && instruction_complete {
-            println!("{}", trace(&nes.bus.cpu, &nes.bus));
+        if let Some((symbols, filter)) = debug_trace
+            && instruction_complete
+            && filter.allows_next(&nes.bus.cpu, &nes.bus)
+        {
+            println!("{}", trace_with_symbols(&nes.bus.cpu, &nes.bus, symbols));
         }
 
-        if frame_complete {
+        let done = match vsync_source {
+            VsyncSource::Host => frame_complete,
+            VsyncSource::Emulated => vblank,
+        };
+        if done {
             break;
         }
     }
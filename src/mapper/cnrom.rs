@@ -1,14 +1,39 @@
 use crate::cart::Mirroring;
 use crate::mapper::{ChrSource, Mapper};
+use std::rc::Rc;
 
 const CHR_BANK_SIZE: usize = 0x2000;
 
+/// Copy protection on mapper 185 boards: diodes on the bank register's data
+/// lines disconnect CHR unless the game writes the board's key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ChrProtection {
+    /// Key unknown (submapper 0). Every known game enables CHR with a value
+    /// whose low nibble is non-zero other than $13, and disables it with $00
+    /// or $13.
+    Heuristic,
+    /// CHR is enabled when D1-D0 equal the key (submappers 4-7).
+    Key(u8),
+}
+
+impl ChrProtection {
+    fn enables(self, data: u8) -> bool {
+        match self {
+            ChrProtection::Heuristic => data & 0x0F != 0 && data != 0x13,
+            ChrProtection::Key(key) => data & 0x03 == key,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct CnromMapper {
-    prg_rom: Vec<u8>,
-    chr: Vec<u8>,
+    prg_rom: Rc<[u8]>,
+    chr: Rc<[u8]>,
     chr_is_ram: bool,
     prg_ram: Vec<u8>,
     chr_bank: u8,
+    protection: Option<ChrProtection>,
+    chr_enabled: bool,
     mirroring: Mirroring,
 }
 
@@ -22,18 +47,46 @@ impl CnromMapper {
         };
 
         CnromMapper {
-            prg_rom,
-            chr,
+            prg_rom: prg_rom.into(),
+            chr: chr.into(),
             chr_is_ram,
             prg_ram: vec![0; 0x2000],
             chr_bank: 0,
+            protection: None,
+            chr_enabled: true,
             mirroring,
         }
     }
 
-    fn chr_bank_count(&self) -> usize {
-        let count = self.chr.len() / CHR_BANK_SIZE;
-        if count == 0 { 1 } else { count }
+    /// Mapper 185: CNROM with copy protection. While disabled, CHR reads
+    /// return open bus, approximated as $FF.
+    pub fn protected(
+        prg_rom: Vec<u8>,
+        chr_rom: Vec<u8>,
+        mirroring: Mirroring,
+        submapper: u8,
+    ) -> Self {
+        let protection = match submapper {
+            4..=7 => ChrProtection::Key(submapper & 0x03),
+            _ => ChrProtection::Heuristic,
+        };
+        CnromMapper {
+            protection: Some(protection),
+            ..Self::new(prg_rom, chr_rom, mirroring)
+        }
+    }
+
+    // Offset of the selected 8KB bank. Bank bits beyond the CHR size's
+    // address lines are dropped; for sizes that aren't a power of two (e.g.
+    // 24KB) the remaining banks wrap.
+    fn chr_bank_address(&self) -> usize {
+        let banks = self.chr.len().div_ceil(CHR_BANK_SIZE).max(1);
+        let index = (self.chr_bank as usize & (banks.next_power_of_two() - 1)) % banks;
+        index * CHR_BANK_SIZE
+    }
+
+    fn chr_index(&self, addr: u16) -> usize {
+        (self.chr_bank_address() + (addr as usize & 0x1FFF)) % self.chr.len()
     }
 }
 
@@ -53,38 +106,60 @@ impl Mapper for CnromMapper {
         }
     }
 
+    fn prg_mapped(&self, addr: u16) -> bool {
+        addr >= 0x6000
+    }
+
+    fn prg_bank(&self, addr: u16) -> Option<usize> {
+        (addr >= 0x8000 && !self.prg_rom.is_empty()).then_some(0)
+    }
+
+    fn debug_state(&self) -> Vec<(String, String)> {
+        let mut state = vec![("CHR bank".to_string(), self.chr_bank.to_string())];
+        if let Some(protection) = self.protection {
+            state.push(("Protection".to_string(), format!("{:?}", protection)));
+            state.push(("CHR enabled".to_string(), self.chr_enabled.to_string()));
+        }
+        state
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
     fn write_prg(&mut self, addr: u16, data: u8) {
         match addr {
             0x6000..=0x7FFF => {
                 self.prg_ram[(addr - 0x6000) as usize] = data;
             }
             0x8000..=0xFFFF => {
-                let count = self.chr_bank_count() as u8;
-                self.chr_bank = if count == 0 { 0 } else { data % count };
+                self.chr_bank = data;
+                if let Some(protection) = self.protection {
+                    self.chr_enabled = protection.enables(data);
+                }
             }
             _ => {}
         }
     }
 
     fn read_chr(&self, addr: u16, _source: ChrSource) -> u8 {
-        if self.chr.is_empty() {
+        if !self.chr_enabled {
+            0xFF
+        } else if self.chr.is_empty() {
             0
         } else {
-            let bank = (self.chr_bank as usize % self.chr_bank_count()) * CHR_BANK_SIZE;
-            let offset = (addr as usize) & 0x1FFF;
-            let index = bank + offset;
-            self.chr[index % self.chr.len()]
+            self.chr[self.chr_index(addr)]
         }
     }
 
     fn write_chr(&mut self, addr: u16, data: u8) {
-        if self.chr_is_ram && !self.chr.is_empty() {
-            let bank = (self.chr_bank as usize % self.chr_bank_count()) * CHR_BANK_SIZE;
-            let offset = (addr as usize) & 0x1FFF;
-            let index = bank + offset;
-            let len = self.chr.len();
-            let idx = index % len;
-            self.chr[idx] = data;
+        if self.chr_is_ram && self.chr_enabled && !self.chr.is_empty() {
+            let index = self.chr_index(addr);
+            Rc::make_mut(&mut self.chr)[index] = data;
         }
     }
 
@@ -92,3 +167,64 @@ impl Mapper for CnromMapper {
         self.mirroring.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterned_chr(size: usize) -> Vec<u8> {
+        (0..size).map(|i| (i / CHR_BANK_SIZE) as u8).collect()
+    }
+
+    #[test]
+    fn clone_shares_rom_and_copies_chr_ram() {
+        let mut mapper = CnromMapper::new(vec![0; 0x8000], vec![], Mirroring::Vertical);
+        let snapshot = mapper.clone();
+        assert!(Rc::ptr_eq(&mapper.prg_rom, &snapshot.prg_rom));
+
+        mapper.write_chr(0x0010, 0x42);
+        assert_eq!(mapper.read_chr(0x0010, ChrSource::Cpu), 0x42);
+        assert_eq!(snapshot.read_chr(0x0010, ChrSource::Cpu), 0);
+    }
+
+    #[test]
+    fn bank_is_masked_to_chr_size() {
+        let mut mapper =
+            CnromMapper::new(vec![0; 0x8000], patterned_chr(0x4000), Mirroring::Vertical);
+        mapper.write_prg(0x8000, 0x03);
+        assert_eq!(mapper.read_chr(0x0000, ChrSource::Background), 1);
+        mapper.write_prg(0x8000, 0x02);
+        assert_eq!(mapper.read_chr(0x1FFF, ChrSource::Background), 0);
+
+        let mut mapper =
+            CnromMapper::new(vec![0; 0x8000], patterned_chr(0x8000), Mirroring::Vertical);
+        mapper.write_prg(0x8000, 0x07);
+        assert_eq!(mapper.read_chr(0x0000, ChrSource::Background), 3);
+
+        // 24KB: bank 3 isn't there and wraps to bank 0.
+        let mut mapper =
+            CnromMapper::new(vec![0; 0x8000], patterned_chr(0x6000), Mirroring::Vertical);
+        mapper.write_prg(0x8000, 0x02);
+        assert_eq!(mapper.read_chr(0x0000, ChrSource::Background), 2);
+        mapper.write_prg(0x8000, 0x03);
+        assert_eq!(mapper.read_chr(0x0000, ChrSource::Background), 0);
+    }
+
+    #[test]
+    fn protected_chr_needs_the_key() {
+        let chr = vec![0x42; CHR_BANK_SIZE];
+        let mut mapper =
+            CnromMapper::protected(vec![0; 0x8000], chr.clone(), Mirroring::Vertical, 6);
+        mapper.write_prg(0x8000, 0x21);
+        assert_eq!(mapper.read_chr(0x0000, ChrSource::Background), 0xFF);
+        mapper.write_prg(0x8000, 0x22);
+        assert_eq!(mapper.read_chr(0x0000, ChrSource::Background), 0x42);
+
+        let mut mapper = CnromMapper::protected(vec![0; 0x8000], chr, Mirroring::Vertical, 0);
+        for (data, enabled) in [(0x00, false), (0x13, false), (0x01, true), (0x20, false)] {
+            mapper.write_prg(0x8000, data);
+            let expected = if enabled { 0x42 } else { 0xFF };
+            assert_eq!(mapper.read_chr(0x0100, ChrSource::Background), expected);
+        }
+    }
+}
@@ -1,11 +1,12 @@
 use crate::cart::Mirroring;
-use crate::mapper::{ChrSource, Mapper};
+use crate::mapper::{ChrSource, Mapper, debug_banks};
+use std::rc::Rc;
 
 const PRG_BANK_SIZE: usize = 0x4000;
 const CHR_BANK_SIZE_4K: usize = 0x1000;
 const SRAM_BANK_SIZE: usize = 0x2000;
 
-#[derive(Default, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 enum PrgMode {
     Bank32kb,
     FixFirstPage,
@@ -13,16 +14,17 @@ enum PrgMode {
     FixLastPage,
 }
 
-#[derive(Default, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 enum ChrMode {
     #[default]
     Bank8kb,
     Bank4kb,
 }
 
+#[derive(Clone)]
 pub struct Mmc1Mapper {
-    prg_rom: Vec<u8>,
-    chr: Vec<u8>,
+    prg_rom: Rc<[u8]>,
+    chr: Rc<[u8]>,
     chr_is_ram: bool,
     prg_ram: Vec<u8>,
 
@@ -61,8 +63,8 @@ impl Mmc1Mapper {
         };
 
         let mut mapper = Mmc1Mapper {
-            prg_rom,
-            chr,
+            prg_rom: prg_rom.into(),
+            chr: chr.into(),
             chr_is_ram,
             prg_ram: vec![0; SRAM_BANK_SIZE],
             prg_mode: PrgMode::FixLastPage,
@@ -216,6 +218,51 @@ impl Mapper for Mmc1Mapper {
         }
     }
 
+    fn prg_mapped(&self, addr: u16) -> bool {
+        addr >= 0x8000 || (addr >= 0x6000 && !self.prg_ram_disabled && !self.prg_ram.is_empty())
+    }
+
+    fn prg_bank(&self, addr: u16) -> Option<usize> {
+        match addr {
+            _ if self.prg_rom.is_empty() => None,
+            0x8000..=0xBFFF => Some(self.prg_banks[0] / PRG_BANK_SIZE),
+            0xC000..=0xFFFF => Some(self.prg_banks[1] / PRG_BANK_SIZE),
+            _ => None,
+        }
+    }
+
+    fn debug_state(&self) -> Vec<(String, String)> {
+        vec![
+            (
+                "Shift register".to_string(),
+                format!("{:05b} ({} writes)", self.shift_reg, self.shift_writes),
+            ),
+            ("PRG mode".to_string(), format!("{:?}", self.prg_mode)),
+            ("CHR mode".to_string(), format!("{:?}", self.chr_mode)),
+            (
+                "PRG banks".to_string(),
+                debug_banks(&self.prg_banks, PRG_BANK_SIZE),
+            ),
+            (
+                "CHR banks".to_string(),
+                debug_banks(&self.chr_banks, CHR_BANK_SIZE_4K),
+            ),
+            ("PRG RAM bank".to_string(), self.sram_bank.to_string()),
+            (
+                "PRG RAM enabled".to_string(),
+                (!self.prg_ram_disabled).to_string(),
+            ),
+        ]
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
     fn write_prg(&mut self, addr: u16, val: u8) {
         match addr {
             0x6000..=0x7FFF => {
@@ -281,7 +328,7 @@ impl Mapper for Mmc1Mapper {
             let bank = if addr < 0x1000 { self.chr_banks[0] } else { self.chr_banks[1] };
             let offset = bank + (addr as usize & 0x0FFF);
             if offset < self.chr.len() {
-                self.chr[offset] = val;
+                Rc::make_mut(&mut self.chr)[offset] = val;
             }
         }
     }
@@ -1,28 +1,33 @@
 use crate::cart::Mirroring;
-use crate::mapper::{ChrSource, Mapper};
+use crate::mapper::{ChrSource, Mapper, debug_banks};
+use std::rc::Rc;
 
 const PRG_BANK_SIZE: usize = 0x2000;
 const CHR_BANK_SIZE_1K: usize = 0x0400;
-const CHR_BANK_SIZE_2K: usize = 0x0800;
 
-#[derive(Clone, Copy, Default, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 enum PrgMode {
     #[default]
     FixLastPages,
     FixFirstPages,
 }
 
-#[derive(Clone, Copy, Default, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 enum ChrMode {
     #[default]
     BiggerFirst,
     BiggerLast,
 }
 
+#[derive(Clone)]
 pub struct Mmc3Mapper {
-    prg_rom: Vec<u8>,
-    chr: Vec<u8>,
-    chr_is_ram: bool,
+    prg_rom: Rc<[u8]>,
+    /// CHR ROM followed by any CHR RAM; bytes from `chr_ram_start` on are
+    /// writable.
+    chr: Rc<[u8]>,
+    chr_ram_start: usize,
+    /// Bank number bit that selects CHR RAM on boards with both (TQROM).
+    chr_ram_select: Option<u8>,
     prg_ram: Vec<u8>,
 
     reg_select: u8,
@@ -47,13 +52,34 @@ pub struct Mmc3Mapper {
 
 impl Mmc3Mapper {
     pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {
-        let chr_is_ram = chr_rom.is_empty();
-        let chr = if chr_is_ram { vec![0; 0x2000] } else { chr_rom };
+        if chr_rom.is_empty() {
+            Self::with_chr(prg_rom, vec![0; 0x2000], 0, None, mirroring)
+        } else {
+            let len = chr_rom.len();
+            Self::with_chr(prg_rom, chr_rom, len, None, mirroring)
+        }
+    }
 
+    /// TQROM (mapper 119): CHR ROM plus 8KB of CHR RAM, with bit 6 of each
+    /// bank number choosing RAM.
+    pub fn tqrom(prg_rom: Vec<u8>, mut chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {
+        let chr_ram_start = chr_rom.len();
+        chr_rom.resize(chr_ram_start + 0x2000, 0);
+        Self::with_chr(prg_rom, chr_rom, chr_ram_start, Some(0x40), mirroring)
+    }
+
+    fn with_chr(
+        prg_rom: Vec<u8>,
+        chr: Vec<u8>,
+        chr_ram_start: usize,
+        chr_ram_select: Option<u8>,
+        mirroring: Mirroring,
+    ) -> Self {
         let mut mapper = Mmc3Mapper {
-            prg_rom,
-            chr,
-            chr_is_ram,
+            prg_rom: prg_rom.into(),
+            chr: chr.into(),
+            chr_ram_start,
+            chr_ram_select,
             prg_ram: vec![0; 0x2000],
             reg_select: 0,
             prg_mode: PrgMode::default(),
@@ -81,11 +107,6 @@ impl Mmc3Mapper {
         if count == 0 { 1 } else { count }
     }
 
-    fn chr_bank_count(&self) -> usize {
-        let count = self.chr.len() / CHR_BANK_SIZE_1K;
-        if count == 0 { 1 } else { count }
-    }
-
     fn set_prg_page(&mut self, slot: usize, bank_index: u8) {
         if self.prg_rom.is_empty() {
             self.prg_banks[slot] = 0;
@@ -96,19 +117,26 @@ impl Mmc3Mapper {
         self.prg_banks[slot] = index * PRG_BANK_SIZE;
     }
 
-    fn chr_bank_address(&self, value: u8, bank_size: usize) -> usize {
-        if self.chr.is_empty() {
-            0
-        } else {
-            let mut index = value as usize;
-            if bank_size == CHR_BANK_SIZE_2K {
-                index &= !1;
-            }
-            let count = self.chr_bank_count();
-            index %= count;
-            let base = (index * CHR_BANK_SIZE_1K) % self.chr.len();
-            base & !(bank_size - 1)
+    // Start of the 1KB bank `value` selects in the CHR ROM or RAM region it
+    // addresses. Bank bits beyond the region's address lines are dropped; for
+    // sizes that aren't a power of two (e.g. 96KB) the remaining banks wrap.
+    fn chr_bank_address(&self, value: u8) -> usize {
+        let (start, len, index) = match self.chr_ram_select {
+            Some(bit) if value & bit != 0 => (
+                self.chr_ram_start,
+                self.chr.len() - self.chr_ram_start,
+                value & (bit - 1),
+            ),
+            Some(bit) => (0, self.chr_ram_start, value & (bit - 1)),
+            None => (0, self.chr.len(), value),
+        };
+        if len == 0 {
+            return start;
         }
+
+        let banks = len.div_ceil(CHR_BANK_SIZE_1K);
+        let index = (index as usize & (banks.next_power_of_two() - 1)) % banks;
+        start + index * CHR_BANK_SIZE_1K
     }
 
     fn set_chr_pair(&mut self, slot: usize, value: u8) {
@@ -118,9 +146,8 @@ impl Mmc3Mapper {
             return;
         }
 
-        let base = self.chr_bank_address(value, CHR_BANK_SIZE_2K);
-        self.chr_banks[slot] = base;
-        self.chr_banks[slot + 1] = (base + CHR_BANK_SIZE_1K) % self.chr.len();
+        self.chr_banks[slot] = self.chr_bank_address(value & !1);
+        self.chr_banks[slot + 1] = self.chr_bank_address(value | 1);
     }
 
     fn set_chr_single(&mut self, slot: usize, value: u8) {
@@ -129,7 +156,7 @@ impl Mmc3Mapper {
             return;
         }
 
-        self.chr_banks[slot] = self.chr_bank_address(value, CHR_BANK_SIZE_1K);
+        self.chr_banks[slot] = self.chr_bank_address(value);
     }
 
     fn init_prg_banks(&mut self) {
@@ -318,6 +345,52 @@ impl Mapper for Mmc3Mapper {
         }
     }
 
+    fn prg_mapped(&self, addr: u16) -> bool {
+        addr >= 0x8000 || (addr >= 0x6000 && self.sram_read_enabled)
+    }
+
+    fn prg_bank(&self, addr: u16) -> Option<usize> {
+        self.prg_addr(addr).map(|index| index / PRG_BANK_SIZE)
+    }
+
+    fn debug_state(&self) -> Vec<(String, String)> {
+        vec![
+            ("Bank select".to_string(), self.reg_select.to_string()),
+            ("PRG mode".to_string(), format!("{:?}", self.prg_mode)),
+            ("CHR mode".to_string(), format!("{:?}", self.chr_mode)),
+            (
+                "PRG banks".to_string(),
+                debug_banks(&self.prg_banks, PRG_BANK_SIZE),
+            ),
+            (
+                "CHR banks".to_string(),
+                debug_banks(&self.chr_banks, CHR_BANK_SIZE_1K),
+            ),
+            ("IRQ latch".to_string(), self.irq_latch.to_string()),
+            ("IRQ counter".to_string(), self.irq_count.to_string()),
+            ("IRQ reload".to_string(), self.irq_reload.to_string()),
+            ("IRQ enabled".to_string(), self.irq_enabled.to_string()),
+            ("IRQ pending".to_string(), self.irq_pending.to_string()),
+            (
+                "PRG RAM".to_string(),
+                match (self.sram_read_enabled, self.sram_write_enabled) {
+                    (true, true) => "read/write",
+                    (true, false) => "read only",
+                    (false, _) => "disabled",
+                }
+                .to_string(),
+            ),
+        ]
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
     fn write_prg(&mut self, addr: u16, data: u8) {
         match addr {
             0x6000..=0x7FFF => {
@@ -369,9 +442,12 @@ impl Mapper for Mmc3Mapper {
     }
 
     fn write_chr(&mut self, addr: u16, data: u8) {
-        if self.chr_is_ram && !self.chr.is_empty() {
-            let index = self.chr_addr(addr);
-            self.chr[index] = data;
+        if self.chr.is_empty() {
+            return;
+        }
+        let index = self.chr_addr(addr);
+        if index >= self.chr_ram_start {
+            Rc::make_mut(&mut self.chr)[index] = data;
         }
     }
 
@@ -429,6 +505,27 @@ mod tests {
         assert_eq!(mapper.read_prg(0xC000), 1);
     }
 
+    #[test]
+    fn debug_state_lists_banks_and_irq() {
+        let mut mapper = Mmc3Mapper::new(patterned_prg(4), vec![0; 0x2000], Mirroring::Vertical);
+        mapper.write_prg(0x8000, 0x06);
+        mapper.write_prg(0x8001, 0x01);
+        mapper.write_prg(0xC000, 0x20);
+        mapper.write_prg(0xE001, 0x00);
+
+        let state = mapper.debug_state();
+        let value = |label: &str| {
+            state
+                .iter()
+                .find(|(name, _)| name == label)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(value("Bank select"), Some("6"));
+        assert_eq!(value("PRG banks"), Some("1 1 2 3"));
+        assert_eq!(value("IRQ latch"), Some("32"));
+        assert_eq!(value("IRQ enabled"), Some("true"));
+    }
+
     #[test]
     fn irq_counter_respects_latch_and_enable() {
         let prg_rom = patterned_prg(2);
@@ -478,8 +575,12 @@ mod tests {
     }
 
     fn patterned_chr() -> Vec<u8> {
-        let mut chr = vec![0u8; 0x2000];
-        for bank in 0..8 {
+        patterned_chr_banks(8)
+    }
+
+    fn patterned_chr_banks(banks: usize) -> Vec<u8> {
+        let mut chr = vec![0u8; banks * CHR_BANK_SIZE_1K];
+        for bank in 0..banks {
             let start = bank * CHR_BANK_SIZE_1K;
             for i in 0..CHR_BANK_SIZE_1K {
                 chr[start + i] = bank as u8;
@@ -531,4 +632,62 @@ mod tests {
         mapper.write_prg(0x8001, 0x03);
         assert_eq!(mapper.read_chr(0x0000, ChrSource::Cpu), 3);
     }
+
+    #[test]
+    fn chr_bank_numbers_wrap_on_8k_chr() {
+        let mut mapper = Mmc3Mapper::new(vec![0; 0x8000], patterned_chr(), Mirroring::Vertical);
+
+        select_register(&mut mapper, 2);
+        mapper.write_prg(0x8001, 0x0A);
+        assert_eq!(mapper.read_chr(0x1000, ChrSource::Cpu), 2);
+
+        select_register(&mut mapper, 0);
+        mapper.write_prg(0x8001, 0xFF);
+        assert_eq!(mapper.read_chr(0x0000, ChrSource::Cpu), 6);
+        assert_eq!(mapper.read_chr(0x0400, ChrSource::Cpu), 7);
+    }
+
+    #[test]
+    fn chr_banks_on_non_power_of_two_chr() {
+        // 96KB: banks 0-95 exist, A17 is the highest connected line.
+        let mut mapper = Mmc3Mapper::new(
+            vec![0; 0x8000],
+            patterned_chr_banks(96),
+            Mirroring::Vertical,
+        );
+
+        select_register(&mut mapper, 2);
+        for (value, bank) in [(95, 95), (100, 4), (0xC2, 66)] {
+            mapper.write_prg(0x8001, value);
+            assert_eq!(mapper.read_chr(0x1000, ChrSource::Cpu), bank);
+        }
+
+        select_register(&mut mapper, 1);
+        mapper.write_prg(0x8001, 0x5F);
+        assert_eq!(mapper.read_chr(0x0800, ChrSource::Cpu), 94);
+        assert_eq!(mapper.read_chr(0x0C00, ChrSource::Cpu), 95);
+    }
+
+    #[test]
+    fn tqrom_selects_chr_ram_with_bit_6() {
+        let mut mapper = Mmc3Mapper::tqrom(
+            vec![0; 0x8000],
+            patterned_chr_banks(64),
+            Mirroring::Vertical,
+        );
+
+        select_register(&mut mapper, 2);
+        mapper.write_prg(0x8001, 0x05);
+        mapper.write_chr(0x1000, 0xAA);
+        assert_eq!(mapper.read_chr(0x1000, ChrSource::Cpu), 5);
+
+        mapper.write_prg(0x8001, 0x41);
+        mapper.write_chr(0x1000, 0xAA);
+        assert_eq!(mapper.read_chr(0x1000, ChrSource::Cpu), 0xAA);
+
+        // RAM bank numbers wrap within the 8KB of RAM.
+        select_register(&mut mapper, 3);
+        mapper.write_prg(0x8001, 0x49);
+        assert_eq!(mapper.read_chr(0x1400, ChrSource::Cpu), 0xAA);
+    }
 }
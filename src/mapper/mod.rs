@@ -12,7 +12,35 @@ pub enum ChrSource {
     Cpu,
 }
 
-pub trait Mapper {
+/// Bank numbers for [`Mapper::debug_state`], from byte offsets into ROM.
+pub(crate) fn debug_banks(offsets: &[usize], bank_size: usize) -> String {
+    offsets
+        .iter()
+        .map(|offset| format!("{}", offset / bank_size))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Lets `Box<dyn Mapper>` be cloned for state snapshots. Implemented for every
+/// mapper that derives `Clone`. Mappers hold ROM in an `Rc<[u8]>`, so a clone
+/// shares it; CHR RAM is copied on the first write after a clone.
+pub trait MapperClone {
+    fn box_clone(&self) -> Box<dyn Mapper>;
+}
+
+impl<T: Mapper + Clone + 'static> MapperClone for T {
+    fn box_clone(&self) -> Box<dyn Mapper> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Mapper> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+pub trait Mapper: MapperClone {
     fn read_prg(&self, addr: u16) -> u8;
     fn write_prg(&mut self, addr: u16, data: u8);
     fn read_chr(&self, addr: u16, source: ChrSource) -> u8;
@@ -20,6 +48,30 @@ pub trait Mapper {
     fn peek_prg(&self, addr: u16) -> u8 {
         self.read_prg(addr)
     }
+    /// Whether the cartridge drives the data bus on a read of `addr`
+    /// ($4020-$FFFF). Reads it doesn't answer see open bus.
+    fn prg_mapped(&self, addr: u16) -> bool {
+        addr >= 0x8000
+    }
+    /// PRG ROM bank mapped at `addr`, counted in this mapper's bank size.
+    /// Used for bank-qualified breakpoints.
+    fn prg_bank(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+    /// Internal registers (bank registers, IRQ counters, shift registers) as
+    /// label/value pairs, so a debugger can show any mapper without code of
+    /// its own for each.
+    fn debug_state(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
+    /// Work RAM at $6000-$7FFF, if the mapper has any. Battery-backed saves
+    /// are read from and restored into this.
+    fn prg_ram(&self) -> Option<&[u8]> {
+        None
+    }
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
     fn mirroring(&self) -> crate::cart::Mirroring;
     fn handle_scanline(&mut self, _rendering_enabled: bool) {}
     fn poll_irq(&self) -> Option<u8> {
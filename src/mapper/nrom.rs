@@ -1,9 +1,11 @@
 use crate::cart::Mirroring;
 use crate::mapper::{ChrSource, Mapper};
+use std::rc::Rc;
 
+#[derive(Clone)]
 pub struct NromMapper {
-    prg_rom: Vec<u8>,
-    chr: Vec<u8>,
+    prg_rom: Rc<[u8]>,
+    chr: Rc<[u8]>,
     chr_is_ram: bool,
     mirroring: Mirroring,
 }
@@ -14,8 +16,8 @@ impl NromMapper {
         let chr = if chr_is_ram { vec![0; 0x2000] } else { chr_rom };
 
         NromMapper {
-            prg_rom,
-            chr,
+            prg_rom: prg_rom.into(),
+            chr: chr.into(),
             chr_is_ram,
             mirroring,
         }
@@ -41,6 +43,14 @@ impl Mapper for NromMapper {
         self.prg_rom[offset]
     }
 
+    fn debug_state(&self) -> Vec<(String, String)> {
+        vec![("CHR RAM".to_string(), self.chr_is_ram.to_string())]
+    }
+
+    fn prg_bank(&self, addr: u16) -> Option<usize> {
+        (addr >= 0x8000 && !self.prg_rom.is_empty()).then_some(0)
+    }
+
     fn write_prg(&mut self, _addr: u16, _data: u8) {
         // NROM has no PRG RAM, ignore writes
     }
@@ -52,7 +62,7 @@ impl Mapper for NromMapper {
     fn write_chr(&mut self, addr: u16, data: u8) {
         if self.chr_is_ram {
             let index = addr as usize % self.chr.len();
-            self.chr[index] = data;
+            Rc::make_mut(&mut self.chr)[index] = data;
         }
     }
 
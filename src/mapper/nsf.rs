@@ -1,9 +1,11 @@
 use crate::cart::Mirroring;
-use crate::mapper::{ChrSource, Mapper};
+use crate::mapper::{ChrSource, Mapper, debug_banks};
+use std::rc::Rc;
 
+#[derive(Clone)]
 pub struct NsfMapper {
-    prg_rom: Vec<u8>,
-    chr: Vec<u8>,
+    prg_rom: Rc<[u8]>,
+    chr: Rc<[u8]>,
     chr_is_ram: bool,
 
     mirroring: Mirroring,
@@ -26,9 +28,9 @@ impl NsfMapper {
         let chr = if chr_is_ram { vec![0; 0x2000] } else { chr_rom };
 
         NsfMapper {
-            prg_rom,
+            prg_rom: prg_rom.into(),
             banks,
-            chr,
+            chr: chr.into(),
             chr_is_ram,
             mirroring,
         }
@@ -57,6 +59,14 @@ impl Mapper for NsfMapper {
         self.prg_rom[off]
     }
 
+    fn prg_bank(&self, addr: u16) -> Option<usize> {
+        (addr >= 0x8000 && !self.prg_rom.is_empty()).then(|| self.prg_offset(addr) / 0x1000)
+    }
+
+    fn debug_state(&self) -> Vec<(String, String)> {
+        vec![("PRG banks".to_string(), debug_banks(&self.banks, 1))]
+    }
+
     fn write_prg(&mut self, addr: u16, data: u8) {
         if (0x5FF8..=0x5FFF).contains(&addr) {
             let idx = (addr - 0x5FF8) as usize;
@@ -72,7 +82,7 @@ impl Mapper for NsfMapper {
     fn write_chr(&mut self, addr: u16, data: u8) {
         if self.chr_is_ram {
             let idx = (addr as usize) % self.chr.len();
-            self.chr[idx] = data;
+            Rc::make_mut(&mut self.chr)[idx] = data;
         }
     }
 
@@ -1,11 +1,13 @@
 use crate::cart::Mirroring;
 use crate::mapper::{ChrSource, Mapper};
+use std::rc::Rc;
 
 const PRG_BANK_SIZE: usize = 0x4000;
 
+#[derive(Clone)]
 pub struct UxromMapper {
-    prg_rom: Vec<u8>,
-    chr: Vec<u8>,
+    prg_rom: Rc<[u8]>,
+    chr: Rc<[u8]>,
     chr_is_ram: bool,
     prg_ram: Vec<u8>,
     bank_select: u8,
@@ -22,8 +24,8 @@ impl UxromMapper {
         };
 
         UxromMapper {
-            prg_rom,
-            chr,
+            prg_rom: prg_rom.into(),
+            chr: chr.into(),
             chr_is_ram,
             prg_ram: vec![0; 0x2000],
             bank_select: 0,
@@ -69,6 +71,31 @@ impl Mapper for UxromMapper {
         }
     }
 
+    fn prg_mapped(&self, addr: u16) -> bool {
+        addr >= 0x6000
+    }
+
+    fn prg_bank(&self, addr: u16) -> Option<usize> {
+        match addr {
+            _ if self.prg_rom.is_empty() => None,
+            0x8000..=0xBFFF => Some(self.bank_select as usize % self.prg_bank_count()),
+            0xC000..=0xFFFF => Some(self.prg_bank_count() - 1),
+            _ => None,
+        }
+    }
+
+    fn debug_state(&self) -> Vec<(String, String)> {
+        vec![("PRG bank".to_string(), self.bank_select.to_string())]
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
     fn write_prg(&mut self, addr: u16, data: u8) {
         match addr {
             0x6000..=0x7FFF => {
@@ -93,7 +120,7 @@ impl Mapper for UxromMapper {
     fn write_chr(&mut self, addr: u16, data: u8) {
         if self.chr_is_ram && !self.chr.is_empty() {
             let index = addr as usize % self.chr.len();
-            self.chr[index] = data;
+            Rc::make_mut(&mut self.chr)[index] = data;
         }
     }
 
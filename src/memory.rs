@@ -3,9 +3,27 @@ pub trait Memory {
 
     fn write(&mut self, addr: u16, data: u8);
 
+    /// Reads the opcode of the instruction about to run. The CPU uses this
+    /// instead of [`Memory::read`] for opcode fetches only.
+    fn fetch_opcode(&mut self, addr: u16) -> u8 {
+        self.read(addr)
+    }
+
+    /// Cartridge PRG bank visible at `addr`, if the memory is banked.
+    fn prg_bank(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+
+    /// Takes an NMI edge raised while the current instruction was running.
+    /// Memory that advances the rest of the machine during accesses reports
+    /// it here so an interrupt sequence in progress can be hijacked.
+    fn take_nmi(&mut self) -> bool {
+        false
+    }
+
     fn read_u16(&mut self, addr: u16) -> u16 {
         let lo = self.read(addr) as u16;
-        let hi = self.read(addr + 1) as u16;
+        let hi = self.read(addr.wrapping_add(1)) as u16;
         (hi << 8) | lo
     }
 
@@ -13,6 +31,105 @@ pub trait Memory {
         let lo = (value & 0xFF) as u8;
         let hi = (value >> 8) as u8;
         self.write(addr, lo);
-        self.write(addr + 1, hi);
+        self.write(addr.wrapping_add(1), hi);
+    }
+
+    /// Reads `len` bytes from `addr` up, wrapping at $FFFF. Each byte is an
+    /// ordinary [`Memory::read`], side effects included.
+    fn read_range(&mut self, addr: u16, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| self.read(addr.wrapping_add(i as u16)))
+            .collect()
+    }
+
+    /// Writes `data` from `addr` up, wrapping at $FFFF.
+    fn write_slice(&mut self, addr: u16, data: &[u8]) {
+        for (i, &byte) in data.iter().enumerate() {
+            self.write(addr.wrapping_add(i as u16), byte);
+        }
+    }
+
+    /// [`Memory::read_range`] without collecting the bytes.
+    fn read_iter(&mut self, addr: u16, len: usize) -> impl Iterator<Item = u8> + '_
+    where
+        Self: Sized,
+    {
+        (0..len).map(move |i| self.read(addr.wrapping_add(i as u16)))
+    }
+
+    /// [`Memory::write_slice`] from any source of bytes.
+    fn write_iter(&mut self, addr: u16, data: impl IntoIterator<Item = u8>)
+    where
+        Self: Sized,
+    {
+        let mut addr = addr;
+        for byte in data {
+            self.write(addr, byte);
+            addr = addr.wrapping_add(1);
+        }
+    }
+}
+
+/// 64KB of plain RAM, for running programs outside a NES; see
+/// [`crate::cpu::CPU::load`].
+#[derive(Clone)]
+pub struct FlatMemory {
+    pub data: Vec<u8>,
+}
+
+impl FlatMemory {
+    pub fn new() -> Self {
+        FlatMemory {
+            data: vec![0; 0x10000],
+        }
+    }
+}
+
+impl Default for FlatMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Memory for FlatMemory {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.data[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.data[addr as usize] = data;
+    }
+
+    fn read_range(&mut self, addr: u16, len: usize) -> Vec<u8> {
+        let start = addr as usize;
+        match self.data.get(start..start + len) {
+            Some(bytes) => bytes.to_vec(),
+            None => self.read_iter(addr, len).collect(),
+        }
+    }
+
+    fn write_slice(&mut self, addr: u16, data: &[u8]) {
+        let start = addr as usize;
+        match self.data.get_mut(start..start + data.len()) {
+            Some(bytes) => bytes.copy_from_slice(data),
+            None => self.write_iter(addr, data.iter().copied()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ranges_wrap_at_ffff() {
+        let mut memory = FlatMemory::new();
+        memory.write_slice(0x0200, &[1, 2, 3]);
+        assert_eq!(memory.read_range(0x01FF, 5), [0, 1, 2, 3, 0]);
+
+        memory.write_slice(0xFFFE, &[4, 5, 6]);
+        assert_eq!(memory.read_range(0xFFFE, 3), [4, 5, 6]);
+        memory.write_iter(0x0300, (0..4).map(|i| i * 2));
+        assert_eq!(memory.read_iter(0x0300, 4).sum::<u8>(), 12);
     }
 }
@@ -0,0 +1,265 @@
+//! Runtime metrics for long-running setups (cabinets, kiosks), exported as
+//! JSON or in the Prometheus text format, e.g. for node_exporter's textfile
+//! collector.
+
+use std::collections::VecDeque;
+use std::path::Path;
+use std::time::Duration;
+
+/// Frames kept for the fps and percentile figures, ten seconds at 60 fps.
+pub const METRICS_WINDOW: usize = 600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricsFormat {
+    Json,
+    Prometheus,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    frame_times: VecDeque<Duration>,
+    frames: u64,
+    audio_underruns: u64,
+    audio_latency: Option<Duration>,
+    audio_jitter: Option<Duration>,
+    rewind_buffer_bytes: Option<usize>,
+    last_state_save: Option<Duration>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the wall-clock time taken by one presented frame.
+    pub fn record_frame(&mut self, frame_time: Duration) {
+        if self.frame_times.len() == METRICS_WINDOW {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(frame_time);
+        self.frames += 1;
+    }
+
+    /// Sets the running total of audio callbacks that ran out of samples.
+    pub fn set_audio_underruns(&mut self, underruns: u64) {
+        self.audio_underruns = underruns;
+    }
+
+    /// Sets the audio queue size in use and, when it was picked from
+    /// measured callback jitter, that jitter.
+    pub fn set_audio_latency(&mut self, latency: Duration, jitter: Option<Duration>) {
+        self.audio_latency = Some(latency);
+        self.audio_jitter = jitter;
+    }
+
+    pub fn set_rewind_buffer_bytes(&mut self, bytes: usize) {
+        self.rewind_buffer_bytes = Some(bytes);
+    }
+
+    pub fn record_state_save(&mut self, elapsed: Duration) {
+        self.last_state_save = Some(elapsed);
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Average frame rate over the last [`METRICS_WINDOW`] frames.
+    pub fn fps(&self) -> f64 {
+        let total: Duration = self.frame_times.iter().sum();
+        if total.is_zero() {
+            return 0.0;
+        }
+        self.frame_times.len() as f64 / total.as_secs_f64()
+    }
+
+    /// Nearest-rank percentile (0-100) of recent frame times.
+    pub fn frame_time_percentile(&self, percentile: f64) -> Option<Duration> {
+        if self.frame_times.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<Duration> = self.frame_times.iter().copied().collect();
+        sorted.sort_unstable();
+        let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+        Some(sorted[rank.clamp(1, sorted.len()) - 1])
+    }
+
+    pub fn to_json(&self) -> String {
+        let millis = |d: Option<Duration>| match d {
+            Some(d) => format!("{:.3}", d.as_secs_f64() * 1000.0),
+            None => "null".to_string(),
+        };
+        let rewind = match self.rewind_buffer_bytes {
+            Some(bytes) => bytes.to_string(),
+            None => "null".to_string(),
+        };
+        format!(
+            concat!(
+                "{{\"frames\":{},\"fps\":{:.2},",
+                "\"frame_time_ms\":{{\"p50\":{},\"p95\":{},\"p99\":{}}},",
+                "\"audio_underruns\":{},\"audio_latency_ms\":{},\"audio_jitter_ms\":{},",
+                "\"rewind_buffer_bytes\":{},",
+                "\"state_save_ms\":{}}}"
+            ),
+            self.frames,
+            self.fps(),
+            millis(self.frame_time_percentile(50.0)),
+            millis(self.frame_time_percentile(95.0)),
+            millis(self.frame_time_percentile(99.0)),
+            self.audio_underruns,
+            millis(self.audio_latency),
+            millis(self.audio_jitter),
+            rewind,
+            millis(self.last_state_save),
+        )
+    }
+
+    /// Prometheus text exposition format. Metrics that haven't been recorded
+    /// yet are left out rather than reported as zero.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(&str, f64)]| {
+            out += &format!(
+                "# HELP pico_{} {}\n# TYPE pico_{} {}\n",
+                name, help, name, kind
+            );
+            for (labels, value) in samples {
+                out += &format!("pico_{}{} {}\n", name, labels, value);
+            }
+        };
+
+        metric(
+            "frames_total",
+            "counter",
+            "Frames presented.",
+            &[("", self.frames as f64)],
+        );
+        metric(
+            "fps",
+            "gauge",
+            "Recent average frame rate.",
+            &[("", self.fps())],
+        );
+        let quantiles: Vec<(&str, f64)> = [
+            ("{quantile=\"0.5\"}", 50.0),
+            ("{quantile=\"0.95\"}", 95.0),
+            ("{quantile=\"0.99\"}", 99.0),
+        ]
+        .into_iter()
+        .filter_map(|(labels, p)| Some((labels, self.frame_time_percentile(p)?.as_secs_f64())))
+        .collect();
+        metric(
+            "frame_time_seconds",
+            "summary",
+            "Recent wall-clock frame times.",
+            &quantiles,
+        );
+        metric(
+            "audio_underruns_total",
+            "counter",
+            "Audio callbacks that ran out of samples.",
+            &[("", self.audio_underruns as f64)],
+        );
+        if let Some(latency) = self.audio_latency {
+            metric(
+                "audio_latency_seconds",
+                "gauge",
+                "Audio queued ahead of playback.",
+                &[("", latency.as_secs_f64())],
+            );
+        }
+        if let Some(jitter) = self.audio_jitter {
+            metric(
+                "audio_jitter_seconds",
+                "gauge",
+                "Audio callback jitter the latency was sized for.",
+                &[("", jitter.as_secs_f64())],
+            );
+        }
+        if let Some(bytes) = self.rewind_buffer_bytes {
+            metric(
+                "rewind_buffer_bytes",
+                "gauge",
+                "Memory held by the rewind buffer.",
+                &[("", bytes as f64)],
+            );
+        }
+        if let Some(elapsed) = self.last_state_save {
+            metric(
+                "state_save_seconds",
+                "gauge",
+                "Duration of the last state save.",
+                &[("", elapsed.as_secs_f64())],
+            );
+        }
+        out
+    }
+
+    /// Replaces `path` with the current metrics. The file is written next to
+    /// it and renamed into place, so readers never see a partial file.
+    pub fn write_to(&self, path: &Path, format: MetricsFormat) -> Result<(), String> {
+        let text = match format {
+            MetricsFormat::Json => self.to_json() + "\n",
+            MetricsFormat::Prometheus => self.to_prometheus(),
+        };
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, text)
+            .and_then(|_| std::fs::rename(&tmp, path))
+            .map_err(|e| format!("Failed to write metrics to {}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_percentiles_and_export() {
+        let mut metrics = Metrics::new();
+        assert_eq!(metrics.frame_time_percentile(50.0), None);
+        for ms in 1..=100 {
+            metrics.record_frame(Duration::from_millis(ms));
+        }
+        metrics.set_audio_underruns(3);
+
+        assert_eq!(
+            metrics.frame_time_percentile(50.0),
+            Some(Duration::from_millis(50))
+        );
+        assert_eq!(
+            metrics.frame_time_percentile(99.0),
+            Some(Duration::from_millis(99))
+        );
+        assert!((metrics.fps() - 100.0 / 5.05).abs() < 1e-9);
+
+        assert_eq!(
+            metrics.to_json(),
+            concat!(
+                "{\"frames\":100,\"fps\":19.80,",
+                "\"frame_time_ms\":{\"p50\":50.000,\"p95\":95.000,\"p99\":99.000},",
+                "\"audio_underruns\":3,\"audio_latency_ms\":null,\"audio_jitter_ms\":null,",
+                "\"rewind_buffer_bytes\":null,",
+                "\"state_save_ms\":null}"
+            )
+        );
+
+        let prometheus = metrics.to_prometheus();
+        assert!(prometheus.contains("pico_frame_time_seconds{quantile=\"0.95\"} 0.095\n"));
+        assert!(prometheus.contains("pico_audio_underruns_total 3\n"));
+        assert!(!prometheus.contains("rewind"));
+        assert!(!prometheus.contains("audio_latency"));
+
+        metrics.set_audio_latency(Duration::from_millis(45), Some(Duration::from_millis(12)));
+        assert!(
+            metrics
+                .to_json()
+                .contains("\"audio_latency_ms\":45.000,\"audio_jitter_ms\":12.000,")
+        );
+        assert!(
+            metrics
+                .to_prometheus()
+                .contains("pico_audio_jitter_seconds 0.012\n")
+        );
+    }
+}
@@ -0,0 +1,114 @@
+//! Stand-ins for parts of the console in unit tests. A [`BusDevice`]
+//! attached with [`crate::bus::Bus::attach_device`] answers every CPU read
+//! and write in its range before the rest of the memory map sees them, so a
+//! test can script what a register returns or capture what the program
+//! writes to it. Debugger reads ([`crate::bus::Bus::peek`]) don't reach
+//! devices.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+
+pub trait BusDevice {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, data: u8);
+}
+
+#[derive(Default)]
+struct MockState {
+    responses: HashMap<u16, VecDeque<u8>>,
+    reads: Vec<u16>,
+    writes: Vec<(u16, u8)>,
+}
+
+/// Answers reads from scripted values and records every access. Clones
+/// share their state, so keep one to inspect after attaching another.
+#[derive(Clone, Default)]
+pub struct MockDevice {
+    state: Rc<RefCell<MockState>>,
+}
+
+impl MockDevice {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues values for reads of `addr`, one per read. The last one
+    /// repeats once the others are used up; with none, reads return 0.
+    pub fn respond(&self, addr: u16, values: &[u8]) {
+        let mut state = self.state.borrow_mut();
+        state.responses.entry(addr).or_default().extend(values);
+    }
+
+    /// Addresses read so far, oldest first.
+    pub fn reads(&self) -> Vec<u16> {
+        self.state.borrow().reads.clone()
+    }
+
+    /// Writes so far, oldest first.
+    pub fn writes(&self) -> Vec<(u16, u8)> {
+        self.state.borrow().writes.clone()
+    }
+}
+
+impl BusDevice for MockDevice {
+    fn read(&mut self, addr: u16) -> u8 {
+        let mut state = self.state.borrow_mut();
+        state.reads.push(addr);
+        let Some(values) = state.responses.get_mut(&addr) else {
+            return 0;
+        };
+        if values.len() > 1 {
+            values.pop_front().unwrap_or(0)
+        } else {
+            values.front().copied().unwrap_or(0)
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.state.borrow_mut().writes.push((addr, data));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::apu::APU;
+    use crate::asm::assemble;
+    use crate::bus::Bus;
+    use crate::cart::test::test_rom;
+    use crate::cpu::ResetKind;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_scripts_reads_and_captures_writes() {
+        let program = assemble(
+            "
+                    .org $8000
+            reset:  bit $2002
+                    bpl reset
+                    lda #$3F
+                    sta $4000
+            done:   jmp done
+                    .org $FFFC
+                    .word reset, reset
+            ",
+        )
+        .unwrap();
+        let apu = APU::new(48_000, Arc::new(Mutex::new(VecDeque::new())));
+        let mut bus = Bus::new(test_rom(program.slice(0x8000, 0x8000)), apu);
+        let ppu = MockDevice::new();
+        ppu.respond(0x2002, &[0x00, 0x00, 0x80]);
+        let apu = MockDevice::new();
+        bus.attach_device(0x2000..=0x2007, Box::new(ppu.clone()));
+        bus.attach_device(0x4000..=0x4013, Box::new(apu.clone()));
+        bus.cpu_reset(ResetKind::PowerOn);
+
+        while bus.cpu.registers.pc != program.labels["done"] {
+            bus.step_cpu();
+        }
+        assert_eq!(ppu.reads(), [0x2002; 3]);
+        assert_eq!(apu.writes(), [(0x4000, 0x3F)]);
+    }
+}
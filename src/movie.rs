@@ -17,6 +17,7 @@ pub struct MovieHeader {
     pub port0: InputDevice,
     pub port1: InputDevice,
     pub port2: FamicomExpPort,
+    pub input_timing: InputTiming,
     pub binary: bool,
     pub length: Option<usize>,
     pub rom_filename: String,
@@ -34,6 +35,16 @@ pub enum InputDevice {
     Zapper = 2,
 }
 
+/// What each input record in the log corresponds to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputTiming {
+    /// One record per frame (standard FM2).
+    Frame,
+    /// One record per controller strobe, so input can change between polls
+    /// within a frame. Set with the `inputTiming strobe` header extension.
+    Strobe,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FamicomExpPort {
     None = 0,
@@ -105,7 +116,9 @@ impl FM2Movie {
 
         let movie_header = parse_header(&header)?;
 
-        let input_log = parse_input_log(lines.clone(), &movie_header)?;
+        // The loop above consumed the first record line; parse_input_log skips
+        // header lines itself, so hand it the whole file.
+        let input_log = parse_input_log(contents.lines(), &movie_header)?;
 
         Ok(FM2Movie {
             header: movie_header,
@@ -130,70 +143,41 @@ impl FM2Movie {
         let input = self
             .get_frame_input(frame)
             .ok_or_else(|| format!("Frame {} out of range", frame))?;
+        input.apply(joypad1, joypad2);
+        Ok(())
+    }
 
-        if let Some(gamepad_input) = &input.port0_input {
-            let mut buttons = JoypadButton::empty();
-
-            if gamepad_input.right {
-                buttons |= JoypadButton::RIGHT;
-            }
-            if gamepad_input.left {
-                buttons |= JoypadButton::LEFT;
-            }
-            if gamepad_input.down {
-                buttons |= JoypadButton::DOWN;
-            }
-            if gamepad_input.up {
-                buttons |= JoypadButton::UP;
-            }
-            if gamepad_input.start {
-                buttons |= JoypadButton::START;
-            }
-            if gamepad_input.select {
-                buttons |= JoypadButton::SELECT;
-            }
-            if gamepad_input.b {
-                buttons |= JoypadButton::BUTTON_B;
-            }
-            if gamepad_input.a {
-                buttons |= JoypadButton::BUTTON_A;
-            }
+    /// Input for the `latch`-th controller strobe since power-on (counting
+    /// from 1), for movies with `inputTiming strobe`.
+    pub fn get_latch_input(&self, latch: u64) -> Option<&InputRecord> {
+        let index = usize::try_from(latch.checked_sub(1)?).ok()?;
+        self.input_log.get(index)
+    }
+}
 
-            joypad1.button_status = buttons;
+impl InputRecord {
+    pub fn apply(&self, joypad1: &mut crate::joypad::Joypad, joypad2: &mut crate::joypad::Joypad) {
+        if let Some(gamepad_input) = &self.port0_input {
+            joypad1.button_status = gamepad_input.buttons();
         }
-
-        if let Some(gamepad_input) = &input.port1_input {
-            let mut buttons = JoypadButton::empty();
-
-            if gamepad_input.right {
-                buttons |= JoypadButton::RIGHT;
-            }
-            if gamepad_input.left {
-                buttons |= JoypadButton::LEFT;
-            }
-            if gamepad_input.down {
-                buttons |= JoypadButton::DOWN;
-            }
-            if gamepad_input.up {
-                buttons |= JoypadButton::UP;
-            }
-            if gamepad_input.start {
-                buttons |= JoypadButton::START;
-            }
-            if gamepad_input.select {
-                buttons |= JoypadButton::SELECT;
-            }
-            if gamepad_input.b {
-                buttons |= JoypadButton::BUTTON_B;
-            }
-            if gamepad_input.a {
-                buttons |= JoypadButton::BUTTON_A;
-            }
-
-            joypad2.button_status = buttons;
+        if let Some(gamepad_input) = &self.port1_input {
+            joypad2.button_status = gamepad_input.buttons();
         }
+    }
+}
 
-        Ok(())
+impl GamepadInput {
+    pub fn buttons(&self) -> JoypadButton {
+        let mut buttons = JoypadButton::empty();
+        buttons.set(JoypadButton::RIGHT, self.right);
+        buttons.set(JoypadButton::LEFT, self.left);
+        buttons.set(JoypadButton::DOWN, self.down);
+        buttons.set(JoypadButton::UP, self.up);
+        buttons.set(JoypadButton::START, self.start);
+        buttons.set(JoypadButton::SELECT, self.select);
+        buttons.set(JoypadButton::BUTTON_B, self.b);
+        buttons.set(JoypadButton::BUTTON_A, self.a);
+        buttons
     }
 }
 
@@ -267,6 +251,12 @@ fn parse_header(header_text: &str) -> Result<MovieHeader, String> {
         None => FamicomExpPort::None,
     };
 
+    let input_timing = match pairs.get("inputTiming").copied() {
+        None | Some("frame") => InputTiming::Frame,
+        Some("strobe") => InputTiming::Strobe,
+        Some(v) => return Err(format!("Invalid inputTiming value: {}", v)),
+    };
+
     let binary = pairs.get("binary").map(|v| *v == "1").unwrap_or(false);
 
     let length = pairs.get("length").and_then(|v| v.parse::<usize>().ok());
@@ -296,6 +286,7 @@ fn parse_header(header_text: &str) -> Result<MovieHeader, String> {
         port0,
         port1,
         port2,
+        input_timing,
         binary,
         length,
         rom_filename,
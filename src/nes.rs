@@ -1,57 +1,498 @@
-use crate::{apu::APU, bus::Bus, cart::Cart, joypad::Joypad, mapper::Mapper};
+use std::time::Duration;
+
+#[cfg(feature = "expansion-port")]
+use crate::expansion::{DeviceId, ExpansionRead, ExpansionWrite};
+use crate::{
+    access_log::AccessLog,
+    apu::{APU, ChannelActivity},
+    bus::{Bus, Dma},
+    callstack::CallStack,
+    cart::Cart,
+    cheats::CheatList,
+    cpu::{CPU, CpuModel, InterruptType, ResetKind, StepResult, StopReason, WatchHit},
+    frame_sink::{FrameSink, FrameSinkId, FrameSinks},
+    hooks::{HookCallback, HookId, HookKind},
+    joypad::Joypad,
+    link::LinkPort,
+    mapper::Mapper,
+    memory::Memory,
+    movie::{FM2Movie, InputTiming},
+    poke::{Poke, PpuRegisters},
+    ppu::{
+        PPU,
+        framebuffer::Framebuffer,
+        sprite::{Sprite, decode_oam},
+    },
+    profiler::Profiler,
+    reverse::History,
+    stack_check::StackCheck,
+    storage::StorageBackend,
+    trace::TraceHistory,
+    trigger::{Capture, Trigger, TriggerEvent, Triggers},
+    watchdog::Watchdog,
+};
 
 pub struct ClockResult {
+    /// The PPU finished a frame during this clock.
     pub frame_complete: bool,
     pub instruction_complete: bool,
+    /// Set when the CPU entered an NMI or IRQ handler instead of running an
+    /// instruction.
+    pub interrupt: Option<InterruptType>,
+    /// The PPU entered vertical blank during this clock.
+    pub vblank: bool,
+    pub stop: Option<StopReason>,
+}
+
+/// What [`Nes::run_for_cycles`] or [`Nes::run_until`] did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunResult {
+    /// CPU cycles that elapsed, DMA stalls included.
+    pub cycles: u64,
+    /// Set when a breakpoint or watchpoint ended the run early, or the CPU
+    /// halted.
+    pub stop: Option<StopReason>,
+}
+
+/// Passed to the vblank callback when the emulated PPU enters vertical blank.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VblankEvent {
+    pub frame: u64,
+    /// Emulated time since power-on; see [`Nes::presentation_time`].
+    pub time: Duration,
+    /// Whether the game has NMI on vblank enabled, i.e. whether the CPU is
+    /// about to be interrupted.
+    pub nmi_enabled: bool,
+}
+
+/// Version byte of [`Nes::dump_state`].
+pub const DUMP_VERSION: u8 = 1;
+
+pub type VblankCallback = Box<dyn FnMut(&VblankEvent)>;
+pub type WatchCallback = Box<dyn FnMut(&WatchHit)>;
+
+/// In-memory copy of all mutable machine state, for rewind and run-ahead.
+///
+/// Unlike a portable save state this is a plain struct clone: it is only valid
+/// for the running build and the cartridge it was taken from. Taken between
+/// two [`Nes::clock_cycle`] calls, it holds the instruction in progress too.
+#[derive(Clone)]
+pub struct Snapshot {
+    cpu: CPU,
+    ppu: PPU,
+    apu: APU,
+    mapper: Box<dyn Mapper>,
+    joypads: [Joypad; 2],
+    dma: Dma,
+    cpu_cycles: u64,
+    link: Option<LinkPort>,
+    system_clock: u64,
+    vblank_dot: u64,
+    nmi_latched: bool,
+    overclock_cycles: u32,
+    data_bus: u8,
+    data_bus_cycle: u64,
+    nmi_history: u8,
+    irq_history: u8,
 }
 
 pub struct Nes {
     pub bus: Bus,
-    pub system_clock: u64,
+    frame_sinks: FrameSinks,
+    vblank_callback: Option<VblankCallback>,
+    watch_callback: Option<WatchCallback>,
+    triggers: Triggers,
+    captures: Vec<Capture>,
+    event_triggers: Triggers,
+    trigger_events: Vec<TriggerEvent>,
+    poked: bool,
 }
 
 impl Nes {
     pub fn new(cart: Cart, apu: APU) -> Self {
+        Self::with_model(cart, apu, CpuModel::default())
+    }
+
+    /// Builds a console around the given CPU, which also sets the APU's
+    /// clock and the CPU:PPU clock ratio. PAL consoles still use the NTSC
+    /// frame height.
+    pub fn with_model(cart: Cart, apu: APU, model: CpuModel) -> Self {
         Nes {
-            bus: Bus::new(cart, apu),
-            system_clock: 0,
+            bus: Bus::with_model(cart, apu, model),
+            frame_sinks: FrameSinks::new(),
+            vblank_callback: None,
+            watch_callback: None,
+            triggers: Triggers::default(),
+            captures: Vec::new(),
+            event_triggers: Triggers::default(),
+            trigger_events: Vec::new(),
+            poked: false,
         }
     }
 
-    pub fn reset(&mut self) {
-        self.bus.cpu_reset();
+    /// Starts the CPU from the reset vector. Use [`ResetKind::PowerOn`] for
+    /// a freshly loaded cartridge and [`ResetKind::Reset`] for the button.
+    pub fn reset(&mut self, kind: ResetKind) {
+        self.bus.cpu_reset(kind);
+        if let Some(call_stack) = &mut self.bus.call_stack {
+            call_stack.clear();
+        }
     }
 
+    /// Runs the CPU for one instruction, interrupt entry or OAM DMA
+    /// transfer. The PPU and APU catch up at each of its memory accesses, so
+    /// one clock covers between 2 and several hundred CPU cycles; see
+    /// [`Bus::step_cpu`].
     pub fn clock(&mut self) -> ClockResult {
-        let frame_complete = self.bus.ppu_clock();
-        let mut instruction_complete = false;
+        let result = self.bus.step_cpu();
+        self.finish_clock(Some(result))
+    }
 
-        if self.system_clock % 3 == 0 {
-            instruction_complete = self.bus.cpu_clock();
-            self.bus.apu_clock();
-        }
+    /// Runs the CPU for one cycle, or one whole OAM DMA transfer; see
+    /// [`Bus::step_cpu_cycle`]. A snapshot can be taken between any two
+    /// calls, part way through an instruction. The result reports the
+    /// instruction, its interrupt and its stop on the cycle that finishes
+    /// it.
+    pub fn clock_cycle(&mut self) -> ClockResult {
+        let result = self.bus.step_cpu_cycle();
+        self.finish_clock(result)
+    }
 
-        if self.bus.poll_nmi() {
-            self.bus.cpu_nmi();
+    // Handles what happened during a clock, `result` being the instruction
+    // it finished, if any.
+    fn finish_clock(&mut self, result: Option<StepResult>) -> ClockResult {
+        let events = self.bus.take_events();
+        if events.frame_complete {
+            self.bus.apply_freeze_cheats();
+            self.check_triggers();
+            let jammed_at = self.bus.cpu.jammed_at();
+            let frame = self.bus.ppu.frame_count;
+            if let Some(kind) = self
+                .bus
+                .watchdog
+                .as_mut()
+                .and_then(|watchdog| watchdog.end_frame(frame, jammed_at))
+            {
+                self.reset(kind);
+            }
         }
-
-        if self.bus.poll_irq() {
-            self.bus.cpu_irq();
+        let time = self.presentation_time();
+        if events.vblank
+            && let Some(callback) = &mut self.vblank_callback
+        {
+            callback(&VblankEvent {
+                frame: self.bus.ppu.frame_count,
+                time,
+                nmi_enabled: self.bus.ppu.ctrl.generate_vblank_nmi(),
+            });
         }
 
-        self.system_clock = self.system_clock.wrapping_add(1);
+        let mut stop = result.and_then(|result| result.stop);
+        if let Some(StopReason::Watchpoint(hit)) = stop
+            && let Some(callback) = &mut self.watch_callback
+        {
+            callback(&hit);
+            stop = None;
+        }
 
         ClockResult {
-            frame_complete,
-            instruction_complete,
+            frame_complete: events.frame_complete,
+            instruction_complete: result.is_some_and(|result| result.cycles > 0),
+            interrupt: result.and_then(|result| result.interrupt),
+            vblank: events.vblank,
+            stop,
         }
     }
 
-    pub fn step_frame(&mut self) {
+    /// Runs to the end of the current frame, or until a breakpoint or
+    /// watchpoint is hit.
+    pub fn step_frame(&mut self) -> Option<StopReason> {
         let start_frame = self.bus.ppu.frame_count;
         while self.bus.ppu.frame_count == start_frame {
-            self.clock();
+            if let Some(reason) = debugger_stop(self.clock().stop) {
+                return Some(reason);
+            }
         }
+        None
+    }
+
+    /// Runs until the PPU enters vertical blank, or until a breakpoint or
+    /// watchpoint is hit. Frontends that present on the emulated vblank
+    /// rather than the host's vsync call this once per displayed frame.
+    pub fn step_to_vblank(&mut self) -> Option<StopReason> {
+        loop {
+            let result = self.clock();
+            if let Some(reason) = debugger_stop(result.stop) {
+                return Some(reason);
+            }
+            if result.vblank {
+                return None;
+            }
+        }
+    }
+
+    /// Runs whole instructions until at least `cycles` CPU cycles have
+    /// elapsed. The last instruction or DMA transfer can overshoot, which the
+    /// result reports so a caller slicing time can carry it over.
+    pub fn run_for_cycles(&mut self, cycles: u64) -> RunResult {
+        self.run_until(|_| false, cycles)
+    }
+
+    /// Runs until `condition` holds for the CPU at an instruction boundary,
+    /// e.g. `|cpu| cpu.registers.pc == 0xC000`, or until `max_cycles` have
+    /// elapsed. The condition is checked before the first instruction too.
+    /// A CPU that halts ends the run with [`StopReason::Halted`], or
+    /// [`StopReason::InvalidOpcode`] for a byte it can't decode.
+    pub fn run_until(
+        &mut self,
+        mut condition: impl FnMut(&CPU) -> bool,
+        max_cycles: u64,
+    ) -> RunResult {
+        let start = self.bus.cpu_cycles;
+        let mut stop = None;
+        while self.bus.cpu_cycles - start < max_cycles && !condition(&self.bus.cpu) {
+            stop = self.clock().stop;
+            if stop.is_some() {
+                break;
+            }
+        }
+        RunResult {
+            cycles: self.bus.cpu_cycles - start,
+            stop,
+        }
+    }
+
+    /// Runs one instruction, or a whole subroutine if it is a JSR: stops
+    /// once the call stack is back to its depth before the step. An
+    /// interrupt taken in its place is stepped over the same way. Turns on
+    /// the call stack if it is off.
+    pub fn step_over(&mut self, max_cycles: u64) -> RunResult {
+        self.enable_call_stack();
+        let depth = self.call_depth();
+        self.run_while(max_cycles, |nes| nes.call_depth() > depth)
+    }
+
+    /// Runs until the innermost open subroutine or interrupt handler on the
+    /// call stack returns. Needs the call stack to have been on since the
+    /// call was made.
+    pub fn step_out(&mut self, max_cycles: u64) -> Result<RunResult, String> {
+        let depth = match &self.bus.call_stack {
+            None => return Err("Call stack tracking is off".to_string()),
+            Some(call_stack) if call_stack.frames().is_empty() => {
+                return Err("Not inside a subroutine".to_string());
+            }
+            Some(call_stack) => call_stack.frames().len(),
+        };
+        Ok(self.run_while(max_cycles, |nes| nes.call_depth() >= depth))
+    }
+
+    fn call_depth(&self) -> usize {
+        self.bus
+            .call_stack
+            .as_ref()
+            .map_or(0, |call_stack| call_stack.frames().len())
+    }
+
+    // Runs at least one instruction, then more while `keep_going` holds
+    // after each.
+    fn run_while(&mut self, max_cycles: u64, keep_going: impl Fn(&Self) -> bool) -> RunResult {
+        let start = self.bus.cpu_cycles;
+        loop {
+            let result = self.clock();
+            let stop = result.stop;
+            let done = result.instruction_complete && !keep_going(self);
+            if stop.is_some() || done || self.bus.cpu_cycles - start >= max_cycles {
+                return RunResult {
+                    cycles: self.bus.cpu_cycles - start,
+                    stop,
+                };
+            }
+        }
+    }
+
+    /// CPU cycles since power-on, DMA stalls included.
+    pub fn cpu_cycles(&self) -> u64 {
+        self.bus.cpu_cycles
+    }
+
+    /// Average length of an emulated frame: 16.639ms on NTSC, 19.997ms on
+    /// PAL.
+    pub fn frame_duration(&self) -> Duration {
+        self.bus.cpu.model().frame_time()
+    }
+
+    /// Emulated time from power-on to the start of the last vblank, when
+    /// that frame's picture was finished. A frontend on a variable-refresh
+    /// display can present each frame this far apart from the previous one
+    /// instead of rounding to the host's refresh rate. Overclocked cycles
+    /// don't count.
+    pub fn presentation_time(&self) -> Duration {
+        self.bus.cpu.model().dots_to_time(self.bus.vblank_dot)
+    }
+
+    /// Reports watchpoint hits to `callback` instead of stopping. With no
+    /// callback, hits are returned as [`StopReason::Watchpoint`].
+    pub fn set_watch_callback(&mut self, callback: Option<WatchCallback>) {
+        self.watch_callback = callback;
+    }
+
+    /// Calls `callback` every time the PPU enters vertical blank, so a
+    /// frontend can use the emulated vblank as its vsync source.
+    pub fn set_vblank_callback(&mut self, callback: Option<VblankCallback>) {
+        self.vblank_callback = callback;
+    }
+
+    pub fn clone_state(&self) -> Snapshot {
+        Snapshot {
+            cpu: self.bus.cpu.clone(),
+            ppu: self.bus.ppu.clone(),
+            apu: self.bus.apu.clone(),
+            mapper: self.bus.cart.mapper.clone(),
+            joypads: self.bus.joypads.clone(),
+            dma: self.bus.dma.clone(),
+            cpu_cycles: self.bus.cpu_cycles,
+            link: self.bus.link.clone(),
+            system_clock: self.bus.system_clock,
+            vblank_dot: self.bus.vblank_dot,
+            nmi_latched: self.bus.nmi_latched,
+            overclock_cycles: self.bus.overclock_cycles,
+            data_bus: self.bus.data_bus,
+            data_bus_cycle: self.bus.data_bus_cycle,
+            nmi_history: self.bus.nmi_history,
+            irq_history: self.bus.irq_history,
+        }
+    }
+
+    pub fn restore_state(&mut self, snapshot: &Snapshot) {
+        self.bus.cpu.restore_from(&snapshot.cpu);
+        self.bus.ppu.clone_from(&snapshot.ppu);
+        self.bus.apu.clone_from(&snapshot.apu);
+        self.bus.cart.mapper = snapshot.mapper.clone();
+        self.bus.joypads.clone_from(&snapshot.joypads);
+        self.bus.dma.clone_from(&snapshot.dma);
+        self.bus.cpu_cycles = snapshot.cpu_cycles;
+        self.bus.link.clone_from(&snapshot.link);
+        self.bus.system_clock = snapshot.system_clock;
+        self.bus.vblank_dot = snapshot.vblank_dot;
+        self.bus.nmi_latched = snapshot.nmi_latched;
+        self.bus.overclock_cycles = snapshot.overclock_cycles;
+        self.bus.data_bus = snapshot.data_bus;
+        self.bus.data_bus_cycle = snapshot.data_bus_cycle;
+        self.bus.nmi_history = snapshot.nmi_history;
+        self.bus.irq_history = snapshot.irq_history;
+        self.bus.step_start = None;
+        // The journal and call stack describe the timeline that was left.
+        if let Some(history) = &mut self.bus.history {
+            history.clear();
+        }
+        if let Some(call_stack) = &mut self.bus.call_stack {
+            call_stack.clear();
+        }
+    }
+
+    /// A flat dump of the machine state for bug reports and for diffing two
+    /// runs. It is not a save state and cannot be loaded back. Layout, all
+    /// little-endian: `PICODUMP`, version (u8), PPU frame, system clock and
+    /// CPU cycles (u64 each), A, X, Y, P, SP (u8 each), PC (u16), CPU RAM
+    /// (2KB), nametable RAM (2KB), OAM (256), palette (32), PPUCTRL, PPUMASK,
+    /// PPUSTATUS (u8 each), scanline and dot (i16 each), then PRG RAM as a u32
+    /// length and its bytes.
+    pub fn dump_state(&self) -> Vec<u8> {
+        let cpu = &self.bus.cpu;
+        let ppu = &self.bus.ppu;
+        let mut out = b"PICODUMP".to_vec();
+        out.push(DUMP_VERSION);
+        out.extend_from_slice(&ppu.frame_count.to_le_bytes());
+        out.extend_from_slice(&self.bus.system_clock.to_le_bytes());
+        out.extend_from_slice(&self.bus.cpu_cycles.to_le_bytes());
+        out.extend_from_slice(&[
+            cpu.registers.a,
+            cpu.registers.x,
+            cpu.registers.y,
+            cpu.registers.status.bits(),
+            cpu.registers.sp,
+        ]);
+        out.extend_from_slice(&cpu.registers.pc.to_le_bytes());
+        out.extend_from_slice(&cpu.vram);
+        out.extend_from_slice(&ppu.vram);
+        out.extend_from_slice(&ppu.oam_data);
+        out.extend_from_slice(&ppu.palette_table);
+        out.extend_from_slice(&[ppu.ctrl.bits(), ppu.mask.bits(), ppu.status.bits()]);
+        out.extend_from_slice(&ppu.scanline.to_le_bytes());
+        out.extend_from_slice(&ppu.cycle.to_le_bytes());
+        let prg_ram = self.bus.cart.mapper.prg_ram().unwrap_or(&[]);
+        out.extend_from_slice(&(prg_ram.len() as u32).to_le_bytes());
+        out.extend_from_slice(prg_ram);
+        out
+    }
+
+    pub fn add_frame_sink(&mut self, sink: Box<dyn FrameSink>) -> FrameSinkId {
+        self.frame_sinks.add(sink)
+    }
+
+    pub fn remove_frame_sink(&mut self, id: FrameSinkId) -> Option<Box<dyn FrameSink>> {
+        self.frame_sinks.remove(id)
+    }
+
+    /// Renders the completed frame into `framebuffer` and hands it to every
+    /// registered frame sink.
+    pub fn present_frame(&mut self, framebuffer: &mut Framebuffer) {
+        framebuffer.data.fill(0);
+        self.bus.render_frame(framebuffer);
+        self.frame_sinks.flush();
+        self.frame_sinks.dispatch(framebuffer);
+    }
+
+    /// Plays back a movie recorded with `inputTiming strobe` from inside the
+    /// core: each record is applied the moment the game latches the
+    /// controllers, so input can change between polls within a frame.
+    pub fn attach_subframe_movie(&mut self, movie: FM2Movie) -> Result<(), String> {
+        if movie.header.input_timing != InputTiming::Strobe {
+            return Err("Movie is frame-timed; apply it per frame instead".to_string());
+        }
+        self.bus.subframe_movie = Some(movie);
+        Ok(())
+    }
+
+    pub fn detach_subframe_movie(&mut self) -> Option<FM2Movie> {
+        self.bus.subframe_movie.take()
+    }
+
+    /// Writes battery-backed PRG RAM to `storage` under `key`. Carts without
+    /// a battery have nothing to save.
+    pub fn save_battery(&self, storage: &mut dyn StorageBackend, key: &str) -> Result<(), String> {
+        match self.bus.cart.mapper.prg_ram() {
+            Some(ram) if self.bus.cart.has_battery => storage.store(key, ram),
+            _ => Ok(()),
+        }
+    }
+
+    /// Restores PRG RAM written by [`Nes::save_battery`]. Returns whether a
+    /// save was found.
+    pub fn load_battery(
+        &mut self,
+        storage: &dyn StorageBackend,
+        key: &str,
+    ) -> Result<bool, String> {
+        if !self.bus.cart.has_battery {
+            return Ok(false);
+        }
+        let Some(ram) = self.bus.cart.mapper.prg_ram_mut() else {
+            return Ok(false);
+        };
+        let Some(data) = storage.load(key)? else {
+            return Ok(false);
+        };
+        if data.len() != ram.len() {
+            return Err(format!(
+                "Save {} is {} bytes, expected {}",
+                key,
+                data.len(),
+                ram.len()
+            ));
+        }
+        ram.copy_from_slice(&data);
+        Ok(true)
     }
 
     pub fn joypad_mut(&mut self, index: usize) -> Option<&mut Joypad> {
@@ -62,7 +503,728 @@ impl Nes {
         self.bus.mapper_mut()
     }
 
+    /// Plugs in the virtual link cable; see [`crate::link`].
+    pub fn connect_link(&mut self) {
+        self.bus.link.get_or_insert_with(LinkPort::new);
+    }
+
+    pub fn disconnect_link(&mut self) {
+        self.bus.link = None;
+    }
+
+    pub fn link_mut(&mut self) -> Option<&mut LinkPort> {
+        self.bus.link.as_mut()
+    }
+
+    /// Starts accumulating cycles per address and routine; see
+    /// [`crate::profiler`]. Keeps any profile already running.
+    pub fn enable_profiler(&mut self) {
+        self.bus.profiler.get_or_insert_with(Profiler::new);
+    }
+
+    /// Stops profiling and returns what was collected.
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.bus.profiler.take()
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.bus.profiler.as_ref()
+    }
+
+    /// Starts tracking calls and returns; see [`crate::callstack`]. Keeps
+    /// the stack already being tracked, if any.
+    pub fn enable_call_stack(&mut self) {
+        self.bus.call_stack.get_or_insert_with(CallStack::new);
+    }
+
+    pub fn disable_call_stack(&mut self) {
+        self.bus.call_stack = None;
+    }
+
+    pub fn call_stack(&self) -> Option<&CallStack> {
+        self.bus.call_stack.as_ref()
+    }
+
+    pub fn call_stack_mut(&mut self) -> Option<&mut CallStack> {
+        self.bus.call_stack.as_mut()
+    }
+
+    /// Starts watching for stack wraparound and pushes over data; see
+    /// [`crate::stack_check`]. Keeps the check already running, if any.
+    pub fn enable_stack_check(&mut self) {
+        self.bus.stack_check.get_or_insert_with(StackCheck::new);
+    }
+
+    pub fn disable_stack_check(&mut self) {
+        self.bus.stack_check = None;
+    }
+
+    pub fn stack_check_mut(&mut self) -> Option<&mut StackCheck> {
+        self.bus.stack_check.as_mut()
+    }
+
+    /// Starts keeping the last `len` instructions run; see
+    /// [`TraceHistory`]. Keeps the history already recorded, if any.
+    pub fn enable_trace_history(&mut self, len: usize) {
+        self.bus
+            .trace_history
+            .get_or_insert_with(|| TraceHistory::new(len));
+    }
+
+    pub fn disable_trace_history(&mut self) {
+        self.bus.trace_history = None;
+    }
+
+    pub fn trace_history(&self) -> Option<&TraceHistory> {
+        self.bus.trace_history.as_ref()
+    }
+
+    /// Watches for a jammed or stalled CPU at the end of each frame,
+    /// resetting it if the watchdog says to. `None` turns it off.
+    pub fn set_watchdog(&mut self, watchdog: Option<Watchdog>) {
+        self.bus.watchdog = watchdog;
+    }
+
+    pub fn watchdog_mut(&mut self) -> Option<&mut Watchdog> {
+        self.bus.watchdog.as_mut()
+    }
+
+    /// Logs CPU bus accesses; `None` turns it off. See
+    /// [`crate::access_log`].
+    pub fn set_access_log(&mut self, access_log: Option<AccessLog>) {
+        self.bus.access_log = access_log;
+    }
+
+    pub fn access_log_mut(&mut self) -> Option<&mut AccessLog> {
+        self.bus.access_log.as_mut()
+    }
+
     pub fn joypads_mut(&mut self) -> (&mut Joypad, &mut Joypad) {
         self.bus.joypads_mut()
     }
+
+    /// Adds a trigger that captures the frame its condition fires on; see
+    /// [`crate::trigger`] and [`Nes::take_captures`].
+    pub fn add_trigger(&mut self, trigger: Trigger) {
+        self.triggers.add(trigger);
+    }
+
+    pub fn clear_triggers(&mut self) {
+        self.triggers.clear();
+    }
+
+    pub fn triggers(&self) -> &[Trigger] {
+        &self.triggers.triggers
+    }
+
+    /// Captures taken since the last call, oldest first.
+    pub fn take_captures(&mut self) -> Vec<Capture> {
+        std::mem::take(&mut self.captures)
+    }
+
+    /// Adds a trigger that reports each frame its condition fires on as an
+    /// event, e.g. for achievements; see [`Nes::take_trigger_events`].
+    pub fn add_event_trigger(&mut self, trigger: Trigger) {
+        self.event_triggers.add(trigger);
+    }
+
+    pub fn clear_event_triggers(&mut self) {
+        self.event_triggers.clear();
+    }
+
+    pub fn event_triggers(&self) -> &[Trigger] {
+        &self.event_triggers.triggers
+    }
+
+    /// Event triggers that fired since the last call, oldest first.
+    pub fn take_trigger_events(&mut self) -> Vec<TriggerEvent> {
+        std::mem::take(&mut self.trigger_events)
+    }
+
+    fn check_triggers(&mut self) {
+        if !self.event_triggers.triggers.is_empty() {
+            let bus = &self.bus;
+            let fired = self.event_triggers.check(|addr| bus.peek(addr));
+            let frame = self.bus.ppu.frame_count;
+            self.trigger_events.extend(
+                fired
+                    .into_iter()
+                    .map(|trigger| TriggerEvent { trigger, frame }),
+            );
+        }
+        if self.triggers.triggers.is_empty() {
+            return;
+        }
+        let bus = &self.bus;
+        let fired = self.triggers.check(|addr| bus.peek(addr));
+        for trigger in fired {
+            let mut screenshot = Framebuffer::new();
+            self.bus.render_frame_copy(&mut screenshot);
+            self.captures.push(Capture {
+                trigger,
+                frame: self.bus.ppu.frame_count,
+                state: self.dump_state(),
+                screenshot,
+            });
+        }
+    }
+
+    /// Forces a register or piece of PPU/APU state; see [`crate::poke`].
+    /// The console stays marked as [`Nes::poked`] from then on, restored
+    /// states included.
+    pub fn poke(&mut self, poke: Poke) -> Result<(), String> {
+        poke.validate()?;
+        if !self.poked {
+            if self.bus.subframe_movie.is_some() {
+                log::warn!("Debug poke during movie playback; the run no longer matches the movie");
+            } else {
+                log::warn!("Debug poke; this session is no longer deterministic");
+            }
+            self.poked = true;
+        }
+        match poke {
+            Poke::Register { addr, value } => self.bus.write(addr, value),
+            Poke::Scroll { x, y } => self.bus.ppu.set_scroll_position(x, y),
+            Poke::Sprite { index, sprite } => self.bus.ppu.set_oam_entry(index, sprite.to_bytes()),
+            Poke::ChannelPeriod { channel, period } => {
+                self.bus.apu.set_channel_period(channel, period)?
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether [`Nes::poke`] was used since the console was built.
+    pub fn poked(&self) -> bool {
+        self.poked
+    }
+
+    /// The 64 sprites in OAM; edit them with [`Poke::Sprite`].
+    pub fn sprites(&self) -> Vec<Sprite> {
+        decode_oam(&self.bus.ppu.oam_data)
+    }
+
+    pub fn ppu_registers(&self) -> PpuRegisters {
+        PpuRegisters::of(&self.bus.ppu)
+    }
+
+    /// Timer period of an APU channel, indexed as in
+    /// [`crate::apu::CHANNEL_NAMES`].
+    pub fn channel_period(&self, channel: usize) -> Option<u16> {
+        self.bus.apu.channel_period(channel)
+    }
+
+    /// Which APU channels are sounding, in [`crate::apu::CHANNEL_NAMES`]
+    /// order.
+    pub fn channel_activity(&self) -> [ChannelActivity; 5] {
+        self.bus.apu.channel_activity()
+    }
+
+    /// Records each APU channel on its own; see [`APU::take_stems`].
+    pub fn set_stems_enabled(&mut self, enabled: bool) {
+        self.bus.apu.set_stems_enabled(enabled);
+    }
+
+    pub fn take_stems(&mut self) -> Option<[Vec<f32>; 5]> {
+        self.bus.apu.take_stems()
+    }
+
+    /// Calls `callback` on every CPU access of `kind` to an address in
+    /// `start..=end`; see [`crate::hooks`].
+    pub fn add_hook(
+        &mut self,
+        kind: HookKind,
+        start: u16,
+        end: u16,
+        callback: HookCallback,
+    ) -> HookId {
+        self.bus.hooks.add(kind, start, end, callback)
+    }
+
+    pub fn remove_hook(&mut self, id: HookId) -> bool {
+        self.bus.hooks.remove(id)
+    }
+
+    pub fn clear_hooks(&mut self) {
+        self.bus.hooks.clear();
+    }
+
+    /// Hands CPU accesses to `start..=end`, within `$4018-$5FFF`, to a
+    /// device's callbacks; see [`crate::expansion`].
+    #[cfg(feature = "expansion-port")]
+    pub fn map_expansion(
+        &mut self,
+        start: u16,
+        end: u16,
+        read: ExpansionRead,
+        write: ExpansionWrite,
+    ) -> Result<DeviceId, String> {
+        let id = self.bus.expansion.map(start, end, read, write)?;
+        self.bus.update_pages();
+        Ok(id)
+    }
+
+    #[cfg(feature = "expansion-port")]
+    pub fn unmap_expansion(&mut self, id: DeviceId) -> bool {
+        let unmapped = self.bus.expansion.unmap(id);
+        self.bus.update_pages();
+        unmapped
+    }
+
+    /// Starts journaling the last `capacity` instructions so the debugger
+    /// can step back through them; see [`crate::reverse`].
+    pub fn enable_reverse_step(&mut self, capacity: usize) {
+        self.bus.history = Some(History::new(capacity));
+    }
+
+    pub fn disable_reverse_step(&mut self) {
+        self.bus.history = None;
+    }
+
+    /// Instructions that can currently be stepped back over.
+    pub fn reverse_history_len(&self) -> usize {
+        self.bus.history.as_ref().map_or(0, History::len)
+    }
+
+    /// Undoes the newest journaled instruction. Returns false when the
+    /// history is empty or off.
+    pub fn step_back(&mut self) -> bool {
+        self.bus.step_back()
+    }
+
+    /// Active cheats; see [`crate::cheats`].
+    pub fn cheats(&self) -> &CheatList {
+        &self.bus.cheats
+    }
+
+    pub fn cheats_mut(&mut self) -> &mut CheatList {
+        &mut self.bus.cheats
+    }
+}
+
+// A halted CPU leaves the rest of the machine running, so only debugger
+// stops end a frame step.
+fn debugger_stop(stop: Option<StopReason>) -> Option<StopReason> {
+    stop.filter(|reason| {
+        !matches!(
+            reason,
+            StopReason::Halted | StopReason::InvalidOpcode { .. }
+        )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cart::test::{test_nes, test_rom};
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    // INC $00; INC $01; JMP $8000
+    const COUNTER_LOOP: [u8; 7] = [0xE6, 0x00, 0xE6, 0x01, 0x4C, 0x00, 0x80];
+
+    #[test]
+    fn test_restore_state_replays_identically() {
+        let mut nes = test_nes(&COUNTER_LOOP);
+        nes.step_frame();
+
+        let snapshot = nes.clone_state();
+        nes.step_frame();
+        nes.step_frame();
+        let ram = nes.bus.cpu.vram;
+        let pc = nes.bus.cpu.registers.pc;
+        let clock = nes.bus.system_clock;
+
+        nes.restore_state(&snapshot);
+        assert_ne!(nes.bus.cpu.vram[..2], ram[..2]);
+
+        nes.step_frame();
+        nes.step_frame();
+        assert_eq!(nes.bus.cpu.vram, ram);
+        assert_eq!(nes.bus.cpu.registers.pc, pc);
+        assert_eq!(nes.bus.system_clock, clock);
+    }
+
+    // Counts NMIs at $00 while looping over an RMW and a page-crossing read.
+    fn nmi_loop() -> Nes {
+        let program = crate::asm::assemble(
+            "
+                    .org $8000
+            reset:  lda #$80
+                    sta $2000
+                    ldx #$30
+            loop:   inc $01F0,x
+                    lda $01F0,x
+                    bit $2002
+                    jmp loop
+            nmi:    inc $00
+                    rti
+                    .org $FFFA
+                    .word nmi, reset, nmi
+            ",
+        )
+        .unwrap();
+        test_nes(&program.slice(0x8000, 0x8000))
+    }
+
+    #[test]
+    fn test_clock_cycle_matches_clock() {
+        let mut whole = nmi_loop();
+        let mut cycles = nmi_loop();
+        for _ in 0..30_000 {
+            whole.clock();
+            while !cycles.clock_cycle().instruction_complete {}
+        }
+        assert_ne!(whole.bus.cpu.vram[0], 0);
+        assert_eq!(cycles.dump_state(), whole.dump_state());
+    }
+
+    #[test]
+    fn test_restore_state_part_way_through_an_instruction() {
+        // After the read of INC $01F0,X, and after the first operand fetch
+        // of LDA $01F0,X.
+        for (pc, cycle) in [(0x8007, 4), (0x800A, 2)] {
+            let mut nes = nmi_loop();
+            nes.step_frame();
+            while nes.bus.cpu.registers.pc != pc || nes.bus.cpu.instruction_cycle() != cycle {
+                nes.clock_cycle();
+            }
+
+            let snapshot = nes.clone_state();
+            for _ in 0..40_000 {
+                nes.clock_cycle();
+            }
+            let dump = nes.dump_state();
+
+            nes.restore_state(&snapshot);
+            assert_eq!(nes.bus.cpu.instruction_cycle(), cycle);
+            for _ in 0..40_000 {
+                nes.clock_cycle();
+            }
+            assert_eq!(nes.dump_state(), dump);
+        }
+    }
+
+    #[test]
+    fn test_instances_are_independent() {
+        let mut first = test_nes(&COUNTER_LOOP);
+        let mut second = test_nes(&COUNTER_LOOP);
+        for _ in 0..3 {
+            first.step_frame();
+            second.step_frame();
+        }
+        assert_eq!(first.dump_state(), second.dump_state());
+
+        first.bus.cpu.vram[0x10] = 0xAB;
+        first.step_frame();
+        second.step_frame();
+        assert_eq!(second.bus.cpu.vram[0x10], 0);
+        assert_eq!(first.bus.cpu.vram[..2], second.bus.cpu.vram[..2]);
+    }
+
+    #[test]
+    fn test_pal_cpu_runs_five_cycles_per_sixteen_dots() {
+        let apu = APU::new(48_000, Arc::new(Mutex::new(VecDeque::new())));
+        let mut nes = Nes::with_model(test_rom(vec![0xEA; 0x8000]), apu, CpuModel::Rp2A07);
+        nes.reset(ResetKind::PowerOn);
+        while nes.bus.cpu_cycles < 500 {
+            nes.clock();
+        }
+        // The 500th cycle falls on dot 1597.
+        assert_eq!(nes.bus.system_clock, 1598);
+        assert!(!nes.bus.cpu.decimal_enabled());
+
+        let mut ntsc = test_nes(&[]);
+        while ntsc.bus.cpu_cycles < 534 {
+            ntsc.clock();
+        }
+        assert_eq!(ntsc.bus.system_clock, 1600);
+    }
+
+    #[test]
+    fn test_pal_frames_have_312_lines() {
+        let apu = APU::new(48_000, Arc::new(Mutex::new(VecDeque::new())));
+        let mut nes = Nes::with_model(test_rom(vec![0xEA; 0x8000]), apu, CpuModel::Rp2A07);
+        nes.reset(ResetKind::PowerOn);
+        assert_eq!(nes.step_to_vblank(), None);
+        assert_eq!(nes.bus.ppu.scanline, 241);
+        let start = nes.presentation_time();
+        nes.step_frame();
+        nes.step_to_vblank();
+
+        let frame = CpuModel::Rp2A07.dots_to_time(341 * 312);
+        let gap = nes.presentation_time() - start;
+        assert!(gap.abs_diff(frame).as_nanos() <= 1);
+        assert!(frame.abs_diff(nes.frame_duration()).as_nanos() <= 1);
+    }
+
+    #[test]
+    fn test_overclock_adds_cycles_but_not_dots() {
+        let mut nes = test_nes(&COUNTER_LOOP);
+        let frame = |nes: &mut Nes| {
+            let (cycles, dots) = (nes.bus.cpu_cycles, nes.bus.system_clock);
+            nes.step_frame();
+            (nes.bus.cpu_cycles - cycles, nes.bus.system_clock - dots)
+        };
+        frame(&mut nes);
+        let (cycles, dots) = frame(&mut nes);
+
+        nes.bus.set_overclock(20);
+        frame(&mut nes);
+        let (overclocked, overclocked_dots) = frame(&mut nes);
+        // 20 scanlines of 113.67 cycles, give or take an instruction.
+        assert!(overclocked.abs_diff(cycles + 2273) <= 5);
+        assert!(overclocked_dots.abs_diff(dots) <= 15);
+
+        nes.bus.set_overclock(0);
+        frame(&mut nes);
+        assert!(frame(&mut nes).0.abs_diff(cycles) <= 5);
+    }
+
+    #[test]
+    fn test_run_for_cycles_and_until() {
+        use crate::cpu::Breakpoint;
+
+        // All NOPs, two cycles each.
+        let mut nes = test_nes(&[]);
+        let start = nes.bus.cpu.registers.pc;
+
+        let result = nes.run_for_cycles(10);
+        assert_eq!(
+            result,
+            RunResult {
+                cycles: 10,
+                stop: None
+            }
+        );
+        assert_eq!(nes.run_for_cycles(9).cycles, 10);
+        assert_eq!(nes.bus.cpu.registers.pc, start + 10);
+
+        let result = nes.run_until(|cpu| cpu.registers.pc == start + 16, 1000);
+        assert_eq!(result.cycles, 12);
+        assert_eq!(
+            nes.run_until(|cpu| cpu.registers.pc == start + 16, 1000)
+                .cycles,
+            0
+        );
+        assert_eq!(nes.run_until(|_| false, 7).cycles, 8);
+
+        nes.bus.cpu.add_breakpoint(Breakpoint::at(start + 30));
+        let result = nes.run_for_cycles(100);
+        assert_eq!(result.stop, Some(StopReason::Breakpoint(start + 30)));
+        assert_eq!(nes.bus.cpu.registers.pc, start + 30);
+
+        // NOP; JAM: the run ends there instead of using up its budget.
+        let mut nes = test_nes(&[0xEA, 0x02]);
+        let result = nes.run_for_cycles(1000);
+        assert_eq!(result.stop, Some(StopReason::Halted));
+        assert!(result.cycles < 10);
+        assert_eq!(nes.bus.cpu.jammed_at(), Some(0x8001));
+    }
+
+    #[test]
+    fn test_step_over_and_out() {
+        let program = crate::asm::assemble(
+            "
+                    .org $8000
+            reset:  jsr outer
+                    nop
+            done:   jmp done
+            outer:  jsr inner
+                    lda #1
+                    rts
+            inner:  ldx #2
+                    rts
+                    .org $FFFC
+                    .word reset, reset
+            ",
+        )
+        .unwrap();
+        let mut nes = test_nes(&program.slice(0x8000, 0x7FFC));
+        let addr = |name: &str| program.labels[name];
+
+        nes.step_over(10_000);
+        assert_eq!(nes.bus.cpu.registers.pc, addr("reset") + 3);
+        assert_eq!(nes.bus.cpu.registers.x, 2);
+        assert!(nes.call_stack().unwrap().frames().is_empty());
+        assert!(nes.step_out(10_000).is_err());
+
+        nes.reset(ResetKind::Reset);
+        // Into `outer`, then `inner`, then back out of both.
+        nes.clock();
+        nes.clock();
+        assert_eq!(nes.bus.cpu.registers.pc, addr("inner"));
+        nes.step_out(10_000).unwrap();
+        assert_eq!(nes.bus.cpu.registers.pc, addr("outer") + 3);
+        nes.step_over(10_000);
+        assert_eq!(nes.bus.cpu.registers.a, 1);
+        nes.step_out(10_000).unwrap();
+        assert_eq!(nes.bus.cpu.registers.pc, addr("reset") + 3);
+    }
+
+    #[test]
+    fn test_subframe_movie_changes_input_between_latches() {
+        // Two latch-and-read sequences in one frame, storing the A bit in
+        // $00 and $01.
+        let poll = |dest: u8| {
+            [
+                0xA9, 0x01, 0x8D, 0x16, 0x40, // LDA #1; STA $4016
+                0xA9, 0x00, 0x8D, 0x16, 0x40, // LDA #0; STA $4016
+                0xAD, 0x16, 0x40, 0x85, dest, // LDA $4016; STA dest
+            ]
+        };
+        let mut program = [poll(0x00), poll(0x01)].concat();
+        program.extend([0x4C, 0x1E, 0x80]); // JMP $801E
+
+        let movie = FM2Movie::parse(
+            "version 3\nemuVersion 1\nromFilename test\nguid 0\nromChecksum 0\n\
+             inputTiming strobe\n|0|.......A|........||\n|0|........|........||\n"
+                .as_bytes(),
+        )
+        .unwrap();
+
+        let mut nes = test_nes(&program);
+        nes.attach_subframe_movie(movie).unwrap();
+        nes.step_frame();
+
+        assert_eq!(nes.bus.cpu.vram[0], 1);
+        assert_eq!(nes.bus.cpu.vram[1], 0);
+    }
+
+    #[test]
+    fn test_vblank_callback_fires_once_per_frame() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let mut nes = test_nes(&COUNTER_LOOP);
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let log = events.clone();
+        nes.set_vblank_callback(Some(Box::new(move |event| log.borrow_mut().push(*event))));
+
+        assert_eq!(nes.step_to_vblank(), None);
+        assert_eq!(nes.bus.ppu.scanline, 241);
+        nes.step_frame();
+        nes.step_frame();
+        nes.step_frame();
+
+        let frames: Vec<u64> = events.borrow().iter().map(|e| e.frame).collect();
+        assert_eq!(frames, vec![0, 1, 2]);
+        assert!(!events.borrow()[0].nmi_enabled);
+
+        // Rendering is off, so no dot is skipped and frames are equal.
+        let frame = CpuModel::Rp2A03.dots_to_time(341 * 262);
+        for pair in events.borrow().windows(2) {
+            let gap = pair[1].time - pair[0].time;
+            assert!(gap.abs_diff(frame).as_nanos() <= 1);
+        }
+        assert_eq!(nes.presentation_time(), events.borrow()[2].time);
+        assert_eq!(nes.frame_duration().as_micros(), 16_639);
+    }
+
+    #[test]
+    fn test_step_frame_stops_at_bank_breakpoint() {
+        use crate::cpu::Breakpoint;
+
+        let mut nes = test_nes(&COUNTER_LOOP);
+        nes.bus.cpu.add_breakpoint(Breakpoint::in_bank(0x8004, 1));
+        assert_eq!(nes.step_frame(), None);
+
+        nes.bus.cpu.add_breakpoint(Breakpoint::in_bank(0x8004, 0));
+        assert_eq!(nes.step_frame(), Some(StopReason::Breakpoint(0x8004)));
+        let counter = nes.bus.cpu.vram[0];
+
+        assert_eq!(nes.step_frame(), Some(StopReason::Breakpoint(0x8004)));
+        assert_eq!(nes.bus.cpu.vram[0], counter.wrapping_add(1));
+    }
+
+    #[test]
+    #[cfg_attr(
+        debug_assertions,
+        ignore = "timing is only meaningful in release builds"
+    )]
+    fn test_snapshot_round_trip_under_a_millisecond() {
+        let mut nes = test_nes(&COUNTER_LOOP);
+        nes.step_frame();
+
+        let rounds = 100;
+        let start = std::time::Instant::now();
+        for _ in 0..rounds {
+            let snapshot = nes.clone_state();
+            nes.restore_state(&snapshot);
+        }
+        let per_round = start.elapsed() / rounds;
+        assert!(
+            per_round < std::time::Duration::from_millis(1),
+            "{:?}",
+            per_round
+        );
+    }
+
+    #[test]
+    fn test_watch_callback_replaces_stop() {
+        use crate::cpu::{WatchKind, Watchpoint};
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        let mut nes = test_nes(&COUNTER_LOOP);
+        nes.bus
+            .cpu
+            .add_watchpoint(Watchpoint::new(0x01, WatchKind::Write));
+        let stop = nes.step_frame();
+        assert!(matches!(
+            stop,
+            Some(StopReason::Watchpoint(WatchHit { pc: 0x8002, .. }))
+        ));
+
+        let hits = Rc::new(Cell::new(0));
+        let counter = hits.clone();
+        nes.set_watch_callback(Some(Box::new(move |_| counter.set(counter.get() + 1))));
+        assert_eq!(nes.step_frame(), None);
+        assert!(hits.get() > 0);
+    }
+
+    #[test]
+    fn test_locked_byte_ignores_writes() {
+        use crate::cheats::Cheat;
+
+        // LDA #1; STA $75; LDA $75; STA $10; JMP $8000
+        let mut nes = test_nes(&[
+            0xA9, 0x01, 0x85, 0x75, 0xA5, 0x75, 0x85, 0x10, 0x4C, 0x00, 0x80,
+        ]);
+        nes.cheats_mut().add(Cheat::lock(0x0075, 0x09));
+        for _ in 0..4 {
+            nes.clock();
+        }
+        assert_eq!(nes.bus.peek(0x0075), 0x09);
+        assert_eq!(nes.bus.peek(0x0010), 0x09);
+
+        nes.cheats_mut().remove(0x0075);
+        for _ in 0..5 {
+            nes.clock();
+        }
+        assert_eq!(nes.bus.peek(0x0010), 0x01);
+    }
+
+    #[test]
+    fn test_battery_ram_round_trip() {
+        use crate::memory::Memory;
+        use crate::storage::MemoryStorage;
+
+        let mut storage = MemoryStorage::default();
+        let mut nes = test_nes(&COUNTER_LOOP);
+        nes.bus.write(0x6000, 0x42);
+        nes.save_battery(&mut storage, "game.sav").unwrap();
+        assert!(storage.entries.is_empty());
+
+        nes.bus.cart.has_battery = true;
+        nes.save_battery(&mut storage, "game.sav").unwrap();
+
+        let mut fresh = test_nes(&COUNTER_LOOP);
+        fresh.bus.cart.has_battery = true;
+        assert!(!fresh.load_battery(&storage, "other.sav").unwrap());
+        assert!(fresh.load_battery(&storage, "game.sav").unwrap());
+        assert_eq!(fresh.bus.read(0x6000), 0x42);
+
+        storage.entries.insert("short.sav".to_string(), vec![0; 16]);
+        assert!(fresh.load_battery(&storage, "short.sav").is_err());
+    }
 }
@@ -0,0 +1,124 @@
+//! Composite video simulation. The PPU's picture is turned back into the
+//! signal it sent down the composite cable, 8 samples per pixel against a
+//! colour subcarrier that cycles every 12, and decoded the way a TV would:
+//! brightness and colour are both read from a one-cycle window, so fine
+//! detail bleeds into colour fringes. Each line starts the subcarrier 4
+//! samples later than the last, and with rendering on the PPU's skipped
+//! dot alternates frames between two starting phases, so the fringes crawl.
+//! Behind the `ntsc-filter` feature as it costs far more than drawing the
+//! picture does.
+
+use crate::ppu::framebuffer::Framebuffer;
+use crate::ppu::palette::{self, CHROMA_SCALE, NtscSettings};
+
+/// Signal samples per PPU pixel.
+const SAMPLES_PER_PIXEL: usize = 8;
+/// Samples per cycle of the colour subcarrier.
+const CYCLE: usize = 12;
+/// Output pixels per PPU pixel.
+const OUTPUT_PER_PIXEL: usize = 2;
+
+pub const WIDTH: usize = Framebuffer::WIDTH * OUTPUT_PER_PIXEL;
+pub const HEIGHT: usize = Framebuffer::HEIGHT;
+
+pub struct NtscFilter {
+    settings: NtscSettings,
+    /// One line of signal, padded by half a subcarrier cycle each side.
+    signal: Vec<f32>,
+    output: Vec<u8>,
+}
+
+impl NtscFilter {
+    pub fn new(settings: NtscSettings) -> Self {
+        NtscFilter {
+            settings,
+            signal: vec![0.0; Framebuffer::WIDTH * SAMPLES_PER_PIXEL + CYCLE],
+            output: vec![0; WIDTH * HEIGHT * 3],
+        }
+    }
+
+    /// Filters `framebuffer` into a `WIDTH` x `HEIGHT` RGB picture. `frame`
+    /// is the PPU frame it was drawn in, which picks the subcarrier phase.
+    pub fn apply(&mut self, framebuffer: &Framebuffer, frame: u64) -> &[u8] {
+        let frame_phase = (frame % 2) as usize * 4;
+        let chroma = self.settings.saturation * CHROMA_SCALE;
+        let carrier: Vec<(f32, f32)> = (0..CYCLE)
+            .map(|phase| palette::carrier(phase, self.settings.hue))
+            .collect();
+
+        for y in 0..HEIGHT {
+            let row = &framebuffer.indices[y * Framebuffer::WIDTH..(y + 1) * Framebuffer::WIDTH];
+            // The padding repeats the edge pixels.
+            let line_phase = frame_phase + y * 4;
+            for (n, sample) in self.signal.iter_mut().enumerate() {
+                let x = (n as isize - (CYCLE / 2) as isize)
+                    .clamp(0, (Framebuffer::WIDTH * SAMPLES_PER_PIXEL) as isize - 1)
+                    as usize
+                    / SAMPLES_PER_PIXEL;
+                *sample = palette::signal(row[x], (line_phase + n) % CYCLE);
+            }
+
+            let out = &mut self.output[y * WIDTH * 3..(y + 1) * WIDTH * 3];
+            for (x, rgb) in out.chunks_exact_mut(3).enumerate() {
+                // One subcarrier cycle centred on the output pixel; the
+                // padding shifts the line by half a cycle.
+                let start = x * SAMPLES_PER_PIXEL / OUTPUT_PER_PIXEL
+                    + SAMPLES_PER_PIXEL / OUTPUT_PER_PIXEL / 2;
+                let (mut luma, mut i, mut q) = (0.0, 0.0, 0.0);
+                for n in start..start + CYCLE {
+                    let signal = self.signal[n];
+                    let (cos, sin) = carrier[(line_phase + n) % CYCLE];
+                    luma += signal;
+                    i += signal * cos;
+                    q += signal * sin;
+                }
+                let (r, g, b) = palette::yiq_to_rgb(luma / CYCLE as f32, i * chroma, q * chroma);
+                rgb.copy_from_slice(&[r, g, b]);
+            }
+        }
+        &self.output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ppu::palette::generate_ntsc;
+
+    fn framebuffer(pixel: impl Fn(usize, usize) -> u8) -> Framebuffer {
+        let mut framebuffer = Framebuffer::new();
+        for y in 0..Framebuffer::HEIGHT {
+            for x in 0..Framebuffer::WIDTH {
+                framebuffer.set_indexed_pixel(x, y, pixel(x, y), (0, 0, 0));
+            }
+        }
+        framebuffer
+    }
+
+    #[test]
+    fn test_flat_colour_matches_palette() {
+        let palette = generate_ntsc(NtscSettings::default());
+        let mut filter = NtscFilter::new(NtscSettings::default());
+        let picture = filter.apply(&framebuffer(|_, _| 0x16), 0);
+        let (r, g, b) = palette[0x16];
+        for rgb in picture.chunks_exact(3).step_by(97) {
+            assert!(rgb[0].abs_diff(r) <= 1);
+            assert!(rgb[1].abs_diff(g) <= 1);
+            assert!(rgb[2].abs_diff(b) <= 1);
+        }
+    }
+
+    #[test]
+    fn test_fine_detail_makes_crawling_fringes() {
+        // Alternating black and white columns: no colour in the palette,
+        // but the TV sees a signal at the subcarrier's frequency.
+        let stripes = framebuffer(|x, _| if x % 2 == 0 { 0x0F } else { 0x30 });
+        let mut filter = NtscFilter::new(NtscSettings::default());
+        let even = filter.apply(&stripes, 0).to_vec();
+        let odd = filter.apply(&stripes, 1).to_vec();
+        let centre = (100 * WIDTH + WIDTH / 2) * 3;
+        let pixel = &even[centre..centre + 3];
+        assert!(pixel[0] != pixel[1] || pixel[1] != pixel[2]);
+        assert_ne!(even[centre..centre + 3], odd[centre..centre + 3]);
+    }
+}
@@ -18,6 +18,7 @@ pub enum Mnemonic {
     BMI,
     BNE,
     BPL,
+    BRA,
     BRK,
     BVC,
     BVS,
@@ -50,8 +51,12 @@ pub enum Mnemonic {
     ORA,
     PHA,
     PHP,
+    PHX,
+    PHY,
     PLA,
     PLP,
+    PLX,
+    PLY,
     ROL,
     ROR,
     RLA,
@@ -72,9 +77,12 @@ pub enum Mnemonic {
     STA,
     STX,
     STY,
+    STZ,
     XAA,
     TAX,
     TAY,
+    TRB,
+    TSB,
     TSX,
     TXA,
     TXS,
@@ -102,6 +110,8 @@ pub enum AddressingMode {
     Indirect,
     IndirectX,
     IndirectY,
+    /// `($zp)`, 65C02 only.
+    ZeroPageIndirect,
 }
 
 #[derive(Debug)]
@@ -133,6 +143,8 @@ impl Opcode {
 
 pub struct OpcodeMap {
     opcodes: Vec<Opcode>,
+    // Index into `opcodes` for each opcode byte, so decode is a single lookup.
+    by_code: [Option<usize>; 256],
 }
 
 impl Default for OpcodeMap {
@@ -143,7 +155,8 @@ impl Default for OpcodeMap {
 
 impl OpcodeMap {
     pub fn new() -> Self {
-        OpcodeMap {
+        let mut map = OpcodeMap {
+            by_code: [None; 256],
             opcodes: vec![
                 // ADC
                 Opcode::new(0x69, Mnemonic::ADC, 2, 2, AddressingMode::Immediate),
@@ -481,11 +494,80 @@ impl OpcodeMap {
                 Opcode::new(0xDC, Mnemonic::NOP, 3, 4, AddressingMode::AbsoluteX),
                 Opcode::new(0xFC, Mnemonic::NOP, 3, 4, AddressingMode::AbsoluteX),
             ],
+        };
+
+        for (index, opcode) in map.opcodes.iter().enumerate() {
+            map.by_code[opcode.code as usize] = Some(index);
+        }
+
+        map
+    }
+
+    /// The 65C02 table: the NMOS one with PHX/PHY/PLX/PLY, STZ, BRA,
+    /// TRB/TSB and `($zp)` addressing in place of the opcodes they reuse, and
+    /// the extra cycle of the fixed `JMP ($xxxx)`. The NMOS part's unofficial
+    /// instructions don't exist on a 65C02: every other opcode runs as a NOP
+    /// of the length and cycle count the 65C02 gives it, including the x3, x7,
+    /// xB and xF columns, which are single-cycle NOPs on the original part
+    /// (the WDC bit instructions, `WAI` and `STP` are not implemented). `INC A`,
+    /// `DEC A`, the new `BIT` modes and `JMP ($xxxx,X)` are not implemented
+    /// either and keep their NMOS NOP decoding.
+    pub fn cmos() -> Self {
+        let mut map = Self::new();
+        let additions = [
+            Opcode::new(0xDA, Mnemonic::PHX, 1, 3, AddressingMode::None),
+            Opcode::new(0x5A, Mnemonic::PHY, 1, 3, AddressingMode::None),
+            Opcode::new(0xFA, Mnemonic::PLX, 1, 4, AddressingMode::None),
+            Opcode::new(0x7A, Mnemonic::PLY, 1, 4, AddressingMode::None),
+            Opcode::new(0x64, Mnemonic::STZ, 2, 3, AddressingMode::ZeroPage),
+            Opcode::new(0x74, Mnemonic::STZ, 2, 4, AddressingMode::ZeroPageX),
+            Opcode::new(0x9C, Mnemonic::STZ, 3, 4, AddressingMode::Absolute),
+            Opcode::new(0x9E, Mnemonic::STZ, 3, 5, AddressingMode::AbsoluteX),
+            Opcode::new(0x80, Mnemonic::BRA, 2, 2, AddressingMode::Relative),
+            Opcode::new(0x04, Mnemonic::TSB, 2, 5, AddressingMode::ZeroPage),
+            Opcode::new(0x0C, Mnemonic::TSB, 3, 6, AddressingMode::Absolute),
+            Opcode::new(0x14, Mnemonic::TRB, 2, 5, AddressingMode::ZeroPage),
+            Opcode::new(0x1C, Mnemonic::TRB, 3, 6, AddressingMode::Absolute),
+            Opcode::new(0x12, Mnemonic::ORA, 2, 5, AddressingMode::ZeroPageIndirect),
+            Opcode::new(0x32, Mnemonic::AND, 2, 5, AddressingMode::ZeroPageIndirect),
+            Opcode::new(0x52, Mnemonic::EOR, 2, 5, AddressingMode::ZeroPageIndirect),
+            Opcode::new(0x72, Mnemonic::ADC, 2, 5, AddressingMode::ZeroPageIndirect),
+            Opcode::new(0x92, Mnemonic::STA, 2, 5, AddressingMode::ZeroPageIndirect),
+            Opcode::new(0xB2, Mnemonic::LDA, 2, 5, AddressingMode::ZeroPageIndirect),
+            Opcode::new(0xD2, Mnemonic::CMP, 2, 5, AddressingMode::ZeroPageIndirect),
+            Opcode::new(0xF2, Mnemonic::SBC, 2, 5, AddressingMode::ZeroPageIndirect),
+            Opcode::new(0x6C, Mnemonic::JMP, 3, 6, AddressingMode::Indirect),
+        ];
+        for opcode in additions {
+            let index = map.by_code[opcode.code as usize].expect("NMOS table is complete");
+            map.opcodes[index] = opcode;
+        }
+        for code in 0..=255u8 {
+            if let Some(nop) = Self::cmos_nop(code) {
+                let index = map.by_code[code as usize].expect("NMOS table is complete");
+                map.opcodes[index] = nop;
+            }
         }
+        map
+    }
+
+    /// The NOP a 65C02 runs for an NMOS-only opcode, or `None` if the opcode
+    /// is defined (or replaced in [`OpcodeMap::cmos`]) on the 65C02.
+    fn cmos_nop(code: u8) -> Option<Opcode> {
+        let (bytes, cycles, mode) = match code {
+            _ if matches!(code & 0x0F, 0x03 | 0x07 | 0x0B | 0x0F) => (1, 1, AddressingMode::None),
+            0x02 | 0x22 | 0x42 | 0x62 | 0x82 | 0xC2 | 0xE2 => (2, 2, AddressingMode::Immediate),
+            0x44 => (2, 3, AddressingMode::ZeroPage),
+            0x54 | 0xD4 | 0xF4 => (2, 4, AddressingMode::ZeroPageX),
+            0x5C => (3, 8, AddressingMode::Absolute),
+            0xDC | 0xFC => (3, 4, AddressingMode::Absolute),
+            _ => return None,
+        };
+        Some(Opcode::new(code, Mnemonic::NOP, bytes, cycles, mode))
     }
 
     pub fn find_by_code(&self, code: u8) -> Option<&Opcode> {
-        self.opcodes.iter().find(|opcode| opcode.code == code)
+        self.by_code[code as usize].map(|index| &self.opcodes[index])
     }
 
     #[allow(dead_code)]
@@ -495,3 +577,80 @@ impl OpcodeMap {
 }
 
 pub static CPU_OPCODES: LazyLock<OpcodeMap> = LazyLock::new(OpcodeMap::new);
+pub static CMOS_OPCODES: LazyLock<OpcodeMap> = LazyLock::new(OpcodeMap::cmos);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dispatch_table_covers_every_opcode() {
+        let map = OpcodeMap::new();
+        for code in 0..=255u8 {
+            let opcode = map.find_by_code(code).expect("opcode missing from table");
+            assert_eq!(opcode.code, code);
+        }
+    }
+
+    #[test]
+    fn test_cmos_table_replaces_in_place() {
+        let map = OpcodeMap::cmos();
+        assert_eq!(
+            map.get_opcodes().len(),
+            OpcodeMap::new().get_opcodes().len()
+        );
+        let opcode = map.find_by_code(0xB2).unwrap();
+        assert_eq!(opcode.mnemonic, Mnemonic::LDA);
+        assert_eq!(opcode.mode, AddressingMode::ZeroPageIndirect);
+        assert_eq!(map.find_by_code(0xA9).unwrap().mnemonic, Mnemonic::LDA);
+    }
+
+    #[test]
+    fn test_cmos_table_has_no_nmos_illegals() {
+        use Mnemonic::*;
+        let map = OpcodeMap::cmos();
+        for opcode in map.get_opcodes() {
+            assert!(
+                !matches!(
+                    opcode.mnemonic,
+                    AHX | ALR
+                        | ANC
+                        | ARR
+                        | AXS
+                        | DCP
+                        | ISC
+                        | LAS
+                        | LAX
+                        | LXA
+                        | RLA
+                        | RRA
+                        | SAX
+                        | SHX
+                        | SHY
+                        | SLO
+                        | SRE
+                        | STP
+                        | TAS
+                        | XAA
+                ),
+                "{:#04X} is {:?}",
+                opcode.code,
+                opcode.mnemonic
+            );
+        }
+    }
+
+    #[test]
+    fn test_opcode_table_has_no_duplicates() {
+        let map = OpcodeMap::new();
+        let mut seen = [false; 256];
+        for opcode in map.get_opcodes() {
+            assert!(
+                !seen[opcode.code as usize],
+                "duplicate {:#04X}",
+                opcode.code
+            );
+            seen[opcode.code as usize] = true;
+        }
+    }
+}
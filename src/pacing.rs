@@ -0,0 +1,103 @@
+//! How the frontend waits for the next frame and how it asks the OS to
+//! schedule its threads. Sleeping is cheap but wakes up late by up to the
+//! timer granularity (15.6ms on some Windows systems), which shows as
+//! uneven frame times; spinning is exact but keeps a core busy.
+
+use std::time::{Duration, Instant};
+
+/// Hybrid waits sleep until this long before the deadline, then spin.
+const SPIN_MARGIN: Duration = Duration::from_millis(2);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WaitStrategy {
+    #[default]
+    Sleep,
+    Spin,
+    /// Sleep for most of the wait and spin through the rest.
+    Hybrid,
+}
+
+impl WaitStrategy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WaitStrategy::Sleep => "sleep",
+            WaitStrategy::Spin => "spin",
+            WaitStrategy::Hybrid => "hybrid",
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "sleep" => Ok(WaitStrategy::Sleep),
+            "spin" => Ok(WaitStrategy::Spin),
+            "hybrid" => Ok(WaitStrategy::Hybrid),
+            _ => Err(format!("Unknown wait strategy: {}", value)),
+        }
+    }
+
+    /// Blocks until `deadline`. Returns at once if it has passed.
+    pub fn wait_until(&self, deadline: Instant) {
+        let sleep_until = match self {
+            WaitStrategy::Sleep => deadline,
+            WaitStrategy::Spin => Instant::now(),
+            WaitStrategy::Hybrid => deadline.checked_sub(SPIN_MARGIN).unwrap_or(deadline),
+        };
+        if let Some(wait) = sleep_until.checked_duration_since(Instant::now()) {
+            std::thread::sleep(wait);
+        }
+        while Instant::now() < deadline {
+            std::hint::spin_loop();
+        }
+    }
+}
+
+/// Scheduling priority requested for the emulation and audio threads.
+/// Raising it can fail without administrator rights, in which case the
+/// frontend carries on at normal priority.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ThreadPriority {
+    #[default]
+    Normal,
+    High,
+}
+
+impl ThreadPriority {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ThreadPriority::Normal => "normal",
+            ThreadPriority::High => "high",
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "normal" => Ok(ThreadPriority::Normal),
+            "high" => Ok(ThreadPriority::High),
+            _ => Err(format!("Unknown thread priority: {}", value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_strategies_wait_until_the_deadline() {
+        for strategy in [
+            WaitStrategy::Sleep,
+            WaitStrategy::Spin,
+            WaitStrategy::Hybrid,
+        ] {
+            assert_eq!(WaitStrategy::parse(strategy.name()), Ok(strategy));
+            let deadline = Instant::now() + Duration::from_millis(3);
+            strategy.wait_until(deadline);
+            assert!(Instant::now() >= deadline);
+        }
+        // A missed deadline doesn't wait.
+        let start = Instant::now();
+        WaitStrategy::Hybrid.wait_until(start - Duration::from_millis(5));
+        assert!(start.elapsed() < Duration::from_millis(5));
+        assert!(ThreadPriority::parse("realtime").is_err());
+    }
+}
@@ -0,0 +1,149 @@
+//! Debug reads and writes of PPU and APU state from outside the emulated
+//! CPU, for tools that want to try a scroll position or retune a channel
+//! without patching the game.
+//!
+//! A poke is not part of any input, so a session that used one can no
+//! longer be reproduced from its ROM and movie: [`crate::nes::Nes::poke`]
+//! marks the console as poked for good, and recorders should refuse to
+//! save or label the result.
+
+use crate::apu::CHANNEL_NAMES;
+use crate::ppu::PPU;
+use crate::ppu::sprite::Sprite;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Poke {
+    /// A write to a PPU (`$2000-$2007` and mirrors) or APU (`$4000-$4013`,
+    /// `$4015`, `$4017`) register, with the same side effects as a CPU
+    /// write.
+    Register { addr: u16, value: u8 },
+    /// Moves the scroll to pixel (`x`, `y`) of the 512x480 nametable plane
+    /// for the rest of the frame.
+    Scroll { x: usize, y: usize },
+    /// Sets a channel's timer period directly, indexed as in
+    /// [`CHANNEL_NAMES`].
+    ChannelPeriod { channel: usize, period: u16 },
+    /// Replaces OAM entry `index` (0-63). Games that copy OAM from RAM each
+    /// frame overwrite it at their next DMA; poke the RAM copy to keep it.
+    Sprite { index: usize, sprite: Sprite },
+}
+
+impl Poke {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        match *self {
+            Poke::Register { addr, .. } => match addr {
+                0x2000..=0x3FFF | 0x4000..=0x4013 | 0x4015 | 0x4017 => Ok(()),
+                _ => Err(format!("${:04X} is not a PPU or APU register", addr)),
+            },
+            Poke::Scroll { .. } => Ok(()),
+            Poke::ChannelPeriod { channel, .. } if channel >= CHANNEL_NAMES.len() => {
+                Err(format!("No APU channel {}", channel))
+            }
+            Poke::ChannelPeriod { .. } => Ok(()),
+            Poke::Sprite { index, .. } if index >= 64 => Err(format!("No sprite {}", index)),
+            Poke::Sprite { .. } => Ok(()),
+        }
+    }
+}
+
+/// PPU registers as they stand, without the side effects of reading them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PpuRegisters {
+    pub ctrl: u8,
+    pub mask: u8,
+    pub status: u8,
+    /// Current VRAM address.
+    pub v: u16,
+    /// Temporary VRAM address, the scroll the next frame starts from.
+    pub t: u16,
+    pub fine_x: u8,
+    pub scroll_x: usize,
+    pub scroll_y: usize,
+}
+
+impl PpuRegisters {
+    pub fn of(ppu: &PPU) -> Self {
+        PpuRegisters {
+            ctrl: ppu.ctrl.bits(),
+            mask: ppu.mask.bits(),
+            status: ppu.status.snapshot(),
+            v: ppu.scroll.v_debug(),
+            t: ppu.scroll.t_debug(),
+            fine_x: ppu.scroll.fine_x_debug(),
+            scroll_x: ppu.scroll.scroll_x(),
+            scroll_y: ppu.scroll.scroll_y(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cart::test::test_nes;
+
+    #[test]
+    fn test_pokes_change_state_and_mark_the_console() {
+        let mut nes = test_nes(&[]);
+        assert!(!nes.poked());
+
+        nes.poke(Poke::Register {
+            addr: 0x2001,
+            value: 0x1E,
+        })
+        .unwrap();
+        nes.poke(Poke::Scroll { x: 300, y: 13 }).unwrap();
+        let registers = nes.ppu_registers();
+        assert_eq!(registers.mask, 0x1E);
+        assert_eq!((registers.scroll_x, registers.scroll_y), (44, 13));
+        assert_eq!(nes.bus.ppu.scroll.base_nametable(), 1);
+
+        nes.poke(Poke::ChannelPeriod {
+            channel: 2,
+            period: 0x1AB,
+        })
+        .unwrap();
+        assert_eq!(nes.channel_period(2), Some(0x1AB));
+        assert!(nes.poked());
+
+        assert!(
+            nes.poke(Poke::Register {
+                addr: 0x0000,
+                value: 1
+            })
+            .is_err()
+        );
+        assert!(
+            nes.poke(Poke::ChannelPeriod {
+                channel: 5,
+                period: 1
+            })
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_sprite_pokes_edit_oam() {
+        let mut nes = test_nes(&[]);
+        nes.bus.ppu.oam_data[8..12].copy_from_slice(&[0x40, 0x12, 0x63, 0x80]);
+
+        let mut sprite = nes.sprites()[2];
+        assert_eq!(
+            sprite,
+            Sprite {
+                x: 0x80,
+                y: 0x40,
+                tile: 0x12,
+                palette: 3,
+                behind_background: true,
+                flip_horizontal: true,
+                flip_vertical: false,
+            }
+        );
+        sprite.flip_vertical = true;
+        sprite.x = 0x10;
+        nes.poke(Poke::Sprite { index: 2, sprite }).unwrap();
+        assert_eq!(nes.bus.ppu.oam_data[8..12], [0x40, 0x12, 0xE3, 0x10]);
+        assert_eq!(nes.bus.ppu.render_oam()[8..12], [0x40, 0x12, 0xE3, 0x10]);
+        assert!(nes.poke(Poke::Sprite { index: 64, sprite }).is_err());
+    }
+}
@@ -0,0 +1,181 @@
+//! The background pipeline, run dot by dot as on hardware. Every 8 dots the
+//! PPU fetches a nametable byte, an attribute byte and two pattern bytes at
+//! the VRAM address `v`, then moves `v` to the next tile; the fetched tile
+//! is loaded into 16-bit shift registers that feed one pixel a dot, picked
+//! out by fine X. At dot 256 `v` moves down a row, at 257 its horizontal
+//! bits are reloaded from `t`, and on the pre-render line its vertical bits
+//! too, so scroll writes take effect exactly when they would on a console.
+//! Each pixel is then mixed with the sprite units' output; see
+//! [`super::sprite_pipeline`].
+
+use crate::mapper::{ChrSource, Mapper};
+use crate::ppu::PPU;
+
+pub(crate) const WIDTH: usize = 256;
+pub(crate) const HEIGHT: usize = 240;
+/// Set in [`PPU::pixels`] where the background was transparent and the
+/// pixel shows the backdrop colour.
+pub(crate) const BACKDROP: u8 = 0x80;
+
+#[derive(Clone, Default)]
+pub(crate) struct BackgroundPipeline {
+    // Latches filled by the fetches of the tile in progress.
+    tile: u8,
+    palette: u8,
+    pattern_lo: u8,
+    pattern_hi: u8,
+    // Two tiles of pattern and palette bits, the current one in the high
+    // byte.
+    shift_pattern_lo: u16,
+    shift_pattern_hi: u16,
+    shift_palette_lo: u16,
+    shift_palette_hi: u16,
+}
+
+impl BackgroundPipeline {
+    fn load(&mut self) {
+        self.shift_pattern_lo = (self.shift_pattern_lo & 0xFF00) | self.pattern_lo as u16;
+        self.shift_pattern_hi = (self.shift_pattern_hi & 0xFF00) | self.pattern_hi as u16;
+        let spread = |bit: u8| if bit != 0 { 0xFF } else { 0x00 };
+        self.shift_palette_lo = (self.shift_palette_lo & 0xFF00) | spread(self.palette & 1);
+        self.shift_palette_hi = (self.shift_palette_hi & 0xFF00) | spread(self.palette & 2);
+    }
+
+    fn shift(&mut self) {
+        self.shift_pattern_lo <<= 1;
+        self.shift_pattern_hi <<= 1;
+        self.shift_palette_lo <<= 1;
+        self.shift_palette_hi <<= 1;
+    }
+
+    /// Pattern value (0-3) and palette (0-3) of the pixel under fine X.
+    fn pixel(&self, fine_x: u8) -> (u8, u8) {
+        let bit = 0x8000 >> fine_x;
+        let pick = |shift: u16| (shift & bit != 0) as u8;
+        (
+            pick(self.shift_pattern_hi) << 1 | pick(self.shift_pattern_lo),
+            pick(self.shift_palette_hi) << 1 | pick(self.shift_palette_lo),
+        )
+    }
+}
+
+impl PPU {
+    /// Runs the background and sprite pipelines for the current dot,
+    /// writing the pixel under it on visible lines.
+    pub(crate) fn render_dot(&mut self, mapper: &mut dyn Mapper) {
+        let (line, dot) = (self.scanline, self.cycle as usize);
+        let visible = (0..HEIGHT as i16).contains(&line);
+        let pre_render = line == self.pre_render_line();
+        if !visible && !pre_render {
+            return;
+        }
+
+        let rendering = self.mask.show_background() || self.mask.show_sprites();
+        if rendering {
+            if matches!(dot, 2..=257 | 321..=337) {
+                self.background.shift();
+                self.fetch_background(mapper, dot);
+            }
+            match dot {
+                256 => self.scroll.increment_y(),
+                257 => self.scroll.copy_horizontal_bits(),
+                280..=304 if pre_render => self.scroll.copy_vertical_bits(),
+                _ => {}
+            }
+        }
+
+        if visible && (1..=WIDTH).contains(&dot) {
+            let x = dot - 1;
+            let pixel = self.output_pixel(x);
+            self.pixels[line as usize * WIDTH + x] = pixel;
+        }
+
+        // Sprite fetches for the next line start after the last pixel.
+        if rendering {
+            self.sprite_dot(mapper, visible, dot);
+        }
+    }
+
+    // One step of the 8-dot fetch cycle; each fetch takes two dots, and the
+    // last tile fetched is loaded into the shifters as the next one starts,
+    // at dots 9, 17, ..., 257 and 329, 337.
+    fn fetch_background(&mut self, mapper: &mut dyn Mapper, dot: usize) {
+        let v = self.scroll.v_debug();
+        match (dot - 1) % 8 {
+            0 => {
+                self.background.load();
+                let addr = 0x2000 | (v & 0x0FFF);
+                self.background.tile = self.read_nametable(mapper, addr);
+            }
+            2 => {
+                let (table, column, row) = Self::tile_position(v);
+                self.background.palette = match mapper
+                    .background_palette_override(table, column, row)
+                {
+                    Some(palette) => palette & 0b11,
+                    None => {
+                        let addr = 0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+                        let shift = ((v >> 4) & 0b100) | (v & 0b10);
+                        (self.read_nametable(mapper, addr) >> shift) & 0b11
+                    }
+                };
+            }
+            4 | 6 => {
+                let fine_y = (v >> 12) & 0x07;
+                let tile = self.background.tile;
+                let base = self.ctrl.bknd_pattern_addr() + tile as u16 * 16;
+                let (table, column, row) = Self::tile_position(v);
+                let plane = if dot % 8 == 5 { 0 } else { 8 };
+                let byte = match mapper.background_tile_override(table, column, row, tile, base) {
+                    Some(bytes) => bytes[(fine_y + plane) as usize],
+                    None => mapper.read_chr(base + fine_y + plane, ChrSource::Background),
+                };
+                if plane == 0 {
+                    self.background.pattern_lo = byte;
+                } else {
+                    self.background.pattern_hi = byte;
+                }
+            }
+            7 => self.scroll.increment_x(),
+            _ => {}
+        }
+    }
+
+    /// Nametable, tile column and tile row `v` points at.
+    fn tile_position(v: u16) -> (usize, usize, usize) {
+        (
+            ((v >> 10) & 0x03) as usize,
+            (v & 0x1F) as usize,
+            ((v >> 5) & 0x1F) as usize,
+        )
+    }
+
+    // The system palette index of the pixel at `x`, or the backdrop's with
+    // `BACKDROP` set. Sets the sprite-zero hit flag when sprite 0 and the
+    // background are both opaque here.
+    fn output_pixel(&mut self, x: usize) -> u8 {
+        let shown = self.mask.show_background() && (x >= 8 || self.mask.leftmost_8pxl_background());
+        let (value, palette) = if shown {
+            self.background.pixel(self.scroll.fine_x_debug())
+        } else {
+            (0, 0)
+        };
+        let sprite = self.sprite_pixel(x);
+        if let Some(sprite) = &sprite
+            && sprite.sprite_zero
+            && value != 0
+            && x != 255
+        {
+            self.status.set_sprite_zero_hit(true);
+        }
+
+        let grayscale = if self.mask.is_grayscale() { 0x30 } else { 0x3F };
+        match sprite {
+            Some(sprite) if value == 0 || !sprite.behind_background => {
+                self.palette_table[(0x10 + sprite.palette * 4 + sprite.value) as usize] & grayscale
+            }
+            _ if value != 0 => self.palette_table[(palette * 4 + value) as usize] & grayscale,
+            _ => (self.backdrop_color_index() & grayscale) | BACKDROP,
+        }
+    }
+}
@@ -0,0 +1,85 @@
+//! A rendered frame as system palette indices, with conversions to the pixel
+//! formats frontends usually want (GPU textures, SPI LCDs, terminals).
+
+use crate::ppu::palette::Palette;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    width: usize,
+    height: usize,
+    indices: Vec<u8>,
+}
+
+impl Frame {
+    /// Wraps a row-major buffer of palette indices (0-63). Panics if the
+    /// buffer doesn't hold exactly `width * height` pixels.
+    pub fn from_indices(width: usize, height: usize, indices: Vec<u8>) -> Self {
+        assert_eq!(indices.len(), width * height, "frame buffer size mismatch");
+        Frame {
+            width,
+            height,
+            indices,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// One palette index per pixel, row by row.
+    pub fn indices(&self) -> &[u8] {
+        &self.indices
+    }
+
+    fn colors<'a>(&'a self, palette: &'a Palette) -> impl Iterator<Item = (u8, u8, u8)> + 'a {
+        self.indices
+            .iter()
+            .map(|&index| palette[(index & 0x3F) as usize])
+    }
+
+    /// Four bytes per pixel, R G B A, fully opaque.
+    pub fn to_rgba8888(&self, palette: &Palette) -> Vec<u8> {
+        self.colors(palette)
+            .flat_map(|(r, g, b)| [r, g, b, 0xFF])
+            .collect()
+    }
+
+    /// One native-endian `u16` per pixel, red in the top 5 bits. Panels
+    /// that take big-endian data over SPI want `to_be_bytes` of each.
+    pub fn to_rgb565(&self, palette: &Palette) -> Vec<u16> {
+        self.colors(palette)
+            .map(|(r, g, b)| ((r as u16 >> 3) << 11) | ((g as u16 >> 2) << 5) | (b as u16 >> 3))
+            .collect()
+    }
+
+    /// One byte of luma per pixel (BT.601 weights).
+    pub fn to_grayscale(&self, palette: &Palette) -> Vec<u8> {
+        self.colors(palette)
+            .map(|(r, g, b)| ((299 * r as u32 + 587 * g as u32 + 114 * b as u32) / 1000) as u8)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_conversions() {
+        let mut palette: Palette = [(0, 0, 0); 64];
+        palette[0x30] = (0xFF, 0xFF, 0xFF);
+        palette[0x16] = (0xF8, 0x38, 0x00);
+        let frame = Frame::from_indices(2, 1, vec![0x30, 0x16]);
+
+        assert_eq!(
+            frame.to_rgba8888(&palette),
+            [0xFF, 0xFF, 0xFF, 0xFF, 0xF8, 0x38, 0x00, 0xFF]
+        );
+        assert_eq!(frame.to_rgb565(&palette), [0xFFFF, 0xF9C0]);
+        assert_eq!(frame.to_grayscale(&palette), [0xFF, 0x6B]);
+    }
+}
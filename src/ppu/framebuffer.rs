@@ -1,5 +1,9 @@
+use crate::ppu::frame::Frame;
+
 pub struct Framebuffer {
     pub data: Vec<u8>,
+    /// System palette index of each pixel, as drawn by the renderer.
+    pub indices: Vec<u8>,
 }
 
 impl Default for Framebuffer {
@@ -15,6 +19,7 @@ impl Framebuffer {
     pub fn new() -> Self {
         Framebuffer {
             data: vec![0; (Framebuffer::WIDTH) * (Framebuffer::HEIGHT) * 3],
+            indices: vec![0; Framebuffer::WIDTH * Framebuffer::HEIGHT],
         }
     }
 
@@ -26,4 +31,94 @@ impl Framebuffer {
             self.data[base + 2] = rgb.2;
         }
     }
+
+    pub fn set_indexed_pixel(&mut self, x: usize, y: usize, index: u8, rgb: (u8, u8, u8)) {
+        if let Some(pixel) = self.indices.get_mut(y * Framebuffer::WIDTH + x) {
+            *pixel = index;
+        }
+        self.set_pixel(x, y, rgb);
+    }
+
+    /// The palette indices of the last rendered frame.
+    pub fn frame(&self) -> Frame {
+        Frame::from_indices(
+            Framebuffer::WIDTH,
+            Framebuffer::HEIGHT,
+            self.indices.clone(),
+        )
+    }
+
+    /// Encodes the frame as an uncompressed RGB PNG.
+    pub fn to_png(&self) -> Vec<u8> {
+        // Each scanline is prefixed with filter type 0 (none).
+        let row_len = Framebuffer::WIDTH * 3;
+        let mut raw = Vec::with_capacity((row_len + 1) * Framebuffer::HEIGHT);
+        for row in self.data.chunks(row_len) {
+            raw.push(0);
+            raw.extend_from_slice(row);
+        }
+
+        let mut ihdr = Vec::with_capacity(13);
+        ihdr.extend_from_slice(&(Framebuffer::WIDTH as u32).to_be_bytes());
+        ihdr.extend_from_slice(&(Framebuffer::HEIGHT as u32).to_be_bytes());
+        ihdr.extend_from_slice(&[8, 2, 0, 0, 0]); // 8-bit RGB, no interlace
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        write_chunk(&mut png, b"IHDR", &ihdr);
+        write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+        write_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crate::romdb::crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+// A zlib stream made of stored (uncompressed) deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    while let Some(block) = blocks.next() {
+        out.push(blocks.peek().is_none() as u8);
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    out.extend_from_slice(&((b << 16) | a).to_be_bytes());
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_png_layout() {
+        let mut framebuffer = Framebuffer::new();
+        framebuffer.set_pixel(0, 0, (0xFF, 0x80, 0x00));
+        let png = framebuffer.to_png();
+
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..24], &[0, 0, 1, 0, 0, 0, 0, 240]);
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+
+        // IDAT: zlib header, then the first stored block and the first row.
+        let idat = &png[33 + 8..];
+        assert_eq!(&idat[..3], &[0x78, 0x01, 0x00]);
+        assert_eq!(&idat[7..11], &[0x00, 0xFF, 0x80, 0x00]);
+    }
 }
@@ -1,15 +1,35 @@
+//! The picture processing unit. [`PPU`] holds the registers the CPU sees
+//! at $2000-$2007, 2KB of nametable VRAM (mirrored per the cartridge),
+//! palette RAM and OAM, and is clocked once per dot by the bus: 341 dots a
+//! scanline (340 on the pre-render line of every other NTSC frame while
+//! rendering), 262 scanlines a frame (312 on PAL), with vblank and its NMI
+//! from scanline 241. Pattern data comes from the cartridge through [`Mapper::read_chr`].
+//! The 256x240 picture is drawn a pixel per dot as the PPU is clocked: the
+//! background scrolled by the internal `v`/`t`/fine X registers, mixed with
+//! the eight sprites evaluated for each line; [`render`] copies the
+//! finished frame out. Scroll changes are also kept per scanline as
+//! [`ScrollSegment`]s for debuggers.
+
+mod background;
+pub mod frame;
 pub mod framebuffer;
 pub mod palette;
 pub mod registers;
 pub mod render;
+pub mod sprite;
+mod sprite_pipeline;
 
 use crate::cart::Mirroring;
+use crate::cpu::CpuModel;
 use crate::mapper::{ChrSource, Mapper};
+use background::BackgroundPipeline;
+use palette::Palette;
 use registers::addr::AddrRegister;
 use registers::control::ControlRegister;
 use registers::mask::MaskRegister;
 use registers::scroll::ScrollRegister;
 use registers::status::StatusRegister;
+use sprite_pipeline::SpritePipeline;
 
 #[derive(Clone, Debug)]
 pub struct ScrollSegment {
@@ -20,6 +40,7 @@ pub struct ScrollSegment {
     pub screen_origin: usize,
 }
 
+#[derive(Clone)]
 pub struct PPU {
     pub ctrl: ControlRegister,
     pub mask: MaskRegister,
@@ -32,13 +53,26 @@ pub struct PPU {
     pub oam_data: [u8; 256],
     render_oam_data: [u8; 256],
     pub palette_table: [u8; 32],
+    /// RGB colour for each of the 64 NES colour indices.
+    pub system_palette: Palette,
+    /// Debug aid: when set, every backdrop pixel is drawn in this colour
+    /// instead of the palette entry, which makes transparency bugs stand out.
+    pub backdrop_override: Option<(u8, u8, u8)>,
 
     pub nmi_interrupt: Option<u8>,
     pub cycle: i16,
     pub scanline: i16,
     pub frame_count: u64,
+    /// 262 on NTSC, 312 on PAL; see [`PPU::set_cpu_model`].
+    scanlines: i16,
+    /// NTSC only; see [`CpuModel::skips_odd_frame_dot`].
+    skips_odd_frame_dot: bool,
 
     internal_data_buf: u8,
+    background: BackgroundPipeline,
+    sprites: SpritePipeline,
+    /// The frame so far, one system palette index per pixel.
+    pixels: Vec<u8>,
     scroll_segments: Vec<ScrollSegment>,
     pending_scroll_descriptor: Option<(usize, usize, usize, usize)>,
 }
@@ -60,11 +94,18 @@ impl PPU {
             oam_data: [0; 64 * 4],
             render_oam_data: [0; 64 * 4],
             palette_table: [0; 32],
+            system_palette: *palette::SYSTEM_PALLETE,
+            backdrop_override: None,
             nmi_interrupt: None,
             cycle: 0,
             scanline: 0,
             frame_count: 0,
+            scanlines: CpuModel::default().scanlines() as i16,
+            skips_odd_frame_dot: CpuModel::default().skips_odd_frame_dot(),
             internal_data_buf: 0,
+            background: BackgroundPipeline::default(),
+            sprites: SpritePipeline::default(),
+            pixels: vec![background::BACKDROP; background::WIDTH * background::HEIGHT],
             scroll_segments: Vec::new(),
             pending_scroll_descriptor: None,
         };
@@ -74,6 +115,18 @@ impl PPU {
         ppu
     }
 
+    /// Switches to the frame timing of the PPU paired with `model`.
+    pub fn set_cpu_model(&mut self, model: CpuModel) {
+        self.scanlines = model.scanlines() as i16;
+        self.skips_odd_frame_dot = model.skips_odd_frame_dot();
+    }
+
+    /// The last line of the frame, before the first visible one, which
+    /// prefetches that line's first two tiles.
+    pub(crate) fn pre_render_line(&self) -> i16 {
+        self.scanlines - 1
+    }
+
     pub fn mirror_vram_addr(&self, mapper: &dyn Mapper, addr: u16) -> u16 {
         let mirrored_vram = addr & 0b10111111111111;
         let vram_index = mirrored_vram - 0x2000;
@@ -90,6 +143,14 @@ impl PPU {
         }
     }
 
+    fn read_nametable(&self, mapper: &dyn Mapper, addr: u16) -> u8 {
+        mapper
+            .ppu_read_nametable(addr, &self.vram)
+            .unwrap_or_else(|| self.vram[self.mirror_vram_addr(mapper, addr) as usize])
+    }
+
+    /// Palette RAM is 32 bytes; $3F10, $3F14, $3F18 and $3F1C are the same
+    /// bytes as $3F00, $3F04, $3F08 and $3F0C.
     fn mirror_palette_addr(addr: u16) -> usize {
         let mut palette_index = (addr - 0x3f00) & 0x1f;
         if palette_index >= 0x10 && (palette_index & 0x03) == 0 {
@@ -98,6 +159,31 @@ impl PPU {
         palette_index as usize
     }
 
+    /// Reads palette RAM without touching the $2007 address or read buffer.
+    /// `addr` may be any address in $3F00-$3FFF, mirrors included.
+    pub fn peek_palette(&self, addr: u16) -> u8 {
+        self.palette_table[PPU::mirror_palette_addr(addr)]
+    }
+
+    /// Writes palette RAM for tools, applying the same mirroring as $2007.
+    pub fn write_palette(&mut self, addr: u16, value: u8) {
+        self.palette_table[PPU::mirror_palette_addr(addr)] = value & 0x3f;
+    }
+
+    /// The palette entry used for backdrop pixels. While rendering is forced
+    /// off and the VRAM address points into palette RAM, the PPU outputs the
+    /// entry at that address instead of $3F00, which some games use to fill
+    /// the screen with an arbitrary colour.
+    pub fn backdrop_color_index(&self) -> u8 {
+        let addr = self.scroll.addr();
+        let rendering = self.mask.show_background() || self.mask.show_sprites();
+        if !rendering && (0x3f00..=0x3fff).contains(&addr) {
+            self.peek_palette(addr)
+        } else {
+            self.palette_table[0]
+        }
+    }
+
     pub fn peek_nametable_byte(&self, mapper: &dyn Mapper, addr: u16) -> u8 {
         mapper
             .peek_nametable(addr, &self.vram)
@@ -142,10 +228,18 @@ impl PPU {
         &self.scroll_segments
     }
 
+    /// OAM as it was when the last frame finished, for sprite viewers.
     pub fn render_oam(&self) -> &[u8; 256] {
         &self.render_oam_data
     }
 
+    /// The frame drawn so far, row by row: a system palette index per
+    /// pixel, with bit 7 set where nothing is opaque and the backdrop
+    /// shows.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
     fn current_scroll_descriptor(&self) -> (usize, usize, usize) {
         (
             self.scroll.scroll_x(),
@@ -246,10 +340,24 @@ impl PPU {
         self.queue_scroll_state_change(base_changed);
     }
 
+    /// Debug write of the scroll position; see
+    /// [`ScrollRegister::set_position`]. The rest of the frame renders with
+    /// it, as after a `$2005`/`$2006` write.
+    pub fn set_scroll_position(&mut self, x: usize, y: usize) {
+        self.scroll.set_position(x, y);
+        self.queue_scroll_state_change(true);
+    }
+
     pub fn write_to_mask(&mut self, value: u8) {
         self.mask.update(value);
     }
 
+    /// $2002 as a read would return it, without clearing vblank or the
+    /// write latch.
+    pub fn peek_status(&self) -> u8 {
+        self.status.snapshot()
+    }
+
     pub fn read_status(&mut self) -> u8 {
         let data = self.status.snapshot();
         self.status.reset_vblank_status();
@@ -267,6 +375,14 @@ impl PPU {
         self.oam_addr = self.oam_addr.wrapping_add(1);
     }
 
+    /// Debug write of OAM entry `index` (0-63), also into
+    /// [`PPU::render_oam`] so viewers of a paused frame show it.
+    pub fn set_oam_entry(&mut self, index: usize, bytes: [u8; 4]) {
+        let start = index * 4;
+        self.oam_data[start..start + 4].copy_from_slice(&bytes);
+        self.render_oam_data[start..start + 4].copy_from_slice(&bytes);
+    }
+
     pub fn read_oam_data(&self) -> u8 {
         self.oam_data[self.oam_addr as usize]
     }
@@ -305,6 +421,23 @@ impl PPU {
         self.increment_vram_addr();
     }
 
+    /// $2007 as a read would return it, without moving the VRAM address or
+    /// refilling the read buffer.
+    pub fn peek_data(&self) -> u8 {
+        let addr = self.scroll.addr();
+        match addr {
+            0x3f00..=0x3fff => self.read_palette(addr),
+            _ => self.internal_data_buf,
+        }
+    }
+
+    // Palette reads through $2007 skip the read buffer and, like the
+    // picture, see grayscale mode.
+    fn read_palette(&self, addr: u16) -> u8 {
+        let grayscale = if self.mask.is_grayscale() { 0x30 } else { 0x3f };
+        self.peek_palette(addr) & grayscale
+    }
+
     pub fn read_data(&mut self, mapper: &mut dyn Mapper) -> u8 {
         let addr = self.scroll.addr();
 
@@ -318,18 +451,14 @@ impl PPU {
             }
             0x2000..=0x3eff => {
                 let result = self.internal_data_buf;
-                let value = mapper
-                    .ppu_read_nametable(addr, &self.vram)
-                    .unwrap_or_else(|| self.vram[self.mirror_vram_addr(mapper, addr) as usize]);
-                self.internal_data_buf = value;
+                self.internal_data_buf = self.read_nametable(mapper, addr);
                 result
             }
             0x3f00..=0x3fff => {
-                let palette_index = PPU::mirror_palette_addr(addr);
-                let mirrored_vram_addr = addr - 0x1000;
-                self.internal_data_buf =
-                    self.vram[self.mirror_vram_addr(mapper, mirrored_vram_addr) as usize];
-                self.palette_table[palette_index]
+                // The buffer is filled from the nametable "under" the
+                // palette.
+                self.internal_data_buf = self.read_nametable(mapper, addr - 0x1000);
+                self.read_palette(addr)
             }
             _ => panic!("unexpected access to mirrored space {}", addr),
         }
@@ -344,12 +473,21 @@ impl PPU {
 
     pub fn clock(&mut self, mapper: &mut dyn Mapper) -> bool {
         self.cycle += 1;
+        if self.cycle < 341 {
+            self.render_dot(mapper);
+        }
 
-        if self.cycle >= 341 {
-            if self.is_sprite_zero_hit(self.cycle as usize) {
-                self.status.set_sprite_zero_hit(true);
-            }
+        // Odd frames that render end the pre-render line a dot early.
+        if self.cycle == 340
+            && self.skips_odd_frame_dot
+            && self.scanline == self.pre_render_line()
+            && self.frame_count % 2 == 1
+            && (self.mask.show_background() || self.mask.show_sprites())
+        {
+            self.cycle = 341;
+        }
 
+        if self.cycle >= 341 {
             self.cycle -= 341;
 
             if self.scanline < 240 {
@@ -362,17 +500,17 @@ impl PPU {
             if self.scanline == 241 {
                 self.render_oam_data.copy_from_slice(&self.oam_data);
                 self.status.set_vblank_status(true);
-                self.status.set_sprite_zero_hit(false);
                 if self.ctrl.generate_vblank_nmi() {
                     self.nmi_interrupt = Some(1);
                 }
             }
 
-            if self.scanline >= 262 {
+            if self.scanline >= self.scanlines {
                 self.scanline = 0;
                 self.cycle = 0;
                 self.nmi_interrupt = None;
                 self.status.set_sprite_zero_hit(false);
+                self.status.set_sprite_overflow(false);
                 self.status.reset_vblank_status();
                 self.frame_count = self.frame_count.wrapping_add(1);
                 return true;
@@ -381,14 +519,14 @@ impl PPU {
         false
     }
 
-    pub fn poll_nmi_interrupt(&mut self) -> Option<u8> {
-        self.nmi_interrupt.take()
+    /// True on the dot vertical blank begins (scanline 241), whether or not
+    /// NMI generation is enabled.
+    pub fn vblank_started(&self) -> bool {
+        self.scanline == 241 && self.cycle == 0
     }
 
-    fn is_sprite_zero_hit(&self, cycle: usize) -> bool {
-        let y = self.oam_data[0] as usize;
-        let x = self.oam_data[3] as usize;
-        (y == self.scanline as usize) && x <= cycle && self.mask.show_sprites()
+    pub fn poll_nmi_interrupt(&mut self) -> Option<u8> {
+        self.nmi_interrupt.take()
     }
 }
 
@@ -629,6 +767,36 @@ pub mod test {
         assert_eq!(ppu.palette_table[0x0c], 0x3d & 0x3f);
     }
 
+    #[test]
+    fn test_palette_debug_access_mirrors() {
+        let mut ppu = PPU::empty();
+        ppu.write_palette(0x3f10, 0x2a);
+        assert_eq!(ppu.peek_palette(0x3f00), 0x2a);
+        assert_eq!(ppu.peek_palette(0x3fe0), 0x2a);
+
+        ppu.write_palette(0x3f25, 0xff);
+        assert_eq!(ppu.peek_palette(0x3f05), 0x3f);
+    }
+
+    #[test]
+    fn test_forced_blank_backdrop_follows_vram_address() {
+        let mut ppu = PPU::empty();
+        ppu.write_palette(0x3f00, 0x0f);
+        ppu.write_palette(0x3f07, 0x16);
+
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x07);
+        assert_eq!(ppu.backdrop_color_index(), 0x16);
+
+        ppu.write_to_mask(0b0000_1000);
+        assert_eq!(ppu.backdrop_color_index(), 0x0f);
+
+        ppu.write_to_mask(0);
+        ppu.write_to_ppu_addr(0x20);
+        ppu.write_to_ppu_addr(0x00);
+        assert_eq!(ppu.backdrop_color_index(), 0x0f);
+    }
+
     #[test]
     fn test_palette_reads_update_buffer() {
         let mut mapper = NromMapper::new(vec![], vec![0; 2048], Mirroring::Horizontal);
@@ -649,6 +817,27 @@ pub mod test {
         assert_eq!(ppu.internal_data_buf, 0xaa);
     }
 
+    #[test]
+    fn test_palette_reads_are_direct_and_grayscale() {
+        let mut mapper = NromMapper::new(vec![], vec![0; 2048], Mirroring::Horizontal);
+        let mut ppu = PPU::empty();
+        ppu.write_palette(0x3f04, 0x16);
+        ppu.write_palette(0x3f18, 0x2a);
+
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x04);
+        assert_eq!(ppu.read_data(&mut mapper), 0x16);
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x08);
+        assert_eq!(ppu.read_data(&mut mapper), 0x2a);
+
+        ppu.write_to_mask(0x01);
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x04);
+        assert_eq!(ppu.peek_data(), 0x10);
+        assert_eq!(ppu.read_data(&mut mapper), 0x10);
+    }
+
     #[test]
     fn test_read_status_resets_vblank() {
         let mut ppu = PPU::empty();
@@ -694,4 +883,125 @@ pub mod test {
         ppu.write_to_oam_addr(0x11);
         assert_eq!(ppu.read_oam_data(), 0x66);
     }
+
+    #[test]
+    fn test_background_fine_x_scroll() {
+        // Tile 1 is solid colour 1; only the top-left tile uses it.
+        let mut chr = vec![0; 0x2000];
+        chr[16..24].fill(0xFF);
+        let mut mapper = NromMapper::new(vec![0; 0x8000], chr, Mirroring::Horizontal);
+        let mut ppu = PPU::empty();
+        ppu.write_to_ppu_addr(0x20);
+        ppu.write_to_ppu_addr(0x00);
+        ppu.write_to_data(&mut mapper, 0x01);
+        ppu.write_palette(0x3F00, 0x0F);
+        ppu.write_palette(0x3F01, 0x16);
+        ppu.write_to_scroll(3);
+        ppu.write_to_scroll(0);
+        ppu.write_to_mask(0x0A);
+
+        ppu.scanline = ppu.pre_render_line();
+        ppu.cycle = 0;
+        while ppu.scanline != 1 {
+            ppu.clock(&mut mapper);
+        }
+
+        let line = &ppu.pixels()[..8];
+        assert_eq!(line[..5], [0x16; 5]);
+        assert_eq!(line[5..], [0x0F | background::BACKDROP; 3]);
+    }
+
+    // Clocks to the end of the current frame and returns the dots it took.
+    fn frame_dots(ppu: &mut PPU, mapper: &mut dyn Mapper) -> u32 {
+        let mut dots = 1;
+        while !ppu.clock(mapper) {
+            dots += 1;
+        }
+        dots
+    }
+
+    #[test]
+    fn test_odd_frames_skip_a_dot_while_rendering() {
+        let mut mapper = NromMapper::new(vec![0; 0x4000], vec![0; 0x2000], Mirroring::Horizontal);
+        let mut ppu = PPU::new();
+        frame_dots(&mut ppu, &mut mapper);
+        assert_eq!(frame_dots(&mut ppu, &mut mapper), 341 * 262);
+        assert_eq!(frame_dots(&mut ppu, &mut mapper), 341 * 262);
+
+        ppu.write_to_mask(0x08);
+        let pair = [
+            frame_dots(&mut ppu, &mut mapper),
+            frame_dots(&mut ppu, &mut mapper),
+        ];
+        assert!(pair == [341 * 262, 341 * 262 - 1] || pair == [341 * 262 - 1, 341 * 262]);
+
+        ppu.set_cpu_model(CpuModel::Rp2A07);
+        frame_dots(&mut ppu, &mut mapper);
+        assert_eq!(frame_dots(&mut ppu, &mut mapper), 341 * 312);
+        assert_eq!(frame_dots(&mut ppu, &mut mapper), 341 * 312);
+    }
+
+    // Runs the pre-render line and visible lines up to `until`.
+    fn render_lines(ppu: &mut PPU, mapper: &mut dyn Mapper, until: i16) {
+        ppu.scanline = ppu.pre_render_line();
+        ppu.cycle = 0;
+        while ppu.scanline != until {
+            ppu.clock(mapper);
+        }
+    }
+
+    #[test]
+    fn test_8x16_sprite_uses_pattern_table_from_tile() {
+        // Tile 3 in the $1000 table, the bottom half of sprite tile 3, is
+        // solid colour 1.
+        let mut chr = vec![0; 0x2000];
+        chr[0x1030..0x1038].fill(0xFF);
+        let mut mapper = NromMapper::new(vec![0; 0x8000], chr, Mirroring::Horizontal);
+        let mut ppu = PPU::empty();
+        ppu.write_palette(0x3F11, 0x21);
+        ppu.set_oam_entry(0, [0, 3, 0, 16]);
+        ppu.write_to_ctrl(0x20);
+        ppu.write_to_mask(0x1E);
+
+        render_lines(&mut ppu, &mut mapper, 10);
+
+        let row = |y: usize| &ppu.pixels()[y * 256 + 16..y * 256 + 24];
+        assert!(row(1).iter().all(|&p| p & background::BACKDROP != 0));
+        assert_eq!(row(9), [0x21; 8]);
+    }
+
+    #[test]
+    fn test_sprite_zero_hit_and_overflow() {
+        // Tile 1 is solid colour 1, in the top-left of the background.
+        let mut chr = vec![0; 0x2000];
+        chr[16..24].fill(0xFF);
+        let mut mapper = NromMapper::new(vec![0; 0x8000], chr, Mirroring::Horizontal);
+        let mut ppu = PPU::empty();
+        ppu.write_to_ppu_addr(0x20);
+        ppu.write_to_ppu_addr(0x00);
+        ppu.write_to_data(&mut mapper, 0x01);
+        ppu.write_to_ppu_addr(0x00);
+        ppu.write_to_ppu_addr(0x00);
+        ppu.write_palette(0x3F01, 0x16);
+        ppu.write_palette(0x3F11, 0x21);
+        // Nine sprites on line 1, the ninth at x = 200; sprite 0 is behind
+        // the background.
+        for i in 0..9 {
+            ppu.set_oam_entry(i, [0, 1, if i == 0 { 0x20 } else { 0 }, i as u8 * 25]);
+        }
+        ppu.write_to_mask(0x1E);
+
+        render_lines(&mut ppu, &mut mapper, 1);
+        assert!(!ppu.status.contains(StatusRegister::SPRITE_ZERO_HIT));
+        assert!(ppu.status.contains(StatusRegister::SPRITE_OVERFLOW));
+        while ppu.scanline != 2 {
+            ppu.clock(&mut mapper);
+        }
+
+        let line = &ppu.pixels()[256..512];
+        assert!(ppu.status.contains(StatusRegister::SPRITE_ZERO_HIT));
+        assert_eq!(line[0], 0x16);
+        assert_eq!(line[25], 0x21);
+        assert_ne!(line[200], 0x21);
+    }
 }
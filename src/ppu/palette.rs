@@ -1,7 +1,21 @@
 use std::sync::LazyLock;
 
-pub static SYSTEM_PALLETE: LazyLock<[(u8, u8, u8); 64]> = LazyLock::new(|| {
+pub type Palette = [(u8, u8, u8); 64];
+
+pub static SYSTEM_PALLETE: LazyLock<Palette> = LazyLock::new(|| {
     let bytes = include_bytes!("../../palettes/Composite Direct (FBX).pal");
+    parse_pal(bytes).unwrap()
+});
+
+/// Parses a `.pal` file: 64 RGB triplets. Longer files (e.g. with emphasis
+/// variants appended) contribute only their first 64 colours.
+pub fn parse_pal(bytes: &[u8]) -> Result<Palette, String> {
+    if bytes.len() < 64 * 3 {
+        return Err(format!(
+            "Palette file too short: {} bytes, expected at least 192",
+            bytes.len()
+        ));
+    }
 
     let colors: Vec<(u8, u8, u8)> = bytes
         .chunks(3)
@@ -9,5 +23,124 @@ pub static SYSTEM_PALLETE: LazyLock<[(u8, u8, u8); 64]> = LazyLock::new(|| {
         .map(|rgb| (rgb[0], rgb[1], rgb[2]))
         .collect();
 
-    colors.try_into().unwrap()
-});
+    Ok(colors.try_into().unwrap())
+}
+
+/// Knobs for [`generate_ntsc`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NtscSettings {
+    /// Degrees to rotate every hue by, as a TV's tint control would.
+    pub hue: f32,
+    /// Colour multiplier; 0 is grayscale, 1 the console's own signal.
+    pub saturation: f32,
+}
+
+impl Default for NtscSettings {
+    fn default() -> Self {
+        NtscSettings {
+            hue: 0.0,
+            saturation: 1.0,
+        }
+    }
+}
+
+// Composite signal voltages of the four luma levels, for the low and high
+// halves of a colour's square wave, relative to black (0) and white (1).
+const SIGNAL_BLACK: f32 = 0.518;
+const SIGNAL_WHITE: f32 = 1.962;
+const SIGNAL_LOW: [f32; 4] = [0.350, 0.518, 0.962, 1.550];
+const SIGNAL_HIGH: [f32; 4] = [1.094, 1.506, 1.962, 1.962];
+
+/// Chroma is scaled to match a TV at its default colour setting.
+pub(crate) const CHROMA_SCALE: f32 = 1.0 / 8.0;
+
+/// The composite signal of colour `index` at `phase` (0-11) of the colour
+/// subcarrier, 0 at black and 1 at white. Each colour is a square wave,
+/// high for the 6 phases starting at its hue.
+pub(crate) fn signal(index: u8, phase: usize) -> f32 {
+    let color = (index & 0x0F) as usize;
+    // Columns $E and $F are black at every level.
+    let level = if color > 0x0D {
+        1
+    } else {
+        (index as usize >> 4) & 0x03
+    };
+    let mut low = SIGNAL_LOW[level];
+    let mut high = SIGNAL_HIGH[level];
+    if color == 0x00 {
+        low = high;
+    }
+    if color >= 0x0D {
+        high = low;
+    }
+    let level = if (color + phase) % 12 < 6 { high } else { low };
+    (level - SIGNAL_BLACK) / (SIGNAL_WHITE - SIGNAL_BLACK)
+}
+
+/// The subcarrier at `phase`, as the (I, Q) weights a TV demodulates with,
+/// rotated by `hue` degrees.
+pub(crate) fn carrier(phase: usize, hue: f32) -> (f32, f32) {
+    let angle = std::f32::consts::PI * (phase as f32 + 4.0) / 6.0 + hue.to_radians();
+    (angle.cos(), angle.sin())
+}
+
+pub(crate) fn yiq_to_rgb(y: f32, i: f32, q: f32) -> (u8, u8, u8) {
+    let channel = |v: f32| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+    (
+        channel(y + 0.956 * i + 0.621 * q),
+        channel(y - 0.272 * i - 0.647 * q),
+        channel(y - 1.106 * i + 1.703 * q),
+    )
+}
+
+/// Decodes the PPU's composite signal for each colour the way a TV would:
+/// over one cycle of the colour subcarrier, the signal's average is its
+/// brightness and its phase and swing are its hue and saturation.
+pub fn generate_ntsc(settings: NtscSettings) -> Palette {
+    let mut palette = [(0, 0, 0); 64];
+    for (index, rgb) in palette.iter_mut().enumerate() {
+        let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+        for phase in 0..12 {
+            let signal = signal(index as u8, phase);
+            let (cos, sin) = carrier(phase, settings.hue);
+            y += signal;
+            i += signal * cos;
+            q += signal * sin;
+        }
+        let chroma = settings.saturation * CHROMA_SCALE;
+        *rgb = yiq_to_rgb(y / 12.0, i * chroma, q * chroma);
+    }
+    palette
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_generate_ntsc() {
+        let palette = generate_ntsc(NtscSettings::default());
+        assert_eq!(palette[0x0F], (0, 0, 0));
+        assert_eq!(palette[0x30], (255, 255, 255));
+        let (r, g, b) = palette[0x00];
+        assert!(r == g && g == b);
+        // $16 is red, $12 blue, $1A green.
+        let (r, g, b) = palette[0x16];
+        assert!(r > g && r > b);
+        let (r, g, b) = palette[0x12];
+        assert!(b > r && b > g);
+        let (r, g, b) = palette[0x1A];
+        assert!(g > r && g > b);
+
+        let gray = generate_ntsc(NtscSettings {
+            saturation: 0.0,
+            ..Default::default()
+        });
+        assert!(gray.iter().all(|&(r, g, b)| r == g && g == b));
+        let rotated = generate_ntsc(NtscSettings {
+            hue: 30.0,
+            ..Default::default()
+        });
+        assert_ne!(rotated[0x16], palette[0x16]);
+    }
+}
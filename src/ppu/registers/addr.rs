@@ -1,3 +1,4 @@
+#[derive(Clone)]
 pub struct AddrRegister {
     value: (u8, u8),
     hi_ptr: bool,
@@ -84,6 +84,17 @@ impl ScrollRegister {
         (coarse_y << 3) | fine_y
     }
 
+    /// Points both `t` and `v` at pixel (`x`, `y`) of the four-nametable
+    /// plane, 512x480, and sets fine X to match.
+    pub fn set_position(&mut self, x: usize, y: usize) {
+        let (x, y) = (x % 512, y % 480);
+        let nametable = (x / 256) | ((y / 240) << 1);
+        let (x, y) = (x % 256, y % 240);
+        self.t = ((x >> 3) | ((y >> 3) << 5) | (nametable << 10) | ((y & 7) << 12)) as u16;
+        self.v = self.t;
+        self.x = (x & 7) as u8;
+    }
+
     pub fn base_nametable(&self) -> usize {
         ((self.t >> 10) & 0x03) as usize
     }
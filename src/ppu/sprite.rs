@@ -0,0 +1,48 @@
+//! Decoded OAM entries for sprite viewers and editors. Each of the 64
+//! sprites takes four bytes: Y (one less than the first line drawn), tile,
+//! attributes, X.
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Sprite {
+    pub x: u8,
+    /// As stored in OAM: the sprite is drawn from line `y + 1`.
+    pub y: u8,
+    /// Tile index; in 8x16 mode bit 0 picks the pattern table.
+    pub tile: u8,
+    /// Sprite palette 0-3.
+    pub palette: u8,
+    pub behind_background: bool,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+}
+
+impl Sprite {
+    pub fn from_bytes(bytes: [u8; 4]) -> Self {
+        let [y, tile, attributes, x] = bytes;
+        Sprite {
+            x,
+            y,
+            tile,
+            palette: attributes & 0b11,
+            behind_background: attributes & 0x20 != 0,
+            flip_horizontal: attributes & 0x40 != 0,
+            flip_vertical: attributes & 0x80 != 0,
+        }
+    }
+
+    /// The OAM bytes. Attribute bits 2-4 don't exist in OAM and are zero.
+    pub fn to_bytes(&self) -> [u8; 4] {
+        let attributes = (self.palette & 0b11)
+            | (self.behind_background as u8) << 5
+            | (self.flip_horizontal as u8) << 6
+            | (self.flip_vertical as u8) << 7;
+        [self.y, self.tile, attributes, self.x]
+    }
+}
+
+/// All 64 sprites in `oam`, in OAM order.
+pub fn decode_oam(oam: &[u8; 256]) -> Vec<Sprite> {
+    oam.chunks_exact(4)
+        .map(|entry| Sprite::from_bytes([entry[0], entry[1], entry[2], entry[3]]))
+        .collect()
+}
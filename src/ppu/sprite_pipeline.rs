@@ -0,0 +1,168 @@
+//! Sprite evaluation and the eight sprite units. On each visible line the
+//! PPU clears secondary OAM (dots 1-64), then scans OAM for up to eight
+//! sprites that cover the next line (dots 65-256), setting the overflow
+//! flag with the hardware's buggy search once a ninth turns up. Dots
+//! 257-320 fetch the pattern bytes of the sprites found into the eight
+//! units, which output their pixels on the next line. No sprites are
+//! evaluated on the pre-render line, so none show on line 0.
+
+use crate::mapper::{ChrSource, Mapper};
+use crate::ppu::PPU;
+
+#[derive(Clone, Copy, Default)]
+struct SpriteUnit {
+    x: u8,
+    attributes: u8,
+    // Already flipped horizontally if the sprite is, so bit 7 is the
+    // leftmost pixel.
+    pattern_lo: u8,
+    pattern_hi: u8,
+}
+
+#[derive(Clone)]
+pub(crate) struct SpritePipeline {
+    secondary_oam: [u8; 32],
+    /// Sprites in secondary OAM.
+    found: usize,
+    /// Whether the first sprite in secondary OAM is OAM entry 0.
+    zero_found: bool,
+    units: [SpriteUnit; 8],
+    /// Whether unit 0 holds OAM entry 0, for sprite-zero hits.
+    zero_loaded: bool,
+}
+
+impl Default for SpritePipeline {
+    fn default() -> Self {
+        SpritePipeline {
+            secondary_oam: [0xFF; 32],
+            found: 0,
+            zero_found: false,
+            units: [SpriteUnit::default(); 8],
+            zero_loaded: false,
+        }
+    }
+}
+
+/// A sprite pixel that isn't transparent.
+pub(crate) struct SpritePixel {
+    /// Pattern value, 1-3.
+    pub value: u8,
+    /// Sprite palette, 0-3.
+    pub palette: u8,
+    pub behind_background: bool,
+    pub sprite_zero: bool,
+}
+
+impl PPU {
+    /// Runs sprite evaluation and fetches for the current dot. Only called
+    /// while rendering is enabled, on visible and pre-render lines.
+    pub(crate) fn sprite_dot(&mut self, mapper: &mut dyn Mapper, visible: bool, dot: usize) {
+        match dot {
+            1 => {
+                self.sprites.secondary_oam = [0xFF; 32];
+                self.sprites.found = 0;
+                self.sprites.zero_found = false;
+            }
+            // Evaluation runs over dots 65-256; it is done at once here, at
+            // the end.
+            256 if visible => self.evaluate_sprites(),
+            257 => self.sprites.zero_loaded = self.sprites.zero_found,
+            // Each unit takes 8 dots to fetch, loaded at the last of them.
+            264..=320 if dot.is_multiple_of(8) => self.fetch_sprite(mapper, (dot - 264) / 8),
+            _ => {}
+        }
+    }
+
+    fn sprite_height(&self) -> i16 {
+        self.ctrl.sprite_size() as i16
+    }
+
+    fn evaluate_sprites(&mut self) {
+        let line = self.scanline;
+        let height = self.sprite_height();
+        let in_range = |y: u8| (0..height).contains(&(line - y as i16));
+
+        let mut n = 0;
+        while n < 64 && self.sprites.found < 8 {
+            let entry = &self.oam_data[n * 4..n * 4 + 4];
+            if in_range(entry[0]) {
+                let slot = self.sprites.found * 4;
+                self.sprites.secondary_oam[slot..slot + 4].copy_from_slice(entry);
+                self.sprites.zero_found |= n == 0;
+                self.sprites.found += 1;
+            }
+            n += 1;
+        }
+
+        // With secondary OAM full the PPU keeps looking for a ninth sprite,
+        // but steps the byte it reads as Y along with the entry when one
+        // misses, so it reads tiles, attributes and X as Y instead.
+        let mut m = 0;
+        while n < 64 {
+            if in_range(self.oam_data[n * 4 + m]) {
+                self.status.set_sprite_overflow(true);
+                break;
+            }
+            n += 1;
+            m = (m + 1) % 4;
+        }
+    }
+
+    fn fetch_sprite(&mut self, mapper: &mut dyn Mapper, slot: usize) {
+        if slot >= self.sprites.found {
+            // Empty slots get a transparent pattern.
+            self.sprites.units[slot] = SpriteUnit::default();
+            return;
+        }
+
+        let [y, tile, attributes, x] = {
+            let bytes = &self.sprites.secondary_oam[slot * 4..slot * 4 + 4];
+            [bytes[0], bytes[1], bytes[2], bytes[3]]
+        };
+        let height = self.sprite_height();
+        let mut row = (self.scanline - y as i16) as u16;
+        if attributes & 0x80 != 0 {
+            row = height as u16 - 1 - row;
+        }
+        let addr = if height == 16 {
+            // 8x16 sprites pick their pattern table with bit 0 of the tile
+            // and cover tiles `tile & 0xFE` and the one after it.
+            let bank = (tile as u16 & 0x01) * 0x1000;
+            let tile = (tile as u16 & 0xFE) + row / 8;
+            bank + tile * 16 + row % 8
+        } else {
+            self.ctrl.sprt_pattern_addr() + tile as u16 * 16 + row
+        };
+
+        let mut pattern_lo = mapper.read_chr(addr, ChrSource::Sprite);
+        let mut pattern_hi = mapper.read_chr(addr + 8, ChrSource::Sprite);
+        if attributes & 0x40 != 0 {
+            pattern_lo = pattern_lo.reverse_bits();
+            pattern_hi = pattern_hi.reverse_bits();
+        }
+        self.sprites.units[slot] = SpriteUnit {
+            x,
+            attributes,
+            pattern_lo,
+            pattern_hi,
+        };
+    }
+
+    /// The frontmost opaque sprite pixel at `x` on this line, if any.
+    pub(crate) fn sprite_pixel(&self, x: usize) -> Option<SpritePixel> {
+        if !self.mask.show_sprites() || (x < 8 && !self.mask.leftmost_8pxl_sprite()) {
+            return None;
+        }
+        self.sprites.units.iter().enumerate().find_map(|(i, unit)| {
+            let column = x.checked_sub(unit.x as usize).filter(|&c| c < 8)?;
+            let bit = 7 - column;
+            let value = ((unit.pattern_hi >> bit) & 1) << 1 | ((unit.pattern_lo >> bit) & 1);
+            (value != 0).then_some(SpritePixel {
+                value,
+                palette: unit.attributes & 0b11,
+                behind_background: unit.attributes & 0x20 != 0,
+                sprite_zero: i == 0 && self.sprites.zero_loaded,
+            })
+        })
+    }
+}
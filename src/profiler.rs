@@ -0,0 +1,185 @@
+//! Cycle profiler for finding hot code in 6502 programs. Every instruction's
+//! cycles are added to its address and to the routine it runs in, i.e. the
+//! most recent JSR target or interrupt handler (BRK included) not yet
+//! returned from.
+
+use std::collections::HashMap;
+
+use crate::cpu::InterruptType;
+
+/// Nested calls tracked before the oldest are forgotten, for code that
+/// leaves the stack with something other than RTS/RTI.
+const MAX_CALL_DEPTH: usize = 256;
+
+const BRK: u8 = 0x00;
+const JSR: u8 = 0x20;
+const RTI: u8 = 0x40;
+const RTS: u8 = 0x60;
+
+/// An address in a specific PRG bank, where the cartridge reports one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CodeAddr {
+    pub bank: Option<usize>,
+    pub addr: u16,
+}
+
+impl std::fmt::Display for CodeAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.bank {
+            Some(bank) => write!(f, "{:02X}:{:04X}", bank, self.addr),
+            None => write!(f, "${:04X}", self.addr),
+        }
+    }
+}
+
+/// How [`Profiler::report`] groups cycles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileView {
+    /// Per instruction address.
+    Address,
+    /// Per JSR target or interrupt handler, not counting the routines it
+    /// calls. Code run outside any call is listed as `(top level)`.
+    Routine,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    by_address: HashMap<CodeAddr, u64>,
+    by_routine: HashMap<Option<CodeAddr>, u64>,
+    call_stack: Vec<CodeAddr>,
+    total: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records one CPU step: `at` is where it started, `opcode` the byte
+    /// there, and `next` the address the CPU continues at.
+    pub(crate) fn record(
+        &mut self,
+        at: CodeAddr,
+        opcode: u8,
+        cycles: u8,
+        interrupt: Option<InterruptType>,
+        next: CodeAddr,
+    ) {
+        let cycles = cycles as u64;
+        self.total += cycles;
+        if interrupt.is_some() {
+            self.call(next);
+            *self.by_routine.entry(Some(next)).or_default() += cycles;
+            return;
+        }
+
+        *self.by_address.entry(at).or_default() += cycles;
+        *self
+            .by_routine
+            .entry(self.call_stack.last().copied())
+            .or_default() += cycles;
+        match opcode {
+            JSR | BRK => self.call(next),
+            RTS | RTI => {
+                self.call_stack.pop();
+            }
+            _ => {}
+        }
+    }
+
+    fn call(&mut self, target: CodeAddr) {
+        if self.call_stack.len() == MAX_CALL_DEPTH {
+            self.call_stack.remove(0);
+        }
+        self.call_stack.push(target);
+    }
+
+    /// Cycles recorded since the profiler was created or cleared.
+    pub fn total_cycles(&self) -> u64 {
+        self.total
+    }
+
+    /// Entries with their cycles, highest first.
+    pub fn entries(&self, view: ProfileView) -> Vec<(Option<CodeAddr>, u64)> {
+        let mut entries: Vec<(Option<CodeAddr>, u64)> = match view {
+            ProfileView::Address => self
+                .by_address
+                .iter()
+                .map(|(&addr, &cycles)| (Some(addr), cycles))
+                .collect(),
+            ProfileView::Routine => self
+                .by_routine
+                .iter()
+                .map(|(&addr, &cycles)| (addr, cycles))
+                .collect(),
+        };
+        entries.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        entries
+    }
+
+    /// The `limit` hottest entries as a text table.
+    pub fn report(&self, view: ProfileView, limit: usize) -> String {
+        let mut out = format!("{:>12} {:>6}  {}\n", "cycles", "%", "address");
+        for (addr, cycles) in self.entries(view).into_iter().take(limit) {
+            let share = cycles as f64 * 100.0 / self.total.max(1) as f64;
+            let name = match addr {
+                Some(addr) => addr.to_string(),
+                None => "(top level)".to_string(),
+            };
+            out += &format!("{:>12} {:>5.1}%  {}\n", cycles, share, name);
+        }
+        out
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::cart::test::test_nes;
+
+    #[test]
+    fn test_cycles_by_address_and_routine() {
+        let program = assemble(
+            "
+                    .org $8000
+            loop:   jsr routine
+                    jmp loop
+                    .org $8010
+            routine: inc $00
+                    rts
+                    .org $FFFC
+                    .word loop
+            ",
+        )
+        .unwrap();
+        let mut nes = test_nes(&program.slice(0x8000, 0x8000));
+        nes.enable_profiler();
+        for _ in 0..4 * 100 {
+            nes.clock();
+        }
+
+        let profiler = nes.profiler().unwrap();
+        // JSR 6 + JMP 3 at the top level, INC 5 + RTS 6 in the routine.
+        assert_eq!(profiler.total_cycles(), 20 * 100);
+        let routine = Some(CodeAddr {
+            bank: Some(0),
+            addr: 0x8010,
+        });
+        assert_eq!(
+            profiler.entries(ProfileView::Routine),
+            [(routine, 1100), (None, 900)]
+        );
+        let hottest = profiler.entries(ProfileView::Address)[0];
+        assert_eq!(hottest.0.unwrap().addr, 0x8000);
+        assert_eq!(hottest.1, 600);
+
+        let report = profiler.report(ProfileView::Routine, 10);
+        assert!(report.contains("1100  55.0%  00:8010\n"));
+        assert!(report.contains("(top level)"));
+    }
+}
@@ -0,0 +1,124 @@
+//! Instruction history for stepping the CPU backwards in a debugger.
+//!
+//! While enabled, the bus journals each instruction (or interrupt entry):
+//! the CPU registers before it and the old value of every internal or
+//! cartridge RAM byte it wrote. Stepping back undoes the newest entry. The
+//! PPU, APU and mapper registers are not journaled and stay where they are,
+//! so this is for inspecting how the CPU got somewhere, not for resuming
+//! from an earlier point; use [`crate::nes::Nes::clone_state`] for that.
+
+use std::collections::VecDeque;
+
+use crate::cpu::Registers;
+
+struct Step {
+    registers: Registers,
+    /// Old values, in the order the instruction wrote them.
+    writes: Vec<(u16, u8)>,
+}
+
+pub(crate) struct History {
+    steps: VecDeque<Step>,
+    capacity: usize,
+    current: Option<Step>,
+}
+
+impl History {
+    pub(crate) fn new(capacity: usize) -> Self {
+        History {
+            steps: VecDeque::with_capacity(capacity),
+            capacity,
+            current: None,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.steps.clear();
+        self.current = None;
+    }
+
+    pub(crate) fn begin(&mut self, registers: &Registers) {
+        self.current = Some(Step {
+            registers: registers.clone(),
+            writes: Vec::new(),
+        });
+    }
+
+    /// Records the value `addr` held before a write, if it is RAM.
+    pub(crate) fn record_write(&mut self, addr: u16, old: u8) {
+        if let Some(step) = &mut self.current
+            && matches!(addr, 0x0000..=0x1FFF | 0x6000..=0x7FFF)
+        {
+            step.writes.push((addr, old));
+        }
+    }
+
+    /// Ends the current step, keeping it only if the CPU did something.
+    pub(crate) fn commit(&mut self, ran: bool) {
+        let Some(step) = self.current.take() else {
+            return;
+        };
+        if !ran || self.capacity == 0 {
+            return;
+        }
+        if self.steps.len() == self.capacity {
+            self.steps.pop_front();
+        }
+        self.steps.push_back(step);
+    }
+
+    /// Takes the newest step: the registers before it and the old values
+    /// it overwrote, in write order.
+    pub(crate) fn pop(&mut self) -> Option<(Registers, Vec<(u16, u8)>)> {
+        let step = self.steps.pop_back()?;
+        Some((step.registers, step.writes))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::asm::assemble;
+    use crate::cart::test::test_nes;
+
+    #[test]
+    fn test_step_back_undoes_registers_and_ram() {
+        let program = assemble(
+            "
+                    .org $8000
+            reset:  ldx #3
+            loop:   stx $10
+                    txa
+                    pha
+                    dex
+                    bne loop
+            done:   jmp done
+                    .org $FFFC
+                    .word reset, reset
+            ",
+        )
+        .unwrap();
+        let mut nes = test_nes(&program.slice(0x8000, 0x8000));
+        nes.enable_reverse_step(8);
+
+        let mut trail = Vec::new();
+        for _ in 0..16 {
+            let registers = nes.bus.cpu.registers.clone();
+            let ram = (nes.bus.peek(0x10), nes.bus.peek(0x01FB));
+            trail.push((registers.pc, registers.x, registers.sp, ram));
+            nes.clock();
+        }
+        assert_eq!(nes.reverse_history_len(), 8);
+
+        for expected in trail.iter().rev().take(8) {
+            assert!(nes.step_back());
+            let registers = &nes.bus.cpu.registers;
+            let ram = (nes.bus.peek(0x10), nes.bus.peek(0x01FB));
+            assert_eq!((registers.pc, registers.x, registers.sp, ram), *expected);
+        }
+        assert!(!nes.step_back());
+    }
+}
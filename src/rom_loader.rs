@@ -0,0 +1,139 @@
+//! Builds a cartridge from a ROM that arrives in pieces, for frontends that
+//! fetch it over a slow link or unpack it from a larger file and want to show
+//! progress and stay responsive meanwhile. The header is checked as soon as
+//! it is in, so a file that isn't a ROM fails before the rest is read.
+
+use std::io::Read;
+
+use crate::cart::{Cart, RomHeader};
+
+/// Bytes read per step by [`load_from_reader`].
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadProgress {
+    pub loaded: usize,
+    /// The size the header calls for, once the header is in.
+    pub total: Option<usize>,
+}
+
+impl LoadProgress {
+    /// How much of the ROM is in, from 0 to 1, once the size is known.
+    pub fn fraction(&self) -> Option<f32> {
+        self.total
+            .map(|total| (self.loaded as f32 / total.max(1) as f32).min(1.0))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RomLoader {
+    data: Vec<u8>,
+    header: Option<RomHeader>,
+}
+
+impl RomLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends the next piece of the file. Fails once the header is in if
+    /// it isn't a ROM pico can run.
+    pub fn push(&mut self, chunk: &[u8]) -> Result<LoadProgress, String> {
+        self.data.extend_from_slice(chunk);
+        if self.header.is_none() && self.data.len() >= RomHeader::SIZE {
+            let header = RomHeader::parse(&self.data)?;
+            self.data
+                .reserve(header.expected_len().saturating_sub(self.data.len()));
+            self.header = Some(header);
+        }
+        Ok(self.progress())
+    }
+
+    pub fn progress(&self) -> LoadProgress {
+        LoadProgress {
+            loaded: self.data.len(),
+            total: self.header.as_ref().map(RomHeader::expected_len),
+        }
+    }
+
+    /// Whether everything the header calls for is in.
+    pub fn is_complete(&self) -> bool {
+        self.header
+            .as_ref()
+            .is_some_and(|header| self.data.len() >= header.expected_len())
+    }
+
+    /// The file so far, e.g. for a checksum once complete.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn finish(self) -> Result<Cart, String> {
+        Ok(Cart::new(&self.data)?)
+    }
+}
+
+/// Reads a ROM from `reader` a chunk at a time, calling `progress` after
+/// each, and returns the file's bytes.
+pub fn load_from_reader(
+    mut reader: impl Read,
+    mut progress: impl FnMut(LoadProgress),
+) -> Result<Vec<u8>, String> {
+    let mut loader = RomLoader::new();
+    let mut chunk = vec![0; CHUNK_SIZE];
+    loop {
+        let read = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(format!("Failed to read ROM: {}", e)),
+        };
+        progress(loader.push(&chunk[..read])?);
+    }
+    Ok(loader.data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::demo;
+
+    #[test]
+    fn test_loads_in_pieces() {
+        let rom = demo::hello_world_rom();
+        let mut loader = RomLoader::new();
+        assert_eq!(
+            loader.push(&rom[..10]).unwrap(),
+            LoadProgress {
+                loaded: 10,
+                total: None
+            }
+        );
+        let progress = loader.push(&rom[10..100]).unwrap();
+        assert_eq!(progress.total, Some(rom.len()));
+        assert!(!loader.is_complete());
+        for chunk in rom[100..].chunks(1000) {
+            loader.push(chunk).unwrap();
+        }
+        assert!(loader.is_complete());
+        assert_eq!(loader.progress().fraction(), Some(1.0));
+        assert!(loader.finish().is_ok());
+
+        let mut calls = 0;
+        let data = load_from_reader(&rom[..], |_| calls += 1).unwrap();
+        assert_eq!(data, rom);
+        assert_eq!(calls, rom.len().div_ceil(CHUNK_SIZE));
+    }
+
+    #[test]
+    fn test_rejects_a_bad_header_early() {
+        let mut loader = RomLoader::new();
+        assert!(loader.push(b"PK\x03\x04 not a ROM at all").is_err());
+
+        let rom = demo::hello_world_rom();
+        let mut loader = RomLoader::new();
+        loader.push(&rom[..rom.len() - 1]).unwrap();
+        assert!(!loader.is_complete());
+        assert!(loader.finish().err().unwrap().contains("truncated"));
+    }
+}
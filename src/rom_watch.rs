@@ -0,0 +1,97 @@
+//! Notices a ROM file being rewritten on disk, so a frontend can reload it
+//! after a rebuild. The file is polled; a change is reported once its size
+//! and modification time have held still for a moment, so a build that is
+//! still writing it isn't picked up half done.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How long the file must stay unchanged before a change is reported.
+const SETTLE: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+pub struct RomWatcher {
+    path: PathBuf,
+    loaded: Option<Stamp>,
+    /// The newest stamp seen that differs from `loaded`, and when.
+    pending: Option<(Stamp, Instant)>,
+}
+
+impl RomWatcher {
+    /// Starts watching `path`, taking its current contents as loaded.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let loaded = stamp(&path);
+        RomWatcher {
+            path,
+            loaded,
+            pending: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the file has changed since it was last reported and has
+    /// settled. A missing file (mid-rebuild) is not a change.
+    pub fn poll(&mut self, now: Instant) -> bool {
+        let Some(current) = stamp(&self.path) else {
+            return false;
+        };
+        if Some(current) == self.loaded {
+            self.pending = None;
+            return false;
+        }
+        match self.pending {
+            Some((seen, since)) if seen == current => {
+                if now - since < SETTLE {
+                    return false;
+                }
+                self.loaded = Some(current);
+                self.pending = None;
+                true
+            }
+            _ => {
+                self.pending = Some((current, now));
+                false
+            }
+        }
+    }
+}
+
+fn stamp(path: &Path) -> Option<Stamp> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some(Stamp {
+        modified: metadata.modified().ok(),
+        len: metadata.len(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reports_a_change_once_it_settles() {
+        let path = std::env::temp_dir().join(format!("pico-watch-{}.nes", std::process::id()));
+        std::fs::write(&path, b"old").unwrap();
+        let mut watcher = RomWatcher::new(&path);
+        let start = Instant::now();
+        assert!(!watcher.poll(start));
+
+        std::fs::write(&path, b"rebuilt").unwrap();
+        assert!(!watcher.poll(start));
+        assert!(!watcher.poll(start + SETTLE / 2));
+        assert!(watcher.poll(start + SETTLE));
+        assert!(!watcher.poll(start + SETTLE * 2));
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(!watcher.poll(start + SETTLE * 3));
+    }
+}
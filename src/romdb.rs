@@ -0,0 +1,462 @@
+use std::fmt;
+use std::path::Path;
+
+use crate::cart::{Mirroring, RomFormat, RomHeader};
+
+const fn make_crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC32_TABLE: [u32; 256] = make_crc32_table();
+
+/// CRC-32 (IEEE), the checksum used by No-Intro style ROM databases.
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+/// A known-good dump: checksums of its PRG and CHR data and the header values
+/// it needs.
+#[derive(Debug, Clone)]
+pub struct RomDbEntry {
+    pub name: String,
+    pub prg_crc32: u32,
+    pub chr_crc32: u32,
+    pub prg_size: usize,
+    pub chr_size: usize,
+    pub mapper: u8,
+    pub mirroring: Mirroring,
+}
+
+/// ROM database loaded from a text file with one entry per line:
+///
+/// ```text
+/// # prg_crc32,chr_crc32,prg_kb,chr_kb,mapper,mirroring(h/v/4),name
+/// 12345678,9abcdef0,32,8,0,v,Some Game (USA)
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RomDatabase {
+    entries: Vec<RomDbEntry>,
+}
+
+impl RomDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read ROM database: {}", e))?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut db = RomDatabase::new();
+
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let entry = parse_entry(line).map_err(|e| format!("Line {}: {}", line_no + 1, e))?;
+            db.entries.push(entry);
+        }
+
+        Ok(db)
+    }
+
+    pub fn add(&mut self, entry: RomDbEntry) {
+        self.entries.push(entry);
+    }
+
+    pub fn entries(&self) -> &[RomDbEntry] {
+        &self.entries
+    }
+}
+
+fn parse_entry(line: &str) -> Result<RomDbEntry, String> {
+    let fields: Vec<&str> = line.splitn(7, ',').map(str::trim).collect();
+    if fields.len() != 7 {
+        return Err("expected 7 comma-separated fields".to_string());
+    }
+
+    let crc = |s: &str| u32::from_str_radix(s, 16).map_err(|_| format!("Invalid CRC32: {}", s));
+    let kb = |s: &str| {
+        s.parse::<usize>()
+            .map(|kb| kb * 1024)
+            .map_err(|_| format!("Invalid size: {}", s))
+    };
+
+    let mirroring = match fields[5] {
+        "h" => Mirroring::Horizontal,
+        "v" => Mirroring::Vertical,
+        "4" => Mirroring::FourScreen,
+        other => return Err(format!("Invalid mirroring: {}", other)),
+    };
+
+    Ok(RomDbEntry {
+        prg_crc32: crc(fields[0])?,
+        chr_crc32: crc(fields[1])?,
+        prg_size: kb(fields[2])?,
+        chr_size: kb(fields[3])?,
+        mapper: fields[4]
+            .parse()
+            .map_err(|_| format!("Invalid mapper: {}", fields[4]))?,
+        mirroring,
+        name: fields[6].to_string(),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IntegrityIssue {
+    /// Bytes past the end of the data the header describes.
+    TrailingData {
+        extra_bytes: usize,
+    },
+    /// Header declares more PRG than the known dump has (usually a mirrored
+    /// overdump).
+    PrgOverdump {
+        header_size: usize,
+        expected_size: usize,
+    },
+    /// Header declares more CHR than the known dump has.
+    ChrOverdump {
+        header_size: usize,
+        expected_size: usize,
+    },
+    WrongMapper {
+        header: u8,
+        expected: u8,
+    },
+    WrongMirroring {
+        header: Mirroring,
+        expected: Mirroring,
+    },
+    /// Bytes 12-15 of an iNES 1.0 header hold junk (e.g. "DiskDude!"), which
+    /// corrupts the upper mapper nibble.
+    DirtyHeader,
+    /// CHR matches a known dump but PRG does not: a hack, translation or
+    /// corrupted dump.
+    ModifiedPrg {
+        name: String,
+    },
+    /// Nothing in the database matches.
+    Unknown,
+}
+
+impl fmt::Display for IntegrityIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntegrityIssue::TrailingData { extra_bytes } => {
+                write!(f, "overdump: {} bytes of trailing data", extra_bytes)
+            }
+            IntegrityIssue::PrgOverdump {
+                header_size,
+                expected_size,
+            } => write!(
+                f,
+                "overdump: header declares {} KiB PRG, known dump has {} KiB",
+                header_size / 1024,
+                expected_size / 1024
+            ),
+            IntegrityIssue::ChrOverdump {
+                header_size,
+                expected_size,
+            } => write!(
+                f,
+                "overdump: header declares {} KiB CHR, known dump has {} KiB",
+                header_size / 1024,
+                expected_size / 1024
+            ),
+            IntegrityIssue::WrongMapper { header, expected } => {
+                write!(f, "bad header: mapper {} should be {}", header, expected)
+            }
+            IntegrityIssue::WrongMirroring { header, expected } => write!(
+                f,
+                "bad header: mirroring {:?} should be {:?}",
+                header, expected
+            ),
+            IntegrityIssue::DirtyHeader => write!(f, "bad header: junk in bytes 12-15"),
+            IntegrityIssue::ModifiedPrg { name } => {
+                write!(f, "modified PRG: CHR matches {} but PRG does not", name)
+            }
+            IntegrityIssue::Unknown => write!(f, "ROM not found in database"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IntegrityReport {
+    pub prg_crc32: u32,
+    pub chr_crc32: u32,
+    pub matched: Option<RomDbEntry>,
+    pub issues: Vec<IntegrityIssue>,
+}
+
+impl IntegrityReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Compares a raw iNES file against the database.
+pub fn check(raw: &[u8], db: &RomDatabase) -> Result<IntegrityReport, String> {
+    let header = RomHeader::parse(raw)?;
+    let prg = slice(raw, header.prg_rom_start(), header.prg_rom_size);
+    let chr = slice(raw, header.chr_rom_start(), header.chr_rom_size);
+
+    let mut issues = Vec::new();
+
+    if raw.len() > header.expected_len() {
+        issues.push(IntegrityIssue::TrailingData {
+            extra_bytes: raw.len() - header.expected_len(),
+        });
+    }
+
+    if header.format == RomFormat::INes && raw[12..16].iter().any(|&b| b != 0) {
+        issues.push(IntegrityIssue::DirtyHeader);
+    }
+
+    // Overdumps repeat the real data, so compare only the leading bytes.
+    let matched = db.entries.iter().find(|entry| {
+        entry.prg_size <= prg.len()
+            && entry.chr_size <= chr.len()
+            && crc32(&prg[..entry.prg_size]) == entry.prg_crc32
+            && crc32(&chr[..entry.chr_size]) == entry.chr_crc32
+    });
+
+    match matched {
+        Some(entry) => {
+            if header.prg_rom_size > entry.prg_size {
+                issues.push(IntegrityIssue::PrgOverdump {
+                    header_size: header.prg_rom_size,
+                    expected_size: entry.prg_size,
+                });
+            }
+            if header.chr_rom_size > entry.chr_size {
+                issues.push(IntegrityIssue::ChrOverdump {
+                    header_size: header.chr_rom_size,
+                    expected_size: entry.chr_size,
+                });
+            }
+            if header.mapper != entry.mapper {
+                issues.push(IntegrityIssue::WrongMapper {
+                    header: header.mapper,
+                    expected: entry.mapper,
+                });
+            }
+            if header.mirroring != entry.mirroring {
+                issues.push(IntegrityIssue::WrongMirroring {
+                    header: header.mirroring.clone(),
+                    expected: entry.mirroring.clone(),
+                });
+            }
+        }
+        None => {
+            let chr_only = db.entries.iter().find(|entry| {
+                entry.chr_size > 0
+                    && entry.chr_size <= chr.len()
+                    && crc32(&chr[..entry.chr_size]) == entry.chr_crc32
+            });
+            match chr_only {
+                Some(entry) => issues.push(IntegrityIssue::ModifiedPrg {
+                    name: entry.name.clone(),
+                }),
+                None => issues.push(IntegrityIssue::Unknown),
+            }
+        }
+    }
+
+    Ok(IntegrityReport {
+        prg_crc32: crc32(prg),
+        chr_crc32: crc32(chr),
+        matched: matched.cloned(),
+        issues,
+    })
+}
+
+fn slice(raw: &[u8], start: usize, len: usize) -> &[u8] {
+    let start = start.min(raw.len());
+    let end = (start + len).min(raw.len());
+    &raw[start..end]
+}
+
+/// Rewrites the in-memory header so the mapper and mirroring match the
+/// database, drops junk header bytes and trims trailing data. Returns the fixed
+/// image and one line per change made.
+pub fn fix_header(raw: &[u8], report: &IntegrityReport) -> (Vec<u8>, Vec<String>) {
+    let mut fixed = raw.to_vec();
+    let mut log = Vec::new();
+
+    for issue in &report.issues {
+        match issue {
+            IntegrityIssue::DirtyHeader => {
+                // The junk also lands in byte 7's upper nibble on most dumps.
+                fixed[7] &= 0x0F;
+                fixed[12..16].fill(0);
+                log.push("cleared junk header bytes 7-15".to_string());
+            }
+            IntegrityIssue::TrailingData { extra_bytes } => {
+                fixed.truncate(raw.len() - extra_bytes);
+                log.push(format!("dropped {} bytes of trailing data", extra_bytes));
+            }
+            _ => {}
+        }
+    }
+
+    for issue in &report.issues {
+        match issue {
+            IntegrityIssue::WrongMapper { header, expected } => {
+                fixed[6] = (fixed[6] & 0x0F) | (expected << 4);
+                fixed[7] = (fixed[7] & 0x0F) | (expected & 0xF0);
+                log.push(format!("mapper {} -> {}", header, expected));
+            }
+            IntegrityIssue::WrongMirroring { header, expected } => {
+                fixed[6] &= !0b1001;
+                fixed[6] |= match expected {
+                    Mirroring::Vertical => 0b0001,
+                    Mirroring::FourScreen => 0b1000,
+                    _ => 0,
+                };
+                log.push(format!("mirroring {:?} -> {:?}", header, expected));
+            }
+            _ => {}
+        }
+    }
+
+    (fixed, log)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rom(flags6: u8, prg: &[u8], chr: &[u8]) -> Vec<u8> {
+        let mut raw = vec![
+            0x4E,
+            0x45,
+            0x53,
+            0x1A,
+            (prg.len() / 0x4000) as u8,
+            (chr.len() / 0x2000) as u8,
+            flags6,
+            0,
+        ];
+        raw.resize(16, 0);
+        raw.extend_from_slice(prg);
+        raw.extend_from_slice(chr);
+        raw
+    }
+
+    fn db_for(prg: &[u8], chr: &[u8]) -> RomDatabase {
+        let mut db = RomDatabase::new();
+        db.add(RomDbEntry {
+            name: "Test Game".to_string(),
+            prg_crc32: crc32(prg),
+            chr_crc32: crc32(chr),
+            prg_size: prg.len(),
+            chr_size: chr.len(),
+            mapper: 0,
+            mirroring: Mirroring::Vertical,
+        });
+        db
+    }
+
+    #[test]
+    fn test_crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_parse_database_line() {
+        let db =
+            RomDatabase::parse("# comment\ncbf43926,00000000,32,8,4,h,Game, The (USA)\n").unwrap();
+        let entry = &db.entries()[0];
+        assert_eq!(entry.prg_crc32, 0xCBF4_3926);
+        assert_eq!(entry.prg_size, 32 * 1024);
+        assert_eq!(entry.mapper, 4);
+        assert_eq!(entry.mirroring, Mirroring::Horizontal);
+        assert_eq!(entry.name, "Game, The (USA)");
+    }
+
+    #[test]
+    fn test_clean_rom() {
+        let prg = vec![1u8; 0x4000];
+        let chr = vec![2u8; 0x2000];
+        let report = check(&rom(0x01, &prg, &chr), &db_for(&prg, &chr)).unwrap();
+        assert!(report.is_clean());
+        assert_eq!(report.matched.unwrap().name, "Test Game");
+    }
+
+    #[test]
+    fn test_bad_header_and_fix() {
+        let prg = vec![1u8; 0x4000];
+        let chr = vec![2u8; 0x2000];
+        let db = db_for(&prg, &chr);
+        let mut raw = rom(0x20, &prg, &chr);
+        raw.extend_from_slice(&[0xFF; 100]);
+
+        let report = check(&raw, &db).unwrap();
+        assert!(
+            report
+                .issues
+                .contains(&IntegrityIssue::TrailingData { extra_bytes: 100 })
+        );
+        assert!(report.issues.contains(&IntegrityIssue::WrongMapper {
+            header: 2,
+            expected: 0
+        }));
+        assert!(report.issues.contains(&IntegrityIssue::WrongMirroring {
+            header: Mirroring::Horizontal,
+            expected: Mirroring::Vertical,
+        }));
+
+        let (fixed, log) = fix_header(&raw, &report);
+        assert_eq!(log.len(), 3);
+        assert!(check(&fixed, &db).unwrap().is_clean());
+    }
+
+    #[test]
+    fn test_prg_overdump_and_modified_prg() {
+        let prg = vec![1u8; 0x4000];
+        let chr = vec![2u8; 0x2000];
+        let db = db_for(&prg, &chr);
+
+        let doubled = [prg.clone(), prg.clone()].concat();
+        let report = check(&rom(0x01, &doubled, &chr), &db).unwrap();
+        assert_eq!(
+            report.issues,
+            vec![IntegrityIssue::PrgOverdump {
+                header_size: 0x8000,
+                expected_size: 0x4000,
+            }]
+        );
+
+        let hacked = vec![3u8; 0x4000];
+        let report = check(&rom(0x01, &hacked, &chr), &db).unwrap();
+        assert_eq!(
+            report.issues,
+            vec![IntegrityIssue::ModifiedPrg {
+                name: "Test Game".to_string()
+            }]
+        );
+    }
+}
@@ -0,0 +1,232 @@
+//! Stack diagnostics for homebrew: flags the stack pointer wrapping around
+//! page one, and pushes that overwrite page-one bytes the program uses for
+//! other data.
+//!
+//! A byte counts as data once an instruction other than a push, pull, call
+//! or return reads or writes it while it lies below the stack, in the part
+//! of page one the stack isn't using. Reading arguments off the stack with
+//! `TSX` / `LDA $0103,X` touches bytes above the stack pointer and doesn't
+//! count. Each distinct warning is reported once.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::cpu::InterruptType;
+
+/// Warnings kept until [`StackCheck::take_warnings`].
+const MAX_WARNINGS: usize = 64;
+
+const BRK: u8 = 0x00;
+const PHP: u8 = 0x08;
+const JSR: u8 = 0x20;
+const PLP: u8 = 0x28;
+const RTI: u8 = 0x40;
+const PHA: u8 = 0x48;
+const PHY: u8 = 0x5A;
+const RTS: u8 = 0x60;
+const PLA: u8 = 0x68;
+const PLY: u8 = 0x7A;
+const PHX: u8 = 0xDA;
+const PLX: u8 = 0xFA;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StackProblem {
+    /// A push took the stack pointer from $00 to $FF.
+    Overflow,
+    /// A pull took the stack pointer from $FF to $00.
+    Underflow,
+    /// A push overwrote this page-one address, which held data.
+    Clobber(u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StackWarning {
+    pub problem: StackProblem,
+    /// The instruction that did it, or the one an interrupt preempted.
+    pub pc: u16,
+    /// Stack pointer afterwards.
+    pub sp: u8,
+}
+
+impl fmt::Display for StackWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.problem {
+            StackProblem::Overflow => write!(f, "stack overflow at ${:04X}", self.pc)?,
+            StackProblem::Underflow => write!(f, "stack underflow at ${:04X}", self.pc)?,
+            StackProblem::Clobber(addr) => write!(
+                f,
+                "push at ${:04X} overwrote ${:04X}, which held data",
+                self.pc, addr
+            )?,
+        }
+        write!(f, " (SP ${:02X})", self.sp)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StackCheck {
+    /// Page-one offsets used as data.
+    data: [bool; 256],
+    /// Page-one accesses by the instruction in progress: offset, write.
+    accesses: Vec<(u8, bool)>,
+    warnings: Vec<StackWarning>,
+    reported: HashSet<StackWarning>,
+}
+
+impl Default for StackCheck {
+    fn default() -> Self {
+        StackCheck {
+            data: [false; 256],
+            accesses: Vec::new(),
+            warnings: Vec::new(),
+            reported: HashSet::new(),
+        }
+    }
+}
+
+impl StackCheck {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Warnings raised since the last call, oldest first.
+    pub fn take_warnings(&mut self) -> Vec<StackWarning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Forgets what was seen used as data, and which warnings were given.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Notes a CPU access to page one, at `offset` within it.
+    pub(crate) fn access(&mut self, offset: u8, write: bool) {
+        self.accesses.push((offset, write));
+    }
+
+    /// Records one CPU step: the opcode at `at` ran with the stack pointer
+    /// at `sp` and left it at `next_sp`.
+    pub(crate) fn record(
+        &mut self,
+        at: u16,
+        opcode: u8,
+        interrupt: Option<InterruptType>,
+        sp: u8,
+        next_sp: u8,
+    ) {
+        let accesses = std::mem::take(&mut self.accesses);
+        let pushes = interrupt.is_some() || matches!(opcode, BRK | PHP | JSR | PHA | PHY | PHX);
+        let pulls = interrupt.is_none() && matches!(opcode, PLP | RTI | RTS | PLA | PLY | PLX);
+
+        if !pushes && !pulls {
+            for (offset, _) in accesses {
+                if offset <= sp {
+                    self.data[offset as usize] = true;
+                }
+            }
+            return;
+        }
+
+        let mut problems = Vec::new();
+        if pushes && next_sp > sp {
+            problems.push(StackProblem::Overflow);
+        }
+        if pulls && next_sp < sp {
+            problems.push(StackProblem::Underflow);
+        }
+        if pushes {
+            for (offset, write) in accesses {
+                if write && self.data[offset as usize] {
+                    problems.push(StackProblem::Clobber(0x0100 | offset as u16));
+                }
+            }
+        }
+        for problem in problems {
+            self.warn(StackWarning {
+                problem,
+                pc: at,
+                sp: next_sp,
+            });
+        }
+    }
+
+    fn warn(&mut self, warning: StackWarning) {
+        if self.warnings.len() < MAX_WARNINGS && self.reported.insert(warning) {
+            self.warnings.push(warning);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::cart::test::test_nes;
+
+    #[test]
+    fn test_flags_wraps_and_clobbered_data() {
+        let program = assemble(
+            "
+                    .org $8000
+            reset:  lda #1
+                    sta $0180
+                    ldx #$82
+                    txs
+            push:   pha
+                    pha
+                    pha
+                    ldx #$00
+                    txs
+            over:   pha
+                    ldx #$FF
+                    txs
+            under:  pla
+                    jmp reset
+                    .org $FFFC
+                    .word reset, reset
+            ",
+        )
+        .unwrap();
+        let mut nes = test_nes(&program.slice(0x8000, 0x8000));
+        nes.enable_stack_check();
+        let addr = |name: &str| program.labels[name];
+
+        // Twice round: the second time repeats the same warnings.
+        for _ in 0..2 {
+            while nes.bus.cpu.registers.pc == addr("reset") {
+                nes.clock();
+            }
+            while nes.bus.cpu.registers.pc != addr("reset") {
+                nes.clock();
+            }
+        }
+        let warnings = nes.stack_check_mut().unwrap().take_warnings();
+        assert_eq!(
+            warnings,
+            [
+                StackWarning {
+                    problem: StackProblem::Clobber(0x0180),
+                    pc: addr("push") + 2,
+                    sp: 0x7F,
+                },
+                StackWarning {
+                    problem: StackProblem::Overflow,
+                    pc: addr("over"),
+                    sp: 0xFF,
+                },
+                StackWarning {
+                    problem: StackProblem::Underflow,
+                    pc: addr("under"),
+                    sp: 0x00,
+                },
+            ]
+        );
+        assert_eq!(
+            warnings[0].to_string(),
+            format!(
+                "push at ${:04X} overwrote $0180, which held data (SP $7F)",
+                addr("push") + 2
+            )
+        );
+    }
+}
@@ -0,0 +1,98 @@
+//! Where battery saves and other persistent data end up. The emulator core
+//! only talks to a [`StorageBackend`], so a frontend can keep saves on disk,
+//! in browser localStorage, or anywhere else.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Key-value store for persistent blobs. Keys are flat names such as
+/// `"zelda.sav"`.
+pub trait StorageBackend {
+    /// Returns `Ok(None)` if nothing has been stored under `key`.
+    fn load(&self, key: &str) -> Result<Option<Vec<u8>>, String>;
+    fn store(&mut self, key: &str, data: &[u8]) -> Result<(), String>;
+}
+
+/// Stores each key as a file in one directory.
+pub struct FileStorage {
+    root: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        FileStorage { root: root.into() }
+    }
+
+    /// `$XDG_DATA_HOME/pico`, falling back to `~/.local/share`.
+    pub fn default_dir() -> PathBuf {
+        let base = std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share"))
+            })
+            .unwrap_or_default();
+        base.join("pico")
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, String> {
+        if key.is_empty() || key == "." || key == ".." || key.contains(['/', '\\']) {
+            return Err(format!("Invalid storage key {:?}", key));
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+impl StorageBackend for FileStorage {
+    fn load(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
+        let path = self.path(key)?;
+        match std::fs::read(&path) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+        }
+    }
+
+    fn store(&mut self, key: &str, data: &[u8]) -> Result<(), String> {
+        let path = self.path(key)?;
+        std::fs::create_dir_all(&self.root)
+            .map_err(|e| format!("Failed to create {}: {}", self.root.display(), e))?;
+        std::fs::write(&path, data)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+}
+
+/// Keeps everything in memory. Useful for tests, or as the cache behind a
+/// custom backend.
+#[derive(Default)]
+pub struct MemoryStorage {
+    pub entries: HashMap<String, Vec<u8>>,
+}
+
+impl StorageBackend for MemoryStorage {
+    fn load(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
+        Ok(self.entries.get(key).cloned())
+    }
+
+    fn store(&mut self, key: &str, data: &[u8]) -> Result<(), String> {
+        self.entries.insert(key.to_string(), data.to_vec());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_file_storage_round_trip() {
+        let root = std::env::temp_dir().join(format!("pico-storage-{}", std::process::id()));
+        let mut storage = FileStorage::new(&root);
+
+        assert_eq!(storage.load("game.sav").unwrap(), None);
+        storage.store("game.sav", &[1, 2, 3]).unwrap();
+        assert_eq!(storage.load("game.sav").unwrap(), Some(vec![1, 2, 3]));
+        assert!(storage.store("../escape.sav", &[0]).is_err());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
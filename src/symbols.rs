@@ -0,0 +1,135 @@
+//! Address labels for the disassembler and tracer, loaded from FCEUX name
+//! lists (`.nl`) or ca65 debug files (`ld65 --dbgfile`).
+//!
+//! FCEUX keeps one name list per 16KB PRG bank, `game.nes.<bank>.nl`, and
+//! one for RAM, `game.nes.ram.nl`. Each line is `$ADDR#name#comment`, or
+//! `$ADDR/LEN#name#comment` for an array. Labels from a bank file only
+//! apply while that bank is mapped, with banks numbered as the mapper
+//! reports them (see [`crate::cpu::Breakpoint::in_bank`]).
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    labels: HashMap<(Option<usize>, u16), String>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    /// Names `addr`, in `bank` only if one is given.
+    pub fn insert(&mut self, addr: u16, bank: Option<usize>, name: &str) {
+        self.labels.insert((bank, addr), name.to_string());
+    }
+
+    /// The label for `addr` when `bank` is mapped there, falling back to
+    /// labels not tied to a bank.
+    pub fn label(&self, addr: u16, bank: Option<usize>) -> Option<&str> {
+        bank.and_then(|bank| self.labels.get(&(Some(bank), addr)))
+            .or_else(|| self.labels.get(&(None, addr)))
+            .map(String::as_str)
+    }
+
+    /// Adds the labels in the file `name`, picking the format from its
+    /// extension: `.nl` for FCEUX (the bank taken from the name), `.dbg` for
+    /// ca65.
+    pub fn load(&mut self, name: &str, text: &str) -> Result<(), String> {
+        let lower = name.to_ascii_lowercase();
+        if let Some(stem) = lower.strip_suffix(".nl") {
+            let bank = stem
+                .rsplit_once('.')
+                .and_then(|(_, bank)| usize::from_str_radix(bank, 16).ok());
+            self.load_fceux_nl(text, bank)
+        } else if lower.ends_with(".dbg") {
+            self.load_ca65_dbg(text)
+        } else {
+            Err(format!("Unknown symbol file type: {}", name))
+        }
+    }
+
+    pub fn load_fceux_nl(&mut self, text: &str, bank: Option<usize>) -> Result<(), String> {
+        for (number, line) in text.lines().enumerate() {
+            let Some(entry) = line.trim().strip_prefix('$') else {
+                continue;
+            };
+            let mut fields = entry.splitn(3, '#');
+            let addr = fields.next().unwrap_or_default();
+            let name = fields.next().unwrap_or_default().trim();
+            let addr = addr.split('/').next().unwrap_or_default();
+            let addr = u16::from_str_radix(addr, 16)
+                .map_err(|_| format!("Line {}: invalid address: {}", number + 1, addr))?;
+            if !name.is_empty() {
+                // RAM and register labels apply in every bank.
+                self.insert(addr, bank.filter(|_| addr >= 0x8000), name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes the `sym` records of type `lab`; equates are left out since
+    /// most of them are constants rather than addresses.
+    pub fn load_ca65_dbg(&mut self, text: &str) -> Result<(), String> {
+        for (number, line) in text.lines().enumerate() {
+            let Some(record) = line.strip_prefix("sym\t") else {
+                continue;
+            };
+            let field = |key: &str| {
+                record
+                    .split(',')
+                    .find_map(|pair| pair.strip_prefix(key)?.strip_prefix('='))
+            };
+            if field("type") != Some("lab") {
+                continue;
+            }
+            let (Some(name), Some(val)) = (field("name"), field("val")) else {
+                return Err(format!("Line {}: symbol without name or value", number + 1));
+            };
+            let addr = val
+                .strip_prefix("0x")
+                .and_then(|hex| u16::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| format!("Line {}: invalid value: {}", number + 1, val))?;
+            self.insert(addr, None, name.trim_matches('"'));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_loads_name_lists_and_debug_files() {
+        let mut symbols = SymbolTable::new();
+        symbols
+            .load(
+                "game.nes.ram.nl",
+                "$0000#temp#scratch\n$0300/20#buffer#\n$2000#PPUCTRL#\n",
+            )
+            .unwrap();
+        symbols
+            .load("game.nes.3.nl", "$8000#bank3_entry#\n$C000##no name\n")
+            .unwrap();
+        assert_eq!(symbols.label(0x0300, None), Some("buffer"));
+        assert_eq!(symbols.label(0x2000, Some(3)), Some("PPUCTRL"));
+        assert_eq!(symbols.label(0x8000, Some(3)), Some("bank3_entry"));
+        assert_eq!(symbols.label(0x8000, Some(2)), None);
+        assert_eq!(symbols.label(0xC000, Some(3)), None);
+
+        let dbg = "version\tmajor=2,minor=0\n\
+            sym\tid=0,name=\"reset\",addrsize=absolute,scope=0,def=1,val=0xC000,seg=0,type=lab\n\
+            sym\tid=1,name=\"SPEED\",addrsize=zeropage,scope=0,def=2,val=0x3,type=equ\n";
+        symbols.load("game.dbg", dbg).unwrap();
+        assert_eq!(symbols.label(0xC000, Some(7)), Some("reset"));
+        assert_eq!(symbols.label(0x0003, None), None);
+
+        assert!(symbols.load("game.nes.ram.nl", "$zz#bad#").is_err());
+        assert!(symbols.load("game.sym", "").is_err());
+    }
+}
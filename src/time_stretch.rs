@@ -0,0 +1,151 @@
+//! Changes the tempo of audio without changing its pitch, for running a
+//! game faster or slower than its console did. Input is cut into short
+//! overlapping sequences; each is placed where its start best matches the
+//! end of the previous one and the two are cross-faded, with input skipped
+//! or repeated between sequences to reach the tempo.
+
+use std::collections::VecDeque;
+
+/// Length of each sequence copied to the output.
+const SEQUENCE_MS: usize = 40;
+/// How far from its nominal position a sequence may start.
+const SEEK_MS: usize = 10;
+/// Cross-fade between consecutive sequences.
+const OVERLAP_MS: usize = 8;
+
+pub struct TimeStretch {
+    tempo: f64,
+    sequence: usize,
+    seek: usize,
+    overlap: usize,
+    input: Vec<f32>,
+    /// The end of the last sequence, faded into the start of the next.
+    tail: Vec<f32>,
+    /// Fraction of a sample still to skip, carried between sequences.
+    skip_fraction: f64,
+}
+
+impl TimeStretch {
+    /// `tempo` above 1 plays faster: 1.2 turns 1.2s of input into 1s.
+    pub fn new(sample_rate: u32, tempo: f64) -> Self {
+        let ms = |ms: usize| (sample_rate as usize * ms / 1000).max(1);
+        TimeStretch {
+            tempo,
+            sequence: ms(SEQUENCE_MS),
+            seek: ms(SEEK_MS),
+            overlap: ms(OVERLAP_MS),
+            input: Vec::new(),
+            tail: Vec::new(),
+            skip_fraction: 0.0,
+        }
+    }
+
+    pub fn tempo(&self) -> f64 {
+        self.tempo
+    }
+
+    /// Adds `samples` and appends what can be stretched so far to `out`.
+    /// Some input is held back until the next call.
+    pub fn process(&mut self, samples: impl IntoIterator<Item = f32>, out: &mut VecDeque<f32>) {
+        if self.tempo == 1.0 {
+            out.extend(samples);
+            return;
+        }
+        self.input.extend(samples);
+
+        let needed = self.seek + self.sequence;
+        while self.input.len() >= needed {
+            let offset = if self.tail.is_empty() {
+                0
+            } else {
+                self.best_offset()
+            };
+            let sequence = &self.input[offset..offset + self.sequence];
+            let (head, rest) = sequence.split_at(self.overlap);
+            let (body, tail) = rest.split_at(rest.len() - self.overlap);
+
+            if self.tail.is_empty() {
+                out.extend(head);
+            } else {
+                // Linear cross-fade from the previous tail into this head.
+                let len = self.overlap as f32;
+                out.extend(
+                    self.tail
+                        .iter()
+                        .zip(head)
+                        .enumerate()
+                        .map(|(i, (&from, &to))| {
+                            let t = i as f32 / len;
+                            from * (1.0 - t) + to * t
+                        }),
+                );
+            }
+            out.extend(body);
+            self.tail = tail.to_vec();
+
+            // Each sequence writes `sequence - overlap` samples; consume
+            // `tempo` times that much input.
+            let skip = self.tempo * (self.sequence - self.overlap) as f64 + self.skip_fraction;
+            let whole = skip as usize;
+            self.skip_fraction = skip - whole as f64;
+            self.input.drain(..whole.min(self.input.len()));
+        }
+    }
+
+    // The start within the seek window whose first `overlap` samples best
+    // correlate with the held tail, so the cross-fade doesn't cancel out.
+    fn best_offset(&self) -> usize {
+        let mut best = (0, f32::MIN);
+        for offset in 0..self.seek {
+            let window = &self.input[offset..offset + self.overlap];
+            let (mut correlation, mut energy) = (0.0, 0.0);
+            for (&a, &b) in self.tail.iter().zip(window) {
+                correlation += a * b;
+                energy += b * b;
+            }
+            let score = correlation / (energy + 1e-9).sqrt();
+            if score > best.1 {
+                best = (offset, score);
+            }
+        }
+        best.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Upward zero crossings, which stay proportional to pitch.
+    fn crossings(samples: &[f32]) -> usize {
+        samples
+            .windows(2)
+            .filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0)
+            .count()
+    }
+
+    #[test]
+    fn test_faster_tempo_keeps_pitch() {
+        let rate = 48_000;
+        let tone: Vec<f32> = (0..rate * 2)
+            .map(|i| (i as f32 * 440.0 * std::f32::consts::TAU / rate as f32).sin())
+            .collect();
+        let mut stretch = TimeStretch::new(rate as u32, 1.2);
+        let mut out = VecDeque::new();
+        for chunk in tone.chunks(800) {
+            stretch.process(chunk.iter().copied(), &mut out);
+        }
+        let out: Vec<f32> = out.into();
+
+        let expected = tone.len() as f64 / 1.2;
+        assert!((out.len() as f64 - expected).abs() < expected * 0.05);
+        let pitch_in = crossings(&tone) as f64 / tone.len() as f64;
+        let pitch_out = crossings(&out) as f64 / out.len() as f64;
+        assert!((pitch_out / pitch_in - 1.0).abs() < 0.02);
+
+        let mut same = TimeStretch::new(rate as u32, 1.0);
+        let mut out = VecDeque::new();
+        same.process(tone.iter().copied(), &mut out);
+        assert_eq!(out.len(), tone.len());
+    }
+}
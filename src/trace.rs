@@ -1,11 +1,188 @@
+use std::collections::VecDeque;
+
 use crate::bus::Bus;
-use crate::cpu::CPU;
-use crate::opcodes::{AddressingMode, CPU_OPCODES};
+use crate::cpu::{CPU, CpuModel, InterruptType, Registers};
+use crate::disasm::Disassembler;
+use crate::memory::Memory;
+use crate::opcodes::AddressingMode;
+use crate::symbols::SymbolTable;
 
+/// One nestest-style log line for the instruction at PC, e.g.
+/// `C000  4C F5 C5  JMP $C5F5    A:00 X:00 Y:00 P:24 SP:FD`.
 pub fn trace(cpu: &CPU, bus: &Bus) -> String {
+    format_trace(cpu, bus, None)
+}
+
+/// [`trace`] with operand addresses that have a label in `symbols`
+/// replaced by it, looked up in the PRG bank mapped at the time.
+pub fn trace_with_symbols(cpu: &CPU, bus: &Bus, symbols: &SymbolTable) -> String {
+    format_trace(cpu, bus, Some(symbols))
+}
+
+/// A span of addresses, optionally only while a given PRG bank is mapped
+/// there. Written `C000-FFFF`, `$E0A0`, or `03:8000-9FFF` with a bank.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceRange {
+    pub start: u16,
+    pub end: u16,
+    pub bank: Option<usize>,
+}
+
+impl TraceRange {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (bank, range) = match text.split_once(':') {
+            Some((bank, range)) => {
+                let bank = usize::from_str_radix(bank, 16)
+                    .map_err(|_| format!("Invalid bank: {}", bank))?;
+                (Some(bank), range)
+            }
+            None => (None, text),
+        };
+        let addr = |text: &str| {
+            u16::from_str_radix(text.trim().trim_start_matches('$'), 16)
+                .map_err(|_| format!("Invalid address: {}", text))
+        };
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (addr(start)?, addr(end)?),
+            None => (addr(range)?, addr(range)?),
+        };
+        if start > end {
+            return Err(format!("Invalid range: {}", text));
+        }
+        Ok(TraceRange { start, end, bank })
+    }
+
+    pub fn contains(&self, addr: u16, bank: Option<usize>) -> bool {
+        (self.start..=self.end).contains(&addr) && self.bank.is_none_or(|b| bank == Some(b))
+    }
+}
+
+/// Which instructions get traced, by their address. With no include ranges
+/// everything is included; exclude ranges win over include ranges.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    include: Vec<TraceRange>,
+    exclude: Vec<TraceRange>,
+}
+
+impl TraceFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn include(&mut self, range: TraceRange) {
+        self.include.push(range);
+    }
+
+    pub fn exclude(&mut self, range: TraceRange) {
+        self.exclude.push(range);
+    }
+
+    /// Whether the instruction at `addr`, in PRG bank `bank`, is traced.
+    pub fn allows(&self, addr: u16, bank: Option<usize>) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|r| r.contains(addr, bank)))
+            && !self.exclude.iter().any(|r| r.contains(addr, bank))
+    }
+
+    /// Whether the instruction at PC is traced.
+    pub fn allows_next(&self, cpu: &CPU, bus: &Bus) -> bool {
+        let pc = cpu.registers.pc;
+        self.allows(pc, bus.prg_bank(pc))
+    }
+}
+
+/// One instruction kept by [`TraceHistory`], with the registers before it.
+#[derive(Clone)]
+struct TracedInstruction {
+    registers: Registers,
+    bytes: [u8; 3],
+    cycle: u64,
+    /// Taken instead of running the instruction.
+    interrupt: Option<InterruptType>,
+}
+
+/// The last instructions run, kept cheaply and only formatted on request,
+/// so a crash report can show what led up to it.
+#[derive(Clone)]
+pub struct TraceHistory {
+    entries: VecDeque<TracedInstruction>,
+    capacity: usize,
+}
+
+impl TraceHistory {
+    pub fn new(capacity: usize) -> Self {
+        TraceHistory {
+            entries: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        registers: Registers,
+        bytes: [u8; 3],
+        cycle: u64,
+        interrupt: Option<InterruptType>,
+    ) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(TracedInstruction {
+            registers,
+            bytes,
+            cycle,
+            interrupt,
+        });
+    }
+
+    /// One line per instruction, oldest first, e.g.
+    /// `C000  4C F5 C5  JMP $C5F5        A:00 X:00 Y:00 P:24 SP:FD CYC:7`.
+    /// An interrupt taken at an instruction shows as `NMI` in its place.
+    pub fn lines(&self, model: CpuModel) -> Vec<String> {
+        self.entries
+            .iter()
+            .map(|entry| {
+                let registers = &entry.registers;
+                let asm = match entry.interrupt {
+                    Some(interrupt) => format!("{:04X}  {:?}", registers.pc, interrupt),
+                    None => Disassembler::with_opcodes(&entry.bytes, registers.pc, model.opcodes())
+                        .next()
+                        .map_or_else(String::new, |instruction| instruction.to_string()),
+                };
+                format!(
+                    "{:32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+                    asm,
+                    registers.a,
+                    registers.x,
+                    registers.y,
+                    registers.status.bits(),
+                    registers.sp,
+                    entry.cycle
+                )
+            })
+            .collect()
+    }
+}
+
+fn format_trace(cpu: &CPU, bus: &Bus, symbols: Option<&SymbolTable>) -> String {
+    let name = |addr: u16, zero_page: bool| match symbols
+        .and_then(|symbols| symbols.label(addr, bus.prg_bank(addr)))
+    {
+        Some(label) => label.to_string(),
+        None if zero_page => format!("${:02X}", addr),
+        None => format!("${:04X}", addr),
+    };
     let pc = cpu.registers.pc;
     let opcode = bus.peek(pc);
-    let ops = CPU_OPCODES.find_by_code(opcode).unwrap();
+    let ops = cpu.model().opcodes().find_by_code(opcode).unwrap();
 
     let mut hex_dump = vec![opcode];
     let (mem_addr, stored_value) = match ops.mode {
@@ -25,32 +202,46 @@ pub fn trace(cpu: &CPU, bus: &Bus) -> String {
             let value = bus.peek(pc.wrapping_add(1));
             hex_dump.push(value);
             match ops.mode {
-                AddressingMode::Immediate => format!("#${:02x}", value),
-                AddressingMode::ZeroPage => format!("${:02x} = {:02x}", mem_addr, stored_value),
-                AddressingMode::ZeroPageX => {
-                    format!("${:02x},X @ {:02x} = {:02x}", value, mem_addr, stored_value)
-                }
-                AddressingMode::ZeroPageY => {
-                    format!("${:02x},Y @ {:02x} = {:02x}", value, mem_addr, stored_value)
+                AddressingMode::Immediate => format!("#${:02X}", value),
+                AddressingMode::ZeroPage => {
+                    format!("{} = {:02X}", name(mem_addr, true), stored_value)
                 }
+                AddressingMode::ZeroPageX => format!(
+                    "{},X @ {:02X} = {:02X}",
+                    name(value as u16, true),
+                    mem_addr,
+                    stored_value
+                ),
+                AddressingMode::ZeroPageY => format!(
+                    "{},Y @ {:02X} = {:02X}",
+                    name(value as u16, true),
+                    mem_addr,
+                    stored_value
+                ),
                 AddressingMode::IndirectX => format!(
-                    "(${:02x},X) @ {:02x} = {:04x} = {:02x}",
-                    value,
+                    "({},X) @ {:02X} = {:04X} = {:02X}",
+                    name(value as u16, true),
                     value.wrapping_add(cpu.registers.x),
                     mem_addr,
                     stored_value
                 ),
                 AddressingMode::IndirectY => format!(
-                    "(${:02x}),Y = {:04x} @ {:04x} = {:02x}",
-                    value,
+                    "({}),Y = {:04X} @ {:04X} = {:02X}",
+                    name(value as u16, true),
                     mem_addr.wrapping_sub(cpu.registers.y as u16),
                     mem_addr,
                     stored_value
                 ),
+                AddressingMode::ZeroPageIndirect => format!(
+                    "({}) = {:04X} = {:02X}",
+                    name(value as u16, true),
+                    mem_addr,
+                    stored_value
+                ),
                 AddressingMode::None => {
                     let offset = value as i8;
                     let target = (pc as i32 + 2 + offset as i32) as u16;
-                    format!("${:04x}", target)
+                    name(target, false)
                 }
                 _ => String::new(),
             }
@@ -71,19 +262,25 @@ pub fn trace(cpu: &CPU, bus: &Bus) -> String {
                         } else {
                             read_u16(bus, absolute)
                         };
-                        format!("(${:04x}) = {:04x}", absolute, addr)
+                        format!("({}) = {:04X}", name(absolute, false), addr)
                     } else {
-                        format!("${:04x}", absolute)
+                        name(absolute, false)
                     }
                 }
-                AddressingMode::Absolute => format!("${:04x} = {:02x}", mem_addr, stored_value),
+                AddressingMode::Absolute => {
+                    format!("{} = {:02X}", name(mem_addr, false), stored_value)
+                }
                 AddressingMode::AbsoluteX => format!(
-                    "${:04x},X @ {:04x} = {:02x}",
-                    absolute, mem_addr, stored_value
+                    "{},X @ {:04X} = {:02X}",
+                    name(absolute, false),
+                    mem_addr,
+                    stored_value
                 ),
                 AddressingMode::AbsoluteY => format!(
-                    "${:04x},Y @ {:04x} = {:02x}",
-                    absolute, mem_addr, stored_value
+                    "{},Y @ {:04X} = {:02X}",
+                    name(absolute, false),
+                    mem_addr,
+                    stored_value
                 ),
                 _ => String::new(),
             }
@@ -93,19 +290,19 @@ pub fn trace(cpu: &CPU, bus: &Bus) -> String {
 
     let hex_str = hex_dump
         .iter()
-        .map(|byte| format!("{:02x}", byte))
+        .map(|byte| format!("{:02X}", byte))
         .collect::<Vec<_>>()
         .join(" ");
 
     let asm_str = format!(
-        "{:04x}  {:8} {: >4} {}",
+        "{:04X}  {:8} {: >4} {}",
         pc, hex_str, ops.mnemonic, operand_str
     )
     .trim()
     .to_string();
 
     format!(
-        "{:47} A:{:02x} X:{:02x} Y:{:02x} P:{:02x} SP:{:02x}",
+        "{:47} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
         asm_str,
         cpu.registers.a,
         cpu.registers.x,
@@ -113,7 +310,6 @@ pub fn trace(cpu: &CPU, bus: &Bus) -> String {
         cpu.registers.status,
         cpu.registers.sp
     )
-    .to_ascii_uppercase()
 }
 
 fn operand(bus: &Bus, cpu: &CPU, mode: &AddressingMode) -> (u16, u8) {
@@ -149,7 +345,7 @@ fn operand(bus: &Bus, cpu: &CPU, mode: &AddressingMode) -> (u16, u8) {
         }
         AddressingMode::Indirect => {
             let base = read_u16(bus, pc.wrapping_add(1));
-            let addr = if base & 0x00ff == 0x00ff {
+            let addr = if base & 0x00ff == 0x00ff && cpu.model() != CpuModel::Wdc65C02 {
                 let lo = bus.peek(base);
                 let hi = bus.peek(base & 0xff00);
                 (hi as u16) << 8 | lo as u16
@@ -173,6 +369,13 @@ fn operand(bus: &Bus, cpu: &CPU, mode: &AddressingMode) -> (u16, u8) {
             let deref = ((hi as u16) << 8 | lo as u16).wrapping_add(cpu.registers.y as u16);
             (deref, bus.peek(deref))
         }
+        AddressingMode::ZeroPageIndirect => {
+            let base = bus.peek(pc.wrapping_add(1));
+            let lo = bus.peek(base as u16);
+            let hi = bus.peek(base.wrapping_add(1) as u16);
+            let addr = (hi as u16) << 8 | lo as u16;
+            (addr, bus.peek(addr))
+        }
         AddressingMode::Relative => {
             let offset = bus.peek(pc.wrapping_add(1)) as i8;
             let base = pc.wrapping_add(2);
@@ -188,3 +391,82 @@ fn read_u16(bus: &Bus, addr: u16) -> u16 {
     let hi = bus.peek(addr.wrapping_add(1)) as u16;
     (hi << 8) | lo
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::apu::APU;
+    use crate::cart::test::test_rom;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_trace_with_symbols() {
+        // $8000: STA $0200,X; JSR $8010
+        let mut prg = vec![0xEA; 0x8000];
+        prg[..6].copy_from_slice(&[0x9D, 0x00, 0x02, 0x20, 0x10, 0x80]);
+        let apu = APU::new(48_000, Arc::new(Mutex::new(VecDeque::new())));
+        let mut bus = Bus::new(test_rom(prg), apu);
+        let mut cpu = CPU::new();
+        cpu.registers.pc = 0x8000;
+        cpu.registers.x = 0x05;
+        bus.cpu.vram[0x0205] = 0xAB;
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0x0200, None, "oam_buffer");
+        symbols.insert(0x8010, Some(0), "update");
+        assert!(trace(&cpu, &bus).starts_with("8000  9D 00 02 STA $0200,X @ 0205 = AB "));
+        assert!(
+            trace_with_symbols(&cpu, &bus, &symbols)
+                .starts_with("8000  9D 00 02 STA oam_buffer,X @ 0205 = AB ")
+        );
+
+        cpu.registers.pc = 0x8003;
+        assert!(
+            trace_with_symbols(&cpu, &bus, &symbols).starts_with("8003  20 10 80 JSR update ")
+        );
+        symbols = SymbolTable::new();
+        symbols.insert(0x8010, Some(1), "other_bank");
+        assert!(trace_with_symbols(&cpu, &bus, &symbols).starts_with("8003  20 10 80 JSR $8010 "));
+    }
+
+    #[test]
+    fn test_trace_history_keeps_the_latest() {
+        let mut history = TraceHistory::new(2);
+        let mut registers = CPU::new().registers;
+        registers.pc = 0xC000;
+        history.record(registers.clone(), [0xEA, 0, 0], 7, None);
+        history.record(registers.clone(), [0x4C, 0xF5, 0xC5], 9, None);
+        registers.pc = 0xC5F5;
+        history.record(registers, [0xEA, 0, 0], 12, Some(InterruptType::NMI));
+
+        let lines = history.lines(CpuModel::Rp2A03);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("C000  4C F5 C5  JMP $C5F5       A:00 "));
+        assert!(lines[0].ends_with(" CYC:9"));
+        assert!(lines[1].starts_with("C5F5  NMI "));
+    }
+
+    #[test]
+    fn test_trace_filter() {
+        let mut filter = TraceFilter::new();
+        assert!(filter.allows(0x0300, None));
+
+        filter.include(TraceRange::parse("$C000-$FFFF").unwrap());
+        filter.include(TraceRange::parse("03:8000-9FFF").unwrap());
+        filter.exclude(TraceRange::parse("E000-E0FF").unwrap());
+        filter.exclude(TraceRange::parse("C123").unwrap());
+        assert!(filter.allows(0xC000, Some(7)));
+        assert!(filter.allows(0x8123, Some(3)));
+        assert!(!filter.allows(0x8123, Some(2)));
+        assert!(!filter.allows(0x8123, None));
+        assert!(!filter.allows(0xE080, Some(7)));
+        assert!(!filter.allows(0xC123, Some(7)));
+        assert!(filter.allows(0xC124, Some(7)));
+        assert!(!filter.allows(0x0300, None));
+
+        assert!(TraceRange::parse("FFFF-C000").is_err());
+        assert!(TraceRange::parse("x:8000").is_err());
+        assert!(TraceRange::parse("8000-").is_err());
+    }
+}
@@ -0,0 +1,327 @@
+//! Runs pico beside a trace log from another emulator and stops at the
+//! first instruction where the two disagree. Nintendulator (nestest.log),
+//! FCEUX and Mesen logs are understood: each line needs the PC and the A,
+//! X, Y, P and SP registers, and the CPU cycle count is compared too when
+//! the log has one. Lines without an instruction are skipped.
+//!
+//! Logs only list instructions, so interrupt entries are run through
+//! without consuming a line. The B and unused bits of P are not compared,
+//! as emulators log them differently.
+
+use std::collections::VecDeque;
+
+use crate::cpu::StatusFlags;
+use crate::nes::Nes;
+use crate::trace::trace;
+
+/// Status bits that are compared; B and the unused bit are not.
+const P_MASK: u8 = 0b1100_1111;
+
+/// The CPU state one log line gives for the start of an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceLine {
+    pub pc: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+    /// CPU cycles since power-on, if the log has them.
+    pub cycles: Option<u64>,
+}
+
+impl TraceLine {
+    /// Reads a line in any of the supported formats, or `None` if it doesn't
+    /// describe an instruction.
+    pub fn parse(line: &str) -> Option<TraceLine> {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let mut pc = None;
+        let (mut a, mut x, mut y, mut p, mut sp) = (None, None, None, None, None);
+        let mut cycles = None;
+        // Old nestest logs and Mesen 1 use CYC for the PPU dot, next to SL.
+        let cyc_is_dot = tokens.iter().any(|token| token.starts_with("SL:"));
+
+        let mut i = 0;
+        while i < tokens.len() {
+            let token = tokens[i];
+            i += 1;
+            let Some((key, mut value)) = token.split_once(':') else {
+                if pc.is_none() && token.len() == 4 {
+                    pc = u16::from_str_radix(token, 16).ok();
+                } else if let Some(count) = token.strip_prefix('c')
+                    && cycles.is_none()
+                {
+                    // FCEUX: c1234
+                    cycles = count.parse().ok();
+                }
+                continue;
+            };
+            if value.is_empty() && i < tokens.len() {
+                // `CYC:  7` or `PPU:  0, 21`
+                value = tokens[i];
+                i += 1;
+            }
+            let hex = || u8::from_str_radix(value, 16).ok();
+            match key {
+                // FCEUX: $C000:4C
+                _ if pc.is_none() && key.len() == 5 && key.starts_with('$') => {
+                    pc = u16::from_str_radix(&key[1..], 16).ok();
+                }
+                "A" => a = hex(),
+                "X" => x = hex(),
+                "Y" => y = hex(),
+                "SP" | "S" => sp = hex(),
+                "P" if value.len() == 2 => p = hex(),
+                "P" => p = parse_flag_letters(value),
+                "CYC" if !cyc_is_dot => cycles = value.parse().ok(),
+                "Cycle" => cycles = value.parse().ok(),
+                _ => {}
+            }
+        }
+
+        Some(TraceLine {
+            pc: pc?,
+            a: a?,
+            x: x?,
+            y: y?,
+            p: p?,
+            sp: sp?,
+            cycles,
+        })
+    }
+}
+
+// FCEUX and Mesen write P as flag letters, capitals for set bits, e.g.
+// `nvUbdIzc`.
+fn parse_flag_letters(value: &str) -> Option<u8> {
+    let mut p = 0;
+    for letter in value.chars() {
+        let bit = match letter.to_ascii_lowercase() {
+            'n' => 0x80,
+            'v' => 0x40,
+            'u' => 0x20,
+            'b' => 0x10,
+            'd' => 0x08,
+            'i' => 0x04,
+            'z' => 0x02,
+            'c' => 0x01,
+            _ => return None,
+        };
+        if letter.is_ascii_uppercase() {
+            p |= bit;
+        }
+    }
+    Some(p)
+}
+
+/// Where pico first disagreed with the log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// 1-based line number in the log.
+    pub line: usize,
+    /// The fields that differ: `PC`, `A`, `X`, `Y`, `P`, `SP` or `CYC`.
+    pub fields: Vec<&'static str>,
+    pub expected: String,
+    /// pico's own trace line for the same instruction.
+    pub actual: String,
+    /// The log lines before it that matched, oldest first.
+    pub context: Vec<String>,
+}
+
+impl Divergence {
+    pub fn report(&self) -> String {
+        let mut out = format!(
+            "diverged at line {}: {}\n",
+            self.line,
+            self.fields.join(", ")
+        );
+        for line in &self.context {
+            out += &format!("  {}\n", line);
+        }
+        out += &format!("- {}\n+ {}\n", self.expected, self.actual);
+        out
+    }
+}
+
+/// Steps a [`Nes`] through a log one line at a time.
+pub struct TraceCompare {
+    context: VecDeque<String>,
+    context_len: usize,
+    line: usize,
+    matched: usize,
+    /// Log cycles minus pico's, fixed by the first line that has cycles.
+    cycle_offset: Option<i128>,
+}
+
+impl TraceCompare {
+    /// `context_len` matching lines are kept to show before a divergence.
+    pub fn new(context_len: usize) -> Self {
+        TraceCompare {
+            context: VecDeque::with_capacity(context_len),
+            context_len,
+            line: 0,
+            matched: 0,
+            cycle_offset: None,
+        }
+    }
+
+    /// Lines that described an instruction and matched.
+    pub fn matched(&self) -> usize {
+        self.matched
+    }
+
+    /// Checks `nes` against the next log line, then runs the instruction.
+    /// The first instruction line sets pico's registers if its PC differs,
+    /// for logs that start mid-program like nestest's automation mode.
+    pub fn check(&mut self, nes: &mut Nes, line: &str) -> Result<(), Divergence> {
+        self.line += 1;
+        let Some(expected) = TraceLine::parse(line) else {
+            return Ok(());
+        };
+        if self.matched == 0 && nes.bus.cpu.registers.pc != expected.pc {
+            let registers = &mut nes.bus.cpu.registers;
+            registers.pc = expected.pc;
+            registers.a = expected.a;
+            registers.x = expected.x;
+            registers.y = expected.y;
+            registers.sp = expected.sp;
+            registers.status = StatusFlags::from_bits_truncate(expected.p);
+        }
+
+        let cycles = nes.cpu_cycles() as i128;
+        let offset = match (self.cycle_offset, expected.cycles) {
+            (None, Some(log_cycles)) => *self.cycle_offset.insert(log_cycles as i128 - cycles),
+            (offset, _) => offset.unwrap_or(0),
+        };
+
+        let registers = &nes.bus.cpu.registers;
+        let mut fields = Vec::new();
+        for (name, same) in [
+            ("PC", registers.pc == expected.pc),
+            ("A", registers.a == expected.a),
+            ("X", registers.x == expected.x),
+            ("Y", registers.y == expected.y),
+            ("P", (registers.status.bits() ^ expected.p) & P_MASK == 0),
+            ("SP", registers.sp == expected.sp),
+            (
+                "CYC",
+                expected
+                    .cycles
+                    .is_none_or(|log_cycles| log_cycles as i128 == cycles + offset),
+            ),
+        ] {
+            if !same {
+                fields.push(name);
+            }
+        }
+
+        let ppu = &nes.bus.ppu;
+        let actual = format!(
+            "{} PPU:{:3},{:3} CYC:{}",
+            trace(&nes.bus.cpu, &nes.bus),
+            ppu.scanline,
+            ppu.cycle,
+            cycles + offset
+        );
+        if !fields.is_empty() {
+            return Err(Divergence {
+                line: self.line,
+                fields,
+                expected: line.trim_end().to_string(),
+                actual,
+                context: self.context.iter().cloned().collect(),
+            });
+        }
+
+        if self.context_len > 0 {
+            if self.context.len() == self.context_len {
+                self.context.pop_front();
+            }
+            self.context.push_back(line.trim_end().to_string());
+        }
+        self.matched += 1;
+
+        loop {
+            let result = nes.clock();
+            // A halted CPU shows up as a PC mismatch on the next line.
+            if result.stop.is_some() || (result.instruction_complete && result.interrupt.is_none())
+            {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::cart::test::test_nes;
+
+    #[test]
+    fn test_parses_nestest_fceux_and_mesen_lines() {
+        let expected = TraceLine {
+            pc: 0xC000,
+            a: 0x01,
+            x: 0x02,
+            y: 0x03,
+            p: 0x24,
+            sp: 0xFD,
+            cycles: Some(7),
+        };
+        let lines = [
+            "C000  4C F5 C5  JMP $C5F5                       A:01 X:02 Y:03 P:24 SP:FD PPU:  0, 21 CYC:7",
+            "c7        A:01 X:02 Y:03 S:FD P:nvUbdIzc  $C000:4C F5 C5  JMP $C5F5",
+            "C000 $4C $F5 $C5  JMP $C5F5   A:01 X:02 Y:03 P:24 SP:FD CYC:21  SL:0   CPU Cycle:7",
+        ];
+        for line in lines {
+            assert_eq!(TraceLine::parse(line), Some(expected), "{}", line);
+        }
+        assert_eq!(TraceLine::parse("Log started"), None);
+    }
+
+    #[test]
+    fn test_stops_at_first_divergence() {
+        let program = assemble(
+            "
+                    .org $8000
+            reset:  ldx #0
+            loop:   inx
+                    stx $10
+                    cpx #5
+                    bne loop
+            done:   jmp done
+                    .org $FFFC
+                    .word reset, reset
+            ",
+        )
+        .unwrap();
+        let new_nes = || test_nes(&program.slice(0x8000, 0x8000));
+
+        // A reference log from pico itself, with one register changed.
+        let mut nes = new_nes();
+        let mut log = Vec::new();
+        for _ in 0..12 {
+            log.push(format!(
+                "{} CYC:{}",
+                trace(&nes.bus.cpu, &nes.bus),
+                nes.cpu_cycles()
+            ));
+            nes.clock();
+        }
+        log[9] = log[9].replace("X:02", "X:07");
+
+        let mut nes = new_nes();
+        let mut compare = TraceCompare::new(3);
+        let divergence = log
+            .iter()
+            .find_map(|line| compare.check(&mut nes, line).err())
+            .unwrap();
+        assert_eq!(compare.matched(), 9);
+        assert_eq!(divergence.line, 10);
+        assert_eq!(divergence.fields, ["X"]);
+        assert_eq!(divergence.context, log[6..9]);
+        assert!(divergence.actual.contains("X:02"));
+        assert!(divergence.report().starts_with("diverged at line 10: X\n"));
+    }
+}
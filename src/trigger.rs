@@ -0,0 +1,371 @@
+//! Capture triggers: conditions on RAM, checked at the end of every frame,
+//! that save a screenshot and a state dump of the frame they fire on. Meant
+//! for catching one-frame glitches without watching for them. The same
+//! conditions can instead raise events, e.g. for achievements; see
+//! [`crate::nes::Nes::add_event_trigger`].
+//!
+//! Conditions compare a byte with its value at the end of the previous
+//! frame, so checking them costs nothing per memory access:
+//!
+//! - `$0075 changes`, `$0075 changes to 8`, `$0075 changes from 3 to 2`
+//! - `$0075 is 8`, which holds on every frame the byte is 8
+//! - `$07E0 increases`, `$07E0 decreases` (as unsigned bytes), `$07E0
+//!   increases by 10` (exactly, wrapping past $FF)
+//! - several joined with `and`, which all have to hold on the same frame
+//!
+//! (`$` for hex, plain numbers are decimal).
+
+use std::fmt;
+
+use crate::ppu::framebuffer::Framebuffer;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    /// The byte differs from its value at the end of the previous frame.
+    Changes(u16),
+    /// The byte changed and now holds the value.
+    ChangesTo(u16, u8),
+    /// The byte went from the first value to the second.
+    ChangesFrom(u16, u8, u8),
+    /// The byte holds the value, changed or not.
+    Is(u16, u8),
+    Increases(u16),
+    Decreases(u16),
+    /// The byte went up by exactly this much, wrapping past $FF.
+    IncreasesBy(u16, u8),
+    /// The byte went down by exactly this much, wrapping past $00.
+    DecreasesBy(u16, u8),
+    /// All of these hold on the same frame.
+    All(Vec<Condition>),
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let clauses = text
+            .split(" and ")
+            .map(|clause| Self::parse_clause(clause).ok_or(text))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|text| format!("Invalid trigger condition: {}", text))?;
+        Ok(match <[Condition; 1]>::try_from(clauses) {
+            Ok([clause]) => clause,
+            Err(clauses) => Condition::All(clauses),
+        })
+    }
+
+    fn parse_clause(text: &str) -> Option<Self> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let byte = |text: &str| parse_number(text).and_then(|value| u8::try_from(value).ok());
+        let (addr, rest) = words.split_first()?;
+        let addr = parse_number(addr)?;
+        Some(match rest {
+            ["changes"] => Condition::Changes(addr),
+            ["changes", "to", value] => Condition::ChangesTo(addr, byte(value)?),
+            ["changes", "from", from, "to", to] => {
+                Condition::ChangesFrom(addr, byte(from)?, byte(to)?)
+            }
+            ["is", value] => Condition::Is(addr, byte(value)?),
+            ["increases"] => Condition::Increases(addr),
+            ["decreases"] => Condition::Decreases(addr),
+            ["increases", "by", delta] => Condition::IncreasesBy(addr, byte(delta)?),
+            ["decreases", "by", delta] => Condition::DecreasesBy(addr, byte(delta)?),
+            _ => return None,
+        })
+    }
+
+    /// Every address the condition reads.
+    fn addrs(&self) -> Vec<u16> {
+        match *self {
+            Condition::Changes(addr)
+            | Condition::ChangesTo(addr, _)
+            | Condition::ChangesFrom(addr, _, _)
+            | Condition::Is(addr, _)
+            | Condition::Increases(addr)
+            | Condition::Decreases(addr)
+            | Condition::IncreasesBy(addr, _)
+            | Condition::DecreasesBy(addr, _) => vec![addr],
+            Condition::All(ref clauses) => clauses.iter().flat_map(Condition::addrs).collect(),
+        }
+    }
+
+    /// Whether the condition holds, given each address's value at the end
+    /// of the previous frame and now.
+    fn holds(&self, values: &impl Fn(u16) -> (u8, u8)) -> bool {
+        match *self {
+            Condition::All(ref clauses) => clauses.iter().all(|clause| clause.holds(values)),
+            Condition::Changes(addr)
+            | Condition::ChangesTo(addr, _)
+            | Condition::ChangesFrom(addr, _, _)
+            | Condition::Is(addr, _)
+            | Condition::Increases(addr)
+            | Condition::Decreases(addr)
+            | Condition::IncreasesBy(addr, _)
+            | Condition::DecreasesBy(addr, _) => {
+                let (last, value) = values(addr);
+                match *self {
+                    Condition::Changes(_) => value != last,
+                    Condition::ChangesTo(_, target) => value != last && value == target,
+                    Condition::ChangesFrom(_, from, to) => last == from && value == to,
+                    Condition::Is(_, target) => value == target,
+                    Condition::Increases(_) => value > last,
+                    Condition::Decreases(_) => value < last,
+                    Condition::IncreasesBy(_, delta) => value == last.wrapping_add(delta),
+                    Condition::DecreasesBy(_, delta) => value == last.wrapping_sub(delta),
+                    Condition::All(_) => unreachable!(),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Changes(addr) => write!(f, "${:04X} changes", addr),
+            Condition::ChangesTo(addr, value) => write!(f, "${:04X} changes to {}", addr, value),
+            Condition::ChangesFrom(addr, from, to) => {
+                write!(f, "${:04X} changes from {} to {}", addr, from, to)
+            }
+            Condition::Is(addr, value) => write!(f, "${:04X} is {}", addr, value),
+            Condition::Increases(addr) => write!(f, "${:04X} increases", addr),
+            Condition::Decreases(addr) => write!(f, "${:04X} decreases", addr),
+            Condition::IncreasesBy(addr, delta) => {
+                write!(f, "${:04X} increases by {}", addr, delta)
+            }
+            Condition::DecreasesBy(addr, delta) => {
+                write!(f, "${:04X} decreases by {}", addr, delta)
+            }
+            Condition::All(clauses) => {
+                for (i, clause) in clauses.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(" and ")?;
+                    }
+                    write!(f, "{}", clause)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn parse_number(text: &str) -> Option<u16> {
+    match text.strip_prefix('$') {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trigger {
+    pub name: String,
+    pub condition: Condition,
+}
+
+/// What a trigger saved when it fired.
+pub struct Capture {
+    pub trigger: String,
+    /// PPU frame counter of the captured frame.
+    pub frame: u64,
+    /// [`crate::nes::Nes::dump_state`] at the end of the frame.
+    pub state: Vec<u8>,
+    pub screenshot: Framebuffer,
+}
+
+/// A trigger added with [`crate::nes::Nes::add_event_trigger`] firing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TriggerEvent {
+    pub trigger: String,
+    /// PPU frame counter of the frame it fired on.
+    pub frame: u64,
+}
+
+/// Triggers, the addresses each one reads, and the bytes it last saw there.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Triggers {
+    pub(crate) triggers: Vec<Trigger>,
+    addrs: Vec<Vec<u16>>,
+    last: Vec<Option<Vec<u8>>>,
+}
+
+impl Triggers {
+    pub(crate) fn add(&mut self, trigger: Trigger) {
+        self.addrs.push(trigger.condition.addrs());
+        self.triggers.push(trigger);
+        self.last.push(None);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.triggers.clear();
+        self.addrs.clear();
+        self.last.clear();
+    }
+
+    /// Checks every trigger against memory read through `peek` and returns
+    /// the names of those that fired. A trigger's first check only records
+    /// the current value.
+    pub(crate) fn check(&mut self, peek: impl Fn(u16) -> u8) -> Vec<String> {
+        let mut fired = Vec::new();
+        for ((trigger, addrs), last) in self.triggers.iter().zip(&self.addrs).zip(&mut self.last) {
+            let values: Vec<u8> = addrs.iter().map(|&addr| peek(addr)).collect();
+            if let Some(last) = last {
+                let lookup = |addr: u16| {
+                    let i = addrs.iter().position(|&a| a == addr).unwrap_or(0);
+                    (last[i], values[i])
+                };
+                if trigger.condition.holds(&lookup) {
+                    fired.push(trigger.name.clone());
+                }
+            }
+            *last = Some(values);
+        }
+        fired
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::cart::test::test_nes;
+
+    #[test]
+    fn test_conditions_fire_on_change() {
+        let condition = Condition::parse("$0075 changes to 8").unwrap();
+        assert_eq!(condition, Condition::ChangesTo(0x75, 8));
+        assert_eq!(
+            Condition::parse(&condition.to_string()),
+            Ok(condition.clone())
+        );
+        assert_eq!(
+            Condition::parse("117 changes"),
+            Ok(Condition::Changes(0x75))
+        );
+        assert!(Condition::parse("$0075 changes to 300").is_err());
+        assert!(Condition::parse("$0075 equals 8").is_err());
+
+        let mut triggers = Triggers::default();
+        triggers.add(Trigger {
+            name: "lives".to_string(),
+            condition,
+        });
+        triggers.add(Trigger {
+            name: "any".to_string(),
+            condition: Condition::Changes(0x75),
+        });
+        // Already 8 on the first check: nothing changed yet.
+        assert!(triggers.check(|_| 8).is_empty());
+        assert_eq!(triggers.check(|_| 7), ["any"]);
+        assert_eq!(triggers.check(|_| 8), ["lives", "any"]);
+        assert!(triggers.check(|_| 8).is_empty());
+    }
+
+    #[test]
+    fn test_values_deltas_and_combinations() {
+        let text = "$0075 changes from 3 to 2 and $0760 is 1";
+        let condition = Condition::parse(text).unwrap();
+        assert_eq!(
+            condition,
+            Condition::All(vec![
+                Condition::ChangesFrom(0x75, 3, 2),
+                Condition::Is(0x760, 1)
+            ])
+        );
+        assert_eq!(
+            condition.to_string(),
+            "$0075 changes from 3 to 2 and $0760 is 1"
+        );
+        assert!(Condition::parse("$0075 changes and").is_err());
+
+        let mut triggers = Triggers::default();
+        for (name, condition) in [
+            ("died", text),
+            ("world2", "$0760 is 1"),
+            ("score", "$07E0 increases by 10"),
+            ("down", "$07E0 decreases"),
+        ] {
+            triggers.add(Trigger {
+                name: name.to_string(),
+                condition: Condition::parse(condition).unwrap(),
+            });
+        }
+        let frame = |lives: u8, world: u8, score: u8| {
+            move |addr| match addr {
+                0x75 => lives,
+                0x760 => world,
+                _ => score,
+            }
+        };
+        assert!(triggers.check(frame(3, 0, 0xFA)).is_empty());
+        // Up by 10 through $FF, but lower than before.
+        assert_eq!(triggers.check(frame(2, 0, 0x04)), ["score", "down"]);
+        assert_eq!(triggers.check(frame(3, 1, 0x04)), ["world2"]);
+        assert_eq!(
+            triggers.check(frame(2, 1, 0x01)),
+            ["died", "world2", "down"]
+        );
+    }
+
+    #[test]
+    fn test_nes_raises_events() {
+        let program = assemble(
+            "
+                    .org $8000
+            reset:  lda #$80
+                    sta $2000
+            loop:   jmp loop
+            nmi:    inc $10
+                    rti
+                    .org $FFFA
+                    .word nmi, reset, reset
+            ",
+        )
+        .unwrap();
+        let mut nes = test_nes(&program.slice(0x8000, 0x8000));
+        nes.add_event_trigger(Trigger {
+            name: "tick".to_string(),
+            condition: Condition::parse("$10 increases by 1").unwrap(),
+        });
+
+        for _ in 0..4 {
+            nes.step_frame();
+        }
+        let events = nes.take_trigger_events();
+        let frames: Vec<u64> = events.iter().map(|event| event.frame).collect();
+        assert_eq!(frames, [2, 3, 4]);
+        assert!(events.iter().all(|event| event.trigger == "tick"));
+        assert!(nes.take_captures().is_empty());
+    }
+
+    #[test]
+    fn test_nes_captures_the_frame_a_trigger_fires_on() {
+        // Counts frames in $10 from the NMI handler.
+        let program = assemble(
+            "
+                    .org $8000
+            reset:  lda #$80
+                    sta $2000
+            loop:   jmp loop
+            nmi:    inc $10
+                    rti
+                    .org $FFFA
+                    .word nmi, reset, reset
+            ",
+        )
+        .unwrap();
+        let mut nes = test_nes(&program.slice(0x8000, 0x8000));
+        nes.add_trigger(Trigger {
+            name: "fifth".to_string(),
+            condition: Condition::parse("$10 changes to 5").unwrap(),
+        });
+
+        for _ in 0..10 {
+            nes.step_frame();
+        }
+        let captures = nes.take_captures();
+        assert_eq!(captures.len(), 1);
+        let capture = &captures[0];
+        assert_eq!(capture.trigger, "fifth");
+        assert_eq!(capture.frame, 5);
+        assert_eq!(&capture.state[..8], b"PICODUMP");
+        assert!(nes.take_captures().is_empty());
+    }
+}
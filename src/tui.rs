@@ -0,0 +1,203 @@
+//! Terminal output for `--tui`: frames drawn with upper half block
+//! characters, two pixels per cell, in 24-bit or 256-colour ANSI, plus
+//! keyboard decoding for terminals in raw mode.
+
+use crate::joypad::JoypadButton;
+use crate::ppu::framebuffer::Framebuffer;
+
+/// Frames a button stays held after its key was last seen. Terminals send
+/// no key releases, so a held key is only visible through autorepeat.
+pub const HOLD_FRAMES: u8 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TuiColor {
+    TrueColor,
+    /// The xterm 256-colour palette, for terminals without 24-bit colour.
+    Ansi256,
+}
+
+/// Renders `framebuffer` scaled to fit `columns` x `rows` cells, keeping
+/// its aspect ratio. The output starts by homing the cursor, so printing
+/// each frame redraws in place.
+pub fn render(framebuffer: &Framebuffer, columns: usize, rows: usize, color: TuiColor) -> String {
+    let scale = (columns as f64 / Framebuffer::WIDTH as f64)
+        .min(2.0 * rows as f64 / Framebuffer::HEIGHT as f64)
+        .min(1.0);
+    let width = ((Framebuffer::WIDTH as f64 * scale) as usize).max(1);
+    let height = ((Framebuffer::HEIGHT as f64 * scale) as usize / 2).max(1);
+
+    let pixel = |x: usize, y: usize| {
+        let x = x * Framebuffer::WIDTH / width;
+        let y = (y * Framebuffer::HEIGHT / (height * 2)).min(Framebuffer::HEIGHT - 1);
+        let base = (y * Framebuffer::WIDTH + x) * 3;
+        let rgb = &framebuffer.data[base..base + 3];
+        (rgb[0], rgb[1], rgb[2])
+    };
+
+    let mut out = String::from("\x1b[H");
+    for row in 0..height {
+        let mut last = None;
+        for column in 0..width {
+            let cell = (pixel(column, row * 2), pixel(column, row * 2 + 1));
+            if last != Some(cell) {
+                out += &format!(
+                    "\x1b[{};{}m",
+                    sgr_color(38, cell.0, color),
+                    sgr_color(48, cell.1, color)
+                );
+                last = Some(cell);
+            }
+            out.push('▀');
+        }
+        out += "\x1b[0m\r\n";
+    }
+    out
+}
+
+fn sgr_color(base: u8, (r, g, b): (u8, u8, u8), color: TuiColor) -> String {
+    match color {
+        TuiColor::TrueColor => format!("{};2;{};{};{}", base, r, g, b),
+        TuiColor::Ansi256 => format!("{};5;{}", base, ansi256(r, g, b)),
+    }
+}
+
+/// Nearest xterm 256-colour index, from the 6x6x6 cube or the grey ramp.
+pub fn ansi256(r: u8, g: u8, b: u8) -> u8 {
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    let level = |c: u8| {
+        (0..6)
+            .min_by_key(|&i| (LEVELS[i] as i32 - c as i32).abs())
+            .unwrap()
+    };
+    let (ri, gi, bi) = (level(r), level(g), level(b));
+    let cube = (LEVELS[ri], LEVELS[gi], LEVELS[bi]);
+
+    let average = (r as u32 + g as u32 + b as u32) / 3;
+    let grey_index = (average.saturating_sub(3) / 10).min(23);
+    let grey = (8 + 10 * grey_index) as u8;
+
+    let distance = |(cr, cg, cb): (u8, u8, u8)| {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(cr, r) + d(cg, g) + d(cb, b)
+    };
+    if distance((grey, grey, grey)) < distance(cube) {
+        232 + grey_index as u8
+    } else {
+        16 + 36 * ri as u8 + 6 * gi as u8 + bi as u8
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TuiKey {
+    Button(JoypadButton),
+    Reset,
+    Quit,
+}
+
+/// Decodes raw-mode terminal input: arrow keys for the d-pad, X and Z for A
+/// and B, Enter for Start, Space for Select, R to reset and Q, Esc or
+/// Ctrl-C to quit.
+pub fn parse_keys(bytes: &[u8]) -> Vec<TuiKey> {
+    let mut keys = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let key = match bytes[i] {
+            0x1B if matches!(bytes.get(i + 1), Some(b'[' | b'O')) && i + 2 < bytes.len() => {
+                i += 2;
+                match bytes[i] {
+                    b'A' => Some(TuiKey::Button(JoypadButton::UP)),
+                    b'B' => Some(TuiKey::Button(JoypadButton::DOWN)),
+                    b'C' => Some(TuiKey::Button(JoypadButton::RIGHT)),
+                    b'D' => Some(TuiKey::Button(JoypadButton::LEFT)),
+                    _ => None,
+                }
+            }
+            0x1B | 0x03 | b'q' | b'Q' => Some(TuiKey::Quit),
+            b'x' | b'X' => Some(TuiKey::Button(JoypadButton::BUTTON_A)),
+            b'z' | b'Z' => Some(TuiKey::Button(JoypadButton::BUTTON_B)),
+            b'\r' | b'\n' => Some(TuiKey::Button(JoypadButton::START)),
+            b' ' => Some(TuiKey::Button(JoypadButton::SELECT)),
+            b'r' | b'R' => Some(TuiKey::Reset),
+            _ => None,
+        };
+        keys.extend(key);
+        i += 1;
+    }
+    keys
+}
+
+/// Buttons pressed from the terminal, each released [`HOLD_FRAMES`] frames
+/// after its key was last seen.
+#[derive(Debug, Default)]
+pub struct HeldButtons {
+    frames_left: [u8; 8],
+}
+
+impl HeldButtons {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn press(&mut self, button: JoypadButton) {
+        for (bit, frames) in self.frames_left.iter_mut().enumerate() {
+            if button.bits() & (1 << bit) != 0 {
+                *frames = HOLD_FRAMES;
+            }
+        }
+    }
+
+    /// The buttons held this frame. Call once per frame.
+    pub fn next_frame(&mut self) -> JoypadButton {
+        let mut held = JoypadButton::empty();
+        for (bit, frames) in self.frames_left.iter_mut().enumerate() {
+            if *frames > 0 {
+                *frames -= 1;
+                held |= JoypadButton::from_bits_truncate(1 << bit);
+            }
+        }
+        held
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render_fits_terminal() {
+        let mut framebuffer = Framebuffer::new();
+        framebuffer.set_pixel(0, 0, (0xFF, 0x00, 0x00));
+        let out = render(&framebuffer, 80, 24, TuiColor::TrueColor);
+
+        assert!(out.starts_with("\x1b[H\x1b[38;2;255;0;0;48;2;0;0;0m▀"));
+        let lines: Vec<&str> = out.split("\r\n").filter(|l| !l.is_empty()).collect();
+        assert_eq!(lines.len(), 24);
+        assert_eq!(lines[1].matches('▀').count(), 51);
+
+        let out = render(&framebuffer, 300, 200, TuiColor::Ansi256);
+        assert!(out.contains("\x1b[38;5;196;48;5;16m"));
+        assert_eq!(out.matches("\r\n").count(), 120);
+    }
+
+    #[test]
+    fn test_keys_are_held_between_repeats() {
+        let keys = parse_keys(b"\x1b[Ax\x1bq");
+        assert_eq!(
+            keys,
+            [
+                TuiKey::Button(JoypadButton::UP),
+                TuiKey::Button(JoypadButton::BUTTON_A),
+                TuiKey::Quit,
+                TuiKey::Quit,
+            ]
+        );
+
+        let mut held = HeldButtons::new();
+        held.press(JoypadButton::UP);
+        for _ in 0..HOLD_FRAMES {
+            assert_eq!(held.next_frame(), JoypadButton::UP);
+        }
+        assert_eq!(held.next_frame(), JoypadButton::empty());
+        assert_eq!(ansi256(0x80, 0x80, 0x80), 244);
+    }
+}
@@ -0,0 +1,170 @@
+//! Notices a console that has stopped making progress during long unattended
+//! runs, such as test ROMs and fuzzing: the CPU hitting a jam opcode, or a
+//! game going a number of frames without writing to an "alive" region of
+//! memory it normally updates every frame (a frame counter, say). Each trip
+//! is recorded as an event and can reset the console.
+
+use std::fmt;
+
+use crate::cpu::ResetKind;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchdogTrip {
+    /// The CPU ran a jam opcode at this address.
+    Jammed(u16),
+    /// Nothing was written to the alive region for this many frames.
+    Stalled(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchdogEvent {
+    pub trip: WatchdogTrip,
+    /// The PPU frame that ended when the watchdog tripped.
+    pub frame: u64,
+    /// How the console was reset afterwards, if it was.
+    pub reset: Option<ResetKind>,
+}
+
+impl fmt::Display for WatchdogEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "frame {}: ", self.frame)?;
+        match self.trip {
+            WatchdogTrip::Jammed(pc) => write!(f, "CPU jammed at ${:04X}", pc)?,
+            WatchdogTrip::Stalled(frames) => write!(f, "no sign of life for {} frames", frames)?,
+        }
+        match self.reset {
+            Some(ResetKind::PowerOn) => write!(f, ", powered off and on"),
+            Some(ResetKind::Reset) => write!(f, ", reset"),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct AliveRegion {
+    start: u16,
+    end: u16,
+    frames: u32,
+}
+
+/// Watches for jams by default; see [`Watchdog::alive_region`] and
+/// [`Watchdog::auto_reset`].
+#[derive(Debug, Clone, Default)]
+pub struct Watchdog {
+    alive: Option<AliveRegion>,
+    reset: Option<ResetKind>,
+    /// Frames since the alive region was last written.
+    quiet_frames: u32,
+    written: bool,
+    /// Set once a trip has been reported, until the condition clears.
+    stalled: bool,
+    jammed: bool,
+    events: Vec<WatchdogEvent>,
+}
+
+impl Watchdog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also trips when the CPU writes nothing to `start..=end` for
+    /// `frames` frames in a row.
+    pub fn alive_region(mut self, start: u16, end: u16, frames: u32) -> Self {
+        self.alive = Some(AliveRegion { start, end, frames });
+        self
+    }
+
+    /// Resets the console this way each time the watchdog trips.
+    pub fn auto_reset(mut self, kind: Option<ResetKind>) -> Self {
+        self.reset = kind;
+        self
+    }
+
+    /// Trips since the last call, oldest first.
+    pub fn take_events(&mut self) -> Vec<WatchdogEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Notes a CPU write to `addr`.
+    pub(crate) fn write(&mut self, addr: u16) {
+        if let Some(alive) = self.alive
+            && (alive.start..=alive.end).contains(&addr)
+        {
+            self.written = true;
+        }
+    }
+
+    /// Checks the frame that just ended, with the CPU jammed at
+    /// `jammed_at` if it is. Returns how to reset the console if it tripped.
+    pub(crate) fn end_frame(&mut self, frame: u64, jammed_at: Option<u16>) -> Option<ResetKind> {
+        let mut trip = None;
+        match jammed_at {
+            Some(pc) if !self.jammed => {
+                self.jammed = true;
+                trip = Some(WatchdogTrip::Jammed(pc));
+            }
+            Some(_) => {}
+            None => self.jammed = false,
+        }
+        if let Some(alive) = self.alive {
+            if std::mem::take(&mut self.written) {
+                self.quiet_frames = 0;
+                self.stalled = false;
+            } else {
+                self.quiet_frames = self.quiet_frames.saturating_add(1);
+                if self.quiet_frames >= alive.frames && !self.stalled {
+                    self.stalled = true;
+                    trip = trip.or(Some(WatchdogTrip::Stalled(self.quiet_frames)));
+                }
+            }
+        }
+
+        let trip = trip?;
+        self.events.push(WatchdogEvent {
+            trip,
+            frame,
+            reset: self.reset,
+        });
+        if self.reset.is_some() {
+            self.quiet_frames = 0;
+            self.stalled = false;
+            self.jammed = false;
+        }
+        self.reset
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cart::test::test_nes;
+
+    #[test]
+    fn test_trips_on_jam_and_resets() {
+        // NOP; JAM
+        let mut nes = test_nes(&[0xEA, 0x02]);
+        nes.set_watchdog(Some(Watchdog::new().auto_reset(Some(ResetKind::Reset))));
+        for _ in 0..3 {
+            nes.step_frame();
+        }
+        let events = nes.watchdog_mut().unwrap().take_events();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].trip, WatchdogTrip::Jammed(0x8001));
+        assert_eq!(events[0].to_string(), "frame 1: CPU jammed at $8001, reset");
+    }
+
+    #[test]
+    fn test_trips_once_when_alive_region_goes_quiet() {
+        // INC $10; BNE back, until $10 wraps to zero; then JMP to itself.
+        let mut nes = test_nes(&[0xE6, 0x10, 0xD0, 0xFC, 0x4C, 0x04, 0x80]);
+        nes.set_watchdog(Some(Watchdog::new().alive_region(0x0010, 0x0010, 5)));
+        for _ in 0..20 {
+            nes.step_frame();
+        }
+        let events = nes.watchdog_mut().unwrap().take_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].trip, WatchdogTrip::Stalled(5));
+        assert_eq!(events[0].reset, None);
+        assert_eq!(nes.bus.cpu.jammed_at(), None);
+    }
+}
@@ -0,0 +1,46 @@
+//! Writes mono 16-bit PCM WAV files.
+
+/// Encodes `samples`, from -1.0 to 1.0, as a WAV file.
+pub fn encode(samples: &[f32], sample_rate: u32) -> Vec<u8> {
+    let data_len = (samples.len() * 2) as u32;
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+
+    wav.extend_from_slice(b"fmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // mono
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * 2).to_le_bytes()); // bytes per second
+    wav.extend_from_slice(&2u16.to_le_bytes()); // bytes per frame
+    wav.extend_from_slice(&16u16.to_le_bytes());
+
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for &sample in samples {
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+        wav.extend_from_slice(&value.to_le_bytes());
+    }
+    wav
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let wav = encode(&[0.0, 1.0, -1.0, 2.0], 48_000);
+
+        assert_eq!(wav.len(), 44 + 8);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(&wav[4..8], &44u32.to_le_bytes());
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(&wav[24..28], &48_000u32.to_le_bytes());
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(&wav[40..44], &8u32.to_le_bytes());
+        assert_eq!(&wav[44..], &[0, 0, 0xFF, 0x7F, 0x01, 0x80, 0xFF, 0x7F]);
+    }
+}
@@ -0,0 +1,568 @@
+//! Differential fuzzing of the CPU core against a small reference 6502.
+//!
+//! Each case fills memory with random bytes, writes a run of random
+//! documented instructions at a random address and steps both CPUs from the
+//! same random registers, comparing registers, flags and the bytes written
+//! after every instruction, and all of memory at the end. A case ends early
+//! when the program reaches an undocumented opcode, which the reference
+//! doesn't model.
+//!
+//! The reference decodes opcodes from their `aaabbbcc` bit fields rather
+//! than a listing, so it shares nothing with `opcodes.rs`. It models the
+//! NES CPU: no decimal mode. `PICO_FUZZ_SEED` and `PICO_FUZZ_CASES` choose
+//! the run; a failure names the seed and case to rerun.
+
+use pico::cpu::{CPU, CpuModel, StatusFlags};
+use pico::memory::FlatMemory;
+
+const CASES: u64 = 300;
+const INSTRUCTIONS: usize = 64;
+/// B and the unused bit don't exist in P and aren't compared.
+const P_MASK: u8 = 0b1100_1111;
+
+const C: u8 = 0x01;
+const Z: u8 = 0x02;
+const I: u8 = 0x04;
+const V: u8 = 0x40;
+const N: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    IndirectX,
+    IndirectY,
+    Indirect,
+    Relative,
+}
+
+impl Mode {
+    fn operand_bytes(self) -> u16 {
+        match self {
+            Mode::Implied | Mode::Accumulator => 0,
+            Mode::Absolute | Mode::AbsoluteX | Mode::AbsoluteY | Mode::Indirect => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// Mnemonic and addressing mode of a documented opcode.
+fn decode(opcode: u8) -> Option<(&'static str, Mode)> {
+    let implied = match opcode {
+        0x00 => "BRK",
+        0x08 => "PHP",
+        0x18 => "CLC",
+        0x20 => return Some(("JSR", Mode::Absolute)),
+        0x28 => "PLP",
+        0x38 => "SEC",
+        0x40 => "RTI",
+        0x48 => "PHA",
+        0x58 => "CLI",
+        0x60 => "RTS",
+        0x68 => "PLA",
+        0x78 => "SEI",
+        0x88 => "DEY",
+        0x8A => "TXA",
+        0x98 => "TYA",
+        0x9A => "TXS",
+        0xA8 => "TAY",
+        0xAA => "TAX",
+        0xB8 => "CLV",
+        0xBA => "TSX",
+        0xC8 => "INY",
+        0xCA => "DEX",
+        0xD8 => "CLD",
+        0xE8 => "INX",
+        0xEA => "NOP",
+        0xF8 => "SED",
+        _ => "",
+    };
+    if !implied.is_empty() {
+        return Some((implied, Mode::Implied));
+    }
+
+    let (a, b, c) = (opcode >> 5, (opcode >> 2) & 7, opcode & 3);
+    let mode = match (c, a, b) {
+        (1, 4, 2) => return None,
+        (1, _, _) => [
+            Mode::IndirectX,
+            Mode::ZeroPage,
+            Mode::Immediate,
+            Mode::Absolute,
+            Mode::IndirectY,
+            Mode::ZeroPageX,
+            Mode::AbsoluteY,
+            Mode::AbsoluteX,
+        ][b as usize],
+        (2, 5, 0) => Mode::Immediate,
+        (2, _, 1) => Mode::ZeroPage,
+        (2, 0..=3, 2) => Mode::Accumulator,
+        (2, _, 3) => Mode::Absolute,
+        (2, 4 | 5, 5) => Mode::ZeroPageY,
+        (2, _, 5) => Mode::ZeroPageX,
+        (2, 5, 7) => Mode::AbsoluteY,
+        (2, 0..=3 | 6 | 7, 7) => Mode::AbsoluteX,
+        (0, _, 4) => Mode::Relative,
+        (0, 5..=7, 0) => Mode::Immediate,
+        (0, 1 | 4..=7, 1) => Mode::ZeroPage,
+        (0, 3, 3) => Mode::Indirect,
+        (0, 1..=7, 3) => Mode::Absolute,
+        (0, 4 | 5, 5) => Mode::ZeroPageX,
+        (0, 5, 7) => Mode::AbsoluteX,
+        _ => return None,
+    };
+    let mnemonic = match (c, mode) {
+        (0, Mode::Relative) => ["BPL", "BMI", "BVC", "BVS", "BCC", "BCS", "BNE", "BEQ"][a as usize],
+        (0, _) => ["", "BIT", "JMP", "JMP", "STY", "LDY", "CPY", "CPX"][a as usize],
+        (1, _) => ["ORA", "AND", "EOR", "ADC", "STA", "LDA", "CMP", "SBC"][a as usize],
+        _ => ["ASL", "ROL", "LSR", "ROR", "STX", "LDX", "DEC", "INC"][a as usize],
+    };
+    Some((mnemonic, mode))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct State {
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    sp: u8,
+    pc: u16,
+}
+
+impl std::fmt::Display for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+            self.pc, self.a, self.x, self.y, self.p, self.sp
+        )
+    }
+}
+
+struct Reference {
+    state: State,
+    memory: Vec<u8>,
+    /// Addresses written by the last instruction.
+    writes: Vec<u16>,
+}
+
+impl Reference {
+    fn read(&self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    fn read_u16(&self, addr: u16) -> u16 {
+        u16::from_le_bytes([self.read(addr), self.read(addr.wrapping_add(1))])
+    }
+
+    // A pointer in page zero, whose high byte wraps within the page.
+    fn read_zero_page_u16(&self, addr: u8) -> u16 {
+        u16::from_le_bytes([
+            self.read(addr as u16),
+            self.read(addr.wrapping_add(1) as u16),
+        ])
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.memory[addr as usize] = value;
+        self.writes.push(addr);
+    }
+
+    fn push(&mut self, value: u8) {
+        self.write(0x0100 | self.state.sp as u16, value);
+        self.state.sp = self.state.sp.wrapping_sub(1);
+    }
+
+    fn pull(&mut self) -> u8 {
+        self.state.sp = self.state.sp.wrapping_add(1);
+        self.read(0x0100 | self.state.sp as u16)
+    }
+
+    fn set_flag(&mut self, flag: u8, on: bool) {
+        if on {
+            self.state.p |= flag;
+        } else {
+            self.state.p &= !flag;
+        }
+    }
+
+    fn set_nz(&mut self, value: u8) {
+        self.set_flag(Z, value == 0);
+        self.set_flag(N, value & 0x80 != 0);
+    }
+
+    fn address(&self, mode: Mode, operand: u16) -> u16 {
+        let s = &self.state;
+        let zp = operand as u8;
+        match mode {
+            Mode::ZeroPage => zp as u16,
+            Mode::ZeroPageX => zp.wrapping_add(s.x) as u16,
+            Mode::ZeroPageY => zp.wrapping_add(s.y) as u16,
+            Mode::Absolute => operand,
+            Mode::AbsoluteX => operand.wrapping_add(s.x as u16),
+            Mode::AbsoluteY => operand.wrapping_add(s.y as u16),
+            Mode::IndirectX => self.read_zero_page_u16(zp.wrapping_add(s.x)),
+            Mode::IndirectY => self.read_zero_page_u16(zp).wrapping_add(s.y as u16),
+            Mode::Indirect => {
+                // The pointer's high byte comes from the same page.
+                let high = (operand & 0xFF00) | (operand as u8).wrapping_add(1) as u16;
+                u16::from_le_bytes([self.read(operand), self.read(high)])
+            }
+            _ => unreachable!("{:?} has no address", mode),
+        }
+    }
+
+    fn compare(&mut self, register: u8, value: u8) {
+        self.set_flag(C, register >= value);
+        self.set_nz(register.wrapping_sub(value));
+    }
+
+    fn add(&mut self, value: u8) {
+        let a = self.state.a;
+        let sum = a as u16 + value as u16 + (self.state.p & C) as u16;
+        let result = sum as u8;
+        self.set_flag(C, sum > 0xFF);
+        self.set_flag(V, (a ^ result) & (value ^ result) & 0x80 != 0);
+        self.state.a = result;
+        self.set_nz(result);
+    }
+
+    /// Runs one instruction, or returns `None` on an undocumented opcode.
+    fn step(&mut self) -> Option<()> {
+        self.writes.clear();
+        let pc = self.state.pc;
+        let opcode = self.read(pc);
+        let (mnemonic, mode) = decode(opcode)?;
+        let operand = match mode.operand_bytes() {
+            0 => 0,
+            1 => self.read(pc.wrapping_add(1)) as u16,
+            _ => self.read_u16(pc.wrapping_add(1)),
+        };
+        let next = pc.wrapping_add(1 + mode.operand_bytes());
+        self.state.pc = next;
+
+        let load = |cpu: &Self| match mode {
+            Mode::Immediate => operand as u8,
+            Mode::Accumulator => cpu.state.a,
+            _ => cpu.read(cpu.address(mode, operand)),
+        };
+        // Read-modify-write on memory or A.
+        let modify = |cpu: &mut Self, f: &dyn Fn(&mut Self, u8) -> u8| {
+            let value = load(cpu);
+            let result = f(cpu, value);
+            match mode {
+                Mode::Accumulator => cpu.state.a = result,
+                _ => {
+                    let addr = cpu.address(mode, operand);
+                    cpu.write(addr, result);
+                }
+            }
+            cpu.set_nz(result);
+        };
+        let branch = |cpu: &mut Self, flag: u8, set: bool| {
+            if (cpu.state.p & flag != 0) == set {
+                cpu.state.pc = next.wrapping_add(operand as u8 as i8 as u16);
+            }
+        };
+
+        match mnemonic {
+            "LDA" => {
+                self.state.a = load(self);
+                self.set_nz(self.state.a);
+            }
+            "LDX" => {
+                self.state.x = load(self);
+                self.set_nz(self.state.x);
+            }
+            "LDY" => {
+                self.state.y = load(self);
+                self.set_nz(self.state.y);
+            }
+            "STA" => self.write(self.address(mode, operand), self.state.a),
+            "STX" => self.write(self.address(mode, operand), self.state.x),
+            "STY" => self.write(self.address(mode, operand), self.state.y),
+            "ORA" => {
+                self.state.a |= load(self);
+                self.set_nz(self.state.a);
+            }
+            "AND" => {
+                self.state.a &= load(self);
+                self.set_nz(self.state.a);
+            }
+            "EOR" => {
+                self.state.a ^= load(self);
+                self.set_nz(self.state.a);
+            }
+            "ADC" => self.add(load(self)),
+            "SBC" => self.add(!load(self)),
+            "CMP" => self.compare(self.state.a, load(self)),
+            "CPX" => self.compare(self.state.x, load(self)),
+            "CPY" => self.compare(self.state.y, load(self)),
+            "BIT" => {
+                let value = load(self);
+                self.set_flag(Z, self.state.a & value == 0);
+                self.set_flag(N, value & 0x80 != 0);
+                self.set_flag(V, value & 0x40 != 0);
+            }
+            "ASL" => modify(self, &|cpu, value| {
+                cpu.set_flag(C, value & 0x80 != 0);
+                value << 1
+            }),
+            "LSR" => modify(self, &|cpu, value| {
+                cpu.set_flag(C, value & 0x01 != 0);
+                value >> 1
+            }),
+            "ROL" => modify(self, &|cpu, value| {
+                let carry = cpu.state.p & C;
+                cpu.set_flag(C, value & 0x80 != 0);
+                value << 1 | carry
+            }),
+            "ROR" => modify(self, &|cpu, value| {
+                let carry = cpu.state.p & C;
+                cpu.set_flag(C, value & 0x01 != 0);
+                value >> 1 | carry << 7
+            }),
+            "INC" => modify(self, &|_, value| value.wrapping_add(1)),
+            "DEC" => modify(self, &|_, value| value.wrapping_sub(1)),
+            "INX" | "DEX" | "INY" | "DEY" | "TAX" | "TAY" | "TXA" | "TYA" | "TSX" => {
+                let s = self.state;
+                let value = match mnemonic {
+                    "INX" => s.x.wrapping_add(1),
+                    "DEX" => s.x.wrapping_sub(1),
+                    "INY" => s.y.wrapping_add(1),
+                    "DEY" => s.y.wrapping_sub(1),
+                    "TXA" => s.x,
+                    "TYA" => s.y,
+                    "TSX" => s.sp,
+                    _ => s.a,
+                };
+                match mnemonic {
+                    "INX" | "DEX" | "TAX" | "TSX" => self.state.x = value,
+                    "INY" | "DEY" | "TAY" => self.state.y = value,
+                    _ => self.state.a = value,
+                }
+                self.set_nz(value);
+            }
+            "TXS" => self.state.sp = self.state.x,
+            "CLC" => self.set_flag(C, false),
+            "SEC" => self.set_flag(C, true),
+            "CLI" => self.set_flag(I, false),
+            "SEI" => self.set_flag(I, true),
+            "CLV" => self.set_flag(V, false),
+            "CLD" => self.set_flag(0x08, false),
+            "SED" => self.set_flag(0x08, true),
+            "NOP" => {}
+            "PHA" => self.push(self.state.a),
+            "PHP" => self.push(self.state.p | 0x30),
+            "PLA" => {
+                self.state.a = self.pull();
+                self.set_nz(self.state.a);
+            }
+            "PLP" => self.state.p = self.pull(),
+            "BPL" => branch(self, N, false),
+            "BMI" => branch(self, N, true),
+            "BVC" => branch(self, V, false),
+            "BVS" => branch(self, V, true),
+            "BCC" => branch(self, C, false),
+            "BCS" => branch(self, C, true),
+            "BNE" => branch(self, Z, false),
+            "BEQ" => branch(self, Z, true),
+            "JMP" => self.state.pc = self.address(mode, operand),
+            "JSR" => {
+                let last = next.wrapping_sub(1);
+                self.push((last >> 8) as u8);
+                self.push(last as u8);
+                self.state.pc = operand;
+            }
+            "RTS" => {
+                let low = self.pull();
+                let high = self.pull();
+                self.state.pc = u16::from_le_bytes([low, high]).wrapping_add(1);
+            }
+            "BRK" => {
+                let ret = pc.wrapping_add(2);
+                self.push((ret >> 8) as u8);
+                self.push(ret as u8);
+                self.push(self.state.p | 0x30);
+                self.set_flag(I, true);
+                self.state.pc = self.read_u16(0xFFFE);
+            }
+            "RTI" => {
+                self.state.p = self.pull();
+                let low = self.pull();
+                let high = self.pull();
+                self.state.pc = u16::from_le_bytes([low, high]);
+            }
+            _ => unreachable!("{}", mnemonic),
+        }
+        Some(())
+    }
+}
+
+// xorshift64*
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn byte(&mut self) -> u8 {
+        (self.next() >> 32) as u8
+    }
+}
+
+fn pico_state(cpu: &CPU) -> State {
+    let r = &cpu.registers;
+    State {
+        a: r.a,
+        x: r.x,
+        y: r.y,
+        p: r.status.bits(),
+        sp: r.sp,
+        pc: r.pc,
+    }
+}
+
+fn same(expected: &State, actual: &State) -> bool {
+    State {
+        p: expected.p & P_MASK,
+        ..*expected
+    } == State {
+        p: actual.p & P_MASK,
+        ..*actual
+    }
+}
+
+/// Runs one case, returning a description of the first divergence.
+fn run_case(seed: u64) -> Result<usize, String> {
+    let mut rng = Rng(seed | 1);
+    let mut memory: Vec<u8> = (0..0x10000).map(|_| rng.byte()).collect();
+
+    let documented: Vec<u8> = (0..=255).filter(|&op| decode(op).is_some()).collect();
+    let start = 0x0200 + (rng.next() % 0xE000) as u16;
+    let mut addr = start;
+    for _ in 0..INSTRUCTIONS {
+        let opcode = documented[rng.next() as usize % documented.len()];
+        memory[addr as usize] = opcode;
+        addr = addr.wrapping_add(1 + decode(opcode).unwrap().1.operand_bytes());
+    }
+
+    let state = State {
+        a: rng.byte(),
+        x: rng.byte(),
+        y: rng.byte(),
+        p: rng.byte() & !0x10 | 0x20,
+        sp: rng.byte(),
+        pc: start,
+    };
+    let mut reference = Reference {
+        state,
+        memory: memory.clone(),
+        writes: Vec::new(),
+    };
+    let mut flat = FlatMemory::new();
+    flat.data = memory;
+    let mut cpu = CPU::with_model(CpuModel::Rp2A03);
+    cpu.registers.a = state.a;
+    cpu.registers.x = state.x;
+    cpu.registers.y = state.y;
+    cpu.registers.status = StatusFlags::from_bits_truncate(state.p);
+    cpu.registers.sp = state.sp;
+    cpu.registers.pc = state.pc;
+
+    let mut steps = 0;
+    while steps < INSTRUCTIONS * 2 {
+        let before = reference.state;
+        let pc = before.pc;
+        let bytes: Vec<String> = (0..3)
+            .map(|i| format!("{:02X}", reference.read(pc.wrapping_add(i))))
+            .collect();
+        if reference.step().is_none() {
+            break;
+        }
+        cpu.step(&mut flat);
+        steps += 1;
+
+        let actual = pico_state(&cpu);
+        let mut problems = Vec::new();
+        if !same(&reference.state, &actual) {
+            problems.push(format!(
+                "expected {}\n  actual   {}",
+                reference.state, actual
+            ));
+        }
+        for &addr in &reference.writes {
+            let (expected, actual) = (reference.memory[addr as usize], flat.data[addr as usize]);
+            if expected != actual {
+                problems.push(format!(
+                    "${:04X}: expected {:02X}, actual {:02X}",
+                    addr, expected, actual
+                ));
+            }
+        }
+        if !problems.is_empty() {
+            return Err(format!(
+                "step {}: {} at ${:04X} ({:?})\n  before   {}\n  {}",
+                steps,
+                bytes.join(" "),
+                pc,
+                decode(reference.read(pc)).map(|(mnemonic, _)| mnemonic),
+                before,
+                problems.join("\n  ")
+            ));
+        }
+    }
+
+    if let Some(addr) = (0..0x10000).find(|&addr| reference.memory[addr] != flat.data[addr]) {
+        return Err(format!(
+            "${:04X} differs at the end: expected {:02X}, actual {:02X}",
+            addr, reference.memory[addr], flat.data[addr]
+        ));
+    }
+    Ok(steps)
+}
+
+fn env_u64(name: &str) -> Option<u64> {
+    let value = std::env::var(name).ok()?;
+    match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+#[test]
+fn reference_decodes_the_documented_opcodes() {
+    assert_eq!((0..=255).filter(|&op| decode(op).is_some()).count(), 151);
+    assert_eq!(decode(0x6C), Some(("JMP", Mode::Indirect)));
+    assert_eq!(decode(0xBE), Some(("LDX", Mode::AbsoluteY)));
+    assert_eq!(decode(0x96), Some(("STX", Mode::ZeroPageY)));
+    assert_eq!(decode(0x9E), None);
+}
+
+#[test]
+fn cpu_matches_reference_on_random_programs() {
+    let seed = env_u64("PICO_FUZZ_SEED").unwrap_or(0x5EED_6502);
+    let cases = env_u64("PICO_FUZZ_CASES").unwrap_or(CASES);
+    let mut rng = Rng(seed | 1);
+    let mut steps = 0;
+    for case in 0..cases {
+        match run_case(rng.next()) {
+            Ok(case_steps) => steps += case_steps,
+            Err(divergence) => panic!("seed {:#X}, case {}, {}", seed, case, divergence),
+        }
+    }
+    // Most cases should get well into their program.
+    assert!(steps as u64 > cases * 8, "only {} steps", steps);
+}
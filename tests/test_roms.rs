@@ -0,0 +1,125 @@
+//! Accuracy suite driven by the public test ROMs.
+//!
+//! Fetch the ROMs first with `cargo run --bin fetch_test_roms`. When the cache
+//! directory is missing every test here is skipped.
+
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use pico::apu::APU;
+use pico::cart::Cart;
+use pico::cpu::ResetKind;
+use pico::nes::Nes;
+
+// blargg's test ROMs report through $6000: the status byte, a signature at
+// $6001-$6003 and a zero-terminated message from $6004.
+const STATUS_ADDR: u16 = 0x6000;
+const STATUS_RUNNING: u8 = 0x80;
+const STATUS_NEEDS_RESET: u8 = 0x81;
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const MAX_FRAMES: usize = 60 * 60;
+
+fn cache_dir() -> PathBuf {
+    std::env::var_os("PICO_TEST_ROMS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/test-roms"))
+}
+
+fn load(rom: &str) -> Option<Nes> {
+    let dir = cache_dir();
+    if !dir.is_dir() {
+        eprintln!(
+            "skipping {}: test ROMs not found, run `cargo run --bin fetch_test_roms`",
+            rom
+        );
+        return None;
+    }
+
+    let bytes = std::fs::read(dir.join(rom)).unwrap_or_else(|e| panic!("{}: {}", rom, e));
+    let cart = Cart::new(&bytes).unwrap_or_else(|e| panic!("{}: {}", rom, e));
+    let apu = APU::new(48_000, Arc::new(Mutex::new(VecDeque::new())));
+    let mut nes = Nes::new(cart, apu);
+    nes.reset(ResetKind::PowerOn);
+    Some(nes)
+}
+
+fn has_signature(nes: &Nes) -> bool {
+    (0..3).all(|i| nes.bus.peek(STATUS_ADDR + 1 + i) == SIGNATURE[i as usize])
+}
+
+fn message(nes: &Nes) -> String {
+    let mut text = String::new();
+    let mut addr = STATUS_ADDR + 4;
+    while addr < 0x7FFF {
+        let byte = nes.bus.peek(addr);
+        if byte == 0 {
+            break;
+        }
+        text.push(byte as char);
+        addr += 1;
+    }
+    text
+}
+
+fn run_blargg(rom: &str) {
+    let Some(mut nes) = load(rom) else {
+        return;
+    };
+
+    for _ in 0..MAX_FRAMES {
+        nes.step_frame();
+
+        if !has_signature(&nes) {
+            continue;
+        }
+
+        match nes.bus.peek(STATUS_ADDR) {
+            STATUS_RUNNING => {}
+            STATUS_NEEDS_RESET => nes.reset(ResetKind::Reset),
+            0 => return,
+            code => panic!("{} failed ({:#04X}): {}", rom, code, message(&nes)),
+        }
+    }
+
+    panic!("{} did not finish within {} frames", rom, MAX_FRAMES);
+}
+
+macro_rules! blargg_tests {
+    ($($name:ident => $rom:expr,)*) => {
+        $(
+            #[test]
+            fn $name() {
+                run_blargg($rom);
+            }
+        )*
+    };
+}
+
+blargg_tests! {
+    instr_basics => "instr_test-v5/rom_singles/01-basics.nes",
+    instr_implied => "instr_test-v5/rom_singles/02-implied.nes",
+    instr_immediate => "instr_test-v5/rom_singles/03-immediate.nes",
+    instr_zero_page => "instr_test-v5/rom_singles/04-zero_page.nes",
+    instr_zp_xy => "instr_test-v5/rom_singles/05-zp_xy.nes",
+    instr_absolute => "instr_test-v5/rom_singles/06-absolute.nes",
+    instr_abs_xy => "instr_test-v5/rom_singles/07-abs_xy.nes",
+    instr_ind_x => "instr_test-v5/rom_singles/08-ind_x.nes",
+    instr_ind_y => "instr_test-v5/rom_singles/09-ind_y.nes",
+    instr_branches => "instr_test-v5/rom_singles/10-branches.nes",
+    instr_stack => "instr_test-v5/rom_singles/11-stack.nes",
+    instr_jmp_jsr => "instr_test-v5/rom_singles/12-jmp_jsr.nes",
+    instr_rts => "instr_test-v5/rom_singles/13-rts.nes",
+    instr_rti => "instr_test-v5/rom_singles/14-rti.nes",
+    instr_brk => "instr_test-v5/rom_singles/15-brk.nes",
+    instr_special => "instr_test-v5/rom_singles/16-special.nes",
+    cpu_interrupts_cli_latency => "cpu_interrupts_v2/rom_singles/1-cli_latency.nes",
+    cpu_interrupts_nmi_and_brk => "cpu_interrupts_v2/rom_singles/2-nmi_and_brk.nes",
+    cpu_interrupts_nmi_and_irq => "cpu_interrupts_v2/rom_singles/3-nmi_and_irq.nes",
+    cpu_interrupts_branch_delays_irq => "cpu_interrupts_v2/rom_singles/5-branch_delays_irq.nes",
+    apu_len_ctr => "apu_test/rom_singles/1-len_ctr.nes",
+    apu_len_table => "apu_test/rom_singles/2-len_table.nes",
+    apu_irq_flag => "apu_test/rom_singles/3-irq_flag.nes",
+    apu_jitter => "apu_test/rom_singles/4-jitter.nes",
+    ppu_vbl_basics => "ppu_vbl_nmi/rom_singles/01-vbl_basics.nes",
+}